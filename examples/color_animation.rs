@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use nes_emulator::events::{Event, SharedEventBus};
@@ -9,6 +10,7 @@ fn main() {
     const INTER_FRAME_DELAY: Duration = Duration::from_millis(16);
 
     let event_bus = SharedEventBus::new();
+    let events = event_bus.subscribe();
     let mut ui = GtkUi::builder().with_event_bus(event_bus.clone()).build();
     ui.start();
 
@@ -16,10 +18,10 @@ fn main() {
         for direction in [true, false] {
             for step in 0..160 {
                 let frame = colors_animation_frame(step, direction);
-                ui.render(frame);
+                ui.render(Arc::new(frame));
                 std::thread::sleep(INTER_FRAME_DELAY);
 
-                if event_bus.access().emitted(Event::SwitchOff) {
+                if events.drain().contains(&Event::SwitchOff) {
                     break 'outer;
                 }
             }
@@ -0,0 +1,95 @@
+//! Custom UI
+//!
+//! Frames can be presented anywhere implementing the [`Ui`] trait. This
+//! example draws frames as ASCII art in the terminal, once per second, and
+//! switches the NES off after a few seconds.
+//!
+//! Usage: `cargo run --example custom_ui [ROM]`
+//!
+//! Without a ROM, a small test program is shown.
+
+use std::sync::Arc;
+
+use nes_emulator::errors::UiError;
+use nes_emulator::events::{Event, SharedEventBus};
+use nes_emulator::graphics::Frame;
+use nes_emulator::testing::scroll_split_cartidge;
+use nes_emulator::ui::Ui;
+use nes_emulator::{Cartidge, Nes};
+
+// Frames between ASCII art draws (NTSC runs at ~60 FPS)
+const DRAW_EVERY: u64 = 60;
+const SWITCH_OFF_AFTER: u64 = 3 * DRAW_EVERY;
+
+// From dark to bright
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+struct TerminalUi {
+    event_bus: SharedEventBus,
+    title: String,
+    frames: u64,
+}
+
+impl Ui for TerminalUi {
+    fn start(&mut self) -> Result<(), UiError> {
+        println!("Terminal UI started");
+        Ok(())
+    }
+
+    fn render(&mut self, frame: Arc<Frame>) {
+        self.frames += 1;
+        if self.frames.is_multiple_of(DRAW_EVERY) {
+            println!("{} - frame {}", self.title, frame.info.index);
+            draw(&frame);
+        }
+
+        // Stop after some frames, as a user closing the window would do
+        if self.frames == SWITCH_OFF_AFTER {
+            self.event_bus.emit(Event::SwitchOff);
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    fn stop(&mut self) -> Result<(), UiError> {
+        println!("Terminal UI stopped after {} frames", self.frames);
+        Ok(())
+    }
+}
+
+/// Draw a frame scaled down to 64x30 characters
+fn draw(frame: &Frame) {
+    for row in frame.inner.iter().step_by(8) {
+        let line: String = row
+            .iter()
+            .step_by(4)
+            .map(|pixel| {
+                let luma = 0.2126 * pixel.red() + 0.7152 * pixel.green() + 0.0722 * pixel.blue();
+                let shade = (luma * (SHADES.len() - 1) as f64).round() as usize;
+                SHADES[shade] as char
+            })
+            .collect();
+        println!("{line}");
+    }
+}
+
+fn main() {
+    let cartidge = match std::env::args().nth(1) {
+        Some(path) => Cartidge::new(path),
+        None => scroll_split_cartidge(),
+    };
+
+    let mut nes = Nes::builder().with_cartidge(cartidge).build();
+    let ui = TerminalUi {
+        event_bus: nes.event_bus(),
+        title: String::new(),
+        frames: 0,
+    };
+    nes.set_ui(Box::new(ui));
+
+    if let Err(error) = nes.run() {
+        eprintln!("{error}");
+    }
+}
@@ -0,0 +1,252 @@
+//! Debugger
+//!
+//! A minimal command line debugger built on top of the public API: it steps
+//! the CPU instruction by instruction, runs until a breakpoint is hit and
+//! inspects CPU, PPU and memory.
+//!
+//! Usage: `cargo run --example debugger [ROM]`
+//!
+//! Commands:
+//! - `s [N]`: step N instructions (1 by default)
+//! - `t [N]`: step N instructions (10 by default) printing a trace of them
+//! - `b ADDR [CONDITION]`: set a breakpoint at ADDR, only hit when CONDITION
+//!   is true, e.g., `b nmi A == $20 && [$00FE] > 3`. See the expression
+//!   module for the syntax
+//! - `c`: continue until a breakpoint is hit (or a frame budget runs out)
+//! - `m ADDR [LEN]`: dump LEN bytes (16 by default) of memory from ADDR.
+//!   Dumping the same memory again highlights the bytes that changed
+//! - `d [ADDR] [N]`: disassemble N instructions (8 by default) from ADDR (PC
+//!   by default)
+//! - `l FILE`: load symbols from an FCEUX name list (.nl) or ca65 debug info
+//!   (.dbg) file
+//! - `k`: show the call stack, innermost call first
+//! - `p`: show PPU state
+//! - `v [FILE]`: show which PRG ROM ranges have been executed (X), read (R)
+//!   or written (W), or save the coverage bitmap to FILE
+//! - `q`: quit
+//!
+//! Addresses are hexadecimal (`C000` or `$C000`) or symbol names, with an
+//! optional offset (`reset+3`).
+//!
+//! Without a ROM, a small test program is debugged.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use nes_emulator::coverage::Access;
+use nes_emulator::debugger::Breakpoint;
+use nes_emulator::disassembler::Disassembler;
+use nes_emulator::expression::Expression;
+use nes_emulator::interfaces::{AddressRange, Bus};
+use nes_emulator::memory_viewer::MemoryViewer;
+use nes_emulator::settings::UiKind;
+use nes_emulator::symbols::SymbolTable;
+use nes_emulator::testing::scroll_split_cartidge;
+use nes_emulator::{Cartidge, Nes};
+
+// Max instructions to run on continue. Around 10 frames
+const CONTINUE_BUDGET: usize = 100_000;
+
+fn main() {
+    let cartidge = match std::env::args().nth(1) {
+        Some(path) => Cartidge::new(path),
+        None => scroll_split_cartidge(),
+    };
+
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(cartidge)
+        .build();
+    nes.start_coverage();
+    nes.start_call_tracking();
+    let mut breakpoints = HashMap::new();
+    let mut disassembler = Disassembler::new();
+    let mut memory_viewer: Option<MemoryViewer> = None;
+
+    print_cpu(&nes, &disassembler);
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("s");
+        let argument = words.next();
+
+        match command {
+            "s" => {
+                let steps = argument.and_then(|n| n.parse().ok()).unwrap_or(1);
+                for _ in 0..steps {
+                    nes.step_instruction().unwrap();
+                }
+                print_cpu(&nes, &disassembler);
+            }
+
+            "t" => {
+                let steps = argument.and_then(|n| n.parse().ok()).unwrap_or(10);
+                for _ in 0..steps {
+                    print_instruction(&nes, &disassembler, nes.cpu_state().pc);
+                    nes.step_instruction().unwrap();
+                }
+                print_cpu(&nes, &disassembler);
+            }
+
+            "b" => match argument.and_then(|text| disassembler.symbols().parse_address(text)) {
+                Some(address) => {
+                    let condition = words.collect::<Vec<_>>().join(" ");
+                    let mut breakpoint = Breakpoint::new(address);
+                    if !condition.is_empty() {
+                        match Expression::parse_with_symbols(&condition, disassembler.symbols()) {
+                            Ok(condition) => breakpoint = breakpoint.with_condition(condition),
+                            Err(error) => {
+                                println!("{error}");
+                                continue;
+                            }
+                        }
+                    }
+                    breakpoints.insert(address, breakpoint);
+                    println!(
+                        "Breakpoint set at {}",
+                        disassembler.symbols().describe(address)
+                    );
+                }
+                None => println!("Usage: b ADDR [CONDITION]"),
+            },
+
+            "c" => {
+                let hit = (0..CONTINUE_BUDGET).any(|_| {
+                    nes.step_instruction().unwrap();
+                    let cpu = nes.cpu_state();
+                    breakpoints.get(&cpu.pc).is_some_and(|breakpoint| {
+                        breakpoint.is_hit(&cpu, |address| nes.peek(address))
+                    })
+                });
+                if !hit {
+                    println!("No breakpoint hit after {CONTINUE_BUDGET} instructions");
+                }
+                print_cpu(&nes, &disassembler);
+            }
+
+            "d" => {
+                let address = match argument {
+                    Some(text) => disassembler.symbols().parse_address(text),
+                    None => Some(nes.cpu_state().pc),
+                };
+                match address {
+                    Some(mut address) => {
+                        let count = words.next().and_then(|n| n.parse().ok()).unwrap_or(8);
+                        for _ in 0..count {
+                            address = print_instruction(&nes, &disassembler, address);
+                        }
+                    }
+                    None => println!("Usage: d [ADDR] [N]"),
+                }
+            }
+
+            "k" => print_call_stack(&nes, &disassembler),
+
+            "l" => match argument {
+                Some(path) => match SymbolTable::load(path) {
+                    Ok(symbols) => {
+                        println!("{} symbols loaded", symbols.len());
+                        disassembler.set_symbols(symbols);
+                    }
+                    Err(error) => println!("Unable to load symbols: {error}"),
+                },
+                None => println!("Usage: l FILE"),
+            },
+
+            "m" => match argument.and_then(|text| disassembler.symbols().parse_address(text)) {
+                Some(address) => {
+                    let length: u16 = words.next().and_then(|n| n.parse().ok()).unwrap_or(16);
+                    let range = AddressRange {
+                        start: address,
+                        end: address.saturating_add(length.max(1) - 1),
+                    };
+                    let viewer = memory_viewer.get_or_insert_with(|| MemoryViewer::new(range));
+                    if viewer.range() != range {
+                        viewer.set_range(range);
+                    }
+                    print!(
+                        "{}",
+                        viewer.refresh(|address| nes.peek(address)).to_text(true)
+                    );
+                }
+                None => println!("Usage: m ADDR [LEN]"),
+            },
+
+            "p" => println!("{:#?}", nes.ppu_state()),
+
+            "v" => {
+                let coverage = nes.coverage().unwrap();
+                match argument {
+                    Some(path) => match std::fs::write(path, coverage.to_bitmap()) {
+                        Ok(()) => println!("Coverage bitmap saved to {path}"),
+                        Err(error) => println!("Unable to save coverage: {error}"),
+                    },
+                    None => {
+                        print!("{}", coverage.report());
+                        println!(
+                            "{:.1}% of PRG ROM executed",
+                            coverage.ratio(Access::EXECUTE) * 100.0
+                        );
+                    }
+                }
+            }
+
+            "q" => break,
+
+            _ => println!("Unknown command '{command}'"),
+        }
+    }
+}
+
+/// Show CPU registers. The PC points to the next instruction to execute
+fn print_cpu(nes: &Nes, disassembler: &Disassembler) {
+    let cpu = nes.cpu_state();
+    println!(
+        "PC={} A=${:0>2X} X=${:0>2X} Y=${:0>2X} SP=${:0>2X} P=${:0>2X}  (frame {})",
+        disassembler.symbols().describe(cpu.pc),
+        cpu.acc,
+        cpu.x_reg,
+        cpu.y_reg,
+        cpu.sp,
+        cpu.sr,
+        nes.frame_count()
+    );
+}
+
+/// Show how execution reached the current location
+fn print_call_stack(nes: &Nes, disassembler: &Disassembler) {
+    let symbols = disassembler.symbols();
+    let frames = nes.call_stack().unwrap().frames();
+    if frames.is_empty() {
+        println!("No calls");
+    }
+    for (depth, frame) in frames.iter().rev().enumerate() {
+        println!(
+            "#{depth:<3} {} from {} ({:?}, returns to {})",
+            symbols.describe(frame.target),
+            symbols.describe(frame.caller),
+            frame.kind,
+            symbols.describe(frame.return_address),
+        );
+    }
+}
+
+/// Print the instruction at `address`, preceded by its label if any, and
+/// return the address of the next one. Only code is expected to be
+/// disassembled, so reads have no side effects
+fn print_instruction(nes: &Nes, disassembler: &Disassembler, address: u16) -> u16 {
+    if let Some(name) = disassembler.symbols().name(address) {
+        println!("{name}:");
+    }
+    let bus = nes.main_bus.borrow();
+    let instruction = disassembler.disassemble(address, |address| bus.read(address));
+    println!("  {instruction}");
+    instruction.next_address()
+}
@@ -0,0 +1,63 @@
+//! Headless run
+//!
+//! Run a game without UI for some frames and save the last one as a PPM image.
+//! Headless runs are useful for automated tests, bots or servers.
+//!
+//! Usage: `cargo run --example headless_run [ROM] [FRAMES] [OUTPUT]`
+//!
+//! Without a ROM, a small test program is used, so the example runs out of the
+//! box.
+
+use std::fs;
+use std::process::ExitCode;
+
+use nes_emulator::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_emulator::settings::UiKind;
+use nes_emulator::testing::{frame_hash, scroll_split_cartidge};
+use nes_emulator::{Cartidge, Nes};
+
+const DEFAULT_FRAMES: u64 = 60;
+const DEFAULT_OUTPUT: &str = "headless_run.ppm";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let cartidge = match args.next() {
+        Some(path) => Cartidge::new(path),
+        None => scroll_split_cartidge(),
+    };
+    let frames = args
+        .next()
+        .and_then(|frames| frames.parse().ok())
+        .unwrap_or(DEFAULT_FRAMES);
+    let output = args.next().unwrap_or(DEFAULT_OUTPUT.to_string());
+
+    println!("Running {cartidge} for {frames} frames");
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(cartidge)
+        .build();
+    if let Err(error) = nes.run_frames(frames) {
+        eprintln!("{error}");
+        return ExitCode::FAILURE;
+    }
+
+    let frame = nes
+        .last_frame()
+        .expect("Frames are kept when running without UI");
+    println!(
+        "Frame {} hash: 0x{:0>16X}",
+        frame.info.index,
+        frame_hash(frame)
+    );
+
+    // Binary PPM: a tiny header followed by the RGB pixels
+    let mut image = format!("P6\n{SCREEN_WIDTH} {SCREEN_HEIGHT}\n255\n").into_bytes();
+    image.extend(frame.to_rgb24());
+    if let Err(error) = fs::write(&output, image) {
+        eprintln!("Unable to save {output}: {error}");
+        return ExitCode::FAILURE;
+    }
+    println!("Last frame saved in {output}");
+
+    ExitCode::SUCCESS
+}
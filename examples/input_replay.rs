@@ -0,0 +1,73 @@
+//! Input replay
+//!
+//! Emulation is deterministic: the same input produces the same frames. This
+//! example records some input in a movie, saves it as FM2 (FCEUX movie format)
+//! and replays it in a fresh NES, checking both runs end with the same frame.
+//!
+//! Usage: `cargo run --example input_replay [ROM]`
+//!
+//! Without a ROM, a small test program is used (it doesn't read controllers,
+//! so the recorded input has no effect on it).
+
+use std::process::ExitCode;
+
+use nes_emulator::movie::Movie;
+use nes_emulator::settings::UiKind;
+use nes_emulator::testing::{frame_hash, scroll_split_cartidge};
+use nes_emulator::{Cartidge, ControllerState, Nes};
+
+const FRAMES: u64 = 120;
+
+fn cartidge() -> Cartidge {
+    match std::env::args().nth(1) {
+        Some(path) => Cartidge::new(path),
+        None => scroll_split_cartidge(),
+    }
+}
+
+fn headless_nes() -> Nes {
+    Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(cartidge())
+        .build()
+}
+
+fn main() -> ExitCode {
+    // Record: press START for a while and then walk right jumping
+    let mut nes = headless_nes();
+    nes.start_movie_recording();
+    for frame in 0..FRAMES {
+        let state = match frame {
+            10..=15 => ControllerState::START,
+            60.. if frame % 20 < 5 => ControllerState::RIGHT | ControllerState::A,
+            60.. => ControllerState::RIGHT,
+            _ => ControllerState::empty(),
+        };
+        nes.set_controller_one_state(state);
+        nes.run_frames(1).unwrap();
+    }
+    let recorded_hash = frame_hash(nes.last_frame().unwrap());
+    let mut movie = nes.stop_movie_recording().unwrap();
+    movie.set_header("comment", "Recorded by the input_replay example");
+
+    // Movies can be saved and loaded in FM2 format
+    let fm2 = movie.to_fm2();
+    println!("Recorded movie:\n{}...", &fm2[..fm2.len().min(400)]);
+    let movie = Movie::from_fm2(&fm2).unwrap();
+
+    // Replay
+    let mut nes = headless_nes();
+    nes.play_movie(&movie).unwrap();
+    nes.run_frames(FRAMES).unwrap();
+    let replayed_hash = frame_hash(nes.last_frame().unwrap());
+
+    println!("Recorded run last frame: 0x{recorded_hash:0>16X}");
+    println!("Replayed run last frame: 0x{replayed_hash:0>16X}");
+    if recorded_hash == replayed_hash {
+        println!("Replay matches the recording");
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("Replay diverged from the recording!");
+        ExitCode::FAILURE
+    }
+}
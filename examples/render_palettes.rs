@@ -10,6 +10,8 @@
 //! Read more about NES palettes here:
 //! https://www.nesdev.org/wiki/PPU_palettes
 
+use std::sync::Arc;
+
 use nes_emulator::graphics::{Frame, FramePixel, Pixel};
 use nes_emulator::hardware::{PALETTE_MEMORY_START, SCREEN_HEIGHT, SCREEN_WIDTH};
 use nes_emulator::interfaces::Bus;
@@ -50,7 +52,7 @@ fn main() {
     ui.start().unwrap();
 
     let frame = render_palettes(&nes);
-    ui.render(frame);
+    ui.render(Arc::new(frame));
 
     ui.stop().unwrap();
 }
@@ -8,6 +8,8 @@
 //! https://www.nesdev.org/wiki/PPU_palettes
 //!
 
+use std::sync::Arc;
+
 use nes_emulator::graphics::pattern_table::PatternTableAddress;
 use nes_emulator::graphics::{Frame, FramePixel, Pixel};
 use nes_emulator::hardware::PALETTE_MEMORY_START;
@@ -60,7 +62,7 @@ fn main() {
     assert!(palette <= 7);
 
     let frame = render_pattern_tables(&nes, palette);
-    ui.render(frame);
+    ui.render(Arc::new(frame));
 
     ui.stop().unwrap();
 }
@@ -0,0 +1,60 @@
+//! Scripted input
+//!
+//! Play a game driven by text commands like `P1 A+RIGHT 10`, read from the
+//! standard input or a FIFO. Any program able to write text can play, e.g.:
+//!
+//! ```text
+//! $ printf 'WAIT 60\nP1 START\nWAIT 120\nP1 RIGHT 200\n' | cargo run --example scripted_input game.nes
+//! ```
+//!
+//! See the input_script module for the commands syntax.
+//!
+//! Usage: `cargo run --example scripted_input ROM [FIFO]`
+//!
+//! Run it with `--headless` to run without UI, printing the frame count
+//! every second, until the input ends and all its commands have run.
+
+use std::process::ExitCode;
+
+use nes_emulator::input_script::InputScript;
+use nes_emulator::settings::UiKind;
+use nes_emulator::{Cartidge, ControllerButtons, Nes};
+
+fn main() -> ExitCode {
+    let headless = std::env::args().any(|arg| arg == "--headless");
+    let mut arguments = std::env::args().skip(1).filter(|arg| arg != "--headless");
+    let Some(rom) = arguments.next() else {
+        eprintln!("Usage: scripted_input ROM [FIFO] [--headless]");
+        return ExitCode::FAILURE;
+    };
+    let script = match arguments.next() {
+        Some(fifo) => InputScript::open(fifo),
+        None => InputScript::stdin(),
+    };
+
+    let mut nes = Nes::builder()
+        .with_ui(if headless { UiKind::None } else { UiKind::Gtk })
+        .with_controllers(ControllerButtons::default(), None)
+        .with_cartidge(Cartidge::new(rom))
+        .build();
+    nes.play_input_script(script);
+
+    if !headless {
+        return match nes.run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    while !nes.input_script().unwrap().is_finished() {
+        if let Err(error) = nes.run_frames(60) {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+        println!("Frame {}", nes.frame_count());
+    }
+    ExitCode::SUCCESS
+}
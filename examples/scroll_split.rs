@@ -0,0 +1,55 @@
+//! Scroll split
+//!
+//! Many games split the screen in a static status bar and a scrolling
+//! playfield (like Super Mario Bros.). As the PPU only has one set of scroll
+//! registers, games change them mid-frame. To know when, they place sprite 0
+//! right below the status bar and wait for the sprite 0 hit flag.
+//!
+//! This example runs a small test program doing exactly that: the top rows
+//! are not scrolled while the rest of the screen is scrolled 4 pixels to the
+//! right.
+//!
+//! Run it with `--headless` to validate the produced frames without UI. The
+//! process exits with an error if the split is not correct.
+//!
+//! Read more about scrolling here:
+//! https://www.nesdev.org/wiki/PPU_scrolling
+
+use std::process::ExitCode;
+
+use nes_emulator::settings::{NesSettings, UiKind};
+use nes_emulator::testing::{check_scroll_split, run_headless, scroll_split_cartidge};
+use nes_emulator::Nes;
+
+const HEADLESS_FRAMES: u64 = 10;
+
+fn main() -> ExitCode {
+    if std::env::args().any(|arg| arg == "--headless") {
+        let frame = run_headless(scroll_split_cartidge(), HEADLESS_FRAMES);
+        return match check_scroll_split(&frame) {
+            Ok(()) => {
+                println!("Scroll split OK");
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                eprintln!("Scroll split failed: {error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let mut nes = Nes::new(NesSettings {
+        ui_kind: UiKind::Gtk,
+        ..Default::default()
+    });
+    nes.load_cartidge(scroll_split_cartidge());
+    nes.setup_tv();
+
+    match nes.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Audio output
+//!
+//! The APU is not emulated yet, so the NES doesn't produce audio. This module
+//! holds the placeholder mapped in place of its registers.
+
+use crate::interfaces::Memory;
+use crate::processor::memory::Ram;
+use crate::types::SharedWarnings;
+use crate::warnings::WarningKind;
+
+/// Stand-in for APU registers until the APU is emulated. Registers behave as
+/// RAM and writes are reported as [`WarningKind::ApuNotEmulated`]
+pub struct ApuPlaceholder {
+    registers: Ram,
+    start: u16,
+    warnings: SharedWarnings,
+}
+
+impl ApuPlaceholder {
+    /// Registers from `start` to `end` (included)
+    pub(crate) fn new(start: u16, end: u16, warnings: SharedWarnings) -> Self {
+        Self {
+            registers: Ram::new((end - start + 1) as usize),
+            start,
+            warnings,
+        }
+    }
+}
+
+impl Memory for ApuPlaceholder {
+    fn read(&self, address: u16) -> u8 {
+        self.registers.read(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.warnings
+            .borrow_mut()
+            .report(WarningKind::ApuNotEmulated, Some(self.start + address));
+        self.registers.write(address, data);
+    }
+
+    fn size(&self) -> usize {
+        self.registers.size()
+    }
+}
@@ -0,0 +1,278 @@
+//! ROM compatibility scanner
+//!
+//! Run every iNES ROM in a directory headlessly for some frames, in parallel,
+//! and write a CSV compatibility report. Each ROM ends up as:
+//!
+//! - `ok`: it ran all the frames and the screen shows something
+//! - `blank`: it ran all the frames but the screen is a single color
+//! - `error`: the emulator stopped with an error (e.g., a bus fault)
+//! - `panic`: the emulator panicked, e.g., loading an unsupported mapper
+//!
+//! Usage: `compat-scan DIRECTORY [--frames N] [--jobs N] [--output FILE]`
+//!
+//! The report is written to the standard output unless `--output` is given.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use nes_emulator::settings::UiKind;
+use nes_emulator::{Cartidge, Nes};
+
+const DEFAULT_FRAMES: u64 = 300;
+const USAGE: &str = "Usage: compat-scan DIRECTORY [--frames N] [--jobs N] [--output FILE]";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Status {
+    Ok,
+    Blank,
+    Error,
+    Panic,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Ok => "ok",
+            Status::Blank => "blank",
+            Status::Error => "error",
+            Status::Panic => "panic",
+        };
+        f.pad(name)
+    }
+}
+
+struct ScanResult {
+    rom: PathBuf,
+    checksum: Option<u32>,
+    mapper: Option<u8>,
+    status: Status,
+    frames: u64,
+    seconds: f64,
+    details: String,
+}
+
+struct Options {
+    directory: PathBuf,
+    frames: u64,
+    jobs: usize,
+    output: Option<PathBuf>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut directory = None;
+    let mut frames = DEFAULT_FRAMES;
+    let mut jobs = thread::available_parallelism().map_or(1, |jobs| jobs.get());
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("Missing value for {name}"));
+        match arg.as_str() {
+            "--frames" => {
+                frames = value("--frames")?
+                    .parse()
+                    .map_err(|_| "Invalid frame count".to_string())?
+            }
+            "--jobs" => {
+                jobs = value("--jobs")?
+                    .parse()
+                    .ok()
+                    .filter(|&jobs| jobs > 0)
+                    .ok_or("Invalid number of jobs".to_string())?
+            }
+            "--output" => output = Some(PathBuf::from(value("--output")?)),
+            _ if directory.is_none() && !arg.starts_with("--") => {
+                directory = Some(PathBuf::from(arg))
+            }
+            _ => return Err(format!("Unexpected argument {arg}")),
+        }
+    }
+
+    Ok(Options {
+        directory: directory.ok_or(USAGE.to_string())?,
+        frames,
+        jobs,
+        output,
+    })
+}
+
+/// iNES files in `directory`, sorted by name
+fn find_roms(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("nes"))
+        })
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+fn scan(rom: &Path, frames: u64) -> ScanResult {
+    let start = Instant::now();
+    let mut result = ScanResult {
+        rom: rom.to_path_buf(),
+        checksum: None,
+        mapper: None,
+        status: Status::Ok,
+        frames: 0,
+        seconds: 0.0,
+        details: String::new(),
+    };
+
+    // The result is updated while running, so a panic keeps what was known
+    // until then
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let cartidge = Cartidge::new(rom);
+        result.checksum = Some(cartidge.checksum());
+        result.mapper = Some(cartidge.info().mapper);
+
+        let mut nes = Nes::builder()
+            .with_ui(UiKind::None)
+            .with_cartidge(cartidge)
+            .build();
+        for _ in 0..frames {
+            if let Err(error) = nes.run_frames(1) {
+                result.status = Status::Error;
+                result.details = error.to_string();
+                break;
+            }
+            result.frames += 1;
+        }
+
+        let blank = nes.last_frame().is_none_or(|frame| {
+            let pixels = frame.to_rgb24();
+            pixels.chunks(3).all(|pixel| pixel == &pixels[..3])
+        });
+        if result.status == Status::Ok && blank {
+            result.status = Status::Blank;
+        }
+    }));
+
+    if let Err(payload) = outcome {
+        result.status = Status::Panic;
+        result.details = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+    }
+    result.seconds = start.elapsed().as_secs_f64();
+    result
+}
+
+/// Run `roms` in `jobs` worker threads. Results are returned in any order
+fn scan_all(roms: &[PathBuf], frames: u64, jobs: usize) -> Vec<ScanResult> {
+    let next = AtomicUsize::new(0);
+    let (sender, results) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(roms.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || {
+                while let Some(rom) = roms.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = scan(rom, frames);
+                    eprintln!("{:<5} {}", result.status, rom.display());
+                    sender.send(result).unwrap();
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    results.into_iter().collect()
+}
+
+/// CSV field, quoted if needed
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\"").replace('\n', " "))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_report(mut output: impl Write, results: &[ScanResult]) -> io::Result<()> {
+    writeln!(output, "rom,crc32,mapper,status,frames,seconds,details")?;
+    for result in results {
+        let name = result
+            .rom
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        writeln!(
+            output,
+            "{},{},{},{},{},{:.2},{}",
+            csv_field(&name),
+            result
+                .checksum
+                .map_or(String::new(), |checksum| format!("{checksum:08X}")),
+            result
+                .mapper
+                .map_or(String::new(), |mapper| mapper.to_string()),
+            result.status,
+            result.frames,
+            result.seconds,
+            csv_field(&result.details),
+        )?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let roms = match find_roms(&options.directory) {
+        Ok(roms) => roms,
+        Err(error) => {
+            eprintln!("Unable to read {:?}: {error}", options.directory);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!(
+        "Scanning {} ROMs for {} frames with {} jobs",
+        roms.len(),
+        options.frames,
+        options.jobs
+    );
+
+    // Panics are reported in the results, don't clutter the output with them
+    panic::set_hook(Box::new(|_| {}));
+    let mut results = scan_all(&roms, options.frames, options.jobs);
+    let _ = panic::take_hook();
+    results.sort_by(|a, b| a.rom.cmp(&b.rom));
+
+    let written = match options.output.as_ref() {
+        Some(path) => fs::File::create(path).and_then(|file| write_report(file, &results)),
+        None => write_report(io::stdout().lock(), &results),
+    };
+    if let Err(error) = written {
+        eprintln!("Unable to write the report: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    for status in [Status::Ok, Status::Blank, Status::Error, Status::Panic] {
+        let count = results
+            .iter()
+            .filter(|result| result.status == status)
+            .count();
+        eprintln!("{status:<5} {count}");
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,230 @@
+//! Screenshot bursts
+//!
+//! A [`ScreenshotBurst`] saves every Nth frame to a directory for a bounded
+//! number of frames, to make timelapses or document compatibility reports.
+//! Files are named after the frame number, e.g., `frame_00000120.png`, so
+//! they sort in order.
+//!
+//! Start one with
+//! [`Nes::start_screenshot_burst`](crate::Nes::start_screenshot_burst).
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::graphics::Frame;
+use crate::utils::crc32;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Largest stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = u16::MAX as usize;
+
+/// Image format of the captured frames
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CaptureFormat {
+    /// PNG, readable anywhere. It's not compressed, to keep the encoder
+    /// simple, so files take as much as raw ones
+    #[default]
+    Png,
+    /// Binary ("raw") PPM: a tiny header followed by the RGB pixels
+    Raw,
+}
+
+impl CaptureFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CaptureFormat::Png => "png",
+            CaptureFormat::Raw => "ppm",
+        }
+    }
+
+    pub fn encode(&self, frame: &Frame) -> Vec<u8> {
+        match self {
+            CaptureFormat::Png => encode_png(frame),
+            CaptureFormat::Raw => encode_ppm(frame),
+        }
+    }
+}
+
+/// What a [`ScreenshotBurst`] captures and where it saves it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BurstSettings {
+    /// Where frames are saved. It's created if needed
+    pub directory: PathBuf,
+    /// Capture one frame every `interval` frames, starting with the first
+    pub interval: u64,
+    /// Frames the burst lasts, captured or not
+    pub duration: u64,
+    /// Image format of the files
+    pub format: CaptureFormat,
+}
+
+/// Frames being captured, see the [module](self) documentation
+#[derive(Debug)]
+pub struct ScreenshotBurst {
+    settings: BurstSettings,
+    elapsed: u64,
+    captured: Vec<PathBuf>,
+}
+
+impl ScreenshotBurst {
+    pub fn new(settings: BurstSettings) -> io::Result<Self> {
+        if settings.interval == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "capture interval must be at least 1",
+            ));
+        }
+        fs::create_dir_all(&settings.directory)?;
+        Ok(Self {
+            settings,
+            elapsed: 0,
+            captured: Vec::new(),
+        })
+    }
+
+    pub fn settings(&self) -> &BurstSettings {
+        &self.settings
+    }
+
+    /// Files saved so far, in order
+    pub fn captured(&self) -> &[PathBuf] {
+        &self.captured
+    }
+
+    /// Whether the burst lasted its duration
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.settings.duration
+    }
+
+    /// File `frame` is saved to
+    pub fn path(&self, frame: &Frame) -> PathBuf {
+        self.settings.directory.join(format!(
+            "frame_{:0>8}.{}",
+            frame.info.index,
+            self.settings.format.extension()
+        ))
+    }
+
+    /// End the burst before its duration, e.g., after failing to save
+    pub fn finish(&mut self) {
+        self.elapsed = self.elapsed.max(self.settings.duration);
+    }
+
+    /// A frame has been produced, save it if it's due
+    pub fn observe_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        if self.is_finished() {
+            return Ok(());
+        }
+        let due = self.elapsed.is_multiple_of(self.settings.interval);
+        self.elapsed += 1;
+        if due {
+            let path = self.path(frame);
+            fs::write(&path, self.settings.format.encode(frame))?;
+            self.captured.push(path);
+        }
+        Ok(())
+    }
+}
+
+fn frame_size(frame: &Frame) -> (usize, usize) {
+    let width = frame.inner.first().map_or(0, Vec::len);
+    (width, frame.inner.len())
+}
+
+/// Encode `frame` as a binary PPM
+pub fn encode_ppm(frame: &Frame) -> Vec<u8> {
+    let (width, height) = frame_size(frame);
+    let mut image = format!("P6\n{width} {height}\n255\n").into_bytes();
+    image.extend(frame.to_rgb24());
+    image
+}
+
+/// Encode `frame` as an uncompressed 24-bit PNG
+pub fn encode_png(frame: &Frame) -> Vec<u8> {
+    let (width, height) = frame_size(frame);
+
+    // Every row starts with its filter type, none
+    let mut rows = Vec::with_capacity((width * 3 + 1) * height);
+    for row in frame.to_rgb24().chunks(width * 3) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no
+    // interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&rows));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// Zlib stream of stored deflate blocks, i.e., without compression
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    for index in 0..blocks {
+        let start = index * MAX_STORED_BLOCK;
+        let block = &data[start..data.len().min(start + MAX_STORED_BLOCK)];
+        stream.push(u8::from(index == blocks - 1));
+        stream.extend((block.len() as u16).to_le_bytes());
+        stream.extend((!(block.len() as u16)).to_le_bytes());
+        stream.extend(block);
+    }
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MODULO;
+        b = (b + a) % MODULO;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Pixel;
+    use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_png_encoding() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let png = encode_png(&Frame::new(Pixel::new_rgb_byte(0xFF, 0x80, 0x00)));
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], (SCREEN_WIDTH as u32).to_be_bytes());
+        assert_eq!(png[20..24], (SCREEN_HEIGHT as u32).to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // Rows are split in stored blocks, the last one flagged as final
+        let rows = (SCREEN_WIDTH * 3 + 1) * SCREEN_HEIGHT;
+        let stream = zlib_stored(&vec![0; rows]);
+        let blocks = rows.div_ceil(MAX_STORED_BLOCK);
+        assert_eq!(stream.len(), 2 + blocks * 5 + rows + 4);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + (blocks - 1) * (MAX_STORED_BLOCK + 5)], 1);
+    }
+}
@@ -1,18 +1,76 @@
+use std::cmp::Ordering;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::mappers::mapper_map;
-use crate::mappers::{Mapper, MapperSpecs};
+use crate::errors::RomError;
+use crate::hardware::{CARTIDGE_RAM_SIZE, CARTIDGE_RAM_START, RESET_VECTOR};
+use crate::mappers::{self, mapper_map, mapper_name, valid_program_rom_size, MapperStatus};
+use crate::mappers::{FlatMapper, Mapper, MapperSpecs};
 use crate::processor::memory::Mirroring;
-use crate::utils::bv;
+use crate::utils::{bv, crc32};
+
+/// iNES files start with ASCII "NES" and MS-DOS end-of-file (0x1A)
+const INES_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 
 pub struct Cartidge {
     name: String,
     pub mapper: Box<dyn Mapper>,
     header: CartidgeHeader,
+    checksum: u32,
+}
+
+/// Cartidge metadata, obtained from its file name and iNES header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartidgeInfo {
+    /// Game title, the file name without extension
+    pub title: String,
+    pub mapper: u8,
+    pub mapper_name: &'static str,
+    /// PRG ROM size in bytes
+    pub program_rom_size: usize,
+    /// CHR ROM size in bytes. 0 means the cartidge uses CHR RAM
+    pub character_rom_size: usize,
+    pub mirroring: Mirroring,
+    /// Battery-backed PRG RAM (saved games)
+    pub battery: bool,
+    pub region: Region,
+}
+
+/// How strictly ROM files are checked when loading them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RomValidation {
+    /// Files must have exactly the PRG and CHR sizes their header declares
+    #[default]
+    Strict,
+
+    /// Truncated files are padded with zeros and extra data is discarded,
+    /// like most emulators do. Bad dumps may still be playable
+    Lenient,
+}
+
+/// Memory map of raw 6502 programs, see [`Cartidge::from_raw_prg`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawMapping {
+    /// Cartidge space of an NROM board: PRG RAM ($6000-$7FFF) and PRG ROM
+    /// ($8000-$FFFF), whose writes are ignored. The rest of the NES is there
+    #[default]
+    Nrom,
+
+    /// 64 kB of RAM over the whole address space, replacing the NES memory
+    /// and registers, like functional tests loaded at $0000 expect (e.g.,
+    /// Klaus Dormann's, with self-modifying code at $0400)
+    Flat,
+}
+
+/// TV system a cartidge was made for, as declared in its header. Most dumps
+/// don't set it, so it's not a reliable source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
 }
 
 impl Cartidge {
@@ -21,88 +79,281 @@ impl Cartidge {
     /// Read more about iNES ROM file format in:
     /// https://www.nesdev.org/wiki/INES
     ///
-    /// NES2.0 file format is not implemented.
+    /// NES2.0 file format is not implemented, except for small PRG ROM sizes
+    /// and the TV system.
     ///
     /// Header flags 8 to 10 are ignored.
     ///
     /// *Panic*
     ///
-    /// - iNES file format is expected and can panic if a different file format
-    /// is used.
-    /// - It can also panics if an invalid path is provided
+    /// If the file can't be read or it's not a valid iNES file. Use
+    /// [`Cartidge::load`] to handle these errors
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        if !path.as_ref().exists() {
-            panic!(
-                "Game {:?} not found. Make sure the path is correct",
-                path.as_ref().as_os_str()
-            );
-        }
+        let path = path.as_ref();
+        Self::load(path).unwrap_or_else(|error| panic!("Unable to load game {path:?}: {error}"))
+    }
+
+    /// Load a cartidge from an iNES file, checking the file is a complete
+    /// iNES image. See [`Cartidge::new`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RomError> {
+        Self::load_with(path, RomValidation::Strict)
+    }
 
+    /// Load a cartidge from an iNES file with the given `validation`
+    pub fn load_with(path: impl AsRef<Path>, validation: RomValidation) -> Result<Self, RomError> {
+        let path = path.as_ref();
         let game_name = path
-            .as_ref()
             .file_name()
-            .expect("Expected a .nes file, to a directory")
-            .to_owned()
-            .into_string()
-            .unwrap();
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
 
-        let mut file = File::open(path).unwrap();
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
 
-        let mut header = [0; 16]; // 16 byte header
-        file.read_exact(&mut header).unwrap();
+        Self::try_from_bytes(game_name, &contents, validation)
+    }
 
-        let cartidge_header = CartidgeHeader::parse(&header);
+    /// Create a new cartidge from the contents of an iNES file already loaded
+    /// in memory. Useful to embed ROMs in binaries or build them on the fly.
+    ///
+    /// *Panic*
+    ///
+    /// If `contents` is not a valid iNES image. Use
+    /// [`Cartidge::try_from_bytes`] to handle the error
+    pub fn from_bytes(name: impl Into<String>, contents: &[u8]) -> Self {
+        Self::try_from_bytes(name, contents, RomValidation::Strict)
+            .unwrap_or_else(|error| panic!("Invalid iNES image: {error}"))
+    }
+
+    /// Create a new cartidge from the contents of an iNES file, validating
+    /// them according to `validation`
+    pub fn try_from_bytes(
+        name: impl Into<String>,
+        contents: &[u8],
+        validation: RomValidation,
+    ) -> Result<Self, RomError> {
+        let header: &[u8; 16] = contents
+            .get(..16)
+            .and_then(|header| header.try_into().ok())
+            .ok_or(RomError::RomTooShort {
+                expected: 16,
+                actual: contents.len(),
+            })?;
+        if header[0..4] != INES_MAGIC {
+            return Err(RomError::BadMagic(header[0..4].try_into().unwrap()));
+        }
+
+        let cartidge_header = CartidgeHeader::parse(header);
         debug!("Header: {cartidge_header:#?}");
+        if mappers::info(cartidge_header.mapper).status == MapperStatus::Unsupported {
+            return Err(RomError::UnsupportedMapper(cartidge_header.mapper));
+        }
+        if !valid_program_rom_size(cartidge_header.mapper, cartidge_header.pgr_rom_size) {
+            return Err(RomError::InvalidProgramRomSize {
+                mapper: cartidge_header.mapper,
+                size: cartidge_header.pgr_rom_size,
+            });
+        }
 
-        // Trainer content is ignored for now
-        let _trainer = if cartidge_header.trainer {
-            let mut buf = [0; 512]; // 512-byte trainer at 0x7000 - 0x71FF
-            file.read_exact(&mut buf).unwrap();
-            Some(buf)
-        } else {
-            None
-        };
+        let expected = cartidge_header.file_size();
+        let actual = contents.len();
+        let mut contents = contents.to_vec();
+        match (validation, actual.cmp(&expected)) {
+            (_, Ordering::Equal) => {}
+            (RomValidation::Strict, Ordering::Less) => {
+                return Err(RomError::RomTooShort { expected, actual });
+            }
+            (RomValidation::Strict, Ordering::Greater) => {
+                return Err(RomError::SizeMismatch { expected, actual });
+            }
+            (RomValidation::Lenient, _) => {
+                warn!("ROM has {actual} bytes but its header declares {expected}, resizing it");
+                contents.resize(expected, 0);
+            }
+        }
+
+        // Trainer content is ignored for now: 512 bytes at 0x7000 - 0x71FF
+        let trainer_size = if cartidge_header.trainer { 512 } else { 0 };
+        let (program_rom, character_rom) =
+            contents[16 + trainer_size..].split_at(cartidge_header.pgr_rom_size);
 
         let mapper_specs = MapperSpecs {
             program_ram_capacity: cartidge_header.pgr_ram_size,
             program_rom_capacity: cartidge_header.pgr_rom_size,
             character_memory_capacity: cartidge_header.chr_rom_size,
         };
-        let mut mapper = mapper_map(cartidge_header.mapper, mapper_specs);
-
-        // let program_rom = Rc::new(RefCell::new(Rom::new(cartidge_header.pgr_rom_size)));
-        let mut buf = vec![0; cartidge_header.pgr_rom_size];
-        file.read_exact(&mut buf).unwrap();
-        mapper.load_program_rom(&buf);
-        // program_rom.borrow_mut().load(0, &buf);
-
-        // let character_memory = Rc::new(RefCell::new(Ram::new(cartidge_header.chr_rom_size)));
-        let mut buf = vec![0; cartidge_header.chr_rom_size];
-        file.read_exact(&mut buf).unwrap();
-        mapper.load_character_memory(&buf);
-        // character_memory.borrow_mut().load(0, &buf);
-
-        let mut rest = Vec::new();
-        file.read_to_end(&mut rest).unwrap();
-        if !rest.is_empty() {
-            panic!("This cartidge has more memory than expected!");
-        }
+        let mut mapper = mapper_map(cartidge_header.mapper, mapper_specs)?;
+        mapper.load_program_rom(program_rom);
+        mapper.load_character_memory(character_rom);
 
-        Self {
-            name: game_name,
+        Ok(Self {
+            name: name.into(),
             mapper,
             header: cartidge_header,
+            checksum: crc32(&contents[16 + trainer_size..]),
+        })
+    }
+
+    /// Create a cartidge running a raw 6502 binary, without iNES header,
+    /// e.g., to use the emulator as a 6502 test runner.
+    ///
+    /// `bytes` are loaded at `load_address` of the memory `mapping` provides
+    /// and the reset vector is set to `reset_vector`, overwriting the binary
+    /// if it reaches $FFFC. Pattern tables are 8 kB of CHR RAM. The cartidge
+    /// is reported as mapper 0.
+    ///
+    /// Fails if the binary doesn't fit in that memory from `load_address`
+    pub fn from_raw_prg(
+        bytes: &[u8],
+        load_address: u16,
+        reset_vector: u16,
+        mapping: RawMapping,
+    ) -> Result<Self, RomError> {
+        let start = match mapping {
+            RawMapping::Nrom => CARTIDGE_RAM_START,
+            RawMapping::Flat => 0,
+        };
+        let end = load_address as usize + bytes.len();
+        if load_address < start || end > 0x10000 {
+            return Err(RomError::RawProgramDoesNotFit {
+                size: bytes.len(),
+                load_address,
+            });
+        }
+
+        let mut space = vec![0; 0x10000 - start as usize];
+        let offset = (load_address - start) as usize;
+        space[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let vector = (RESET_VECTOR - start) as usize;
+        space[vector..vector + 2].copy_from_slice(&reset_vector.to_le_bytes());
+
+        let mut header = CartidgeHeader {
+            pgr_rom_size: 0,
+            chr_rom_size: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            mapper: 0,
+            pgr_ram_size: 0,
+            region: Region::Ntsc,
+        };
+        let mapper: Box<dyn Mapper> = match mapping {
+            RawMapping::Nrom => {
+                let (program_ram, program_rom) = space.split_at(CARTIDGE_RAM_SIZE as usize);
+                header.pgr_rom_size = program_rom.len();
+                header.pgr_ram_size = program_ram.len();
+                let mut mapper = mapper_map(
+                    header.mapper,
+                    MapperSpecs {
+                        program_ram_capacity: header.pgr_ram_size,
+                        program_rom_capacity: header.pgr_rom_size,
+                        character_memory_capacity: 8 * 1024,
+                    },
+                )?;
+                mapper.load_program_rom(program_rom);
+                mapper
+                    .program_ram_ref()
+                    .borrow_mut()
+                    .write_block(0, program_ram);
+                mapper
+            }
+            RawMapping::Flat => {
+                header.pgr_ram_size = space.len();
+                let mut mapper = FlatMapper::new(MapperSpecs {
+                    program_ram_capacity: header.pgr_ram_size,
+                    program_rom_capacity: 0,
+                    character_memory_capacity: 8 * 1024,
+                });
+                mapper.load_program_rom(&space);
+                Box::new(mapper)
+            }
+        };
+
+        Ok(Self {
+            name: "6502 program.bin".to_string(),
+            mapper,
+            header,
+            checksum: crc32(&space),
+        })
+    }
+
+    /// Whether `contents` is a complete iNES image: a valid header followed
+    /// by exactly the memories it declares
+    pub(crate) fn is_complete_image(contents: &[u8]) -> bool {
+        let Some(header) = contents.get(..16) else {
+            return false;
+        };
+        if header[0..4] != INES_MAGIC {
+            return false;
         }
+        let header = CartidgeHeader::parse(header.try_into().unwrap());
+        contents.len() == header.file_size()
     }
 
     pub fn mirroring(&self) -> Mirroring {
         self.header.mirroring
     }
+
+    /// Name the cartidge was created with, usually its file name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// CRC-32 of the PRG and CHR ROMs, identifying the game regardless of its
+    /// file name and header
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn info(&self) -> CartidgeInfo {
+        let title = Path::new(&self.name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(&self.name)
+            .to_string();
+
+        CartidgeInfo {
+            title,
+            mapper: self.header.mapper,
+            mapper_name: mapper_name(self.header.mapper),
+            program_rom_size: self.header.pgr_rom_size,
+            character_rom_size: self.header.chr_rom_size,
+            mirroring: self.header.mirroring,
+            battery: self.header.battery,
+            region: self.header.region,
+        }
+    }
+
+    /// Enable or disable bus conflicts emulation on discrete boards (UxROM,
+    /// CNROM...). They are emulated by default, as in real hardware, but some
+    /// dumps and homebrew games expect boards without them
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.mapper.set_bus_conflicts(enabled);
+    }
 }
 
 impl std::fmt::Display for Cartidge {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        write!(f, "{}", self.info())
+    }
+}
+
+impl std::fmt::Display for CartidgeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (mapper {} {}, {} KB PRG, {} KB CHR, {:?} mirroring",
+            self.title,
+            self.mapper,
+            self.mapper_name,
+            self.program_rom_size / 1024,
+            self.character_rom_size / 1024,
+            self.mirroring,
+        )?;
+        if self.battery {
+            write!(f, ", battery")?;
+        }
+        write!(f, ", {:?})", self.region)
     }
 }
 
@@ -112,6 +363,9 @@ struct CartidgeHeader {
     pub chr_rom_size: usize,
     pub mirroring: Mirroring,
 
+    // Battery-backed PGR RAM at $6000-$7FFF
+    pub battery: bool,
+
     // 512-byte trainer at 0x7000-0x71FF (stored before PGR data)
     pub trainer: bool,
 
@@ -119,30 +373,46 @@ struct CartidgeHeader {
     pub mapper: u8,
 
     pub pgr_ram_size: usize,
+
+    pub region: Region,
 }
 
 impl CartidgeHeader {
+    /// Parse an iNES header. Its magic number (bytes 0-3) must have been
+    /// checked already
     fn parse(header: &[u8; 16]) -> Self {
-        // (bytes 0-3) - NES cartidges started with ASCII "NES" and MS-DOS
-        // end-of-file (0x1A)
-        assert!(
-            header[0..4] == [0x4E, 0x45, 0x53, 0x1A],
-            "Invalid iNES header"
-        );
+        // (byte 7, bits 2-3) - NES 2.0 identifier. Only its PRG ROM sizes and
+        // TV system are supported, other fields are read as iNES ones
+        let nes2 = header[7] & 0x0C == 0x08;
 
-        // (byte 4) - Size of PGR ROM in 16 KB units
-        let pgr_rom_size = (header[4] as usize) * 16 * 1024;
+        // (byte 4) - Size of PGR ROM in 16 KB units. NES 2.0 can express
+        // smaller sizes as 2^E * (MM * 2 + 1), with byte 4 being EEEEEEMM and
+        // the low nibble of byte 9 set to $F
+        let pgr_rom_size = if nes2 && header[9] & 0x0F == 0x0F {
+            let exponent = (header[4] >> 2) as u32;
+            let multiplier = (header[4] & 0x03) as usize * 2 + 1;
+            2usize
+                .checked_pow(exponent)
+                .and_then(|size| size.checked_mul(multiplier))
+                .unwrap_or(usize::MAX)
+        } else {
+            (header[4] as usize) * 16 * 1024
+        };
 
         // (byte 5) - Size of CHR ROM in 8 KB units
         let chr_rom_size = (header[5] as usize) * 8 * 1024;
 
         // (byte 6) - Mapper, mirroring, battery, trainer
-        let mirroring = if bv(header[6], 0) == 0 {
+        let mirroring = if bv(header[6], 3) != 0 {
+            // Four-screen VRAM ignores the mirroring bit
+            Mirroring::FourScreen
+        } else if bv(header[6], 0) == 0 {
             Mirroring::Horizontal
         } else {
             Mirroring::Vertical
         };
 
+        let battery = bv(header[6], 1) != 0;
         let trainer = bv(header[6], 2) != 0;
 
         let mapper_number = (header[7] & 0xF0) | ((header[6] & 0xF0) >> 4);
@@ -156,20 +426,38 @@ impl CartidgeHeader {
             8 * 1024
         };
 
+        // (byte 9) - TV system. Bit 0: NTSC (0) or PAL (1). NES 2.0 has it in
+        // byte 12
+        let tv_system = if nes2 { header[12] } else { header[9] };
+        let region = if bv(tv_system, 0) == 0 {
+            Region::Ntsc
+        } else {
+            Region::Pal
+        };
+
         Self {
             pgr_rom_size,
             chr_rom_size,
             mirroring,
+            battery,
             trainer,
             mapper: mapper_number,
             pgr_ram_size,
+            region,
         }
     }
+
+    /// Size of the iNES file described by this header
+    fn file_size(&self) -> usize {
+        let trainer_size = if self.trainer { 512 } else { 0 };
+        (16 + trainer_size + self.chr_rom_size).saturating_add(self.pgr_rom_size)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::ines_image;
 
     #[test]
     fn test_cartidge_new() {
@@ -181,4 +469,188 @@ mod tests {
             8 * 1024
         );
     }
+
+    #[test]
+    fn test_cartidge_info() {
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 2, 0, 0x23, 0x00, 0, 1];
+        image.resize(16 + 2 * 16 * 1024, 0);
+        let cartidge = Cartidge::from_bytes("roms/Some Game (Europe).nes", &image);
+
+        let info = cartidge.info();
+        assert_eq!(info.title, "Some Game (Europe)");
+        assert_eq!(info.mapper, 2);
+        assert_eq!(info.mapper_name, "UxROM");
+        assert_eq!(info.program_rom_size, 32 * 1024);
+        assert_eq!(info.character_rom_size, 0);
+        assert_eq!(info.mirroring, Mirroring::Vertical);
+        assert!(info.battery);
+        assert_eq!(info.region, Region::Pal);
+        assert_eq!(cartidge.name(), "roms/Some Game (Europe).nes");
+    }
+
+    #[test]
+    fn test_invalid_roms() {
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0];
+        image.resize(16 + 16 * 1024 + 8 * 1024, 0xEA);
+        let strict = |contents: &[u8]| {
+            Cartidge::try_from_bytes("bad.nes", contents, RomValidation::Strict).err()
+        };
+
+        assert!(matches!(
+            strict(&image[..10]),
+            Some(RomError::RomTooShort {
+                expected: 16,
+                actual: 10
+            })
+        ));
+        assert!(matches!(
+            strict(b"PK\x03\x04 not a ROM at all"),
+            Some(RomError::BadMagic([0x50, 0x4B, 0x03, 0x04]))
+        ));
+        assert!(matches!(
+            strict(&image[..1000]),
+            Some(RomError::RomTooShort {
+                expected: 24592,
+                actual: 1000
+            })
+        ));
+        let mut overdump = image.clone();
+        overdump.push(0);
+        assert!(matches!(
+            strict(&overdump),
+            Some(RomError::SizeMismatch { .. })
+        ));
+
+        // Lenient loading pads the missing CHR ROM with zeros
+        let cartidge =
+            Cartidge::try_from_bytes("bad.nes", &image[..20000], RomValidation::Lenient).unwrap();
+        let character_memory = cartidge.mapper.character_memory_ref();
+        assert_eq!(character_memory.borrow().size(), 8 * 1024);
+        assert_eq!(character_memory.borrow().read(0x0000), 0xEA);
+        assert_eq!(character_memory.borrow().read(0x1FFF), 0x00);
+        assert!(Cartidge::try_from_bytes("bad.nes", &overdump, RomValidation::Lenient).is_ok());
+
+        assert!(matches!(
+            Cartidge::load("roms/missing.nes"),
+            Err(RomError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_small_program_rom() {
+        // NES 2.0 header with 4 kB of PRG ROM (2^12 * 1) and 8 kB of CHR ROM
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 12 << 2, 1, 0x00, 0x08, 0, 0x0F];
+        image.resize(16, 0);
+        let mut prg = vec![0xEA; 4 * 1024];
+        prg[0xFFC..0xFFE].copy_from_slice(&[0x00, 0xF0]);
+        image.extend_from_slice(&prg);
+        image.resize(16 + 4 * 1024 + 8 * 1024, 0);
+
+        let cartidge = Cartidge::from_bytes("tiny.nes", &image);
+        assert_eq!(cartidge.info().program_rom_size, 4 * 1024);
+        assert_eq!(cartidge.info().region, Region::Ntsc);
+        // Mirrored all over $8000-$FFFF
+        let rom = cartidge.mapper.program_rom_ref();
+        assert_eq!(rom.borrow().size(), 32 * 1024);
+        assert_eq!(rom.borrow().read(0x0FFD), 0xF0);
+        assert_eq!(rom.borrow().read(0x7FFD), 0xF0);
+        assert_eq!(cartidge.mapper.program_rom_offset(0x7FFD), Some(0x0FFD));
+
+        // 24 kB can't be mirrored over 32 kB
+        image[4] = (13 << 2) | 1;
+        assert!(matches!(
+            Cartidge::try_from_bytes("odd.nes", &image, RomValidation::Lenient),
+            Err(RomError::InvalidProgramRomSize {
+                mapper: 0,
+                size: 24576
+            })
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let prg = vec![0; 32 * 1024];
+        let chr = vec![0; 8 * 1024];
+        assert!(matches!(
+            Cartidge::try_from_bytes(
+                "ab.nes",
+                &ines_image(0xAB, false, &prg, &chr),
+                RomValidation::Strict
+            ),
+            Err(RomError::UnsupportedMapper(0xAB))
+        ));
+
+        // Known but not implemented
+        assert!(matches!(
+            Cartidge::try_from_bytes(
+                "mmc3.nes",
+                &ines_image(4, false, &prg, &chr),
+                RomValidation::Strict
+            ),
+            Err(RomError::UnsupportedMapper(4))
+        ));
+    }
+
+    #[test]
+    fn test_cartidge_from_raw_prg() {
+        // LDA $6000, STA $0200, JMP $8006
+        let program = [0xAD, 0x00, 0x60, 0x8D, 0x00, 0x02, 0x4C, 0x06, 0x80];
+        let mut contents = vec![0; 0x2000];
+        contents[0] = 0x42;
+        contents.extend_from_slice(&program);
+
+        let cartidge = Cartidge::from_raw_prg(&contents, 0x6000, 0x8000, RawMapping::Nrom).unwrap();
+        assert_eq!(cartidge.info().mapper, 0);
+        assert_eq!(cartidge.info().program_rom_size, 32 * 1024);
+        let rom = cartidge.mapper.program_rom_ref();
+        assert_eq!(rom.borrow().read(0x0000), 0xAD);
+        assert_eq!(rom.borrow().read(0x7FFC), 0x00);
+        assert_eq!(rom.borrow().read(0x7FFD), 0x80);
+
+        let mut nes = crate::Nes::builder()
+            .with_ui(crate::settings::UiKind::None)
+            .with_cartidge(cartidge)
+            .build();
+        for _ in 0..3 {
+            nes.step_instruction().unwrap();
+        }
+        assert_eq!(nes.peek(0x0200), Some(0x42));
+        assert_eq!(nes.cpu_state().pc, 0x8006);
+
+        assert!(matches!(
+            Cartidge::from_raw_prg(&program, 0x0400, 0x0400, RawMapping::Nrom),
+            Err(RomError::RawProgramDoesNotFit {
+                size: 9,
+                load_address: 0x0400
+            })
+        ));
+        assert!(Cartidge::from_raw_prg(&program, 0xFFFA, 0xFFFA, RawMapping::Flat).is_err());
+    }
+
+    #[test]
+    fn test_cartidge_flat_mapping() {
+        // LDA #$42, STA $040C (LDA operand), STA $2002, LDA #$00, STA $10,
+        // JMP $0410
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x42, 0x8D, 0x0C, 0x04, 0x8D, 0x02, 0x20,
+            0xEA, 0xEA, 0xEA, 0xA9, 0x00, 0x85, 0x10, 0xEA,
+            0x4C, 0x10, 0x04,
+        ];
+        let cartidge = Cartidge::from_raw_prg(&program, 0x0400, 0x0400, RawMapping::Flat).unwrap();
+        let mut nes = crate::Nes::builder()
+            .with_ui(crate::settings::UiKind::None)
+            .with_cartidge(cartidge)
+            .build();
+        for _ in 0..10 {
+            nes.step_instruction().unwrap();
+        }
+
+        // Code below $6000 modified itself, and PPU registers are RAM
+        assert_eq!(nes.cpu_state().pc, 0x0410);
+        assert_eq!(nes.peek(0x040C), Some(0x42));
+        assert_eq!(nes.peek(0x0010), Some(0x42));
+        assert_eq!(nes.peek(0x2002), Some(0x42));
+        assert_eq!(nes.peek(0xFFFD), Some(0x04));
+    }
 }
@@ -0,0 +1,264 @@
+//! Condition engine
+//!
+//! User-defined memory predicates evaluated once per frame. When all the
+//! predicates of a condition become true, its callback is fired. This is the
+//! building block for achievement-like integrations (RetroAchievements style)
+//! or automated test assertions.
+//!
+//! Predicates compare operands, which can be constants, the current value of a
+//! memory address or its value in the previous frame (to detect deltas).
+//! Conditions only fire when they transition from unmet to met, so a condition
+//! that remains true doesn't fire every frame.
+//!
+
+use std::collections::HashMap;
+
+pub type ConditionId = usize;
+
+pub type ConditionCallback = Box<dyn FnMut(ConditionId)>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operand {
+    Constant(u8),
+    /// Value of an address in the current frame
+    Memory(u16),
+    /// Value of an address in the previous frame
+    PreviousMemory(u16),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn compare(&self, left: u8, right: u8) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Predicate {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+/// Set of predicates that must be true at the same time
+#[derive(Clone, Debug, Default)]
+pub struct Condition {
+    predicates: Vec<Predicate>,
+}
+
+impl Condition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, left: Operand, comparison: Comparison, right: Operand) -> Self {
+        self.predicates.push(Predicate {
+            left,
+            comparison,
+            right,
+        });
+        self
+    }
+
+    /// Memory at `address` equals `value`
+    pub fn memory_equals(self, address: u16, value: u8) -> Self {
+        self.with(
+            Operand::Memory(address),
+            Comparison::Equal,
+            Operand::Constant(value),
+        )
+    }
+
+    /// Memory at `address` has changed since the previous frame
+    pub fn memory_changed(self, address: u16) -> Self {
+        self.with(
+            Operand::Memory(address),
+            Comparison::NotEqual,
+            Operand::PreviousMemory(address),
+        )
+    }
+
+    /// Memory at `address` has increased since the previous frame
+    pub fn memory_increased(self, address: u16) -> Self {
+        self.with(
+            Operand::Memory(address),
+            Comparison::Greater,
+            Operand::PreviousMemory(address),
+        )
+    }
+
+    pub fn predicates(&self) -> &[Predicate] {
+        &self.predicates
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = u16> + '_ {
+        self.predicates
+            .iter()
+            .flat_map(|predicate| [predicate.left, predicate.right])
+            .filter_map(|operand| match operand {
+                Operand::Constant(_) => None,
+                Operand::Memory(address) | Operand::PreviousMemory(address) => Some(address),
+            })
+    }
+}
+
+struct Trigger {
+    condition: Condition,
+    callback: ConditionCallback,
+    met: bool,
+}
+
+/// Evaluates registered conditions every frame
+#[derive(Default)]
+pub struct ConditionEngine {
+    triggers: HashMap<ConditionId, Trigger>,
+    next_id: ConditionId,
+    previous: HashMap<u16, u8>,
+}
+
+impl ConditionEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, condition: Condition, callback: ConditionCallback) -> ConditionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.triggers.insert(
+            id,
+            Trigger {
+                condition,
+                callback,
+                met: false,
+            },
+        );
+        id
+    }
+
+    pub fn remove(&mut self, id: ConditionId) {
+        self.triggers.remove(&id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Evaluate all conditions reading memory with `read`. Addresses `read`
+    /// can't access make their predicates false
+    pub fn evaluate(&mut self, read: impl Fn(u16) -> Option<u8>) {
+        let mut current = HashMap::new();
+        for trigger in self.triggers.values() {
+            for address in trigger.condition.addresses() {
+                current.entry(address).or_insert_with(|| read(address));
+            }
+        }
+
+        let value = |operand: Operand| match operand {
+            Operand::Constant(value) => Some(value),
+            Operand::Memory(address) => current.get(&address).copied().flatten(),
+            Operand::PreviousMemory(address) => self.previous.get(&address).copied(),
+        };
+
+        let mut fired = Vec::new();
+        for (id, trigger) in self.triggers.iter_mut() {
+            let met = trigger.condition.predicates.iter().all(|predicate| {
+                match (value(predicate.left), value(predicate.right)) {
+                    (Some(left), Some(right)) => predicate.comparison.compare(left, right),
+                    _ => false,
+                }
+            });
+
+            if met && !trigger.met {
+                fired.push(*id);
+            }
+            trigger.met = met;
+        }
+
+        fired.sort_unstable();
+        for id in fired {
+            if let Some(trigger) = self.triggers.get_mut(&id) {
+                (trigger.callback)(id);
+            }
+        }
+
+        self.previous = current
+            .into_iter()
+            .filter_map(|(address, value)| Some((address, value?)))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_conditions_fire_on_transition() {
+        let mut engine = ConditionEngine::new();
+        let fired = Rc::new(RefCell::new(Vec::new()));
+
+        let lives = {
+            let fired = Rc::clone(&fired);
+            engine.add(
+                Condition::new().memory_equals(0x0075, 0),
+                Box::new(move |id| fired.borrow_mut().push(id)),
+            )
+        };
+        let score = {
+            let fired = Rc::clone(&fired);
+            engine.add(
+                Condition::new().memory_increased(0x07DD),
+                Box::new(move |id| fired.borrow_mut().push(id)),
+            )
+        };
+
+        let memory = RefCell::new([0_u8; 0x0800]);
+        let read = |address: u16| memory.borrow().get(address as usize).copied();
+
+        memory.borrow_mut()[0x0075] = 3;
+        engine.evaluate(read);
+        assert!(fired.borrow().is_empty());
+
+        memory.borrow_mut()[0x07DD] = 1;
+        engine.evaluate(read);
+        assert_eq!(*fired.borrow(), vec![score]);
+
+        memory.borrow_mut()[0x0075] = 0;
+        engine.evaluate(read);
+        engine.evaluate(read);
+        assert_eq!(*fired.borrow(), vec![score, lives]);
+    }
+
+    #[test]
+    fn test_unreadable_memory_is_false() {
+        let mut engine = ConditionEngine::new();
+        let fired = Rc::new(RefCell::new(false));
+        let flag = Rc::clone(&fired);
+        engine.add(
+            Condition::new().memory_equals(0x2002, 0),
+            Box::new(move |_| *flag.borrow_mut() = true),
+        );
+
+        engine.evaluate(|_| None);
+        assert!(!*fired.borrow());
+    }
+}
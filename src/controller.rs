@@ -1,20 +1,49 @@
-use std::cell::RefCell;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 
 use bitflags::bitflags;
 
 use crate::events::KeyboardListener;
-use crate::interfaces::Memory;
+use crate::graphics::Frame;
+use crate::input::InputDevice;
+use crate::input_macro::{InputMacro, MacroPlayback};
+use crate::keyboard::Key;
 use crate::utils;
 
+/// Standard NES controller, driven by the keyboard, the host application or
+/// input macros
 pub struct Controller {
     enabled: bool,
     buttons: ControllerButtons,
     keyboard_listener: KeyboardListener,
-    controller_snapshot: RefCell<InnerController>,
+    // Parallel-in serial-out register read bit by bit, A button first
+    shift_register: Cell<u8>,
+    // While strobe is high, the shift register is continuously reloaded
+    strobe: bool,
+
+    // Last polled state
+    state: ControllerState,
+
+    // Input delay: states polled in the last frames, oldest first. Games
+    // latch the oldest one, so input reaches them this many frames later
+    input_delay: u8,
+    delayed: VecDeque<ControllerState>,
+    // Input polled this frame, before applying the delay
+    polled: ControllerState,
+
+    // State set by the host application, overrides keyboard input
+    host_state: Option<ControllerState>,
+
+    // Input macros
+    recording: Option<InputMacro>,
+    playback: Option<MacroPlayback>,
+    macro_bindings: HashMap<Key, InputMacro>,
 }
 
 bitflags! {
-    struct InnerController: u8 {
+    /// Pressed buttons of a controller, in the same order they're read
+    #[derive(Default)]
+    pub struct ControllerState: u8 {
         const A = 0b1000_0000;
         const B = 0b0100_0000;
         const SELECT = 0b0010_0000;
@@ -32,46 +61,153 @@ impl Controller {
             enabled: false,
             buttons: ControllerButtons::default(),
             keyboard_listener: keyboard,
-            controller_snapshot: RefCell::new(InnerController::empty()),
+            shift_register: Cell::new(0),
+            strobe: false,
+            state: ControllerState::empty(),
+            input_delay: 0,
+            delayed: VecDeque::new(),
+            polled: ControllerState::empty(),
+            host_state: None,
+            recording: None,
+            playback: None,
+            macro_bindings: HashMap::new(),
         }
     }
 
     pub fn connect(&mut self, buttons: ControllerButtons) {
         self.enabled = true;
         self.buttons = ControllerButtons {
-            left: buttons.left.to_uppercase().next().unwrap(),
-            down: buttons.down.to_uppercase().next().unwrap(),
-            right: buttons.right.to_uppercase().next().unwrap(),
-            up: buttons.up.to_uppercase().next().unwrap(),
-            select: buttons.select.to_uppercase().next().unwrap(),
-            start: buttons.start.to_uppercase().next().unwrap(),
-            a: buttons.a.to_uppercase().next().unwrap(),
-            b: buttons.b.to_uppercase().next().unwrap(),
+            left: buttons.left.normalized(),
+            down: buttons.down.normalized(),
+            right: buttons.right.normalized(),
+            up: buttons.up.normalized(),
+            select: buttons.select.normalized(),
+            start: buttons.start.normalized(),
+            a: buttons.a.normalized(),
+            b: buttons.b.normalized(),
         }
     }
 
     pub fn disconnect(&mut self) {
         self.enabled = false;
+        self.host_state = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.enabled
+    }
+
+    /// Buttons latched the last time the game polled the controller
+    pub fn state(&self) -> ControllerState {
+        self.state
+    }
+
+    /// Drive the controller directly with `state` instead of the keyboard.
+    /// Useful for frontends with their own input handling. The controller is
+    /// connected if it wasn't
+    pub fn set_state(&mut self, state: ControllerState) {
+        self.enabled = true;
+        self.host_state = Some(state);
+    }
+
+    /// Stop driving the controller with [`Controller::set_state`], giving it
+    /// back to the keyboard
+    pub fn clear_state(&mut self) {
+        self.host_state = None;
+    }
+
+    /// Delay input `frames` frames, like netplay does, so local play has the
+    /// same timing. Buttons pressed in the meantime are released
+    pub fn set_input_delay(&mut self, frames: u8) {
+        if frames != self.input_delay {
+            self.input_delay = frames;
+            self.delayed = VecDeque::from(vec![ControllerState::empty(); frames as usize]);
+        }
+    }
+
+    pub fn input_delay(&self) -> u8 {
+        self.input_delay
+    }
+
+    /// Start recording polled input, one state per frame. Input is recorded
+    /// before the delay is applied
+    pub fn start_macro_recording(&mut self) {
+        self.recording = Some(InputMacro::new());
+    }
+
+    /// Stop recording and return the recorded macro, if any
+    pub fn stop_macro_recording(&mut self) -> Option<InputMacro> {
+        self.recording.take()
+    }
+
+    /// Replay `input_macro` starting on the next poll. Keyboard input is
+    /// ignored while a macro is playing
+    pub fn play_macro(&mut self, input_macro: InputMacro) {
+        self.playback = Some(MacroPlayback::new(input_macro));
+    }
+
+    pub fn is_playing_macro(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Play `input_macro` every time `key` is pressed
+    pub fn bind_macro(&mut self, key: Key, input_macro: InputMacro) {
+        self.macro_bindings.insert(key.normalized(), input_macro);
+    }
+
+    pub fn unbind_macro(&mut self, key: Key) {
+        self.macro_bindings.remove(&key.normalized());
+    }
+
+    fn state_from_keys(&self, keys: impl IntoIterator<Item = Key>) -> ControllerState {
+        let mut state = ControllerState::empty();
+        for key in keys {
+            if key == self.buttons.left {
+                state.insert(ControllerState::LEFT);
+            } else if key == self.buttons.down {
+                state.insert(ControllerState::DOWN);
+            } else if key == self.buttons.right {
+                state.insert(ControllerState::RIGHT);
+            } else if key == self.buttons.up {
+                state.insert(ControllerState::UP);
+            } else if key == self.buttons.select {
+                state.insert(ControllerState::SELECT);
+            } else if key == self.buttons.start {
+                state.insert(ControllerState::START);
+            } else if key == self.buttons.a {
+                state.insert(ControllerState::A);
+            } else if key == self.buttons.b {
+                state.insert(ControllerState::B);
+            } else {
+                // ignore
+            }
+        }
+        state
     }
 }
 
-impl Memory for Controller {
-    fn read(&self, _address: u16) -> u8 {
+impl InputDevice for Controller {
+    fn read(&self) -> u8 {
         if !self.enabled {
             return 0;
         }
 
-        let data = utils::bv(self.controller_snapshot.borrow().bits(), 7);
-        let updated =
-            InnerController::from_bits(self.controller_snapshot.borrow().bits() << 1).unwrap();
-        *self.controller_snapshot.borrow_mut() = updated;
-        // println!("[controller] read: {data:0>8b} updated: {updated:0>8b}");
-        data
+        // With strobe high the register is reloaded all the time, so reads
+        // always return the A button
+        if self.strobe {
+            return utils::bv(self.state.bits(), 7);
+        }
+
+        // Once the 8 buttons have been read, official controllers return 1s
+        let register = self.shift_register.get();
+        self.shift_register.set((register << 1) | 1);
+        utils::bv(register, 7)
     }
 
-    fn write(&mut self, _address: u16, _data: u8) {
-        // TODO: if write = 1, signal the controller to poll it's input. write =
-        // 0 to end polling. Read bit by bit
+    fn write(&mut self, data: u8) {
+        // Writing 1 signals the controller to poll its input and writing 0
+        // ends polling, keeping the latched state to be read bit by bit
+        self.strobe = data & 1 == 1;
 
         if !self.enabled {
             // if controller not enabled, buffer will be emptied so we don't
@@ -80,68 +216,261 @@ impl Memory for Controller {
             return;
         }
 
+        if !self.strobe {
+            return;
+        }
+
         // Read PISO (Parallel-In Serial-Out)
         let input = self.keyboard_listener.read();
-        if input.is_empty() {
-            return;
+
+        if self.playback.is_none() {
+            let bound_macro = input
+                .iter()
+                .find_map(|key| self.macro_bindings.get(key))
+                .cloned();
+            if let Some(input_macro) = bound_macro {
+                self.play_macro(input_macro);
+            }
         }
 
-        let mut state = InnerController::empty();
-        for c in input.chars() {
-            let c = c.to_uppercase().next().unwrap();
-            if c == self.buttons.left {
-                state.insert(InnerController::LEFT);
-            } else if c == self.buttons.down {
-                state.insert(InnerController::DOWN);
-            } else if c == self.buttons.right {
-                state.insert(InnerController::RIGHT);
-            } else if c == self.buttons.up {
-                state.insert(InnerController::UP);
-            } else if c == self.buttons.select {
-                state.insert(InnerController::SELECT);
-            } else if c == self.buttons.start {
-                state.insert(InnerController::START);
-            } else if c == self.buttons.a {
-                state.insert(InnerController::A);
-            } else if c == self.buttons.b {
-                state.insert(InnerController::B);
-            } else {
-                // ignore
+        self.polled = match (self.playback.as_ref(), self.host_state) {
+            (Some(playback), _) => playback.current(),
+            (None, Some(host_state)) => host_state,
+            (None, None) => self.state_from_keys(input),
+        };
+        let state = self.delayed.front().copied().unwrap_or(self.polled);
+        self.state = state;
+        self.shift_register.set(state.bits());
+    }
+
+    /// Macros advance one state per frame
+    fn end_frame(&mut self, _frame: &Frame) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(self.polled);
+        }
+
+        if self.input_delay > 0 {
+            self.delayed.pop_front();
+            self.delayed.push_back(self.polled);
+        }
+
+        if let Some(playback) = self.playback.as_mut() {
+            playback.advance();
+            if playback.finished() {
+                self.playback = None;
             }
         }
-        println!();
+    }
 
-        *self.controller_snapshot.borrow_mut() = state;
-        // println!("[controller] New controller: {:0>8b}", input.bits());
+    /// Input settings, like macros or host state, are not part of the
+    /// snapshot. Delayed input is, as the game will still read it
+    fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = vec![
+            self.shift_register.get(),
+            self.strobe as u8,
+            self.state.bits(),
+        ];
+        snapshot.extend(self.delayed.iter().map(|state| state.bits()));
+        snapshot
     }
 
-    fn size(&self) -> usize {
-        1
+    fn restore(&mut self, snapshot: &[u8]) {
+        if let [shift_register, strobe, state, ref delayed @ ..] = *snapshot {
+            self.shift_register.set(shift_register);
+            self.strobe = strobe == 1;
+            self.state = ControllerState::from_bits_truncate(state);
+            self.set_input_delay(delayed.len() as u8);
+            self.delayed = delayed
+                .iter()
+                .map(|&state| ControllerState::from_bits_truncate(state))
+                .collect();
+        }
     }
 }
 
+/// Keyboard bindings for each of the controller buttons
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ControllerButtons {
-    pub left: char,
-    pub down: char,
-    pub right: char,
-    pub up: char,
-    pub select: char,
-    pub start: char,
-    pub a: char,
-    pub b: char,
+    pub left: Key,
+    pub down: Key,
+    pub right: Key,
+    pub up: Key,
+    pub select: Key,
+    pub start: Key,
+    pub a: Key,
+    pub b: Key,
 }
 
 impl Default for ControllerButtons {
     fn default() -> Self {
         Self {
-            left: 'S',
-            down: 'D',
-            right: 'F',
-            up: 'E',
-            select: 'G',
-            start: 'H',
-            a: 'J',
-            b: 'K',
+            left: Key::Char('S'),
+            down: Key::Char('D'),
+            right: Key::Char('F'),
+            up: Key::Char('E'),
+            select: Key::Char('G'),
+            start: Key::Char('H'),
+            a: Key::Char('J'),
+            b: Key::Char('K'),
+        }
+    }
+}
+
+impl ControllerButtons {
+    /// Bindings using the arrow keys for the D-pad
+    pub fn arrows() -> Self {
+        Self {
+            left: Key::Left,
+            down: Key::Down,
+            right: Key::Right,
+            up: Key::Up,
+            select: Key::RightShift,
+            start: Key::Enter,
+            a: Key::Char('X'),
+            b: Key::Char('Z'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::KeyboardChannel;
+
+    fn poll(controller: &mut Controller) -> ControllerState {
+        controller.write(1);
+        controller.write(0);
+        let mut bits = 0;
+        for _ in 0..8 {
+            bits = (bits << 1) | controller.read();
+        }
+        ControllerState::from_bits(bits).unwrap()
+    }
+
+    #[test]
+    fn test_macro_record_and_replay() {
+        let channel = KeyboardChannel::new();
+        let mut keyboard = channel.publisher();
+        let mut controller = Controller::new(channel.listener());
+        controller.connect(ControllerButtons::default());
+
+        let frame = Frame::black();
+        controller.start_macro_recording();
+        for key in ['e', 'j', 'h'] {
+            keyboard.push_char(key);
+            poll(&mut controller);
+            controller.end_frame(&frame);
         }
+        let recorded = controller.stop_macro_recording().unwrap();
+        assert_eq!(
+            recorded.frames(),
+            [
+                ControllerState::UP,
+                ControllerState::A,
+                ControllerState::START
+            ]
+        );
+
+        controller.bind_macro(Key::Char('m'), recorded);
+        keyboard.push_char('m');
+        let mut replayed = Vec::new();
+        while replayed.is_empty() || controller.is_playing_macro() {
+            replayed.push(poll(&mut controller));
+            controller.end_frame(&frame);
+        }
+        assert_eq!(
+            replayed,
+            [
+                ControllerState::UP,
+                ControllerState::A,
+                ControllerState::START
+            ]
+        );
+        assert_eq!(poll(&mut controller), ControllerState::empty());
+    }
+
+    #[test]
+    fn test_strobe() {
+        let channel = KeyboardChannel::new();
+        let mut keyboard = channel.publisher();
+        let mut controller = Controller::new(channel.listener());
+        controller.connect(ControllerButtons::default());
+
+        // A and Select pressed
+        keyboard.press_key(Key::Char('J'));
+        keyboard.press_key(Key::Char('G'));
+
+        // Strobe high: A button is read over and over
+        controller.write(1);
+        assert!((0..10).all(|_| controller.read() == 1));
+
+        controller.write(0);
+        let reads: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(reads, [1, 0, 1, 0, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_clear_state() {
+        let channel = KeyboardChannel::new();
+        let mut keyboard = channel.publisher();
+        let mut controller = Controller::new(channel.listener());
+        controller.connect(ControllerButtons::default());
+
+        keyboard.press_key(Key::Char('J'));
+        controller.set_state(ControllerState::START);
+        assert_eq!(poll(&mut controller), ControllerState::START);
+
+        controller.clear_state();
+        assert_eq!(poll(&mut controller), ControllerState::A);
+    }
+
+    #[test]
+    fn test_input_delay() {
+        let channel = KeyboardChannel::new();
+        let mut controller = Controller::new(channel.listener());
+        controller.set_input_delay(2);
+        controller.start_macro_recording();
+
+        let frame = Frame::black();
+        let pressed = [
+            ControllerState::A,
+            ControllerState::B,
+            ControllerState::START,
+        ];
+        let mut polled = Vec::new();
+        for state in pressed.into_iter().chain([ControllerState::empty(); 2]) {
+            controller.set_state(state);
+            polled.push(poll(&mut controller));
+            controller.end_frame(&frame);
+        }
+        assert_eq!(
+            polled,
+            [
+                ControllerState::empty(),
+                ControllerState::empty(),
+                ControllerState::A,
+                ControllerState::B,
+                ControllerState::START,
+            ]
+        );
+        // Recordings keep the input without delay
+        assert_eq!(
+            &controller.stop_macro_recording().unwrap().frames()[..3],
+            pressed
+        );
+
+        // Delayed input survives snapshots
+        controller.set_state(ControllerState::SELECT);
+        poll(&mut controller);
+        controller.end_frame(&frame);
+        let snapshot = controller.snapshot();
+        let mut restored = Controller::new(channel.listener());
+        restored.restore(&snapshot);
+        restored.set_state(ControllerState::empty());
+        assert_eq!(restored.input_delay(), 2);
+        assert_eq!(poll(&mut restored), ControllerState::empty());
+        restored.end_frame(&frame);
+        assert_eq!(poll(&mut restored), ControllerState::SELECT);
     }
 }
@@ -0,0 +1,150 @@
+//! PRG ROM code coverage
+//!
+//! A [`CoverageMap`] records how every PRG ROM byte has been accessed by the
+//! CPU: executed as part of an instruction, read as data or written (mapper
+//! register writes). Addresses are PRG ROM offsets, as in the ROM file
+//! without its header, so bank switched code is told apart.
+//!
+//! Executed bytes are the opcode and operand fetches of instructions: reads
+//! from the 3 bytes following the instruction address. Self-referencing code
+//! reading its own operands as data is also seen as executed.
+//!
+//! Start recording with [`Nes::start_coverage`](crate::Nes::start_coverage).
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// How a PRG ROM byte has been accessed
+    #[derive(Default)]
+    pub struct Access: u8 {
+        const EXECUTE = 0b0000_0001;
+        const READ = 0b0000_0010;
+        const WRITE = 0b0000_0100;
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoverageMap {
+    accesses: Vec<Access>,
+}
+
+impl CoverageMap {
+    pub fn new(program_rom_size: usize) -> Self {
+        Self {
+            accesses: vec![Access::empty(); program_rom_size],
+        }
+    }
+
+    /// Record an access to the PRG ROM `offset`. Offsets out of the ROM are
+    /// ignored
+    pub fn record(&mut self, offset: usize, access: Access) {
+        if let Some(accesses) = self.accesses.get_mut(offset) {
+            accesses.insert(access);
+        }
+    }
+
+    /// Accesses to the PRG ROM `offset` since recording started
+    pub fn accesses(&self, offset: usize) -> Access {
+        self.accesses.get(offset).copied().unwrap_or_default()
+    }
+
+    /// PRG ROM size
+    pub fn len(&self) -> usize {
+        self.accesses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accesses.is_empty()
+    }
+
+    /// Fraction of PRG ROM bytes accessed with any of `access`
+    pub fn ratio(&self, access: Access) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let covered = self
+            .accesses
+            .iter()
+            .filter(|accesses| accesses.intersects(access))
+            .count();
+        covered as f64 / self.len() as f64
+    }
+
+    /// One byte per PRG ROM byte with its [`Access`] bits, ready to be saved
+    /// as a raw bitmap aligned with the ROM
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        self.accesses.iter().map(Access::bits).collect()
+    }
+
+    /// Contiguous PRG ROM offset ranges with exactly the same accesses, except
+    /// the never accessed ones
+    pub fn ranges(&self) -> Vec<(Range<usize>, Access)> {
+        let mut ranges: Vec<(Range<usize>, Access)> = Vec::new();
+        for (offset, &access) in self.accesses.iter().enumerate() {
+            if access.is_empty() {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((range, last)) if range.end == offset && *last == access => {
+                    range.end += 1;
+                }
+                _ => ranges.push((offset..offset + 1, access)),
+            }
+        }
+        ranges
+    }
+
+    /// Human readable report, one range per line: `$00000-$0001F  X R -`
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for (range, access) in self.ranges() {
+            let flag = |flag, letter| if access.contains(flag) { letter } else { '-' };
+            writeln!(
+                report,
+                "${:0>5X}-${:0>5X}  {} {} {}",
+                range.start,
+                range.end - 1,
+                flag(Access::EXECUTE, 'X'),
+                flag(Access::READ, 'R'),
+                flag(Access::WRITE, 'W'),
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage_ranges() {
+        let mut coverage = CoverageMap::new(0x100);
+        for offset in 0x10..0x14 {
+            coverage.record(offset, Access::EXECUTE);
+        }
+        coverage.record(0x14, Access::READ);
+        coverage.record(0x80, Access::READ);
+        coverage.record(0x80, Access::WRITE);
+        coverage.record(0x1000, Access::WRITE);
+
+        assert_eq!(
+            coverage.ranges(),
+            vec![
+                (0x10..0x14, Access::EXECUTE),
+                (0x14..0x15, Access::READ),
+                (0x80..0x81, Access::READ | Access::WRITE),
+            ]
+        );
+        assert_eq!(
+            coverage.report(),
+            "$00010-$00013  X - -\n$00014-$00014  - R -\n$00080-$00080  - R W\n"
+        );
+        assert_eq!(coverage.to_bitmap()[0x80], 0b110);
+        assert_eq!(coverage.ratio(Access::EXECUTE), 4.0 / 256.0);
+    }
+}
@@ -0,0 +1,197 @@
+//! Debugging helpers
+//!
+//! Building blocks for debuggers stepping the NES with
+//! [`Nes::step_instruction`](crate::Nes::step_instruction), like the one in
+//! the *examples/* folder.
+
+use crate::expression::Expression;
+use crate::processor::cpu::CpuState;
+
+/// Stop execution when the CPU is about to run the instruction at `address`
+/// and, if any, `condition` is true
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Expression>,
+}
+
+impl Breakpoint {
+    pub fn new(address: u16) -> Self {
+        Self {
+            address,
+            condition: None,
+        }
+    }
+
+    pub fn with_condition(mut self, condition: Expression) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Whether execution should stop before running the next instruction,
+    /// reading memory with `read` (see [`Nes::peek`](crate::Nes::peek))
+    pub fn is_hit(&self, cpu: &CpuState, read: impl Fn(u16) -> Option<u8>) -> bool {
+        cpu.pc == self.address
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.is_true(cpu, read))
+    }
+}
+
+/// How a [`CallFrame`] was entered
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CallKind {
+    /// JSR
+    Subroutine,
+    /// BRK instruction
+    Break,
+    Nmi,
+    Irq,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// Address of the JSR/BRK instruction, or of the instruction interrupted
+    pub caller: u16,
+    /// Subroutine or interrupt handler address
+    pub target: u16,
+    /// Where execution continues after returning
+    pub return_address: u16,
+    /// Stack pointer before the call, restored when it returns
+    pub stack_pointer: u8,
+}
+
+/// Best-effort call stack, rebuilt from the JSR, RTS, BRK, RTI instructions
+/// and interrupts the CPU executes. Games don't always return the way they
+/// were called (e.g., they pull the return address or reset the stack), so
+/// returns unwind every frame at or below the resulting stack pointer.
+///
+/// Start recording it with
+/// [`Nes::start_call_tracking`](crate::Nes::start_call_tracking).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    /// Frames kept, the stack page can't hold more return addresses
+    pub const MAX_DEPTH: usize = 128;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames from the outermost call to the innermost one
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// The instruction `name` at `pc` has been executed with the stack
+    /// pointer at `stack_pointer`, leaving the CPU in state `after`
+    pub fn observe_instruction(
+        &mut self,
+        name: &str,
+        pc: u16,
+        stack_pointer: u8,
+        after: &CpuState,
+    ) {
+        match name {
+            "JSR" => self.push(CallFrame {
+                kind: CallKind::Subroutine,
+                caller: pc,
+                target: after.pc,
+                return_address: pc.wrapping_add(3),
+                stack_pointer,
+            }),
+            "BRK" => self.push(CallFrame {
+                kind: CallKind::Break,
+                caller: pc,
+                target: after.pc,
+                // BRK skips a padding byte
+                return_address: pc.wrapping_add(2),
+                stack_pointer,
+            }),
+            "RTS" | "RTI" => self.unwind(after.sp),
+            _ => {}
+        }
+    }
+
+    /// An interrupt has been attended interrupting the instruction at `pc`
+    pub fn observe_interrupt(
+        &mut self,
+        kind: CallKind,
+        pc: u16,
+        stack_pointer: u8,
+        after: &CpuState,
+    ) {
+        self.push(CallFrame {
+            kind,
+            caller: pc,
+            target: after.pc,
+            return_address: pc,
+            stack_pointer,
+        });
+    }
+
+    fn push(&mut self, frame: CallFrame) {
+        if self.frames.len() == Self::MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    fn unwind(&mut self, stack_pointer: u8) {
+        // The stack grows downwards: deeper frames have lower stack pointers
+        while let Some(frame) = self.frames.last() {
+            if frame.stack_pointer > stack_pointer {
+                break;
+            }
+            self.frames.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(pc: u16, sp: u8) -> CpuState {
+        CpuState {
+            pc,
+            sp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut stack = CallStack::new();
+        stack.observe_instruction("JSR", 0xC000, 0xFD, &after(0xC100, 0xFB));
+        stack.observe_instruction("JSR", 0xC105, 0xFB, &after(0xC200, 0xF9));
+        stack.observe_interrupt(CallKind::Nmi, 0xC210, 0xF9, &after(0xE000, 0xF6));
+        assert_eq!(stack.depth(), 3);
+        assert_eq!(stack.frames()[1].return_address, 0xC108);
+        assert_eq!(stack.frames()[2].kind, CallKind::Nmi);
+
+        stack.observe_instruction("RTI", 0xE010, 0xF6, &after(0xC210, 0xF9));
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.frames()[1].target, 0xC200);
+
+        // The inner subroutine pulls its return address and the outer one
+        // returns: both frames are gone
+        stack.observe_instruction("PLA", 0xC200, 0xF9, &after(0xC201, 0xFA));
+        stack.observe_instruction("PLA", 0xC201, 0xFA, &after(0xC202, 0xFB));
+        stack.observe_instruction("RTS", 0xC202, 0xFB, &after(0xC003, 0xFD));
+        assert_eq!(stack.depth(), 0);
+    }
+}
@@ -0,0 +1,189 @@
+//! 6502 disassembler
+//!
+//! Turns machine code back into assembly, naming addresses with a
+//! [`SymbolTable`] when available:
+//!
+//! ```text
+//! $C004  20 00 C1  JSR init_ppu
+//! $C007  AD 02 20  LDA $2002
+//! ```
+//!
+//! Only legal opcodes are decoded, other bytes are shown as `.db $XX`.
+
+use std::fmt;
+
+use crate::processor::instruction::AddressingMode;
+use crate::processor::instruction_set::InstructionSet;
+use crate::symbols::SymbolTable;
+
+/// A disassembled instruction
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    /// Opcode followed by its operands
+    pub bytes: Vec<u8>,
+    /// Assembly, e.g., `LDA #$10`
+    pub text: String,
+}
+
+impl DisassembledInstruction {
+    /// Address of the instruction following this one
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:0>2X}"))
+            .collect();
+        write!(
+            f,
+            "${:0>4X}  {:<8}  {}",
+            self.address,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+pub struct Disassembler {
+    instruction_set: InstructionSet,
+    symbols: SymbolTable,
+}
+
+impl Default for Disassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Disassembler {
+    pub fn new() -> Self {
+        Self {
+            instruction_set: InstructionSet::new_legal_opcode_set(),
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Disassemble the instruction at `address`, reading memory with `read`.
+    /// Make sure `read` has no side effects, e.g., it doesn't read PPU
+    /// registers
+    pub fn disassemble(&self, address: u16, read: impl Fn(u16) -> u8) -> DisassembledInstruction {
+        let opcode = read(address);
+        let Some(instruction) = self.instruction_set.lookup(opcode) else {
+            return DisassembledInstruction {
+                address,
+                bytes: vec![opcode],
+                text: format!(".db ${opcode:0>2X}"),
+            };
+        };
+
+        let bytes: Vec<u8> = (0..instruction.bytes as u16)
+            .map(|offset| read(address.wrapping_add(offset)))
+            .collect();
+        let byte = bytes.get(1).copied().unwrap_or_default();
+        let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or_default()]);
+        let symbol = |address: u16| self.symbols.format(address);
+        let zero_page = |address: u8| match self.symbols.name(address as u16) {
+            Some(name) => name.to_string(),
+            None => format!("${address:0>2X}"),
+        };
+
+        let operand = match instruction.addressing_mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${byte:0>2X}"),
+            AddressingMode::Absolute => symbol(word),
+            AddressingMode::AbsoluteX => format!("{},X", symbol(word)),
+            AddressingMode::AbsoluteY => format!("{},Y", symbol(word)),
+            AddressingMode::ZeroPage => zero_page(byte),
+            AddressingMode::ZeroPageX => format!("{},X", zero_page(byte)),
+            AddressingMode::ZeroPageY => format!("{},Y", zero_page(byte)),
+            AddressingMode::IndirectX => format!("({},X)", zero_page(byte)),
+            AddressingMode::IndirectY => format!("({}),Y", zero_page(byte)),
+            AddressingMode::Relative => {
+                let target = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+                symbol(target)
+            }
+            AddressingMode::Indirect => format!("({})", symbol(word)),
+        };
+
+        let text = if operand.is_empty() {
+            instruction.name.to_string()
+        } else {
+            format!("{} {operand}", instruction.name)
+        };
+        DisassembledInstruction {
+            address,
+            bytes,
+            text,
+        }
+    }
+
+    /// Disassemble `count` consecutive instructions from `address`
+    pub fn disassemble_many(
+        &self,
+        address: u16,
+        count: usize,
+        read: impl Fn(u16) -> u8,
+    ) -> Vec<DisassembledInstruction> {
+        let mut instructions = Vec::with_capacity(count);
+        let mut address = address;
+        for _ in 0..count {
+            let instruction = self.disassemble(address, &read);
+            address = instruction.next_address();
+            instructions.push(instruction);
+        }
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_with_symbols() {
+        // LDA #$10; STA $2000; JSR init; BNE -7; LDA ($20),Y; unknown
+        let program = [
+            0xA9, 0x10, 0x8D, 0x00, 0x20, 0x20, 0x00, 0xC1, 0xD0, 0xF6, 0xB1, 0x20, 0x02,
+        ];
+        let read = |address: u16| program[(address - 0xC000) as usize];
+
+        let mut disassembler = Disassembler::new();
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xC100, "init");
+        symbols.insert(0x2000, "PPUCTRL");
+        symbols.insert(0xC000, "reset");
+        disassembler.set_symbols(symbols);
+
+        let lines: Vec<String> = disassembler
+            .disassemble_many(0xC000, 6, read)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "$C000  A9 10     LDA #$10",
+                "$C002  8D 00 20  STA PPUCTRL",
+                "$C005  20 00 C1  JSR init",
+                "$C008  D0 F6     BNE reset",
+                "$C00A  B1 20     LDA ($20),Y",
+                "$C00C  02        .db $02",
+            ]
+        );
+    }
+}
@@ -3,15 +3,25 @@
 //!
 //! This module encapsulate the DMA logic in [`DmaController`]
 //!
+//! The APU DMC channel fetches its sample bytes from the main bus through DMA
+//! too, stalling the CPU while doing so. The APU is not emulated yet, but the
+//! bus arbitration and stall accounting are: a DMC read is requested with
+//! [`DmaController::request_dmc_read`] and the fetched byte is collected with
+//! [`DmaController::take_dmc_sample`].
+//!
+//! See more information: https://www.nesdev.org/wiki/DMA#DMC_DMA
+//!
 
-use crate::interfaces::Bus;
+use crate::errors::{NesError, StateError};
 use crate::interfaces::Memory;
+use crate::snapshot::{StateReader, StateWriter, DMA_ALIGNMENT_VERSION};
 use crate::types::{SharedBus, SharedPpu};
 use log::debug;
 
 /// DMA controller is responsible to manage DMA. Once DMA starts,
 /// [`DmaController`] is able to track the progress and indicate ending of DMA
 /// process
+#[derive(Clone)]
 pub struct DmaController {
     /// indicate whether DMA is active or not
     transfer: bool,
@@ -19,6 +29,9 @@ pub struct DmaController {
     /// indicates a dummy DMA cycle when it's synchronizing
     dummy: bool,
 
+    /// halt and alignment cycles run by the current OAM DMA
+    alignment_cycles: u8,
+
     /// high 8-bits of main bus address for OAM DMA transfer
     page: u8,
 
@@ -27,8 +40,77 @@ pub struct DmaController {
 
     /// Byte of OAM data read from the CPU to write to the PPU
     data: u8,
+
+    /// Address of the pending DMC sample read, if any
+    dmc_address: Option<u16>,
+
+    /// CPU cycles left until the DMC sample read completes
+    dmc_stall: u8,
+
+    /// Whether the CPU has already been halted for the pending DMC read
+    dmc_halted: bool,
+
+    /// Sample byte fetched for the DMC, not yet collected
+    dmc_sample: Option<u8>,
+
+    /// Total CPU cycles stolen by DMC DMA
+    dmc_stalled_cycles: u64,
+}
+
+/// Byte transferred by OAM DMA
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OamDmaWrite {
+    /// Main bus address the byte was read from
+    pub source: u16,
+    /// OAM address written, OAMADDR at the time of the write
+    pub oam_address: u8,
+    pub data: u8,
+}
+
+/// OAM DMA transfer started or finished, see
+/// [`Event::OamDmaStarted`](crate::events::Event::OamDmaStarted)
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct OamDmaTransfer {
+    /// High byte of the main bus page copied to OAM
+    pub page: u8,
+    /// CPU cycle of the first halt cycle or the last write
+    pub cpu_cycle: u64,
+}
+
+/// Inspection of the DMA controller, see
+/// [`Nes::dma_state`](crate::Nes::dma_state)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DmaState {
+    /// OAM DMA transfer in progress
+    pub oam_active: bool,
+    /// High byte of the page being copied by OAM DMA, or the last one copied
+    pub source_page: u8,
+    /// Bytes OAM DMA has still to copy
+    pub bytes_remaining: u16,
+    /// Whether OAM DMA is halting the CPU and waiting for a read cycle to
+    /// start copying
+    pub aligning: bool,
+    /// Halt and alignment cycles run by the current or last OAM DMA, 1 or 2
+    /// depending on the cycle it started
+    pub alignment_cycles: u8,
+    /// Address of the pending DMC sample read, if any
+    pub dmc_address: Option<u16>,
+    /// Total CPU cycles stolen by DMC DMA
+    pub dmc_stalled_cycles: u64,
 }
 
+/// Function called on every OAM DMA write, see
+/// [`Nes::set_oam_dma_hook`](crate::Nes::set_oam_dma_hook)
+pub type OamDmaHook = Box<dyn FnMut(&OamDmaWrite)>;
+
+/// CPU cycles a DMC sample read stalls the CPU: halt, dummy, alignment and
+/// read cycles
+pub const DMC_DMA_STALL_CYCLES: u8 = 4;
+
+/// CPU cycles a DMC sample read stalls the CPU when it happens during an OAM
+/// DMA, as the CPU is already halted
+pub const DMC_DMA_STALL_CYCLES_DURING_OAM_DMA: u8 = 2;
+
 #[derive(Default)]
 pub enum DmaCycle {
     #[default]
@@ -37,13 +119,55 @@ pub enum DmaCycle {
 }
 
 impl DmaController {
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.bool(self.transfer);
+        state.bool(self.dummy);
+        if state.version >= DMA_ALIGNMENT_VERSION {
+            state.u8(self.alignment_cycles);
+        }
+        state.u8(self.page);
+        state.u8(self.addr);
+        state.u8(self.data);
+        state.option(self.dmc_address, StateWriter::u16);
+        state.u8(self.dmc_stall);
+        state.bool(self.dmc_halted);
+        state.option(self.dmc_sample, StateWriter::u8);
+        state.u64(self.dmc_stalled_cycles);
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.transfer = state.bool()?;
+        self.dummy = state.bool()?;
+        // Older states assume an OAM DMA in progress has started already
+        self.alignment_cycles = if state.version >= DMA_ALIGNMENT_VERSION {
+            state.u8()?
+        } else {
+            u8::from(self.transfer)
+        };
+        self.page = state.u8()?;
+        self.addr = state.u8()?;
+        self.data = state.u8()?;
+        self.dmc_address = state.option(StateReader::u16)?;
+        self.dmc_stall = state.u8()?;
+        self.dmc_halted = state.bool()?;
+        self.dmc_sample = state.option(StateReader::u8)?;
+        self.dmc_stalled_cycles = state.u64()?;
+        Ok(())
+    }
+
     pub fn new() -> Self {
         Self {
             transfer: false,
             dummy: true,
+            alignment_cycles: 0,
             data: 0,
             page: 0,
             addr: 0,
+            dmc_address: None,
+            dmc_stall: 0,
+            dmc_halted: false,
+            dmc_sample: None,
+            dmc_stalled_cycles: 0,
         }
     }
 
@@ -51,6 +175,32 @@ impl DmaController {
         self.transfer
     }
 
+    /// Whether OAM DMA has been requested and its first cycle hasn't run yet
+    pub fn is_oam_dma_starting(&self) -> bool {
+        self.transfer && self.dummy && self.alignment_cycles == 0
+    }
+
+    /// High byte of the page being copied by OAM DMA, or the last one copied
+    pub fn oam_dma_page(&self) -> u8 {
+        self.page
+    }
+
+    pub fn state(&self) -> DmaState {
+        DmaState {
+            oam_active: self.transfer,
+            source_page: self.page,
+            bytes_remaining: if self.transfer {
+                0x100 - self.addr as u16
+            } else {
+                0
+            },
+            aligning: self.transfer && self.dummy,
+            alignment_cycles: self.alignment_cycles,
+            dmc_address: self.dmc_address,
+            dmc_stalled_cycles: self.dmc_stalled_cycles,
+        }
+    }
+
     pub fn dma_cycle(&self, cpu_clock: u64) -> DmaCycle {
         if cpu_clock % 2 == 0 {
             DmaCycle::Read
@@ -59,30 +209,98 @@ impl DmaController {
         }
     }
 
-    pub fn oam_dma_transfer(&mut self, cpu_clock: u64, main_bus: &SharedBus, ppu: &SharedPpu) {
+    /// Run a CPU cycle of OAM DMA. Returns the write done, if it was a write
+    /// cycle
+    pub fn oam_dma_transfer(
+        &mut self,
+        cpu_clock: u64,
+        main_bus: &SharedBus,
+        ppu: &SharedPpu,
+    ) -> Option<OamDmaWrite> {
         if self.dummy {
-            if cpu_clock % 2 == 0 {
+            // Wait for an odd cycle, so the transfer starts reading in an even
+            // one. It takes 1 or 2 cycles depending on the start alignment
+            self.alignment_cycles += 1;
+            if cpu_clock % 2 == 1 {
                 self.dummy = false;
             }
+            None
         } else {
             match self.dma_cycle(cpu_clock) {
                 DmaCycle::Read => {
                     self.oam_dma_read(main_bus);
+                    None
                 }
-                DmaCycle::Write => {
-                    self.oam_data_write(ppu);
-                }
+                DmaCycle::Write => Some(self.oam_data_write(ppu)),
+            }
+        }
+    }
+
+    /// Request a DMC sample byte read from the main bus. The CPU will be
+    /// stalled until the read completes. A request while another is in
+    /// progress replaces its address
+    pub fn request_dmc_read(&mut self, address: u16) {
+        if self.dmc_address.is_none() {
+            self.dmc_halted = false;
+            self.dmc_stall = if self.transfer {
+                DMC_DMA_STALL_CYCLES_DURING_OAM_DMA
+            } else {
+                DMC_DMA_STALL_CYCLES
+            };
+        }
+        self.dmc_address = Some(address);
+    }
+
+    pub fn is_dmc_dma_active(&self) -> bool {
+        self.dmc_address.is_some()
+    }
+
+    /// Whether the next DMC DMA cycle is the first one, i.e., the one
+    /// halting the CPU
+    pub fn is_dmc_dma_halting(&self) -> bool {
+        self.dmc_address.is_some() && !self.dmc_halted
+    }
+
+    /// Run a CPU cycle of DMC DMA. The sample is read from the main bus on the
+    /// last stalled cycle
+    pub fn dmc_dma_transfer(&mut self, main_bus: &SharedBus) {
+        self.dmc_halted = true;
+        self.dmc_stall -= 1;
+        self.dmc_stalled_cycles += 1;
+
+        if self.dmc_stall == 0 {
+            if let Some(address) = self.dmc_address.take() {
+                self.dmc_sample = Some(main_bus.borrow().dma_read(address));
+                debug!("DMC DMA read sample from ${address:0>4X}");
             }
         }
     }
 
+    /// Collect the last sample byte read by DMC DMA
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    /// Total CPU cycles the CPU has been stalled by DMC DMA
+    pub fn dmc_stalled_cycles(&self) -> u64 {
+        self.dmc_stalled_cycles
+    }
+
+    /// Read the next byte of the source page. Any page can be the source:
+    /// RAM, cartidge ROM or RAM and even unmapped pages, which read as open
+    /// bus
     fn oam_dma_read(&mut self, main_bus: &SharedBus) {
         let oam_addr = ((self.page as u16) << 8) | self.addr as u16;
-        self.data = main_bus.borrow().read(oam_addr);
+        self.data = main_bus.borrow().dma_read(oam_addr);
     }
 
-    fn oam_data_write(&mut self, ppu: &SharedPpu) {
-        ppu.borrow_mut().oam_dma_write(self.addr, self.data);
+    /// Write the byte read to OAM through OAMDATA
+    fn oam_data_write(&mut self, ppu: &SharedPpu) -> OamDmaWrite {
+        let write = OamDmaWrite {
+            source: ((self.page as u16) << 8) | self.addr as u16,
+            oam_address: ppu.borrow_mut().oam_dma_write(self.data),
+            data: self.data,
+        };
         self.addr = self.addr.wrapping_add(1);
 
         // once we wrap around, we've done 256 read-write cycles and filled the
@@ -93,6 +311,7 @@ impl DmaController {
         if finish {
             debug!("OAM DMA finished");
         }
+        write
     }
 }
 
@@ -101,9 +320,17 @@ impl Memory for DmaController {
         panic!("OAM DMA is a write only memory position!");
     }
 
+    fn try_read(&self, address: u16) -> Result<u8, NesError> {
+        Err(NesError::NesInternalError(
+            "OAM DMA is a write only memory position!".to_string(),
+        ))
+    }
+
     fn write(&mut self, address: u16, data: u8) {
         debug!("OAM DMA starts for page: ${data:0>2X}");
         self.transfer = true;
+        self.dummy = true;
+        self.alignment_cycles = 0;
         self.page = data;
         self.addr = 0;
     }
@@ -112,3 +339,181 @@ impl Memory for DmaController {
         1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::events::SharedEventBus;
+    use crate::graphics::ppu::Ppu;
+    use crate::hardware::{OAMADDR, OAMDATA, OAM_DMA, PPU_REGISTERS_START};
+    use crate::interfaces::{AddressRange, Bus as _};
+    use crate::processor::bus::Bus;
+    use crate::processor::memory::Ram;
+
+    fn main_bus() -> SharedBus {
+        let ram = Rc::new(RefCell::new(Ram::new(0x800)));
+        ram.borrow_mut().write(0x0123, 0xAB);
+        let program_rom = Rc::new(RefCell::new(Ram::new(0x8000)));
+        for address in 0..0x100 {
+            program_rom
+                .borrow_mut()
+                .write(0x0100 + address, 0xFF - address as u8);
+        }
+
+        let bus = Rc::new(RefCell::new(Bus::new("test-bus")));
+        bus.borrow_mut()
+            .attach(
+                "RAM",
+                ram,
+                AddressRange {
+                    start: 0x0000,
+                    end: 0x07FF,
+                },
+            )
+            .unwrap();
+        bus.borrow_mut()
+            .attach(
+                "PRG ROM",
+                program_rom,
+                AddressRange {
+                    start: 0x8000,
+                    end: 0xFFFF,
+                },
+            )
+            .unwrap();
+        bus
+    }
+
+    /// Run a complete OAM DMA from `page` and return the resulting OAM
+    fn oam_dma(bus: &SharedBus, page: u8) -> Vec<u8> {
+        let graphics_bus = Rc::new(RefCell::new(Bus::new("test-graphics-bus")));
+        let ppu = Rc::new(RefCell::new(Ppu::new(graphics_bus, SharedEventBus::new())));
+        let mut dma = DmaController::new();
+
+        // Starting on an odd cycle takes 1 dummy cycle plus 256 reads and
+        // writes
+        dma.write(OAM_DMA, page);
+        let mut cycles = 0;
+        while dma.is_oam_dma_active(cycles + 1) {
+            dma.oam_dma_transfer(cycles + 1, bus, &ppu);
+            cycles += 1;
+        }
+        assert_eq!(cycles, 513);
+
+        let mut ppu = ppu.borrow_mut();
+        (0..=u8::MAX)
+            .map(|address| {
+                ppu.write(OAMADDR - PPU_REGISTERS_START, address);
+                ppu.read(OAMDATA - PPU_REGISTERS_START)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_oam_dma_from_ram_and_rom() {
+        let bus = main_bus();
+
+        let oam = oam_dma(&bus, 0x01);
+        assert_eq!(oam[0x23], 0xAB);
+        assert_eq!(oam.iter().filter(|&&byte| byte != 0).count(), 1);
+
+        let oam = oam_dma(&bus, 0x81);
+        let expected: Vec<u8> = (0..=u8::MAX).rev().collect();
+        assert_eq!(oam, expected);
+    }
+
+    #[test]
+    fn test_oam_dma_from_unmapped_page() {
+        let bus = main_bus();
+
+        // Unmapped pages read the last value on the bus
+        bus.borrow().read(0x0123);
+        let oam = oam_dma(&bus, 0x50);
+        assert!(oam.iter().all(|&byte| byte == 0xAB));
+    }
+
+    #[test]
+    fn test_oam_dma_starts_at_oam_addr() {
+        let bus = main_bus();
+        let graphics_bus = Rc::new(RefCell::new(Bus::new("test-graphics-bus")));
+        let ppu = Rc::new(RefCell::new(Ppu::new(graphics_bus, SharedEventBus::new())));
+        let mut dma = DmaController::new();
+
+        ppu.borrow_mut().write(OAMADDR - PPU_REGISTERS_START, 0xFE);
+        dma.write(OAM_DMA, 0x81);
+        assert!(dma.is_oam_dma_starting());
+        assert_eq!(dma.state().bytes_remaining, 256);
+        let mut writes = Vec::new();
+        let mut cycle = 1;
+        while dma.is_oam_dma_active(cycle) {
+            writes.extend(dma.oam_dma_transfer(cycle, &bus, &ppu));
+            if cycle == 4 {
+                assert_eq!(dma.state().bytes_remaining, 255);
+            }
+            cycle += 1;
+        }
+
+        assert_eq!(writes.len(), 256);
+        assert_eq!(
+            dma.state(),
+            DmaState {
+                oam_active: false,
+                source_page: 0x81,
+                bytes_remaining: 0,
+                aligning: false,
+                alignment_cycles: 1,
+                dmc_address: None,
+                dmc_stalled_cycles: 0,
+            }
+        );
+        let first = OamDmaWrite {
+            source: 0x8100,
+            oam_address: 0xFE,
+            data: 0xFF,
+        };
+        assert_eq!(writes[0], first);
+        assert_eq!((writes[2].source, writes[2].oam_address), (0x8102, 0x00));
+
+        // OAMADDR wraps back to where it started
+        let mut ppu = ppu.borrow_mut();
+        assert_eq!(ppu.read(OAMDATA - PPU_REGISTERS_START), 0xFF);
+        ppu.write(OAMADDR - PPU_REGISTERS_START, 0x00);
+        assert_eq!(ppu.read(OAMDATA - PPU_REGISTERS_START), 0xFD);
+    }
+
+    #[test]
+    fn test_dmc_dma_stalls_cpu() {
+        let bus = main_bus();
+        let mut dma = DmaController::new();
+
+        dma.request_dmc_read(0x0123);
+        for _ in 0..DMC_DMA_STALL_CYCLES {
+            assert!(dma.is_dmc_dma_active());
+            assert_eq!(dma.take_dmc_sample(), None);
+            dma.dmc_dma_transfer(&bus);
+        }
+
+        assert!(!dma.is_dmc_dma_active());
+        assert_eq!(dma.take_dmc_sample(), Some(0xAB));
+        assert_eq!(dma.take_dmc_sample(), None);
+        assert_eq!(dma.dmc_stalled_cycles(), DMC_DMA_STALL_CYCLES.into());
+    }
+
+    #[test]
+    fn test_dmc_dma_during_oam_dma() {
+        let bus = main_bus();
+        let mut dma = DmaController::new();
+
+        dma.write(OAM_DMA, 0x02);
+        dma.request_dmc_read(0x0123);
+        for _ in 0..DMC_DMA_STALL_CYCLES_DURING_OAM_DMA {
+            dma.dmc_dma_transfer(&bus);
+        }
+
+        assert!(!dma.is_dmc_dma_active());
+        assert_eq!(dma.take_dmc_sample(), Some(0xAB));
+    }
+}
@@ -31,6 +31,14 @@ pub enum NesError {
         source: UiError,
     },
 
+    #[error(
+        "Snapshot taken with cartidge {snapshot:?} can't be restored with cartidge {inserted:?}"
+    )]
+    SnapshotMismatch {
+        snapshot: Option<String>,
+        inserted: Option<String>,
+    },
+
     #[error("NES internal error: {0}")]
     NesInternalError(String),
 }
@@ -64,6 +72,84 @@ pub enum BusError {
     },
 }
 
+/// ROM files errors
+#[derive(Debug, Error)]
+pub enum RomError {
+    #[error("Unable to read ROM file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("ROM is too short: expected {expected} bytes but found {actual}")]
+    RomTooShort { expected: usize, actual: usize },
+
+    #[error("Not an iNES ROM, it starts with {0:02X?} instead of \"NES\\x1A\"")]
+    BadMagic([u8; 4]),
+
+    #[error("ROM size mismatch: expected {expected} bytes but found {actual}")]
+    SizeMismatch { expected: usize, actual: usize },
+
+    #[error("Mapper {mapper} can't have {size} bytes of PRG ROM")]
+    InvalidProgramRomSize { mapper: u8, size: usize },
+
+    #[error("Mapper {0} is not supported")]
+    UnsupportedMapper(u8),
+
+    #[error("Raw program of {size} bytes doesn't fit at ${load_address:0>4X}")]
+    RawProgramDoesNotFit { size: usize, load_address: u16 },
+}
+
+/// Movie files errors
+#[derive(Debug, Error)]
+pub enum MovieError {
+    #[error("Unable to access movie file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid movie line {line}: {details}")]
+    InvalidLine { line: usize, details: String },
+
+    #[error("Unsupported movie: {0}")]
+    Unsupported(String),
+}
+
+/// Scripted input errors
+#[derive(Debug, Error, Eq, PartialEq)]
+#[error("Invalid input command '{command}': {details}")]
+pub struct InputScriptError {
+    pub command: String,
+    pub details: String,
+}
+
+/// Saved state decoding errors
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Not a saved state")]
+    InvalidFormat,
+
+    #[error("Unsupported saved state version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Malformed saved state: {0}")]
+    Malformed(String),
+
+    #[error("Saved state of ROM {saved:08X?} can't be loaded with ROM {inserted:08X?}")]
+    CartidgeMismatch {
+        saved: Option<u32>,
+        inserted: Option<u32>,
+    },
+}
+
+/// Frame delta decoding errors
+#[derive(Debug, Error)]
+pub enum FrameDeltaError {
+    #[error("Unsupported frame delta version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Malformed frame delta: {0}")]
+    Malformed(String),
+
+    #[error("Frame delta applies to frame {base} but the current frame is {current:?}")]
+    MissingBase { base: u64, current: Option<u64> },
+}
+
 /// UI errors
 #[derive(Debug, Error)]
 pub enum UiError {
@@ -76,3 +162,38 @@ pub enum UiError {
     #[error("unhandled UI error: {0}")]
     Unhandled(String),
 }
+
+/// Symbol (label) files errors
+#[derive(Debug, Error)]
+pub enum SymbolError {
+    #[error("Unable to access symbol file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid symbol file line {line}: {details}")]
+    InvalidLine { line: usize, details: String },
+}
+
+/// Debugger expressions errors
+#[derive(Debug, Error, Eq, PartialEq)]
+#[error("Invalid expression at column {column}: {details}")]
+pub struct ExpressionError {
+    /// Position of the error in the expression text, starting at 1
+    pub column: usize,
+    pub details: String,
+}
+
+/// Reference trace errors
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("Unable to access trace file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid trace line {line}: {details}")]
+    InvalidLine { line: usize, details: String },
+
+    #[error("{0}")]
+    Divergence(Box<crate::reference_trace::Divergence>),
+
+    #[error("Emulation stopped: {0}")]
+    Emulation(#[from] NesError),
+}
@@ -1,75 +1,232 @@
 /// Inter-component events in the NES system
 ///
 /// Some of the components in the NES are capable of communicating some events
-/// so other components can react to them. This could be flipping a bit in some
-/// line, writing to some register...
+/// so other components can react to them, e.g., a frame being ready. Events
+/// are coarse lifecycle notifications: interrupts, which need to be seen
+/// within a CPU cycle, use interrupt lines instead (see
+/// [`InterruptLine`](crate::InterruptLine))
 ///
 /// This module abstracts this events and provide an event bus so components can
 /// be notified or poll for events
 ///
 use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex, MutexGuard},
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        Arc, Mutex, MutexGuard,
+    },
 };
 
 use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError, TrySendError};
-use log::warn;
+use log::{trace, warn};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+use crate::dma::OamDmaTransfer;
+use crate::graphics::palette_memory::PaletteRam;
+use crate::graphics::ppu::WarmUpWrite;
+use crate::interfaces::BusFault;
+use crate::keyboard::Key;
+use crate::warnings::EmulatorWarning;
+use crate::watchdog::WatchdogReport;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Event {
     /// Switch-off is the event that gracefully stops the whole system
     SwitchOff,
 
-    /// Non-maskable interrupt
-    ///
-    /// This event is generated by the PPU and is used to communicate the
-    /// vertical blank state to the CPU (when CPU can interact again with the
-    /// PPU after a frame have been rendered)
-    NMI,
-
     /// PPU has completely computed the next frame, the GUI can now be updated
     /// with it
     FrameReady,
+
+    /// Replace the inserted cartidge with the ROM found in this path
+    LoadRom(PathBuf),
+
+    /// A bus access failed and was ignored (only in tolerant mode)
+    BusFault(BusFault),
+
+    /// The game seems stuck in a loop (only with the watchdog enabled)
+    WatchdogTriggered(WatchdogReport),
+
+    /// A PPU register write was ignored as the PPU was warming up (only with
+    /// the accurate profile)
+    WarmUpWriteIgnored(WarmUpWrite),
+
+    /// Palette RAM changed during the last frame. It has the new contents
+    PaletteChanged(PaletteRam),
+
+    /// PPU has rendered these rows of the current frame (only with beam
+    /// racing enabled)
+    ScanlinesReady(Range<usize>),
+
+    /// The game hit a feature the emulator doesn't support. See
+    /// [`warnings`](crate::warnings)
+    EmulatorWarning(EmulatorWarning),
+
+    /// A write to $4014 halted the CPU to copy a page to OAM
+    OamDmaStarted(OamDmaTransfer),
+
+    /// OAM DMA copied its last byte and the CPU resumes
+    OamDmaFinished(OamDmaTransfer),
+}
+
+/// Events with higher priority are delivered before lower priority ones, even
+/// if they were emitted later. Events with the same priority are delivered in
+/// the order they were emitted
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum EventPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Event {
+    pub fn priority(&self) -> EventPriority {
+        match self {
+            Event::SwitchOff => EventPriority::High,
+            Event::FrameReady => EventPriority::Normal,
+            Event::LoadRom(_) => EventPriority::Low,
+            Event::BusFault(_) => EventPriority::Low,
+            Event::WatchdogTriggered(_) => EventPriority::Low,
+            Event::WarmUpWriteIgnored(_) => EventPriority::Low,
+            Event::PaletteChanged(_) => EventPriority::Low,
+            Event::ScanlinesReady(_) => EventPriority::Normal,
+            Event::EmulatorWarning(_) => EventPriority::Low,
+            Event::OamDmaStarted(_) => EventPriority::Low,
+            Event::OamDmaFinished(_) => EventPriority::Low,
+        }
+    }
 }
 
+pub type SubscriberId = usize;
+
+/// Event bus distributing every emitted event to all its subscribers. Each
+/// subscriber has its own queue, so events consumed by one of them are still
+/// available for the rest
 #[derive(Debug)]
 pub struct EventBus {
-    events: HashSet<Event>,
+    queues: HashMap<SubscriberId, EventQueue>,
+    next_subscriber: SubscriberId,
+    sequence: u64,
 }
 
 impl EventBus {
     pub fn new() -> Self {
         Self {
-            events: HashSet::new(),
+            queues: HashMap::new(),
+            next_subscriber: 0,
+            sequence: 0,
         }
     }
 
-    /// Emit a new event into the bus
+    /// Emit a new event into the bus. Every subscriber will receive it
     pub fn emit(&mut self, event: Event) {
-        self.events.insert(event);
+        if self.queues.is_empty() {
+            trace!("Event {event:?} emitted without subscribers");
+            return;
+        }
+
+        let sequence = self.sequence;
+        self.sequence += 1;
+        for queue in self.queues.values_mut() {
+            queue.push(event.clone(), sequence);
+        }
+    }
+
+    /// Register a new consumer of events. Only events emitted after
+    /// subscribing are received
+    pub fn subscribe(&mut self) -> SubscriberId {
+        let id = self.next_subscriber;
+        self.next_subscriber += 1;
+        self.queues.insert(id, EventQueue::default());
+        id
+    }
+
+    pub fn unsubscribe(&mut self, subscriber: SubscriberId) {
+        self.queues.remove(&subscriber);
+    }
+
+    /// Take the next event for `subscriber`, if any
+    pub fn poll(&mut self, subscriber: SubscriberId) -> Option<Event> {
+        self.queues.get_mut(&subscriber)?.pop()
+    }
+
+    /// Number of events pending to be consumed by `subscriber`
+    pub fn pending(&self, subscriber: SubscriberId) -> usize {
+        self.queues
+            .get(&subscriber)
+            .map(|queue| queue.len())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Priority queue keeping FIFO order between events of the same priority
+#[derive(Debug, Default)]
+struct EventQueue {
+    heap: BinaryHeap<QueuedEvent>,
+}
+
+impl EventQueue {
+    fn push(&mut self, event: Event, sequence: u64) {
+        self.heap.push(QueuedEvent {
+            priority: event.priority(),
+            sequence,
+            event,
+        });
     }
 
-    /// Check if an event has been emitted
-    pub fn emitted(&self, event: Event) -> bool {
-        self.events.contains(&event)
+    fn pop(&mut self) -> Option<Event> {
+        self.heap.pop().map(|queued| queued.event)
     }
 
-    /// Clean the event bus from a specific event. After this, `emitted` will
-    /// return `false`
-    pub fn mark_as_processed(&mut self, event: Event) {
-        self.events.remove(&event);
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct QueuedEvent {
+    priority: EventPriority,
+    sequence: u64,
+    event: Event,
+}
+
+impl Ord for QueuedEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: higher priority first and, for the same
+        // priority, lower sequence (older) first
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 #[derive(Debug)]
 pub struct SharedEventBus {
     event_bus: Arc<Mutex<EventBus>>,
+    // Events emitted so far, readable without locking the bus
+    emitted: Arc<AtomicU64>,
 }
 
 impl SharedEventBus {
     pub fn new() -> Self {
         Self {
             event_bus: Arc::new(Mutex::new(EventBus::new())),
+            emitted: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -78,38 +235,124 @@ impl SharedEventBus {
             .lock()
             .expect("Can't obtain lock in shared event bus")
     }
+
+    /// Emit a new event into the bus
+    pub fn emit(&self, event: Event) {
+        let mut event_bus = self.access();
+        event_bus.emit(event);
+        self.emitted.fetch_add(1, AtomicOrdering::Release);
+    }
+
+    /// Create a new consumer for this event bus
+    pub fn subscribe(&self) -> EventSubscriber {
+        let id = self.access().subscribe();
+        EventSubscriber {
+            id,
+            event_bus: self.clone(),
+            seen: Cell::new(self.emitted()),
+        }
+    }
+
+    fn emitted(&self) -> u64 {
+        self.emitted.load(AtomicOrdering::Acquire)
+    }
+}
+
+impl Default for SharedEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Clone for SharedEventBus {
     fn clone(&self) -> Self {
         Self {
             event_bus: Arc::clone(&self.event_bus),
+            emitted: Arc::clone(&self.emitted),
         }
     }
 }
 
+/// Consumer of events from a [`SharedEventBus`]. Dropping it unsubscribes from
+/// the bus
+#[derive(Debug)]
+pub struct EventSubscriber {
+    id: SubscriberId,
+    event_bus: SharedEventBus,
+    // Events emitted in the bus the last time the queue was found empty
+    seen: Cell<u64>,
+}
+
+impl EventSubscriber {
+    /// Take the next pending event, if any
+    pub fn poll(&self) -> Option<Event> {
+        let mut event_bus = self.event_bus.access();
+        let event = event_bus.poll(self.id);
+        if event.is_none() {
+            self.seen.set(self.event_bus.emitted());
+        }
+        event
+    }
+
+    /// Take all pending events ordered by priority and emission
+    pub fn drain(&self) -> Vec<Event> {
+        let mut event_bus = self.event_bus.access();
+        let events = std::iter::from_fn(|| event_bus.poll(self.id)).collect();
+        self.seen.set(self.event_bus.emitted());
+        events
+    }
+
+    /// Whether there may be pending events, i.e., events have been emitted
+    /// since polling last found the queue empty. Unlike polling, it doesn't
+    /// lock the bus, so it's cheap enough for hot loops
+    pub fn has_pending(&self) -> bool {
+        self.event_bus.emitted() != self.seen.get()
+    }
+}
+
+impl Drop for EventSubscriber {
+    fn drop(&mut self) {
+        self.event_bus.access().unsubscribe(self.id);
+    }
+}
+
 // Keyboard input and reading
+//
+// UIs publish key presses and releases through a keyboard channel. Every
+// listener receives all key events and keeps track of which keys are held, so
+// any number of simultaneously held keys is reported (N-key rollover).
+// Auto-repeated presses of an already held key are filtered out.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyEvent {
+    Pressed(Key),
+    Released(Key),
+}
 
 pub struct KeyboardChannel {
-    sender: Sender<char>,
-    receiver: Receiver<char>,
+    senders: Arc<Mutex<Vec<Sender<KeyEvent>>>>,
 }
 
 impl KeyboardChannel {
     pub fn new() -> Self {
-        let (sender, receiver) = bounded(100);
-        Self { sender, receiver }
+        Self {
+            senders: Arc::new(Mutex::new(Vec::new())),
+        }
     }
 
     pub fn publisher(&self) -> KeyboardPublisher {
         KeyboardPublisher {
-            sender: self.sender.clone(),
+            senders: Arc::clone(&self.senders),
+            held: HashSet::new(),
         }
     }
 
     pub fn listener(&self) -> KeyboardListener {
+        let (sender, receiver) = bounded(100);
+        self.senders.lock().unwrap().push(sender);
         KeyboardListener {
-            receiver: self.receiver.clone(),
+            receiver,
+            state: RefCell::new(KeyboardState::default()),
         }
     }
 }
@@ -122,44 +365,96 @@ impl Default for KeyboardChannel {
 
 #[derive(Debug)]
 pub struct KeyboardPublisher {
-    sender: Sender<char>,
+    senders: Arc<Mutex<Vec<Sender<KeyEvent>>>>,
+    held: HashSet<Key>,
 }
 
 impl KeyboardPublisher {
-    pub fn push_char(&self, c: char) {
-        match self.sender.try_send(c) {
-            Ok(()) => {}
-            Err(TrySendError::Full(_)) => {
-                warn!("Keyboard channel full, dropping char: {c}");
+    /// Publish a key press. Presses of an already held key (auto-repeat) are
+    /// ignored
+    pub fn press_key(&mut self, key: Key) {
+        let key = key.normalized();
+        if self.held.insert(key) {
+            self.publish(KeyEvent::Pressed(key));
+        }
+    }
+
+    pub fn release_key(&mut self, key: Key) {
+        let key = key.normalized();
+        if self.held.remove(&key) {
+            self.publish(KeyEvent::Released(key));
+        }
+    }
+
+    /// Publish a key press immediately followed by its release. Listeners
+    /// still report the key as pressed on their next read
+    pub fn push_key(&mut self, key: Key) {
+        self.press_key(key);
+        self.release_key(key);
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.push_key(Key::from_char(c));
+    }
+
+    fn publish(&self, event: KeyEvent) {
+        for sender in self.senders.lock().unwrap().iter() {
+            match sender.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("Keyboard channel full, dropping key event: {event:?}");
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    // listener dropped, nobody cares about this input anymore
+                }
             }
-            Err(TrySendError::Disconnected(_)) => panic!("Keyboard channel disconnected"),
         }
     }
 }
 
+#[derive(Debug, Default)]
+struct KeyboardState {
+    held: HashSet<Key>,
+}
+
 #[derive(Debug)]
 pub struct KeyboardListener {
-    receiver: Receiver<char>,
+    receiver: Receiver<KeyEvent>,
+    state: RefCell<KeyboardState>,
 }
 
 impl KeyboardListener {
-    /// Read buffered keyboard input
-    pub fn read(&self) -> String {
-        let mut buffer = String::new();
-        while let Some(c) = self.get_char() {
-            buffer.push(c);
+    /// Read keyboard input. Returns all keys currently held plus the ones
+    /// pressed and released since the last read
+    pub fn read(&self) -> HashSet<Key> {
+        let mut state = self.state.borrow_mut();
+        let mut pressed = HashSet::new();
+
+        while let Some(event) = self.get_event() {
+            match event {
+                KeyEvent::Pressed(key) => {
+                    state.held.insert(key);
+                    pressed.insert(key);
+                }
+                KeyEvent::Released(key) => {
+                    state.held.remove(&key);
+                }
+            }
         }
-        buffer
+
+        pressed.extend(state.held.iter().copied());
+        pressed
     }
 
-    /// Flush the keyboard input buffer
+    /// Flush the keyboard input buffer and forget about held keys
     pub fn flush(&self) {
         while self.receiver.try_recv().is_ok() {}
+        self.state.borrow_mut().held.clear();
     }
 
-    fn get_char(&self) -> Option<char> {
+    fn get_event(&self) -> Option<KeyEvent> {
         match self.receiver.try_recv() {
-            Ok(c) => Some(c),
+            Ok(event) => Some(event),
             Err(TryRecvError::Empty) => None,
             Err(TryRecvError::Disconnected) => panic!("Keyboard channel disconnected"),
         }
@@ -173,13 +468,118 @@ mod tests {
     #[test]
     fn test_events() {
         let mut event_bus = EventBus::new();
+        let subscriber = event_bus.subscribe();
+
+        assert_eq!(event_bus.poll(subscriber), None);
+
+        event_bus.emit(Event::SwitchOff);
+        assert_eq!(event_bus.pending(subscriber), 1);
+        assert_eq!(event_bus.poll(subscriber), Some(Event::SwitchOff));
+        assert_eq!(event_bus.poll(subscriber), None);
+    }
+
+    #[test]
+    fn test_events_are_not_coalesced() {
+        let mut event_bus = EventBus::new();
+        let subscriber = event_bus.subscribe();
+
+        event_bus.emit(Event::FrameReady);
+        event_bus.emit(Event::FrameReady);
+        assert_eq!(event_bus.poll(subscriber), Some(Event::FrameReady));
+        assert_eq!(event_bus.poll(subscriber), Some(Event::FrameReady));
+        assert_eq!(event_bus.poll(subscriber), None);
+    }
+
+    #[test]
+    fn test_events_priority_and_ordering() {
+        let mut event_bus = EventBus::new();
+        let subscriber = event_bus.subscribe();
+
+        event_bus.emit(Event::LoadRom(PathBuf::from("a.nes")));
+        event_bus.emit(Event::FrameReady);
+        event_bus.emit(Event::LoadRom(PathBuf::from("b.nes")));
+        event_bus.emit(Event::SwitchOff);
+
+        assert_eq!(event_bus.poll(subscriber), Some(Event::SwitchOff));
+        assert_eq!(event_bus.poll(subscriber), Some(Event::FrameReady));
+        assert_eq!(
+            event_bus.poll(subscriber),
+            Some(Event::LoadRom(PathBuf::from("a.nes")))
+        );
+        assert_eq!(
+            event_bus.poll(subscriber),
+            Some(Event::LoadRom(PathBuf::from("b.nes")))
+        );
+    }
+
+    #[test]
+    fn test_multiple_subscribers() {
+        let event_bus = SharedEventBus::new();
+        let first = event_bus.subscribe();
+
+        event_bus.emit(Event::FrameReady);
+
+        let second = event_bus.subscribe();
+        event_bus.emit(Event::SwitchOff);
+
+        assert!(first.has_pending());
+        assert_eq!(first.drain(), vec![Event::SwitchOff, Event::FrameReady]);
+        assert!(!first.has_pending());
+        assert_eq!(second.poll(), Some(Event::SwitchOff));
+        assert!(second.has_pending());
+        assert_eq!(second.poll(), None);
+        assert!(!second.has_pending());
+
+        drop(second);
+        assert_eq!(event_bus.access().queues.len(), 1);
+    }
+
+    #[test]
+    fn test_keyboard_channel() {
+        let channel = KeyboardChannel::new();
+        let mut publisher = channel.publisher();
+        let listener = channel.listener();
+
+        publisher.push_char('j');
+        publisher.push_key(Key::Up);
+
+        assert_eq!(listener.read(), HashSet::from([Key::Char('J'), Key::Up]));
+        assert!(listener.read().is_empty());
+    }
+
+    #[test]
+    fn test_keyboard_rollover_and_repeat() {
+        let channel = KeyboardChannel::new();
+        let mut publisher = channel.publisher();
+        let one = channel.listener();
+        let two = channel.listener();
+
+        let keys = [
+            Key::Left,
+            Key::Up,
+            Key::Char('J'),
+            Key::Char('K'),
+            Key::Enter,
+        ];
+        for key in keys {
+            publisher.press_key(key);
+        }
+        // auto-repeat
+        for _ in 0..200 {
+            publisher.press_key(Key::Left);
+        }
 
-        assert!(!event_bus.emitted(Event::NMI));
+        assert_eq!(one.read(), HashSet::from(keys));
+        assert_eq!(two.read(), HashSet::from(keys));
 
-        event_bus.emit(Event::NMI);
-        assert!(event_bus.emitted(Event::NMI));
+        publisher.release_key(Key::Up);
+        publisher.release_key(Key::Char('j'));
+        assert_eq!(
+            one.read(),
+            HashSet::from([Key::Left, Key::Char('K'), Key::Enter])
+        );
 
-        event_bus.mark_as_processed(Event::NMI);
-        assert!(!event_bus.emitted(Event::NMI));
+        one.flush();
+        assert!(one.read().is_empty());
     }
 }
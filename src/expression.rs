@@ -0,0 +1,465 @@
+//! Debugger expressions
+//!
+//! Small expression language over CPU registers, flags and memory, used by
+//! conditional breakpoints:
+//!
+//! ```text
+//! A == 0x20 && [0x00FE] > 3
+//! !Z || [player_x] >= $80
+//! ```
+//!
+//! - Registers `A`, `X`, `Y`, `SP`, `PC` and `P`, and flags `C`, `Z`, `I`,
+//!   `D`, `V` and `N` (0 or 1). Names are case insensitive
+//! - Numbers in decimal, hexadecimal (`0x20` or `$20`) and symbol names
+//! - `[ADDR]` reads the byte at `ADDR`
+//! - Operators, by increasing precedence: `||`, `&&`, comparisons (`==`,
+//!   `!=`, `<`, `<=`, `>`, `>=`), bitwise (`&`, `|`, `^`), `+` and `-`, and
+//!   unary `!` and `-`
+//!
+//! Comparisons and logical operators evaluate to 1 (true) or 0 (false). Any
+//! non-zero value is true.
+
+use std::fmt;
+
+use crate::errors::ExpressionError;
+use crate::processor::cpu::CpuState;
+use crate::symbols::SymbolTable;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Node {
+    Constant(i64),
+    Register(Register),
+    /// Status register bit
+    Flag(u8),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    P,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Add,
+    Subtract,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, ExpressionError> {
+        Self::parse_with_symbols(text, &SymbolTable::new())
+    }
+
+    /// Parse `text`, resolving names other than registers and flags with
+    /// `symbols`
+    pub fn parse_with_symbols(text: &str, symbols: &SymbolTable) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            symbols,
+            end_column: text.chars().count() + 1,
+        };
+        let root = parser.parse_or()?;
+        if let Some((column, token)) = parser.peek_with_column() {
+            return Err(ExpressionError {
+                column,
+                details: format!("unexpected {token}"),
+            });
+        }
+        Ok(Self {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+
+    /// Value of the expression. `read` returns the byte at an address, or
+    /// `None` if it can't be read (e.g., it has side effects), in which case
+    /// the expression has no value
+    pub fn evaluate(&self, cpu: &CpuState, read: impl Fn(u16) -> Option<u8>) -> Option<i64> {
+        evaluate(&self.root, cpu, &read)
+    }
+
+    /// Whether the expression is true (non-zero). Expressions without value
+    /// are false
+    pub fn is_true(&self, cpu: &CpuState, read: impl Fn(u16) -> Option<u8>) -> bool {
+        self.evaluate(cpu, read).is_some_and(|value| value != 0)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn evaluate(node: &Node, cpu: &CpuState, read: &dyn Fn(u16) -> Option<u8>) -> Option<i64> {
+    let value = match node {
+        Node::Constant(value) => *value,
+        Node::Register(register) => match register {
+            Register::A => cpu.acc.into(),
+            Register::X => cpu.x_reg.into(),
+            Register::Y => cpu.y_reg.into(),
+            Register::Sp => cpu.sp.into(),
+            Register::Pc => cpu.pc.into(),
+            Register::P => cpu.sr.into(),
+        },
+        Node::Flag(bit) => ((cpu.sr >> bit) & 1).into(),
+        Node::Memory(address) => read(evaluate(address, cpu, read)? as u16)?.into(),
+        Node::Not(operand) => (evaluate(operand, cpu, read)? == 0).into(),
+        Node::Negate(operand) => -evaluate(operand, cpu, read)?,
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, cpu, read)?;
+            // Short-circuit evaluation, so unreadable memory on the right
+            // doesn't matter when the result is already known
+            match operator {
+                BinaryOperator::Or if left != 0 => return Some(1),
+                BinaryOperator::And if left == 0 => return Some(0),
+                _ => {}
+            }
+            let right = evaluate(right, cpu, read)?;
+            match operator {
+                BinaryOperator::Or | BinaryOperator::And => (right != 0).into(),
+                BinaryOperator::Equal => (left == right).into(),
+                BinaryOperator::NotEqual => (left != right).into(),
+                BinaryOperator::Less => (left < right).into(),
+                BinaryOperator::LessOrEqual => (left <= right).into(),
+                BinaryOperator::Greater => (left > right).into(),
+                BinaryOperator::GreaterOrEqual => (left >= right).into(),
+                BinaryOperator::BitAnd => left & right,
+                BinaryOperator::BitOr => left | right,
+                BinaryOperator::BitXor => left ^ right,
+                BinaryOperator::Add => left.wrapping_add(right),
+                BinaryOperator::Subtract => left.wrapping_sub(right),
+            }
+        }
+    };
+    Some(value)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "number {number}"),
+            Token::Name(name) => write!(f, "name '{name}'"),
+            Token::Operator(operator) => write!(f, "'{operator}'"),
+        }
+    }
+}
+
+// Longest operators first, so `<=` isn't read as `<`
+const OPERATORS: [&str; 19] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "&", "|", "^", "+", "-", "!", "(", ")", "[", "]",
+    "$",
+];
+
+/// Split `text` into tokens, each with its column
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < chars.len() {
+        let column = position + 1;
+        let rest: String = chars[position..].iter().collect();
+        let c = chars[position];
+        let word_length = chars[position..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '@')
+            .count();
+
+        if c.is_whitespace() {
+            position += 1;
+        } else if c == '$' || rest.starts_with("0x") || rest.starts_with("0X") {
+            let prefix = if c == '$' { 1 } else { 2 };
+            let digits: String = chars[position + prefix..]
+                .iter()
+                .take_while(|c| c.is_ascii_hexdigit())
+                .collect();
+            let number = i64::from_str_radix(&digits, 16).map_err(|_| ExpressionError {
+                column,
+                details: "invalid hexadecimal number".to_string(),
+            })?;
+            tokens.push((column, Token::Number(number)));
+            position += prefix + digits.len();
+        } else if c.is_ascii_digit() {
+            let word: String = chars[position..position + word_length].iter().collect();
+            let number = word.parse().map_err(|_| ExpressionError {
+                column,
+                details: format!("invalid number '{word}'"),
+            })?;
+            tokens.push((column, Token::Number(number)));
+            position += word_length;
+        } else if word_length > 0 {
+            let word = chars[position..position + word_length].iter().collect();
+            tokens.push((column, Token::Name(word)));
+            position += word_length;
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .ok_or_else(|| ExpressionError {
+                    column,
+                    details: format!("unexpected character '{c}'"),
+                })?;
+            tokens.push((column, Token::Operator(operator)));
+            position += operator.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, one method per precedence level
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    position: usize,
+    symbols: &'a SymbolTable,
+    end_column: usize,
+}
+
+impl Parser<'_> {
+    fn peek_with_column(&self) -> Option<(usize, &Token)> {
+        self.tokens
+            .get(self.position)
+            .map(|(column, token)| (*column, token))
+    }
+
+    fn column(&self) -> usize {
+        self.peek_with_column()
+            .map_or(self.end_column, |(column, _)| column)
+    }
+
+    /// Consume the next token if it's one of `operators`
+    fn accept(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Operator(operator))) if operators.contains(operator) => {
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &'static str) -> Result<(), ExpressionError> {
+        self.accept(&[operator])
+            .map(|_| ())
+            .ok_or_else(|| ExpressionError {
+                column: self.column(),
+                details: format!("expected '{operator}'"),
+            })
+    }
+
+    fn binary_level(
+        &mut self,
+        operators: &[(&'static str, BinaryOperator)],
+        next: fn(&mut Self) -> Result<Node, ExpressionError>,
+        chained: bool,
+    ) -> Result<Node, ExpressionError> {
+        let symbols: Vec<&'static str> = operators.iter().map(|(symbol, _)| *symbol).collect();
+        let mut left = next(self)?;
+        while let Some(symbol) = self.accept(&symbols) {
+            let operator = operators
+                .iter()
+                .find(|(candidate, _)| *candidate == symbol)
+                .unwrap()
+                .1;
+            left = Node::Binary(operator, Box::new(left), Box::new(next(self)?));
+            if !chained {
+                break;
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_or(&mut self) -> Result<Node, ExpressionError> {
+        self.binary_level(&[("||", BinaryOperator::Or)], Self::parse_and, true)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, ExpressionError> {
+        self.binary_level(&[("&&", BinaryOperator::And)], Self::parse_comparison, true)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, ExpressionError> {
+        self.binary_level(
+            &[
+                ("==", BinaryOperator::Equal),
+                ("!=", BinaryOperator::NotEqual),
+                ("<=", BinaryOperator::LessOrEqual),
+                (">=", BinaryOperator::GreaterOrEqual),
+                ("<", BinaryOperator::Less),
+                (">", BinaryOperator::Greater),
+            ],
+            Self::parse_bitwise,
+            false,
+        )
+    }
+
+    fn parse_bitwise(&mut self) -> Result<Node, ExpressionError> {
+        self.binary_level(
+            &[
+                ("&", BinaryOperator::BitAnd),
+                ("|", BinaryOperator::BitOr),
+                ("^", BinaryOperator::BitXor),
+            ],
+            Self::parse_sum,
+            true,
+        )
+    }
+
+    fn parse_sum(&mut self) -> Result<Node, ExpressionError> {
+        self.binary_level(
+            &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+            Self::parse_unary,
+            true,
+        )
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, ExpressionError> {
+        match self.accept(&["!", "-"]) {
+            Some("!") => Ok(Node::Not(Box::new(self.parse_unary()?))),
+            Some(_) => Ok(Node::Negate(Box::new(self.parse_unary()?))),
+            None => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ExpressionError> {
+        if self.accept(&["("]).is_some() {
+            let node = self.parse_or()?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if self.accept(&["["]).is_some() {
+            let address = self.parse_or()?;
+            self.expect("]")?;
+            return Ok(Node::Memory(Box::new(address)));
+        }
+
+        let column = self.column();
+        let node = match self.tokens.get(self.position) {
+            Some((_, Token::Number(number))) => Node::Constant(*number),
+            Some((_, Token::Name(name))) => self.resolve(name).ok_or_else(|| ExpressionError {
+                column,
+                details: format!("unknown name '{name}'"),
+            })?,
+            Some((_, token)) => {
+                return Err(ExpressionError {
+                    column,
+                    details: format!("unexpected {token}"),
+                })
+            }
+            None => {
+                return Err(ExpressionError {
+                    column,
+                    details: "unexpected end of expression".to_string(),
+                })
+            }
+        };
+        self.position += 1;
+        Ok(node)
+    }
+
+    fn resolve(&self, name: &str) -> Option<Node> {
+        let node = match name.to_ascii_uppercase().as_str() {
+            "A" => Node::Register(Register::A),
+            "X" => Node::Register(Register::X),
+            "Y" => Node::Register(Register::Y),
+            "SP" => Node::Register(Register::Sp),
+            "PC" => Node::Register(Register::Pc),
+            "P" => Node::Register(Register::P),
+            "C" => Node::Flag(0),
+            "Z" => Node::Flag(1),
+            "I" => Node::Flag(2),
+            "D" => Node::Flag(3),
+            "V" => Node::Flag(6),
+            "N" => Node::Flag(7),
+            _ => Node::Constant(self.symbols.address(name)?.into()),
+        };
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu() -> CpuState {
+        CpuState {
+            acc: 0x20,
+            x_reg: 3,
+            sp: 0xFD,
+            pc: 0xC000,
+            // N and C set
+            sr: 0b1000_0001,
+            ..Default::default()
+        }
+    }
+
+    fn evaluate(text: &str) -> Option<i64> {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x00FE, "lives");
+        let memory = |address: u16| match address {
+            0x00FE => Some(4),
+            0x0010..=0x001F => Some(address as u8),
+            _ => None,
+        };
+        Expression::parse_with_symbols(text, &symbols)
+            .unwrap()
+            .evaluate(&cpu(), memory)
+    }
+
+    #[test]
+    fn test_expression_evaluation() {
+        assert_eq!(evaluate("A == 0x20 && [0x00FE] > 3"), Some(1));
+        assert_eq!(evaluate("a == $21 || [lives] == 4"), Some(1));
+        assert_eq!(evaluate("N && C && !Z"), Some(1));
+        assert_eq!(evaluate("[0x10 + x] - 1"), Some(0x12));
+        assert_eq!(evaluate("(P & $80) != 0"), Some(1));
+        assert_eq!(evaluate("PC >= $C000 && SP == 253"), Some(1));
+        assert_eq!(evaluate("-X + 1 < 0"), Some(1));
+
+        // Unreadable memory, unless short-circuited
+        assert_eq!(evaluate("[$2002] == 0"), None);
+        assert_eq!(evaluate("X == 4 && [$2002] == 0"), Some(0));
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let error = |text| Expression::parse(text).unwrap_err();
+        assert_eq!(error("A == ").column, 6);
+        assert_eq!(error("A == lives").details, "unknown name 'lives'");
+        assert_eq!(error("[$10").details, "expected ']'");
+        assert_eq!(error("A # 2").column, 3);
+        assert_eq!(error("A 2").details, "unexpected number 2");
+    }
+}
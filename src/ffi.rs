@@ -0,0 +1,337 @@
+//! C ABI bindings
+//!
+//! Flat C interface to embed the emulator core in non-Rust frontends and
+//! plugins. It's only available with the `ffi` feature. To build a shared
+//! library, run:
+//!
+//! ```text
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! All functions receive an opaque `NesHandle` pointer created by
+//! [`nes_create`] that must be released with [`nes_destroy`]. Functions
+//! returning `c_int` return [`NES_OK`] on success or a negative error code.
+//! Panics are caught at the boundary and reported as [`NES_ERROR_INTERNAL`].
+//!
+//! Frames are exposed as packed 8-bit RGB buffers of 256x240 pixels.
+//!
+//! Example (C):
+//!
+//! ```c
+//! NesHandle *nes = nes_create();
+//! nes_load_rom(nes, rom, rom_len);
+//! while (running) {
+//!     nes_set_input(nes, 0, buttons);
+//!     nes_run_frame(nes);
+//!     size_t len;
+//!     const uint8_t *rgb = nes_framebuffer(nes, &len);
+//!     draw(rgb, len);
+//! }
+//! nes_destroy(nes);
+//! ```
+
+use std::os::raw::c_int;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use log::error;
+
+use crate::errors::NesError;
+use crate::settings::{NesSettings, UiKind};
+use crate::ControllerState;
+use crate::Nes;
+use crate::{Cartidge, RomValidation};
+
+pub const NES_OK: c_int = 0;
+pub const NES_ERROR_NULL_POINTER: c_int = -1;
+pub const NES_ERROR_INVALID_ROM: c_int = -2;
+pub const NES_ERROR_NO_CARTIDGE: c_int = -3;
+pub const NES_ERROR_INVALID_ARGUMENT: c_int = -4;
+pub const NES_ERROR_UNSUPPORTED: c_int = -5;
+pub const NES_ERROR_INTERNAL: c_int = -6;
+pub const NES_ERROR_BUFFER_TOO_SMALL: c_int = -7;
+pub const NES_ERROR_INVALID_STATE: c_int = -8;
+
+/// Opaque emulator handle
+pub struct NesHandle {
+    nes: Nes,
+    framebuffer: Vec<u8>,
+}
+
+fn guard(f: impl FnOnce() -> c_int) -> c_int {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        error!("Panic caught at the FFI boundary");
+        NES_ERROR_INTERNAL
+    })
+}
+
+/// Create a new emulator without UI
+#[no_mangle]
+pub extern "C" fn nes_create() -> *mut NesHandle {
+    let handle = catch_unwind(|| NesHandle {
+        nes: Nes::new(NesSettings {
+            ui_kind: UiKind::None,
+            settings_file: None,
+            ..Default::default()
+        }),
+        framebuffer: Vec::new(),
+    });
+
+    match handle {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Destroy an emulator created with [`nes_create`]. Passing a null pointer
+/// does nothing
+///
+/// # Safety
+///
+/// `nes` must be null or a handle returned by [`nes_create`] not yet
+/// destroyed
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(nes: *mut NesHandle) {
+    if !nes.is_null() {
+        drop(Box::from_raw(nes));
+    }
+}
+
+/// Insert a cartidge from an iNES image of `len` bytes
+///
+/// # Safety
+///
+/// `nes` must be a valid handle and `data` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(nes: *mut NesHandle, data: *const u8, len: usize) -> c_int {
+    let (Some(handle), false) = (nes.as_mut(), data.is_null()) else {
+        return NES_ERROR_NULL_POINTER;
+    };
+    let rom = slice::from_raw_parts(data, len);
+
+    let Ok(cartidge) = Cartidge::try_from_bytes("ffi", rom, RomValidation::Strict) else {
+        return NES_ERROR_INVALID_ROM;
+    };
+
+    guard(|| {
+        handle.nes.load_cartidge(cartidge);
+        NES_OK
+    })
+}
+
+/// Run the emulator until the next frame is complete
+///
+/// # Safety
+///
+/// `nes` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(nes: *mut NesHandle) -> c_int {
+    let Some(handle) = nes.as_mut() else {
+        return NES_ERROR_NULL_POINTER;
+    };
+
+    guard(|| match handle.nes.run_frames(1) {
+        Ok(()) => {
+            if let Some(frame) = handle.nes.last_frame() {
+                handle.framebuffer = frame.to_rgb24();
+            }
+            NES_OK
+        }
+        Err(NesError::NoCartidgeInserted) => NES_ERROR_NO_CARTIDGE,
+        Err(error) => {
+            error!("{error}");
+            NES_ERROR_INTERNAL
+        }
+    })
+}
+
+/// Last frame as packed RGB bytes. Its length is written to `len`. Returns
+/// null if no frame has been produced yet. The buffer is valid until the next
+/// call to [`nes_run_frame`] or [`nes_destroy`]
+///
+/// # Safety
+///
+/// `nes` must be a valid handle and `len` null or a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn nes_framebuffer(nes: *const NesHandle, len: *mut usize) -> *const u8 {
+    let Some(handle) = nes.as_ref() else {
+        return ptr::null();
+    };
+
+    if let Some(len) = len.as_mut() {
+        *len = handle.framebuffer.len();
+    }
+
+    if handle.framebuffer.is_empty() {
+        ptr::null()
+    } else {
+        handle.framebuffer.as_ptr()
+    }
+}
+
+/// Set the pressed buttons of a controller `port` (0 or 1). `buttons` bits,
+/// from most to least significant, are: A, B, Select, Start, Up, Down, Left
+/// and Right
+///
+/// # Safety
+///
+/// `nes` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_input(nes: *mut NesHandle, port: u8, buttons: u8) -> c_int {
+    let Some(handle) = nes.as_mut() else {
+        return NES_ERROR_NULL_POINTER;
+    };
+
+    let state = ControllerState::from_bits_truncate(buttons);
+    match port {
+        0 => handle.nes.set_controller_one_state(state),
+        1 => handle.nes.set_controller_two_state(state),
+        _ => return NES_ERROR_INVALID_ARGUMENT,
+    }
+    NES_OK
+}
+
+/// Save the emulator state into `buffer`, of `capacity` bytes. The length of
+/// the state is written to `len`. If it doesn't fit, nothing is written to
+/// `buffer` and [`NES_ERROR_BUFFER_TOO_SMALL`] is returned, so callers can
+/// pass a null `buffer` to query the length first
+///
+/// # Safety
+///
+/// `nes` must be a valid handle, `buffer` null or pointing to `capacity`
+/// writable bytes and `len` null or a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(
+    nes: *const NesHandle,
+    buffer: *mut u8,
+    capacity: usize,
+    len: *mut usize,
+) -> c_int {
+    let Some(handle) = nes.as_ref() else {
+        return NES_ERROR_NULL_POINTER;
+    };
+
+    guard(|| {
+        let state = handle.nes.snapshot().to_bytes();
+        if let Some(len) = len.as_mut() {
+            *len = state.len();
+        }
+        if buffer.is_null() || capacity < state.len() {
+            return NES_ERROR_BUFFER_TOO_SMALL;
+        }
+        slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+        NES_OK
+    })
+}
+
+/// Restore a state of `len` bytes saved with [`nes_save_state`]. It must
+/// have been saved with the same ROM inserted, otherwise
+/// [`NES_ERROR_INVALID_STATE`] is returned
+///
+/// # Safety
+///
+/// `nes` must be a valid handle and `data` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(nes: *mut NesHandle, data: *const u8, len: usize) -> c_int {
+    let (Some(handle), false) = (nes.as_mut(), data.is_null()) else {
+        return NES_ERROR_NULL_POINTER;
+    };
+    let state = slice::from_raw_parts(data, len);
+
+    guard(|| {
+        let snapshot = match handle.nes.load_snapshot(state) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                error!("{error}");
+                return NES_ERROR_INVALID_STATE;
+            }
+        };
+        match handle.nes.restore(&snapshot) {
+            Ok(()) => NES_OK,
+            Err(error) => {
+                error!("{error}");
+                NES_ERROR_INVALID_STATE
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::testing::ines_image;
+
+    #[test]
+    fn test_ffi_run_frame() {
+        // JMP $8000 forever
+        let mut prg = vec![0; 16 * 1024];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let rom = ines_image(0, false, &prg, &[0; 8 * 1024]);
+
+        unsafe {
+            let nes = nes_create();
+            assert_eq!(nes_run_frame(nes), NES_ERROR_NO_CARTIDGE);
+            assert!(nes_framebuffer(nes, ptr::null_mut()).is_null());
+
+            let unsupported = ines_image(0xAB, false, &prg, &[0; 8 * 1024]);
+            assert_eq!(
+                nes_load_rom(nes, unsupported.as_ptr(), unsupported.len()),
+                NES_ERROR_INVALID_ROM
+            );
+
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), NES_OK);
+            assert_eq!(nes_set_input(nes, 0, 0b1001_0000), NES_OK);
+            assert_eq!(nes_set_input(nes, 2, 0), NES_ERROR_INVALID_ARGUMENT);
+            assert_eq!(nes_run_frame(nes), NES_OK);
+
+            let mut len = 0;
+            assert!(!nes_framebuffer(nes, &mut len).is_null());
+            assert_eq!(len, SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+
+            nes_destroy(nes);
+        }
+    }
+
+    #[test]
+    fn test_ffi_save_state() {
+        // INC $00, JMP $8000
+        let mut prg = vec![0; 16 * 1024];
+        prg[..5].copy_from_slice(&[0xE6, 0x00, 0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let rom = ines_image(0, false, &prg, &[0; 8 * 1024]);
+
+        unsafe {
+            let nes = nes_create();
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), NES_OK);
+            assert_eq!(nes_run_frame(nes), NES_OK);
+
+            let mut len = 0;
+            assert_eq!(
+                nes_save_state(nes, ptr::null_mut(), 0, &mut len),
+                NES_ERROR_BUFFER_TOO_SMALL
+            );
+            let mut state = vec![0; len];
+            assert_eq!(
+                nes_save_state(nes, state.as_mut_ptr(), state.len(), &mut len),
+                NES_OK
+            );
+            assert_eq!(len, state.len());
+            let counter = (*nes).nes.peek(0x0000);
+
+            assert_eq!(nes_run_frame(nes), NES_OK);
+            assert_ne!((*nes).nes.peek(0x0000), counter);
+            assert_eq!(nes_load_state(nes, state.as_ptr(), state.len()), NES_OK);
+            assert_eq!((*nes).nes.peek(0x0000), counter);
+
+            assert_eq!(
+                nes_load_state(nes, state.as_ptr(), 4),
+                NES_ERROR_INVALID_STATE
+            );
+
+            nes_destroy(nes);
+        }
+    }
+}
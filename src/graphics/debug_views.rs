@@ -0,0 +1,264 @@
+//! Graphics debug views
+//!
+//! Images of the PPU memory as graphics debuggers show them: both pattern
+//! tables, the four nametables with the visible area, the palettes and the
+//! sprites in OAM. They're built from the PPU with
+//! [`Nes::graphics_debug_view`](crate::Nes::graphics_debug_view) and UIs can
+//! show them every frame (see [`Ui::render_debug_view`](crate::ui::Ui::render_debug_view)).
+//!
+//! Read more about PPU memory here:
+//! https://www.nesdev.org/wiki/PPU_memory_map
+//!
+
+use std::fmt;
+
+use crate::graphics::Pixel;
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Pattern tables are 16x16 tiles of 8x8 pixels
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+/// Width of the four nametables, two screens side by side
+pub const NAMETABLES_WIDTH: usize = SCREEN_WIDTH * 2;
+
+/// Height of the four nametables, two screens one above the other
+pub const NAMETABLES_HEIGHT: usize = SCREEN_HEIGHT * 2;
+
+/// RGB image of arbitrary size
+#[derive(Clone, Debug)]
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels row by row
+    pub pixels: Vec<Pixel>,
+}
+
+impl DebugImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Pixel::BLACK; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    /// Draw the outline of `rect`, wrapping around the image edges
+    pub fn draw_outline(&mut self, rect: ScrollRect, pixel: Pixel) {
+        let (x, y) = (rect.x as usize, rect.y as usize);
+        for offset in 0..rect.width as usize {
+            self.set_pixel_wrapping(x + offset, y, pixel);
+            self.set_pixel_wrapping(x + offset, y + rect.height as usize - 1, pixel);
+        }
+        for offset in 0..rect.height as usize {
+            self.set_pixel_wrapping(x, y + offset, pixel);
+            self.set_pixel_wrapping(x + rect.width as usize - 1, y + offset, pixel);
+        }
+    }
+
+    fn set_pixel_wrapping(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.set_pixel(x % self.width, y % self.height, pixel);
+    }
+
+    /// Nearest neighbor upscaling by an integer factor, so small images stay
+    /// sharp when UIs scale them
+    pub fn scaled(&self, factor: usize) -> Self {
+        let mut scaled = Self::new(self.width * factor, self.height * factor);
+        for y in 0..scaled.height {
+            for x in 0..scaled.width {
+                scaled.set_pixel(x, y, self.pixel(x / factor, y / factor));
+            }
+        }
+        scaled
+    }
+
+    /// Image contents as packed 8-bit RGB values, row by row
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in self.pixels.iter() {
+            for channel in [pixel.red(), pixel.green(), pixel.blue()] {
+                buffer.push((channel * u8::MAX as f64).round() as u8);
+            }
+        }
+        buffer
+    }
+}
+
+/// Area of the nametables shown on screen. It can wrap around the nametables
+/// edges
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ScrollRect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl ScrollRect {
+    /// Visible area according to a loopy register, `t` or `v` when a scanline
+    /// starts, and the fine X scroll
+    pub fn from_registers(vram_addr: u16, fine_x_scroll: u8) -> Self {
+        let coarse_x = vram_addr & 0x1F;
+        let coarse_y = (vram_addr >> 5) & 0x1F;
+        let horizontal_nametable = (vram_addr >> 10) & 1;
+        let vertical_nametable = (vram_addr >> 11) & 1;
+        let fine_y = (vram_addr >> 12) & 0x07;
+
+        Self {
+            x: horizontal_nametable * SCREEN_WIDTH as u16 + coarse_x * 8 + fine_x_scroll as u16,
+            // Coarse Y 30 and 31 are attribute tables, wrap them
+            y: (vertical_nametable * SCREEN_HEIGHT as u16 + coarse_y * 8 + fine_y)
+                % NAMETABLES_HEIGHT as u16,
+            width: SCREEN_WIDTH as u16,
+            height: SCREEN_HEIGHT as u16,
+        }
+    }
+}
+
+/// A sprite in OAM
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SpriteInfo {
+    /// Position in OAM (0 to 63)
+    pub index: u8,
+    pub x: u8,
+    /// Y coordinate as stored in OAM, one less than the first scanline
+    pub y: u8,
+    pub tile: u8,
+    /// Sprite palette (0 to 3, palettes 4 to 7 of the palette memory)
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontally: bool,
+    pub flip_vertically: bool,
+}
+
+impl SpriteInfo {
+    pub fn new(index: u8, x: u8, y: u8, tile: u8, attributes: u8) -> Self {
+        Self {
+            index,
+            x,
+            y,
+            tile,
+            palette: attributes & 0b11,
+            behind_background: attributes & 0b0010_0000 != 0,
+            flip_horizontally: attributes & 0b0100_0000 != 0,
+            flip_vertically: attributes & 0b1000_0000 != 0,
+        }
+    }
+
+    /// Sprites are hidden placing them below the screen
+    pub fn is_visible(&self) -> bool {
+        (self.y as usize) < SCREEN_HEIGHT - 1
+    }
+}
+
+impl fmt::Display for SpriteInfo {
+    /// `#05 (120, 64) tile $3A palette 1 HV` (H/V flips, B behind background)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:0>2} ({:>3}, {:>3}) tile ${:0>2X} palette {} {}{}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.palette,
+            if self.flip_horizontally { "H" } else { "-" },
+            if self.flip_vertically { "V" } else { "-" },
+            if self.behind_background { "B" } else { "-" },
+        )
+    }
+}
+
+/// Graphics state at the end of a frame
+#[derive(Clone, Debug)]
+pub struct GraphicsDebugView {
+    pub frame_index: u64,
+
+    /// Left ($0000) and right ($1000) pattern tables drawn with
+    /// `pattern_table_palette`
+    pub pattern_tables: [DebugImage; 2],
+    pub pattern_table_palette: u8,
+
+    /// The four nametables drawn with the background pattern table
+    pub nametables: DebugImage,
+
+    /// Scroll at the end of the frame. Games changing it mid-frame (e.g., for
+    /// a status bar) show a different area in part of the screen
+    pub scroll: ScrollRect,
+
+    /// Palette memory colors: 4 background palettes followed by 4 sprite
+    /// palettes
+    pub palettes: [Pixel; 32],
+
+    pub sprites: Vec<SpriteInfo>,
+
+    /// Sprites are 8x16 pixels instead of 8x8
+    pub tall_sprites: bool,
+}
+
+impl GraphicsDebugView {
+    /// Palettes as an image of 16 by 2 swatches of `swatch_size` pixels,
+    /// background palettes in the first row
+    pub fn palettes_image(&self, swatch_size: usize) -> DebugImage {
+        let mut image = DebugImage::new(16, 2);
+        for (index, color) in self.palettes.iter().enumerate() {
+            image.set_pixel(index % 16, index / 16, *color);
+        }
+        image.scaled(swatch_size)
+    }
+
+    /// Nametables with the visible area outlined
+    pub fn nametables_with_scroll(&self, outline: Pixel) -> DebugImage {
+        let mut image = self.nametables.clone();
+        image.draw_outline(self.scroll, outline);
+        image
+    }
+
+    /// Sprites in OAM, one per line
+    pub fn sprites_table(&self) -> String {
+        let mut table = format!(
+            "{} sprites ({})\n",
+            self.sprites
+                .iter()
+                .filter(|sprite| sprite.is_visible())
+                .count(),
+            if self.tall_sprites { "8x16" } else { "8x8" }
+        );
+        for sprite in self.sprites.iter() {
+            table.push_str(&sprite.to_string());
+            if !sprite.is_visible() {
+                table.push_str(" (hidden)");
+            }
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_rect() {
+        // Nametable 1 ($2400), coarse X 2, coarse Y 3, fine Y 5, fine X 6
+        let rect = ScrollRect::from_registers((5 << 12) | (1 << 10) | (3 << 5) | 2, 6);
+        assert_eq!((rect.x, rect.y), (256 + 2 * 8 + 6, 3 * 8 + 5));
+
+        // The outline wraps around the nametables
+        let mut image = DebugImage::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+        image.draw_outline(rect, Pixel::RED);
+        let is_red = |x, y| image.pixel(x, y).red() == 1.0;
+        assert!(is_red(rect.x as usize, rect.y as usize));
+        assert!(is_red(rect.x as usize - 257, rect.y as usize));
+        assert!(is_red(rect.x as usize - 257, rect.y as usize + 239));
+        assert!(!is_red(0, 0));
+    }
+}
@@ -0,0 +1,307 @@
+//! Video filters
+//!
+//! Filters post-process frames before UIs present them: upscaling, pixel art
+//! smoothing or CRT looks. A [`VideoFilter`] takes a [`Frame`] and produces a
+//! [`FilteredFrame`], an RGBA buffer usually bigger than the NES screen.
+//!
+//! Built-in filters are selected with [`VideoFilterKind`], either in
+//! [`NesSettings`](crate::settings::NesSettings) or at runtime with
+//! [`Nes::set_video_filter`](crate::Nes::set_video_filter). Custom filters
+//! only need to implement the trait.
+
+use crate::graphics::Frame;
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::settings::VideoFilterKind;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+type Rgba = [u8; BYTES_PER_PIXEL];
+
+/// Filter output: packed 8-bit RGBA values, row by row
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FilteredFrame {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl FilteredFrame {
+    fn new(scale: usize) -> Self {
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        Self {
+            width,
+            height,
+            rgba: vec![0; width * height * BYTES_PER_PIXEL],
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, pixel: Rgba) {
+        let offset = (y * self.width + x) * BYTES_PER_PIXEL;
+        self.rgba[offset..offset + BYTES_PER_PIXEL].copy_from_slice(&pixel);
+    }
+}
+
+pub trait VideoFilter: Send {
+    /// Output size is the NES screen size multiplied by this factor
+    fn scale(&self) -> usize;
+
+    fn apply(&mut self, frame: &Frame) -> FilteredFrame;
+}
+
+/// Build the built-in filter of `kind`, if any
+pub fn build(kind: VideoFilterKind) -> Option<Box<dyn VideoFilter>> {
+    match kind {
+        VideoFilterKind::None => None,
+        VideoFilterKind::Nearest(scale) => Some(Box::new(NearestFilter::new(scale.into()))),
+        VideoFilterKind::Scale2x => Some(Box::new(Scale2xFilter)),
+        VideoFilterKind::Scale3x => Some(Box::new(Scale3xFilter)),
+        VideoFilterKind::Crt => Some(Box::new(CrtFilter::default())),
+    }
+}
+
+/// Frame pixels as RGBA, with clamped access to out of screen neighbors
+struct Source {
+    pixels: Vec<Rgba>,
+}
+
+impl Source {
+    fn new(frame: &Frame) -> Self {
+        let pixels = frame
+            .to_rgb24()
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect();
+        Self { pixels }
+    }
+
+    fn get(&self, x: isize, y: isize) -> Rgba {
+        let x = x.clamp(0, SCREEN_WIDTH as isize - 1) as usize;
+        let y = y.clamp(0, SCREEN_HEIGHT as isize - 1) as usize;
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    fn positions() -> impl Iterator<Item = (usize, usize)> {
+        (0..SCREEN_HEIGHT).flat_map(|y| (0..SCREEN_WIDTH).map(move |x| (x, y)))
+    }
+}
+
+/// Nearest neighbor upscaling: every pixel becomes a square of `scale` pixels
+pub struct NearestFilter {
+    scale: usize,
+}
+
+impl NearestFilter {
+    pub fn new(scale: usize) -> Self {
+        Self {
+            scale: scale.max(1),
+        }
+    }
+}
+
+impl VideoFilter for NearestFilter {
+    fn scale(&self) -> usize {
+        self.scale
+    }
+
+    fn apply(&mut self, frame: &Frame) -> FilteredFrame {
+        let source = Source::new(frame);
+        let mut output = FilteredFrame::new(self.scale);
+        for (x, y) in Source::positions() {
+            let pixel = source.get(x as isize, y as isize);
+            for dy in 0..self.scale {
+                for dx in 0..self.scale {
+                    output.set_pixel(x * self.scale + dx, y * self.scale + dy, pixel);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Scale2x (EPX) pixel art upscaling, smoothing diagonals without blurring
+///
+/// See more information: https://www.scale2x.it/algorithm
+pub struct Scale2xFilter;
+
+impl VideoFilter for Scale2xFilter {
+    fn scale(&self) -> usize {
+        2
+    }
+
+    fn apply(&mut self, frame: &Frame) -> FilteredFrame {
+        let source = Source::new(frame);
+        let mut output = FilteredFrame::new(2);
+        for (x, y) in Source::positions() {
+            let (sx, sy) = (x as isize, y as isize);
+            let e = source.get(sx, sy);
+            let b = source.get(sx, sy - 1);
+            let d = source.get(sx - 1, sy);
+            let f = source.get(sx + 1, sy);
+            let h = source.get(sx, sy + 1);
+
+            let mut pixels = [e; 4];
+            if b != h && d != f {
+                pixels = [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ];
+            }
+            for (i, pixel) in pixels.into_iter().enumerate() {
+                output.set_pixel(x * 2 + i % 2, y * 2 + i / 2, pixel);
+            }
+        }
+        output
+    }
+}
+
+/// Scale3x pixel art upscaling, the 3x variant of Scale2x
+///
+/// See more information: https://www.scale2x.it/algorithm
+pub struct Scale3xFilter;
+
+impl VideoFilter for Scale3xFilter {
+    fn scale(&self) -> usize {
+        3
+    }
+
+    fn apply(&mut self, frame: &Frame) -> FilteredFrame {
+        let source = Source::new(frame);
+        let mut output = FilteredFrame::new(3);
+        for (x, y) in Source::positions() {
+            let (sx, sy) = (x as isize, y as isize);
+            let a = source.get(sx - 1, sy - 1);
+            let b = source.get(sx, sy - 1);
+            let c = source.get(sx + 1, sy - 1);
+            let d = source.get(sx - 1, sy);
+            let e = source.get(sx, sy);
+            let f = source.get(sx + 1, sy);
+            let g = source.get(sx - 1, sy + 1);
+            let h = source.get(sx, sy + 1);
+            let i = source.get(sx + 1, sy + 1);
+
+            let mut pixels = [e; 9];
+            if b != h && d != f {
+                pixels = [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ];
+            }
+            for (index, pixel) in pixels.into_iter().enumerate() {
+                output.set_pixel(x * 3 + index % 3, y * 3 + index / 3, pixel);
+            }
+        }
+        output
+    }
+}
+
+/// Simple CRT look: 3x upscaling with darkened scanlines between NES lines
+pub struct CrtFilter {
+    /// Brightness kept in scanlines, from 0.0 (black) to 1.0 (no scanlines)
+    pub scanline_brightness: f64,
+}
+
+impl Default for CrtFilter {
+    fn default() -> Self {
+        Self {
+            scanline_brightness: 0.5,
+        }
+    }
+}
+
+impl VideoFilter for CrtFilter {
+    fn scale(&self) -> usize {
+        3
+    }
+
+    fn apply(&mut self, frame: &Frame) -> FilteredFrame {
+        let mut output = NearestFilter::new(3).apply(frame);
+        let row_size = output.width * BYTES_PER_PIXEL;
+        for row in output.rgba.chunks_exact_mut(row_size).skip(2).step_by(3) {
+            for pixel in row.chunks_exact_mut(BYTES_PER_PIXEL) {
+                for channel in pixel[..3].iter_mut() {
+                    *channel = (*channel as f64 * self.scanline_brightness).round() as u8;
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{FramePixel, Pixel};
+
+    fn pixel_at(output: &FilteredFrame, x: usize, y: usize) -> Rgba {
+        let offset = (y * output.width + x) * BYTES_PER_PIXEL;
+        output.rgba[offset..offset + BYTES_PER_PIXEL]
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_nearest_filter() {
+        let mut frame = Frame::black();
+        frame.set_pixel(Pixel::WHITE, FramePixel { row: 1, col: 2 });
+
+        let output = NearestFilter::new(2).apply(&frame);
+        assert_eq!((output.width, output.height), (512, 480));
+        assert_eq!(pixel_at(&output, 5, 3), [0xFF; 4]);
+        assert_eq!(pixel_at(&output, 6, 3), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn test_scale2x_smooths_diagonals() {
+        // White staircase: (1, 0) and (0, 1)
+        let mut frame = Frame::black();
+        frame.set_pixel(Pixel::WHITE, FramePixel { row: 0, col: 1 });
+        frame.set_pixel(Pixel::WHITE, FramePixel { row: 1, col: 0 });
+
+        let output = Scale2xFilter.apply(&frame);
+        // The black pixel at (0, 0) gets a white bottom-right corner, the one
+        // at (1, 1) a white top-left corner
+        assert_eq!(pixel_at(&output, 0, 0), [0, 0, 0, 0xFF]);
+        assert_eq!(pixel_at(&output, 1, 1), [0xFF; 4]);
+        assert_eq!(pixel_at(&output, 2, 2), [0xFF; 4]);
+        assert_eq!(pixel_at(&output, 3, 3), [0, 0, 0, 0xFF]);
+
+        // Flat areas are left untouched
+        let output = Scale3xFilter.apply(&Frame::black());
+        assert!(output
+            .rgba
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0, 0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn test_crt_scanlines() {
+        let output = CrtFilter::default().apply(&Frame::new(Pixel::WHITE));
+        assert_eq!(pixel_at(&output, 0, 1), [0xFF; 4]);
+        assert_eq!(pixel_at(&output, 0, 2), [0x80, 0x80, 0x80, 0xFF]);
+    }
+}
@@ -0,0 +1,410 @@
+//! Frame delta encoding
+//!
+//! Consecutive NES frames are usually very similar: a few sprites move over a
+//! mostly static background. Remote-play or web-streaming frontends can send
+//! only what changed between frames instead of full frames.
+//!
+//! [`FrameDeltaEncoder`] splits frames in 8x8 tiles, compares them with the
+//! previous frame and merges the tiles that changed into dirty rectangles.
+//! Pixels of each rectangle are run-length encoded. [`FrameDeltaDecoder`]
+//! applies deltas on the receiver side to rebuild the frames.
+//!
+//! Serialized delta layout (integers are little endian):
+//!
+//! | Size     | Contents                                                  |
+//! |----------|-----------------------------------------------------------|
+//! | 1        | Format version                                            |
+//! | 8        | Frame index                                               |
+//! | 1        | 1 if it's a keyframe, 0 otherwise                         |
+//! | 8        | Index of the frame the delta applies to (not in keyframes)|
+//! | 2        | Number of rectangles                                      |
+//! | 4        | Per rectangle: x, y, width and height in tiles            |
+//! | 4        | Per rectangle: length of its encoded pixels               |
+//! | variable | Per rectangle: runs of (length, red, green, blue)         |
+
+use crate::errors::FrameDeltaError;
+use crate::graphics::Frame;
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const FORMAT_VERSION: u8 = 1;
+const TILE_SIZE: usize = 8;
+const TILES_WIDE: usize = SCREEN_WIDTH / TILE_SIZE;
+const TILES_HIGH: usize = SCREEN_HEIGHT / TILE_SIZE;
+const BYTES_PER_PIXEL: usize = 3;
+
+/// Area of the frame that changed, in tiles, with its run-length encoded
+/// pixels
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirtyRect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+    pixels: Vec<u8>,
+}
+
+impl DirtyRect {
+    /// Pixel coordinates (x, y, width, height) of the rectangle
+    pub fn pixel_area(&self) -> (usize, usize, usize, usize) {
+        (
+            self.x as usize * TILE_SIZE,
+            self.y as usize * TILE_SIZE,
+            self.width as usize * TILE_SIZE,
+            self.height as usize * TILE_SIZE,
+        )
+    }
+}
+
+/// Changes between a frame and the previous one. Keyframes contain the whole
+/// frame and don't depend on any other
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrameDelta {
+    pub index: u64,
+    /// Index of the frame this delta applies to. `None` for keyframes
+    pub base: Option<u64>,
+    pub rects: Vec<DirtyRect>,
+}
+
+impl FrameDelta {
+    pub fn is_keyframe(&self) -> bool {
+        self.base.is_none()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![FORMAT_VERSION];
+        bytes.extend(self.index.to_le_bytes());
+        match self.base {
+            Some(base) => {
+                bytes.push(0);
+                bytes.extend(base.to_le_bytes());
+            }
+            None => bytes.push(1),
+        }
+        bytes.extend((self.rects.len() as u16).to_le_bytes());
+        for rect in self.rects.iter() {
+            bytes.extend([rect.x, rect.y, rect.width, rect.height]);
+            bytes.extend((rect.pixels.len() as u32).to_le_bytes());
+            bytes.extend(&rect.pixels);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FrameDeltaError> {
+        let mut reader = Reader { bytes };
+
+        let version = reader.take::<1>()?[0];
+        if version != FORMAT_VERSION {
+            return Err(FrameDeltaError::UnsupportedVersion(version));
+        }
+        let index = u64::from_le_bytes(reader.take()?);
+        let base = match reader.take::<1>()?[0] {
+            1 => None,
+            _ => Some(u64::from_le_bytes(reader.take()?)),
+        };
+
+        let count = u16::from_le_bytes(reader.take()?);
+        let mut rects = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let [x, y, width, height] = reader.take()?;
+            if x as usize + width as usize > TILES_WIDE || y as usize + height as usize > TILES_HIGH
+            {
+                return Err(FrameDeltaError::Malformed(format!(
+                    "rectangle {width}x{height} at tile ({x}, {y}) is out of the screen"
+                )));
+            }
+            let length = u32::from_le_bytes(reader.take()?) as usize;
+            let pixels = reader.take_slice(length)?.to_vec();
+            rects.push(DirtyRect {
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            });
+        }
+
+        Ok(Self { index, base, rects })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], FrameDeltaError> {
+        Ok(self.take_slice(N)?.try_into().unwrap())
+    }
+
+    fn take_slice(&mut self, length: usize) -> Result<&[u8], FrameDeltaError> {
+        if self.bytes.len() < length {
+            return Err(FrameDeltaError::Malformed("unexpected end of data".into()));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+/// Computes deltas between consecutive frames
+#[derive(Default)]
+pub struct FrameDeltaEncoder {
+    previous: Option<(u64, Vec<u8>)>,
+    keyframe_interval: Option<u64>,
+    since_keyframe: u64,
+}
+
+impl FrameDeltaEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emit a keyframe every `interval` frames, so receivers joining late or
+    /// losing deltas can recover
+    pub fn with_keyframe_interval(mut self, interval: u64) -> Self {
+        self.keyframe_interval = Some(interval);
+        self
+    }
+
+    /// Force the next delta to be a keyframe
+    pub fn request_keyframe(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn encode(&mut self, frame: &Frame) -> FrameDelta {
+        let current = frame.to_rgb24();
+        let index = frame.info.index;
+
+        let keyframe_due = self
+            .keyframe_interval
+            .is_some_and(|interval| self.since_keyframe + 1 >= interval);
+        let delta = match self.previous.as_ref() {
+            Some((base, previous)) if !keyframe_due => {
+                self.since_keyframe += 1;
+                FrameDelta {
+                    index,
+                    base: Some(*base),
+                    rects: dirty_rects(&current, Some(previous)),
+                }
+            }
+            _ => {
+                self.since_keyframe = 0;
+                FrameDelta {
+                    index,
+                    base: None,
+                    rects: dirty_rects(&current, None),
+                }
+            }
+        };
+
+        self.previous = Some((index, current));
+        delta
+    }
+}
+
+/// Rebuilds frames, as packed 8-bit RGB values, from deltas
+pub struct FrameDeltaDecoder {
+    current: Option<u64>,
+    rgb: Vec<u8>,
+}
+
+impl FrameDeltaDecoder {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            rgb: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Apply `delta` and return the resulting frame. Deltas must be applied
+    /// in order, starting with a keyframe
+    pub fn apply(&mut self, delta: &FrameDelta) -> Result<&[u8], FrameDeltaError> {
+        if let Some(base) = delta.base {
+            if self.current != Some(base) {
+                return Err(FrameDeltaError::MissingBase {
+                    base,
+                    current: self.current,
+                });
+            }
+        }
+
+        for rect in delta.rects.iter() {
+            let (x, y, width, height) = rect.pixel_area();
+            let mut pixels = decode_runs(&rect.pixels);
+            for row in y..y + height {
+                for col in x..x + width {
+                    let pixel = pixels.next().ok_or_else(|| {
+                        FrameDeltaError::Malformed("rectangle is missing pixels".into())
+                    })?;
+                    let offset = (row * SCREEN_WIDTH + col) * BYTES_PER_PIXEL;
+                    self.rgb[offset..offset + BYTES_PER_PIXEL].copy_from_slice(&pixel);
+                }
+            }
+        }
+
+        self.current = Some(delta.index);
+        Ok(&self.rgb)
+    }
+}
+
+impl Default for FrameDeltaDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rectangles covering all tiles different from `previous`, or the whole
+/// frame without it. Dirty tiles are merged in horizontal runs and runs
+/// spanning the same columns in consecutive tile rows are merged too
+fn dirty_rects(current: &[u8], previous: Option<&Vec<u8>>) -> Vec<DirtyRect> {
+    let tile_dirty = |tile_x: usize, tile_y: usize| {
+        let Some(previous) = previous else {
+            return true;
+        };
+        (0..TILE_SIZE).any(|row| {
+            let start =
+                ((tile_y * TILE_SIZE + row) * SCREEN_WIDTH + tile_x * TILE_SIZE) * BYTES_PER_PIXEL;
+            let end = start + TILE_SIZE * BYTES_PER_PIXEL;
+            current[start..end] != previous[start..end]
+        })
+    };
+
+    // Rectangles (x, y, width, height) that can still grow downwards
+    let mut open: Vec<(u8, u8, u8, u8)> = Vec::new();
+    let mut closed = Vec::new();
+    for tile_y in 0..TILES_HIGH {
+        let mut runs = Vec::new();
+        let mut tile_x = 0;
+        while tile_x < TILES_WIDE {
+            if !tile_dirty(tile_x, tile_y) {
+                tile_x += 1;
+                continue;
+            }
+            let start = tile_x;
+            while tile_x < TILES_WIDE && tile_dirty(tile_x, tile_y) {
+                tile_x += 1;
+            }
+            runs.push((start as u8, (tile_x - start) as u8));
+        }
+
+        let mut still_open = Vec::new();
+        for rect in open.drain(..) {
+            let (x, _, width, _) = rect;
+            match runs.iter().position(|&run| run == (x, width)) {
+                Some(position) => {
+                    runs.remove(position);
+                    still_open.push((rect.0, rect.1, rect.2, rect.3 + 1));
+                }
+                None => closed.push(rect),
+            }
+        }
+        still_open.extend(
+            runs.into_iter()
+                .map(|(x, width)| (x, tile_y as u8, width, 1)),
+        );
+        open = still_open;
+    }
+    closed.extend(open);
+
+    closed
+        .into_iter()
+        .map(|(x, y, width, height)| DirtyRect {
+            x,
+            y,
+            width,
+            height,
+            pixels: encode_runs(current, (x, y, width, height)),
+        })
+        .collect()
+}
+
+/// Run-length encode the pixels of a rectangle, row by row
+fn encode_runs(rgb: &[u8], (x, y, width, height): (u8, u8, u8, u8)) -> Vec<u8> {
+    let (x, y) = (x as usize * TILE_SIZE, y as usize * TILE_SIZE);
+    let (width, height) = (width as usize * TILE_SIZE, height as usize * TILE_SIZE);
+
+    let mut encoded = Vec::new();
+    let mut run: Option<(u8, &[u8])> = None;
+    for row in y..y + height {
+        for col in x..x + width {
+            let offset = (row * SCREEN_WIDTH + col) * BYTES_PER_PIXEL;
+            let pixel = &rgb[offset..offset + BYTES_PER_PIXEL];
+            run = match run {
+                Some((length, color)) if color == pixel && length < u8::MAX => {
+                    Some((length + 1, color))
+                }
+                Some((length, color)) => {
+                    encoded.push(length);
+                    encoded.extend(color);
+                    Some((1, pixel))
+                }
+                None => Some((1, pixel)),
+            };
+        }
+    }
+    if let Some((length, color)) = run {
+        encoded.push(length);
+        encoded.extend(color);
+    }
+    encoded
+}
+
+fn decode_runs(encoded: &[u8]) -> impl Iterator<Item = [u8; BYTES_PER_PIXEL]> + '_ {
+    encoded
+        .chunks_exact(1 + BYTES_PER_PIXEL)
+        .flat_map(|run| std::iter::repeat_n([run[1], run[2], run[3]], run[0] as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{FramePixel, Pixel};
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let mut encoder = FrameDeltaEncoder::new();
+        let mut decoder = FrameDeltaDecoder::new();
+
+        let mut frame = Frame::new(Pixel::new_rgb_byte(0x10, 0x20, 0x30));
+        let keyframe = encoder.encode(&frame);
+        assert!(keyframe.is_keyframe());
+        assert_eq!(keyframe.rects.len(), 1);
+        // A solid frame is just a few runs
+        assert!(keyframe.to_bytes().len() < 1024);
+
+        // Two pixels in distant tiles change
+        frame.info.index = 1;
+        frame.set_pixel(Pixel::WHITE, FramePixel { row: 3, col: 3 });
+        frame.set_pixel(Pixel::WHITE, FramePixel { row: 200, col: 100 });
+        let delta = encoder.encode(&frame);
+        assert_eq!(delta.base, Some(0));
+        let areas: Vec<_> = delta.rects.iter().map(DirtyRect::pixel_area).collect();
+        assert_eq!(areas, [(0, 0, 8, 8), (96, 200, 8, 8)]);
+
+        for delta in [keyframe, delta] {
+            let delta = FrameDelta::from_bytes(&delta.to_bytes()).unwrap();
+            decoder.apply(&delta).unwrap();
+        }
+        assert_eq!(
+            decoder.apply(&encoder.encode(&frame)).unwrap(),
+            frame.to_rgb24()
+        );
+    }
+
+    #[test]
+    fn test_delta_requires_base() {
+        let mut encoder = FrameDeltaEncoder::new();
+        let mut decoder = FrameDeltaDecoder::new();
+
+        let mut frame = Frame::black();
+        encoder.encode(&frame);
+        frame.info.index = 1;
+        let delta = encoder.encode(&frame);
+        assert!(delta.rects.is_empty());
+        assert!(decoder.apply(&delta).is_err());
+
+        encoder.request_keyframe();
+        assert!(decoder.apply(&encoder.encode(&frame)).is_ok());
+        assert!(FrameDelta::from_bytes(&[FORMAT_VERSION, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Frame time graph overlay
+//!
+//! Draws the time between the last frames as a bar graph in a corner of the
+//! frame, newest on the right, so users can see stutters and report them
+//! precisely. Bars over the NTSC frame budget (~16.6 ms) are red and a line
+//! marks the budget:
+//!
+//! ```text
+//!          |
+//!  --------|-------- budget
+//!  ||||||||||||||||
+//! ```
+
+use std::time::Duration;
+
+use crate::graphics::input_overlay::{corner_position, fill};
+use crate::graphics::{Frame, Pixel};
+use crate::metrics::FRAME_TIME_HISTORY;
+use crate::settings::ScreenCorner;
+
+/// Time a frame takes on an NTSC NES, about 60.1 frames per second
+pub const FRAME_BUDGET: Duration = Duration::from_nanos(16_639_267);
+
+// Bars go from 0 to twice the budget, which is drawn in the middle
+const BARS_HEIGHT: usize = 32;
+const PADDING: usize = 2;
+
+const GRAPH_WIDTH: usize = FRAME_TIME_HISTORY + 2 * PADDING;
+const GRAPH_HEIGHT: usize = BARS_HEIGHT + 2 * PADDING;
+
+const BACKGROUND: Pixel = Pixel::BLACK;
+const ON_TIME: Pixel = Pixel::GREEN;
+const LATE: Pixel = Pixel::RED;
+
+fn budget_line() -> Pixel {
+    Pixel::new_rgb(0.5, 0.5, 0.5)
+}
+
+/// Draw `frame_times`, oldest first, in the `corner` of `frame`. Only the last
+/// [`FRAME_TIME_HISTORY`] are drawn
+pub fn draw(frame: &mut Frame, corner: ScreenCorner, frame_times: &[Duration]) {
+    let (left, top) = corner_position(corner, GRAPH_WIDTH, GRAPH_HEIGHT);
+    fill(frame, left, top, GRAPH_WIDTH, GRAPH_HEIGHT, BACKGROUND);
+
+    // Bars grow up from the row above `bottom`, the line is right above the
+    // bars of frames on budget
+    let bottom = top + PADDING + BARS_HEIGHT;
+    fill(
+        frame,
+        left + PADDING,
+        bottom - BARS_HEIGHT / 2 - 1,
+        FRAME_TIME_HISTORY,
+        1,
+        budget_line(),
+    );
+
+    let skipped = frame_times.len().saturating_sub(FRAME_TIME_HISTORY);
+    let first_bar = left + PADDING + FRAME_TIME_HISTORY - (frame_times.len() - skipped);
+    for (bar, frame_time) in frame_times[skipped..].iter().enumerate() {
+        let height = (frame_time.as_secs_f64() / FRAME_BUDGET.as_secs_f64() * BARS_HEIGHT as f64
+            / 2.0)
+            .round()
+            .clamp(1.0, BARS_HEIGHT as f64) as usize;
+        let color = if *frame_time > FRAME_BUDGET {
+            LATE
+        } else {
+            ON_TIME
+        };
+        fill(frame, first_bar + bar, bottom - height, 1, height, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::SCREEN_HEIGHT;
+
+    fn is(pixel: Pixel, expected: Pixel) -> bool {
+        (pixel.red(), pixel.green(), pixel.blue())
+            == (expected.red(), expected.green(), expected.blue())
+    }
+
+    #[test]
+    fn test_frame_time_graph() {
+        let mut frame = Frame::new(Pixel::BLUE);
+        let frame_times = [FRAME_BUDGET / 2, FRAME_BUDGET * 3 / 2];
+        draw(&mut frame, ScreenCorner::BottomLeft, &frame_times);
+
+        let bottom = SCREEN_HEIGHT - 8 - PADDING - 1;
+        let right = 8 + PADDING + FRAME_TIME_HISTORY - 1;
+        // Newest frame is late: 24 pixels high, over the budget line
+        assert!(is(frame[bottom][right], LATE));
+        assert!(is(frame[bottom - 23][right], LATE));
+        assert!(is(frame[bottom - 24][right], BACKGROUND));
+        // The previous one took half the budget
+        assert!(is(frame[bottom - 7][right - 1], ON_TIME));
+        assert!(is(frame[bottom - 8][right - 1], BACKGROUND));
+        assert!(is(frame[bottom - 16][right - 2], budget_line()));
+        assert!(is(frame[bottom][right - 2], BACKGROUND));
+
+        assert!(is(frame[bottom + PADDING + 1][right], Pixel::BLUE));
+    }
+}
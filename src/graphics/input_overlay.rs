@@ -0,0 +1,136 @@
+//! Input display overlay
+//!
+//! Draws the buttons of the connected controllers in a corner of the frame,
+//! pressed ones highlighted, so streams and TAS videos show the input without
+//! external tools. One pad is drawn per controller, stacked:
+//!
+//! ```text
+//!  .U.
+//!  L.R  s  S  B  A
+//!  .D.
+//! ```
+
+use crate::controller::ControllerState;
+use crate::graphics::{Frame, Pixel};
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::settings::ScreenCorner;
+
+// Each button is a square cell
+const CELL_SIZE: usize = 3;
+const GRID_COLUMNS: usize = 11;
+const GRID_ROWS: usize = 3;
+const PADDING: usize = 2;
+
+const PAD_WIDTH: usize = GRID_COLUMNS * CELL_SIZE + 2 * PADDING;
+const PAD_HEIGHT: usize = GRID_ROWS * CELL_SIZE + 2 * PADDING;
+
+// Distance to the screen edges, so the overlay is visible on TVs cropping
+// the overscan area
+const MARGIN: usize = 8;
+
+/// Grid position (column, row) of every button
+const LAYOUT: [(ControllerState, usize, usize); 8] = [
+    (ControllerState::UP, 1, 0),
+    (ControllerState::LEFT, 0, 1),
+    (ControllerState::RIGHT, 2, 1),
+    (ControllerState::DOWN, 1, 2),
+    (ControllerState::SELECT, 4, 1),
+    (ControllerState::START, 6, 1),
+    (ControllerState::B, 8, 1),
+    (ControllerState::A, 10, 1),
+];
+
+const BACKGROUND: Pixel = Pixel::BLACK;
+const PRESSED: Pixel = Pixel::WHITE;
+
+fn released() -> Pixel {
+    Pixel::new_rgb(0.3, 0.3, 0.3)
+}
+
+/// Draw a pad for each controller `states` in the `corner` of `frame`
+pub fn draw(frame: &mut Frame, corner: ScreenCorner, states: &[ControllerState]) {
+    let (left, top) = corner_position(corner, PAD_WIDTH, states.len() * PAD_HEIGHT);
+    for (index, state) in states.iter().enumerate() {
+        draw_pad(frame, left, top + index * PAD_HEIGHT, *state);
+    }
+}
+
+fn draw_pad(frame: &mut Frame, left: usize, top: usize, state: ControllerState) {
+    fill(frame, left, top, PAD_WIDTH, PAD_HEIGHT, BACKGROUND);
+    for (button, column, row) in LAYOUT {
+        let color = if state.contains(button) {
+            PRESSED
+        } else {
+            released()
+        };
+        fill(
+            frame,
+            left + PADDING + column * CELL_SIZE,
+            top + PADDING + row * CELL_SIZE,
+            CELL_SIZE,
+            CELL_SIZE,
+            color,
+        );
+    }
+}
+
+/// Top left position of an overlay of `width` x `height` pixels in `corner`
+pub(crate) fn corner_position(corner: ScreenCorner, width: usize, height: usize) -> (usize, usize) {
+    match corner {
+        ScreenCorner::TopLeft => (MARGIN, MARGIN),
+        ScreenCorner::TopRight => (SCREEN_WIDTH - MARGIN - width, MARGIN),
+        ScreenCorner::BottomLeft => (MARGIN, SCREEN_HEIGHT - MARGIN - height),
+        ScreenCorner::BottomRight => (
+            SCREEN_WIDTH - MARGIN - width,
+            SCREEN_HEIGHT - MARGIN - height,
+        ),
+    }
+}
+
+pub(crate) fn fill(
+    frame: &mut Frame,
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+    color: Pixel,
+) {
+    for row in frame.inner[top..top + height].iter_mut() {
+        row[left..left + width].fill(color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is(pixel: Pixel, expected: Pixel) -> bool {
+        (pixel.red(), pixel.green(), pixel.blue())
+            == (expected.red(), expected.green(), expected.blue())
+    }
+
+    #[test]
+    fn test_input_overlay() {
+        let mut frame = Frame::new(Pixel::BLUE);
+        draw(
+            &mut frame,
+            ScreenCorner::TopLeft,
+            &[ControllerState::A | ControllerState::UP, ControllerState::B],
+        );
+
+        let cell = |pad: usize, column: usize, row: usize| {
+            let top = MARGIN + pad * PAD_HEIGHT + PADDING + row * CELL_SIZE;
+            let left = MARGIN + PADDING + column * CELL_SIZE;
+            frame[top + 1][left + 1]
+        };
+        assert!(is(cell(0, 10, 1), PRESSED));
+        assert!(is(cell(0, 1, 0), PRESSED));
+        assert!(is(cell(0, 8, 1), released()));
+        assert!(is(cell(1, 8, 1), PRESSED));
+        assert!(is(cell(1, 10, 1), released()));
+
+        assert!(is(frame[MARGIN][MARGIN], BACKGROUND));
+        assert!(is(frame[MARGIN - 1][MARGIN], Pixel::BLUE));
+        assert!(is(frame[MARGIN + 2 * PAD_HEIGHT][MARGIN], Pixel::BLUE));
+    }
+}
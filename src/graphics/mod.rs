@@ -1,5 +1,10 @@
 //! NES graphics hardware emulation
 
+pub mod debug_views;
+pub mod filters;
+pub mod frame_delta;
+pub mod frame_time_graph;
+pub mod input_overlay;
 mod oam;
 pub mod palette;
 pub mod palette_memory;
@@ -9,8 +14,18 @@ pub mod ppu;
 mod ppu_registers;
 mod render_address;
 
+use std::ops::Range;
+
 use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
 
+// Color index of pixels not drawn yet, black in every palette
+const BLACK_COLOR_INDEX: u8 = 0x0F;
+
+// FNV-1a 64-bit constants
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// RGB pixel. Components go from 0.0 to 1.0 and are sRGB encoded
 #[derive(Copy, Clone, Debug)]
 pub struct Pixel {
     red: f64,
@@ -76,11 +91,32 @@ pub struct FramePixel {
     pub col: usize,
 }
 
+/// Video timing metadata of a frame
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameInfo {
+    /// Frame number since power-on, starting at 0
+    pub index: u64,
+
+    /// Emulated time since power-on when the frame was completed, in
+    /// nanoseconds
+    pub emulated_time_ns: u64,
+
+    /// Whether this is an odd frame (field). NTSC PPU alternates even and odd
+    /// frames, being odd ones one dot shorter
+    pub odd: bool,
+}
+
 /// NES screen frame representation. It sizes are the same as the NES screen
 /// (see hardware module)
 #[derive(Clone)]
 pub struct Frame {
     pub inner: InnerFrame,
+
+    /// Timing metadata. Frames not produced by the PPU have it defaulted
+    pub info: FrameInfo,
+
+    indices: Option<Vec<u8>>,
 }
 
 type InnerFrame = Vec<Vec<Pixel>>;
@@ -89,6 +125,8 @@ impl Frame {
     pub fn new(color: Pixel) -> Self {
         Self {
             inner: vec![vec![color; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            info: FrameInfo::default(),
+            indices: None,
         }
     }
 
@@ -99,8 +137,85 @@ impl Frame {
     pub fn set_pixel(&mut self, pixel: Pixel, position: FramePixel) {
         self.inner[position.row][position.col] = pixel;
     }
+
+    /// Set the 6-bit NES color index of a pixel. Its RGB value isn't updated
+    /// until [`Frame::colorize`] is called
+    pub fn set_color_index(&mut self, index: u8, position: FramePixel) {
+        let indices = self
+            .indices
+            .get_or_insert_with(|| vec![BLACK_COLOR_INDEX; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        indices[position.row * SCREEN_WIDTH + position.col] = index & 0x3F;
+    }
+
+    /// NES color index (0-63) of every pixel, row by row, before any palette
+    /// is applied. Only frames produced by the PPU have them
+    pub fn indices(&self) -> Option<&[u8]> {
+        self.indices.as_deref()
+    }
+
+    /// Convert color indices to RGB pixels using `palette`. Frames produced
+    /// by the PPU are already colorized with the configured palette, calling
+    /// it again renders them with a different one
+    pub fn colorize(&mut self, palette: &[Pixel; 64]) {
+        let Some(indices) = self.indices.as_ref() else {
+            return;
+        };
+        for (pixel, index) in self.inner.iter_mut().flatten().zip(indices) {
+            *pixel = palette[*index as usize];
+        }
+    }
+
+    /// `rows` of the frame converted to RGB with `palette`, from their color
+    /// indices. Frames without them return black rows
+    pub fn colorized_rows(&self, rows: Range<usize>, palette: &[Pixel; 64]) -> Vec<Vec<Pixel>> {
+        let Some(indices) = self.indices.as_ref() else {
+            return vec![vec![Pixel::BLACK; SCREEN_WIDTH]; rows.len()];
+        };
+        indices[rows.start * SCREEN_WIDTH..rows.end * SCREEN_WIDTH]
+            .chunks(SCREEN_WIDTH)
+            .map(|row| row.iter().map(|index| palette[*index as usize]).collect())
+            .collect()
+    }
+
+    /// Cheap hash of the frame pixels, to detect repeated frames. Unlike
+    /// [`frame_hash`](crate::testing::frame_hash), it's not meant to be stable
+    /// across versions
+    pub fn content_hash(&self) -> u64 {
+        self.inner
+            .iter()
+            .flatten()
+            .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+            .fold(FNV_OFFSET_BASIS, |hash, channel| {
+                (hash ^ channel.to_bits()).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// Frame contents as packed 8-bit RGB values, row by row
+    pub fn to_rgb24(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        for pixel in self.inner.iter().flatten() {
+            for channel in [pixel.red, pixel.green, pixel.blue] {
+                buffer.push((channel * u8::MAX as f64).round() as u8);
+            }
+        }
+        buffer
+    }
+}
+
+/// Rows of a frame still being rendered, for beam racing frontends. See
+/// [`NesSettings::beam_racing`](crate::settings::NesSettings::beam_racing)
+#[derive(Clone)]
+pub struct PartialFrame {
+    /// Index of the frame the rows belong to, see [`FrameInfo::index`]
+    pub frame_index: u64,
+    /// Screen rows in `pixels`
+    pub rows: Range<usize>,
+    pub pixels: Vec<Vec<Pixel>>,
 }
 
+/// Callback receiving the rows of every [`PartialFrame`]
+pub type ScanlinesHook = Box<dyn FnMut(&PartialFrame)>;
+
 impl Default for Frame {
     fn default() -> Self {
         Self::black()
@@ -2,9 +2,12 @@
 //!
 //! TODO docs
 
+use crate::errors::StateError;
 use crate::interfaces::Memory;
 use crate::processor::memory::Ram;
+use crate::snapshot::{StateReader, StateWriter};
 
+#[derive(Clone)]
 pub struct Oam {
     memory: Ram,
 }
@@ -41,6 +44,14 @@ impl Oam {
             x,
         }
     }
+
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.bytes(self.memory.as_slice());
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(self.memory.as_mut_slice(), "OAM")
+    }
 }
 
 impl std::fmt::Debug for Oam {
@@ -1,7 +1,58 @@
 use crate::graphics::Pixel;
+use crate::settings::ColorSettings;
+
+/// Build the 64 NES colors applying `settings` adjustments
+pub fn build_palette(settings: &ColorSettings) -> [Pixel; 64] {
+    std::array::from_fn(|color| adjust_color(Pixel::from(color as u8), settings))
+}
+
+fn adjust_color(pixel: Pixel, settings: &ColorSettings) -> Pixel {
+    let mut components = [pixel.red(), pixel.green(), pixel.blue()];
+
+    if settings.saturation != 1.0 {
+        let linear = components.map(srgb_to_linear);
+        // Rec. 709 luminance
+        let luminance = 0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2];
+        components = linear.map(|component| {
+            let saturated = luminance + (component - luminance) * settings.saturation;
+            linear_to_srgb(saturated.clamp(0.0, 1.0))
+        });
+    }
+
+    if settings.contrast != 1.0 || settings.brightness != 0.0 {
+        components = components
+            .map(|component| (component - 0.5) * settings.contrast + 0.5 + settings.brightness);
+    }
+
+    components = components.map(|component| component.clamp(0.0, 1.0));
+
+    if settings.gamma != 1.0 {
+        components = components.map(|component| component.powf(1.0 / settings.gamma));
+    }
+
+    Pixel::new_rgb(components[0], components[1], components[2])
+}
+
+/// Decode an sRGB component to linear light
+pub fn srgb_to_linear(component: f64) -> f64 {
+    if component <= 0.04045 {
+        component / 12.92
+    } else {
+        ((component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear light component as sRGB
+pub fn linear_to_srgb(component: f64) -> f64 {
+    if component <= 0.0031308 {
+        component * 12.92
+    } else {
+        1.055 * component.powf(1.0 / 2.4) - 0.055
+    }
+}
 
 impl From<u8> for Pixel {
-    /// Convert a color to it's RGB representation using NTSC video encoding
+    /// Convert a color to it's sRGB representation using NTSC video encoding
     ///
     /// Palette taken from blargg's full palette demo:
     /// https://www.nesdev.org/wiki/PPU_palettes
@@ -80,3 +131,51 @@ impl From<u8> for Pixel {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_settings_keep_palette() {
+        let palette = build_palette(&ColorSettings::default());
+        for (color, pixel) in palette.iter().enumerate() {
+            let original = Pixel::from(color as u8);
+            assert_eq!(pixel.red(), original.red());
+            assert_eq!(pixel.green(), original.green());
+            assert_eq!(pixel.blue(), original.blue());
+        }
+    }
+
+    #[test]
+    fn test_color_adjustments() {
+        let grayscale = build_palette(&ColorSettings {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        let red = grayscale[0x16];
+        assert!((red.red() - red.green()).abs() < 1e-9);
+        assert!((red.green() - red.blue()).abs() < 1e-9);
+
+        let brighter = build_palette(&ColorSettings {
+            brightness: 0.1,
+            ..Default::default()
+        });
+        assert!((brighter[0x00].red() - (84.0 / 255.0 + 0.1)).abs() < 1e-9);
+        assert_eq!(brighter[0x20].red(), (236.0 / 255.0 + 0.1_f64).min(1.0));
+
+        let gamma = build_palette(&ColorSettings {
+            gamma: 2.0,
+            ..Default::default()
+        });
+        assert!((gamma[0x00].red() - (84.0_f64 / 255.0).sqrt()).abs() < 1e-9);
+        assert_eq!(gamma[0x0F].red(), 0.0);
+    }
+
+    #[test]
+    fn test_srgb_round_trip() {
+        for value in [0.0, 0.002, 0.2, 0.5, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-9);
+        }
+    }
+}
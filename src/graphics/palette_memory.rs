@@ -1,41 +1,88 @@
+//! Palette RAM
+//!
+//! 32 bytes storing the 4 background and 4 sprite palettes. Entry 0 of every
+//! sprite palette ($3F10, $3F14, $3F18 and $3F1C) is a mirror of the same
+//! entry of the corresponding background palette ($3F00, $3F04, $3F08 and
+//! $3F0C). Writing the universal background color to $3F10 is common, so
+//! getting this wrong produces wrong backdrop colors in many games.
+//!
+//! Entries are NES color indices (0 to $3F). The whole RAM is available as a
+//! [`PaletteRam`] array with [`Nes::palette_ram`](crate::Nes::palette_ram),
+//! and [`Event::PaletteChanged`](crate::events::Event::PaletteChanged) is
+//! emitted at the end of the frames where any entry changed, so palette
+//! viewers only need to update then.
+//!
+//! See more information: https://www.nesdev.org/wiki/PPU_palettes#Memory_Map
+
+use crate::errors::StateError;
 use crate::hardware::PALETTE_MEMORY_SIZE;
 use crate::interfaces::Memory;
 use crate::processor::memory::Ram;
+use crate::snapshot::{StateReader, StateWriter};
+
+/// Contents of palette RAM. Mirrored entries ($10, $14, $18 and $1C) hold
+/// the same value as the background entries they mirror
+pub type PaletteRam = [u8; PALETTE_MEMORY_SIZE as usize];
 
+#[derive(Clone)]
 pub struct PaletteMemory {
     memory: Ram,
+    // Whether an entry changed since the last check
+    changed: bool,
+}
+
+/// Resolve a palette address to its physical palette RAM entry (0 to $1F).
+/// Both PPU addresses ($3F00-$3FFF) and offsets inside palette memory are
+/// accepted
+pub fn resolve(address: u16) -> u16 {
+    match address & (PALETTE_MEMORY_SIZE - 1) {
+        0x10 => 0x00,
+        0x14 => 0x04,
+        0x18 => 0x08,
+        0x1C => 0x0C,
+        address => address,
+    }
 }
 
 impl PaletteMemory {
     pub fn new() -> Self {
         Self {
             memory: Ram::new(PALETTE_MEMORY_SIZE.into()),
+            changed: false,
+        }
+    }
+
+    pub fn entries(&self) -> PaletteRam {
+        let mut entries = [0; PALETTE_MEMORY_SIZE as usize];
+        for (address, entry) in entries.iter_mut().enumerate() {
+            *entry = self.read(address as u16);
         }
+        entries
+    }
+
+    /// Whether any entry changed its value since the last call
+    pub(crate) fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.bytes(self.memory.as_slice());
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.changed = true;
+        state.bytes_into(self.memory.as_mut_slice(), "palette memory")
     }
 }
 
 impl Memory for PaletteMemory {
     fn read(&self, address: u16) -> u8 {
-        let address = match address {
-            0x10 => 0x00,
-            0x14 => 0x04,
-            0x18 => 0x08,
-            0x1C => 0x0C,
-            address => address,
-        };
-
-        self.memory.read(address)
+        self.memory.read(resolve(address))
     }
 
     fn write(&mut self, address: u16, data: u8) {
-        let address = match address {
-            0x10 => 0x00,
-            0x14 => 0x04,
-            0x18 => 0x08,
-            0x1C => 0x0C,
-            address => address,
-        };
-
+        let address = resolve(address);
+        self.changed |= self.memory.read(address) != data;
         self.memory.write(address, data);
     }
 
@@ -43,3 +90,59 @@ impl Memory for PaletteMemory {
         PALETTE_MEMORY_SIZE.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::PALETTE_MIRRORS;
+    use crate::processor::memory::MirroredMemory;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(0x3F00), 0x00);
+        assert_eq!(resolve(0x3F10), 0x00);
+        assert_eq!(resolve(0x3F1C), 0x0C);
+        assert_eq!(resolve(0x3F11), 0x11);
+        assert_eq!(resolve(0x3F30), 0x00);
+        assert_eq!(resolve(0x14), 0x04);
+        assert_eq!(resolve(0x3FFF), 0x1F);
+    }
+
+    #[test]
+    fn test_backdrop_mirrors() {
+        let mut palettes = MirroredMemory::new(PaletteMemory::new(), PALETTE_MIRRORS.into());
+
+        // Sprite palette entry 0 writes go to background palettes
+        palettes.write(0x10, 0x21);
+        palettes.write(0x14, 0x22);
+        assert_eq!(palettes.read(0x00), 0x21);
+        assert_eq!(palettes.read(0x04), 0x22);
+
+        // and the other way around, also in mirrored regions
+        palettes.write(0x28, 0x23);
+        assert_eq!(palettes.read(0x18), 0x23);
+        assert_eq!(palettes.read(0xFC), palettes.read(0x0C));
+
+        // Other sprite entries are not mirrored
+        palettes.write(0x11, 0x24);
+        assert_eq!(palettes.read(0x01), 0x00);
+
+        let entries = palettes.memory().entries();
+        assert_eq!(entries[0x10], 0x21);
+        assert_eq!(entries[0x11], 0x24);
+    }
+
+    #[test]
+    fn test_palette_changes() {
+        let mut palettes = PaletteMemory::new();
+        assert!(!palettes.take_changed());
+
+        // Writing the same value is not a change
+        palettes.write(0x05, 0x00);
+        assert!(!palettes.take_changed());
+
+        palettes.write(0x15, 0x2A);
+        assert!(palettes.take_changed());
+        assert!(!palettes.take_changed());
+    }
+}
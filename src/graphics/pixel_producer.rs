@@ -3,11 +3,14 @@
 //! Refer to https://www.nesdev.org/wiki/PPU_rendering for more information
 //! about this module
 
-use crate::interfaces::Bus;
-use crate::{hardware::PALETTE_MEMORY_START, types::SharedBus, utils};
+use std::rc::Rc;
 
+use crate::errors::StateError;
+use crate::snapshot::{StateReader, StateWriter};
+use crate::{types::SharedBus, utils};
+
+use super::oam::OamSprite;
 use super::pattern_table::PatternTableAddress;
-use super::{oam::OamSprite, Pixel};
 
 /// PPU's internal set of shift registers and multiplexers responsible of
 /// producing pixel data.
@@ -18,6 +21,7 @@ use super::{oam::OamSprite, Pixel};
 /// Priority multiplexers decide how to combine all data to produce the correct
 /// pixel.
 ///
+#[derive(Clone)]
 pub struct PixelProducer {
     bus: SharedBus,
 
@@ -30,13 +34,24 @@ pub struct PixelProducer {
     /// Up to 8 sprites used for a single scanline
     pub sprites: [OamSprite; 8],
 
+    /// Pattern planes (low, high) of the sprites in the scanline, already
+    /// flipped. They're fetched once per scanline, as the PPU does in cycles
+    /// 257-320, instead of for every produced pixel
+    pub sprite_patterns: [(u8, u8); 8],
+
     pub sprite_pattern_table: u8,
+
+    /// Whether the first loaded sprite is sprite 0
+    pub sprite_zero_loaded: bool,
+
+    /// Set when an opaque sprite 0 pixel overlaps an opaque background pixel
+    pub sprite_zero_hit: bool,
 }
 
 /// XXX TODO
 ///
 /// Internal PPU latches that store temporary information while rendering
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Buffers {
     pub next_tile_number: u8,
     pub next_attributes: u8,
@@ -50,7 +65,7 @@ pub struct Buffers {
 ///
 /// Shifters are 16-bit wide, the high 8 bits are used in the current pixels
 /// being drawn while the low 8 bits will be used for the next tile
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Shifters {
     pub attributes: (u16, u16),
     pub tile_pattern: (u16, u16),
@@ -70,7 +85,146 @@ impl PixelProducer {
                 tile: 0xFF,
                 attributes: 0xFF,
             }; 8],
+            sprite_patterns: [(0, 0); 8],
+            sprite_zero_loaded: false,
+            sprite_zero_hit: false,
+        }
+    }
+
+    /// Copy the state of `other`, keeping this producer bus
+    pub fn restore(&mut self, other: &PixelProducer) {
+        let bus = Rc::clone(&self.bus);
+        *self = other.clone();
+        self.bus = bus;
+    }
+
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.u8(self.fine_x);
+        state.u8(self.buffers.next_tile_number);
+        state.u8(self.buffers.next_attributes);
+        state.u8(self.buffers.next_bit_plane_high);
+        state.u8(self.buffers.next_bit_plane_low);
+        state.u16(self.shifters.attributes.0);
+        state.u16(self.shifters.attributes.1);
+        state.u16(self.shifters.tile_pattern.0);
+        state.u16(self.shifters.tile_pattern.1);
+        for (sprite, (low, high)) in self.sprites.iter().zip(self.sprite_patterns) {
+            state.u8(sprite.x);
+            state.u8(sprite.y);
+            state.u8(sprite.tile);
+            state.u8(sprite.attributes);
+            state.u8(low);
+            state.u8(high);
+        }
+        state.u8(self.sprite_pattern_table);
+        state.bool(self.sprite_zero_loaded);
+        state.bool(self.sprite_zero_hit);
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.fine_x = state.u8()?;
+        self.buffers.next_tile_number = state.u8()?;
+        self.buffers.next_attributes = state.u8()?;
+        self.buffers.next_bit_plane_high = state.u8()?;
+        self.buffers.next_bit_plane_low = state.u8()?;
+        self.shifters.attributes = (state.u16()?, state.u16()?);
+        self.shifters.tile_pattern = (state.u16()?, state.u16()?);
+        for (sprite, pattern) in self.sprites.iter_mut().zip(self.sprite_patterns.iter_mut()) {
+            *sprite = OamSprite {
+                x: state.u8()?,
+                y: state.u8()?,
+                tile: state.u8()?,
+                attributes: state.u8()?,
+            };
+            *pattern = (state.u8()?, state.u8()?);
+        }
+        self.sprite_pattern_table = state.u8()?;
+        self.sprite_zero_loaded = state.bool()?;
+        self.sprite_zero_hit = state.bool()?;
+        Ok(())
+    }
+
+    /// Load sprites to render in the next scanline. Their patterns are
+    /// fetched afterwards, one plane at a time, see
+    /// [`PixelProducer::sprite_pattern_address`]
+    pub fn load_sprites(&mut self, sprites: [OamSprite; 8], sprite_zero: bool, pattern_table: u8) {
+        self.sprites = sprites;
+        self.sprite_zero_loaded = sprite_zero;
+        self.sprite_pattern_table = pattern_table;
+    }
+
+    /// Address of the pattern `plane` (0 low, 1 high) of the sprite in `slot`
+    /// to render in `scan_line`. `sprite_height` is 8 or 16 pixels.
+    ///
+    /// 8x16 sprites ignore the sprite pattern table. They use the table
+    /// selected by bit 0 of their tile number and the next tile for their
+    /// bottom half. Empty slots fetch tile $FF, as the PPU does
+    pub fn sprite_pattern_address(
+        &self,
+        slot: usize,
+        plane: u8,
+        sprite_height: u8,
+        scan_line: u16,
+    ) -> u16 {
+        let sprite = &self.sprites[slot];
+
+        let row = if sprite.y == 0xFF {
+            0
+        } else {
+            // Sprites are drawn one scanline below their Y coordinate. The
+            // sprite size may have shrunk since the sprite was evaluated, the
+            // PPU ignores the row bits beyond it
+            let row = (scan_line - (sprite.y as u16 + 1)) as u8 & (sprite_height - 1);
+            let flip_vertically = utils::bv(sprite.attributes, 7) > 0;
+            if flip_vertically {
+                sprite_height - 1 - row
+            } else {
+                row
+            }
+        };
+
+        let (pattern_table, tile) = if sprite_height == 16 {
+            (sprite.tile & 0x01, (sprite.tile & 0xFE) + row / 8)
+        } else {
+            (self.sprite_pattern_table, sprite.tile)
+        };
+
+        let mut pattern_table_address = PatternTableAddress::new(pattern_table);
+        pattern_table_address.set(PatternTableAddress::TILE_NUMBER, tile);
+        pattern_table_address.set(PatternTableAddress::FINE_Y_OFFSET, row % 8);
+        pattern_table_address.set(PatternTableAddress::BIT_PLANE, plane);
+        pattern_table_address.into()
+    }
+
+    /// Store the fetched pattern `plane` (0 low, 1 high) of the sprite in
+    /// `slot`, flipping it if needed. Data of empty slots is discarded
+    pub fn set_sprite_pattern(&mut self, slot: usize, plane: u8, data: u8) {
+        let sprite = &self.sprites[slot];
+        if sprite.y == 0xFF {
+            return;
+        }
+
+        let flip_horizontally = utils::bv(sprite.attributes, 6) > 0;
+        let data = if flip_horizontally {
+            data.reverse_bits()
+        } else {
+            data
+        };
+
+        let pattern = &mut self.sprite_patterns[slot];
+        if plane == 0 {
+            pattern.0 = data;
+        } else {
+            pattern.1 = data;
+        }
+    }
+
+    /// Remove all sprites, so none is drawn in the next scanline
+    pub fn clear_sprites(&mut self) {
+        for sprite in self.sprites.iter_mut() {
+            sprite.y = 0xFF;
         }
+        self.sprite_zero_loaded = false;
     }
 
     // Load shift registers from internal latches (buffers) so next 8 pixels can
@@ -103,7 +257,17 @@ impl PixelProducer {
         self.shifters.attributes.1 = self.shifters.attributes.1 << 1;
     }
 
-    pub fn produce_pixel(&mut self, col: usize, row: usize) -> Option<Pixel> {
+    /// Produce the palette memory offset of the pixel at (`col`, `row`),
+    /// drawing the `background` and `sprites` layers if enabled. Disabled
+    /// layers are transparent. Resolving it to an actual color is left to the
+    /// caller, so it can be done in batches
+    pub fn produce_pixel(
+        &mut self,
+        col: usize,
+        row: usize,
+        background: bool,
+        sprites: bool,
+    ) -> Option<u8> {
         if col >= 256 || row >= 240 {
             return None;
         }
@@ -117,18 +281,35 @@ impl PixelProducer {
             let palette_hi = utils::bv_16(self.shifters.attributes.1, fine_x_bit);
             (palette_hi << 1) | palette_lo
         };
-        let background_bit_plane = {
+        let background_bit_plane = if background {
             let bit_plane_lo = utils::bv_16(self.shifters.tile_pattern.0, fine_x_bit);
             let bit_plane_hi = utils::bv_16(self.shifters.tile_pattern.1, fine_x_bit);
             (bit_plane_hi << 1) | bit_plane_lo
+        } else {
+            0
         };
 
         // ----------------------------------------------------------------------------------------------------
-        let mut palette_offset = (background_palette << 2) | background_bit_plane;
+        // Transparent background pixels show the backdrop color at $3F00,
+        // whatever their palette. Entry 0 of the other background palettes
+        // ($3F04, $3F08 and $3F0C) is never drawn
+        let mut palette_offset = if background_bit_plane == 0 {
+            0
+        } else {
+            (background_palette << 2) | background_bit_plane
+        };
 
         // Sprites
 
-        for sprite in self.sprites.iter_mut() {
+        if !sprites {
+            return Some(palette_offset as u8);
+        }
+        for (index, (sprite, (low, high))) in self
+            .sprites
+            .iter()
+            .zip(self.sprite_patterns.iter())
+            .enumerate()
+        {
             // no more valid sprites
             if sprite.y == 0xFF {
                 break;
@@ -138,41 +319,16 @@ impl PixelProducer {
                 continue;
             }
 
-            let mut pattern_table_address = PatternTableAddress::new(self.sprite_pattern_table);
-            pattern_table_address.set(PatternTableAddress::TILE_NUMBER, sprite.tile);
-
             let sprite_palette = (sprite.attributes & 0b0000_0011) + 4; // sprite palettes are 4 to 7
 
             // 0 -> front of background, 1 -> behind background
             let priority = utils::bv(sprite.attributes, 5);
-            let flip_horizontally = utils::bv(sprite.attributes, 6) > 0;
-            let flip_vertically = utils::bv(sprite.attributes, 7) > 0;
-
-            // sprites are rendered with 1 scan line offset, we need to
-            // substract it from the row to place it in the correct position
-            let mut y = (row - 1 - sprite.y as usize) as u8;
-            if flip_vertically {
-                y = 7 - y;
-            }
 
-            pattern_table_address.set(PatternTableAddress::FINE_Y_OFFSET, y);
-
-            pattern_table_address.set(PatternTableAddress::BIT_PLANE, 0);
-            let low = self.bus.borrow().read(pattern_table_address.into());
-
-            pattern_table_address.set(PatternTableAddress::BIT_PLANE, 1);
-            let high = self.bus.borrow().read(pattern_table_address.into());
-
-            let mut x = (7 - (col - sprite.x as usize)) as u8;
-            if flip_horizontally {
-                x = 7 - x
-            }
-
-            let sprite_bit_plane = utils::bv(high, x as u8) << 1 | utils::bv(low, x as u8);
+            let x = (7 - (col - sprite.x as usize)) as u8;
+            let sprite_bit_plane = utils::bv(*high, x) << 1 | utils::bv(*low, x);
 
             if background_bit_plane == 0 && sprite_bit_plane == 0 {
-                // EXT in $3F00
-                palette_offset = 0;
+                // backdrop
             } else if background_bit_plane == 0 && sprite_bit_plane > 0 {
                 // paint sprite
                 palette_offset = ((sprite_palette << 2) | sprite_bit_plane) as u16;
@@ -180,6 +336,11 @@ impl PixelProducer {
             } else if background_bit_plane > 0 && sprite_bit_plane == 0 {
                 // paint background
             } else {
+                // Sprite 0 hit doesn't happen at x=255
+                if index == 0 && self.sprite_zero_loaded && col != 255 {
+                    self.sprite_zero_hit = true;
+                }
+
                 if priority == 0 {
                     // paint sprite
                     palette_offset = ((sprite_palette << 2) | sprite_bit_plane) as u16;
@@ -190,12 +351,88 @@ impl PixelProducer {
             }
         }
 
-        let color = Pixel::from(
-            self.bus
-                .borrow()
-                .read(PALETTE_MEMORY_START + palette_offset),
-        );
+        Some(palette_offset as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::processor::bus::Bus as SystemBus;
+
+    const BACKGROUND_PALETTE: u16 = 2;
+    const SPRITE_PALETTE: u8 = 1;
+
+    fn producer(background_opaque: bool, sprite: Option<(bool, bool)>) -> PixelProducer {
+        let bus = Rc::new(RefCell::new(SystemBus::new("test-bus")));
+        let mut producer = PixelProducer::new(bus);
+        producer.clear_sprites();
+
+        producer.shifters.attributes = (0, 0x8000);
+        if background_opaque {
+            producer.shifters.tile_pattern = (0x8000, 0);
+        }
+
+        // (opaque, behind background)
+        if let Some((opaque, behind)) = sprite {
+            producer.sprites[0] = OamSprite {
+                x: 0,
+                y: 0,
+                tile: 0,
+                attributes: SPRITE_PALETTE | if behind { 0b0010_0000 } else { 0 },
+            };
+            producer.sprite_patterns[0] = (if opaque { 0x80 } else { 0 }, 0);
+            producer.sprite_zero_loaded = true;
+        }
+        producer
+    }
 
-        Some(color)
+    #[test]
+    fn test_produce_pixel_priority() {
+        let backdrop = 0;
+        let background = ((BACKGROUND_PALETTE << 2) | 1) as u8;
+        let sprite = ((SPRITE_PALETTE + 4) << 2) | 1;
+
+        let cases = [
+            // (background opaque, sprite, palette offset, sprite 0 hit)
+            (false, None, backdrop, false),
+            (false, Some((false, false)), backdrop, false),
+            (false, Some((false, true)), backdrop, false),
+            (false, Some((true, false)), sprite, false),
+            (false, Some((true, true)), sprite, false),
+            (true, None, background, false),
+            (true, Some((false, false)), background, false),
+            (true, Some((true, false)), sprite, true),
+            (true, Some((true, true)), background, true),
+        ];
+        for (background_opaque, sprite, expected, hit) in cases {
+            let mut producer = producer(background_opaque, sprite);
+            assert_eq!(
+                producer.produce_pixel(0, 0, true, true),
+                Some(expected),
+                "background opaque: {background_opaque}, sprite: {sprite:?}"
+            );
+            assert_eq!(producer.sprite_zero_hit, hit);
+        }
+    }
+
+    #[test]
+    fn test_sprite_size_shrunk_after_evaluation() {
+        let mut producer = producer(false, None);
+        // Evaluated as 8x16 for its 13th row, fetched as 8x8
+        for (attributes, fine_y) in [(0, 4), (0b1000_0000, 3)] {
+            producer.sprites[0] = OamSprite {
+                x: 0,
+                y: 0,
+                tile: 0x20,
+                attributes,
+            };
+            assert_eq!(
+                producer.sprite_pattern_address(0, 0, 8, 13),
+                0x0200 | fine_y
+            );
+        }
     }
 }
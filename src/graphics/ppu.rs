@@ -35,23 +35,38 @@
 //!
 //!
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
 
 use log::{debug, trace};
 
+use crate::errors::StateError;
 use crate::events::Event;
 use crate::events::SharedEventBus;
+use crate::graphics::debug_views::{
+    DebugImage, GraphicsDebugView, ScrollRect, SpriteInfo, NAMETABLES_HEIGHT, NAMETABLES_WIDTH,
+    PATTERN_TABLE_SIZE,
+};
+use crate::graphics::palette::build_palette;
 use crate::graphics::pattern_table::PatternTableAddress;
 use crate::graphics::ppu_registers::PpuRegisters;
 use crate::graphics::ppu_registers::{PpuCtrl, PpuMask};
 use crate::graphics::render_address::RenderAddress;
 use crate::graphics::Frame;
+use crate::graphics::FrameInfo;
 use crate::graphics::FramePixel;
 use crate::graphics::Pixel;
+use crate::hardware::resolve_graphics_address;
 use crate::hardware::OAMDATA;
+use crate::hardware::{MASTER_CLOCK_HZ, PPU_CLOCK_DIVIDER};
 use crate::hardware::{OAMADDR, PPUADDR, PPUCTRL, PPUDATA, PPUMASK, PPUSCROLL, PPUSTATUS};
+use crate::hardware::{PALETTE_MEMORY_SIZE, PALETTE_MEMORY_START, SCREEN_HEIGHT, SCREEN_WIDTH};
 use crate::interfaces::{Bus, Memory};
+use crate::processor::interrupt_line::InterruptLine;
+use crate::settings::{AccuracyProfile, ColorSettings};
+use crate::snapshot::{StateReader, StateWriter};
 use crate::types::SharedBus;
 use crate::utils;
 
@@ -59,6 +74,9 @@ use super::oam::Oam;
 use super::oam::OamSprite;
 use super::pixel_producer::PixelProducer;
 
+/// PPU dots the CPU takes to detect an NMI, i.e., a CPU cycle
+const NMI_DETECTION_DOTS: u16 = 3;
+
 // PPU background scrolling functionality is implemented using nesdev loopy
 // contributor design.
 //
@@ -74,6 +92,7 @@ use super::pixel_producer::PixelProducer;
 pub struct Ppu {
     bus: SharedBus,
     event_bus: SharedEventBus,
+    nmi_line: InterruptLine,
 
     frame: Frame,
 
@@ -85,10 +104,85 @@ pub struct Ppu {
     cycle: u16,
     scan_line: u16,
 
+    // Timing counters used to fill frame metadata
+    frame_index: u64,
+    dots: u64,
+
     pixel_producer: PixelProducer,
+
+    // Palette offsets produced for the current scanline. Colors are resolved
+    // in a batch once the scanline is complete
+    scanline_palette_offsets: [Option<u8>; SCREEN_WIDTH],
+    color_lookup: [Pixel; 64],
+
+    accuracy: AccuracyProfile,
+    // Writes ignored while warming up, until the NES reports them
+    warm_up_writes: Vec<WarmUpWrite>,
+
+    // Scanlines rendered between `ScanlinesReady` events, if enabled
+    beam_racing: Option<usize>,
+
+    // Level of the A12 address line, the dot it last went low and its rises
+    // not yet delivered to the mapper (with the dots it was low before them)
+    a12_high: Cell<bool>,
+    a12_low_since: Cell<u64>,
+    a12_rises: RefCell<Vec<u32>>,
+
+    // Visible area of the nametables of the next frame, the one being drawn
+    // and the last complete one
+    next_scroll: Option<ScrollRect>,
+    scroll: ScrollRect,
+    last_scroll: ScrollRect,
+}
+
+/// Register write ignored because the PPU was warming up. Games writing
+/// PPUCTRL before waiting for two vertical blanks do it, and they only fail
+/// on real consoles
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WarmUpWrite {
+    /// PPUCTRL, PPUMASK, PPUSCROLL or PPUADDR
+    pub register: u16,
+    pub data: u8,
+    /// Program counter of the instruction that wrote (when known)
+    pub pc: Option<u16>,
 }
 
-#[derive(Default)]
+/// Snapshot of the PPU registers and timing
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PpuState {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    pub oam_addr: u8,
+    /// Loopy `v` register: current VRAM address
+    pub vram_addr: u16,
+    /// Loopy `t` register: temporary VRAM address
+    pub temp_vram_addr: u16,
+    pub fine_x_scroll: u8,
+    /// `true` when the next PPUSCROLL/PPUADDR write is the second one
+    pub write_toggle: bool,
+    pub scan_line: u16,
+    pub cycle: u16,
+    pub frame_index: u64,
+}
+
+/// Complete PPU state, except the pixels of the frame being drawn. See
+/// [`Snapshot`](crate::snapshot::Snapshot)
+#[derive(Clone)]
+pub struct PpuSnapshot {
+    registers: PpuRegisters,
+    internal: PpuInternalRegisters,
+    oam: Oam,
+    cycle: u16,
+    scan_line: u16,
+    frame_index: u64,
+    dots: u64,
+    pixel_producer: PixelProducer,
+    scanline_palette_offsets: [Option<u8>; SCREEN_WIDTH],
+}
+
+#[derive(Clone, Default)]
 struct PpuInternalRegisters {
     /// Current VRAM address (15 bits)
     vram_addr: RenderAddress,
@@ -104,18 +198,60 @@ struct PpuInternalRegisters {
     write_toggle: WriteToggle,
 }
 
-#[derive(Default, Debug, Eq, PartialEq)]
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
 enum WriteToggle {
     #[default]
     First,
     Second,
 }
 
+impl PpuSnapshot {
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        self.registers.save(state);
+        state.u16(self.internal.vram_addr.value());
+        state.u16(self.internal.temp_vram_addr.value());
+        state.u8(self.internal.fine_x_scroll);
+        state.bool(self.internal.write_toggle == WriteToggle::Second);
+        self.oam.save(state);
+        state.u16(self.cycle);
+        state.u16(self.scan_line);
+        state.u64(self.frame_index);
+        state.u64(self.dots);
+        self.pixel_producer.save(state);
+        for offset in self.scanline_palette_offsets {
+            state.option(offset, StateWriter::u8);
+        }
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.registers.load(state)?;
+        self.internal.vram_addr = RenderAddress::from(state.u16()?);
+        self.internal.temp_vram_addr = RenderAddress::from(state.u16()?);
+        self.internal.fine_x_scroll = state.u8()?;
+        self.internal.write_toggle = if state.bool()? {
+            WriteToggle::Second
+        } else {
+            WriteToggle::First
+        };
+        self.oam.load(state)?;
+        self.cycle = state.u16()?;
+        self.scan_line = state.u16()?;
+        self.frame_index = state.u64()?;
+        self.dots = state.u64()?;
+        self.pixel_producer.load(state)?;
+        for offset in self.scanline_palette_offsets.iter_mut() {
+            *offset = state.option(StateReader::u8)?;
+        }
+        Ok(())
+    }
+}
+
 impl Ppu {
     pub fn new(bus: SharedBus, event_bus: SharedEventBus) -> Self {
         Self {
             bus: bus.clone(),
             event_bus,
+            nmi_line: InterruptLine::new(),
 
             frame: Frame::black(),
 
@@ -127,12 +263,128 @@ impl Ppu {
             cycle: 0,
             scan_line: 0,
 
+            frame_index: 0,
+            dots: 0,
+
             pixel_producer: PixelProducer::new(bus),
+
+            scanline_palette_offsets: [None; SCREEN_WIDTH],
+            color_lookup: build_palette(&ColorSettings::default()),
+
+            accuracy: AccuracyProfile::default(),
+            warm_up_writes: Vec::new(),
+
+            beam_racing: None,
+
+            a12_high: Cell::new(false),
+            a12_low_since: Cell::new(0),
+            a12_rises: RefCell::new(Vec::new()),
+
+            next_scroll: None,
+            scroll: ScrollRect::from_registers(0, 0),
+            last_scroll: ScrollRect::from_registers(0, 0),
         }
     }
 
+    pub fn set_accuracy(&mut self, accuracy: AccuracyProfile) {
+        self.accuracy = accuracy;
+    }
+
+    /// Set the registers power-up state. With the accurate profile, VBL and
+    /// sprite overflow flags are set, as they are on most consoles
+    pub fn power_up(&mut self) {
+        self.registers.reset();
+        if self.accuracy.is_accurate() {
+            self.registers.set_vertical_blank();
+            self.registers.set_sprite_overflow(true);
+        }
+    }
+
+    /// Whether the PPU is still warming up after power-up. Writes to PPUCTRL,
+    /// PPUMASK, PPUSCROLL and PPUADDR are ignored until the pre-render
+    /// scanline of the first frame, around 29658 CPU cycles. Only emulated
+    /// with the accurate profile
+    ///
+    /// See more information: https://www.nesdev.org/wiki/PPU_power_up_state
+    pub fn warming_up(&self) -> bool {
+        self.accuracy.is_accurate() && self.frame_index == 0 && self.scan_line < 261
+    }
+
+    /// Clock the PPU until it's about to run dot `cycle` of `scan_line`, so
+    /// unit tests can check timing edge cases (e.g., VBL races) without
+    /// running whole frames themselves
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn seek(&mut self, scan_line: u16, cycle: u16) {
+        assert!(
+            scan_line <= 261 && cycle <= 340,
+            "PPU position out of range"
+        );
+        // Dot 0 of scanline 0 is skipped, so some positions may not be
+        // reached in the current frame but in the next one
+        let mut dots = 2 * 262 * 341;
+        while (self.scan_line, self.cycle) != (scan_line, cycle) {
+            assert!(
+                dots > 0,
+                "PPU never reached dot {cycle} of scanline {scan_line}"
+            );
+            self.clock();
+            dots -= 1;
+        }
+    }
+
+    /// Take the register writes ignored while warming up since the last call
+    pub fn take_warm_up_writes(&mut self) -> Vec<WarmUpWrite> {
+        std::mem::take(&mut self.warm_up_writes)
+    }
+
+    /// Wire the PPU NMI output to the CPU `line`
+    pub fn connect_nmi_line(&mut self, line: InterruptLine) {
+        self.nmi_line = line;
+    }
+
+    /// Rebuild the colors used to draw frames with `settings` adjustments
+    pub fn set_color_settings(&mut self, settings: &ColorSettings) {
+        self.color_lookup = build_palette(settings);
+    }
+
+    /// Emit [`Event::ScanlinesReady`] every `scanlines` rendered rows, or stop
+    /// emitting it with `None`
+    pub fn set_beam_racing(&mut self, scanlines: Option<usize>) {
+        self.beam_racing = scanlines.filter(|scanlines| *scanlines > 0);
+    }
+
+    /// Dots to run until the PPU interacts with the rest of the system by
+    /// itself, the last one included: raising NMI at the start of vertical
+    /// blank, finishing the frame or, while beam racing, reporting scanlines.
+    /// Anything else happens when the CPU accesses the PPU
+    pub fn dots_until_event(&self) -> u64 {
+        let scan_line = self.scan_line as u64;
+        let cycle = self.cycle as u64;
+        // The first dot of a frame is skipped
+        let line_end = if scan_line == 0 && cycle == 0 {
+            340
+        } else {
+            341 - cycle
+        };
+        let dots_before = |line: u64| line_end + (line - scan_line - 1) * 341;
+
+        match scan_line {
+            0..=239 if self.beam_racing.is_some() => line_end,
+            0..=240 => dots_before(241) + 2,
+            241 if cycle <= 1 => 2 - cycle,
+            _ => dots_before(262),
+        }
+    }
+
+    /// `rows` of the frame being rendered, converted to RGB. Rows not
+    /// rendered yet are black
+    pub fn frame_rows(&self, rows: Range<usize>) -> Vec<Vec<Pixel>> {
+        self.frame.colorized_rows(rows, &self.color_lookup)
+    }
+
     pub fn clock(&mut self) {
         // Screen rendering never stops
+        self.dots += 1;
 
         if self.scan_line == 0 && self.cycle == 0 {
             // "Odd frame" cycle skip
@@ -159,6 +411,26 @@ impl Ppu {
                 if self.scan_line == 261 && self.cycle == 1 {
                     self.registers.unset_vertical_blank();
                     self.registers.set_sprite_overflow(false);
+                    self.registers.set_sprite_zero_hit(false);
+                }
+
+                // Memory accesses take 2 cycles: the address is set in the
+                // first one and data is read in the second. We do both in the
+                // second. Every 8 cycles, a tile is fetched:
+                //
+                // - 1-2: nametable byte
+                // - 3-4: attribute table byte
+                // - 5-6: pattern table tile low plane
+                // - 7-8: pattern table tile high plane (and coarse X increment)
+                //
+                // Shifters are reloaded at the start of the next tile fetch:
+                // cycles 9, 17, 25... 257, and 329 and 337 for the first two
+                // tiles of the next scanline.
+                //
+                // See the timing diagram for further reference:
+                // https://www.nesdev.org/wiki/PPU_rendering#Frame_timing_diagram
+                if matches!(self.cycle, 2..=257 | 322..=337) && self.bg_rendering_enabled() {
+                    self.pixel_producer.update_shifters();
                 }
 
                 match self.cycle {
@@ -167,24 +439,19 @@ impl Ppu {
                     }
 
                     1..=256 | 321..=336 => {
-                        // Fetching data takes 2 cycles per access, we ignore
-                        // the first and do it in the second. To render a tile
-                        // we need 4 accesses, then we need 8 clocks
-
-                        if self.bg_rendering_enabled() {
-                            self.pixel_producer.update_shifters();
-                        }
-
                         match (self.cycle - 1) % 8 {
+                            0 if self.cycle >= 9 => {
+                                self.pixel_producer.load_shifters();
+                            }
+
                             // Fetch nametable byte
-                            0 => {}
                             1 => {
                                 self.pixel_producer.buffers.next_tile_number =
                                     self.nametable_fetch();
                             }
 
                             // fetch attribute table byte
-                            2 => {
+                            3 => {
                                 let mut next_attributes = self.attributes_fetch();
                                 if self
                                     .internal
@@ -210,27 +477,29 @@ impl Ppu {
 
                                 self.pixel_producer.buffers.next_attributes = next_attributes;
                             }
-                            3 => {}
 
                             // fetch pattern table tile low
-                            4 => {}
-                            5 => {}
+                            5 => {
+                                self.pixel_producer.buffers.next_bit_plane_low = self
+                                    .fetch_pattern_plane(
+                                        self.pixel_producer.buffers.next_tile_number,
+                                        0,
+                                    );
+                            }
 
                             // fetch pattern table tile high
-                            6 => {
-                                let (high_plane, low_plane) = self.fetch_pattern_planes(
-                                    self.pixel_producer.buffers.next_tile_number,
-                                );
-                                self.pixel_producer.buffers.next_bit_plane_high = high_plane;
-                                self.pixel_producer.buffers.next_bit_plane_low = low_plane;
-                            }
                             7 => {
-                                self.pixel_producer.load_shifters();
+                                self.pixel_producer.buffers.next_bit_plane_high = self
+                                    .fetch_pattern_plane(
+                                        self.pixel_producer.buffers.next_tile_number,
+                                        1,
+                                    );
                                 if self.rendering_enabled() {
                                     self.internal.borrow_mut().vram_addr.increment_x();
                                 }
                             }
-                            _ => unreachable!("We are matching exhausively all possible values"),
+
+                            _ => {}
                         }
 
                         if self.cycle == 256 && self.bg_rendering_enabled() {
@@ -238,17 +507,55 @@ impl Ppu {
                         }
                     }
 
-                    257 => {
-                        self.pixel_producer.load_shifters();
-                        if self.bg_rendering_enabled() {
-                            self.internal.borrow_mut().transfer_x();
+                    257..=320 => {
+                        if self.cycle == 257 {
+                            self.pixel_producer.load_shifters();
+                            if self.bg_rendering_enabled() {
+                                self.internal.borrow_mut().transfer_x();
+                            }
+                            if self.rendering_enabled() {
+                                self.evaluate_sprites();
+                            } else {
+                                self.pixel_producer.clear_sprites();
+                            }
                         }
-                    }
 
-                    280..=304 if self.scan_line == 261 => {
-                        if self.bg_rendering_enabled() {
+                        if (280..=304).contains(&self.cycle)
+                            && self.scan_line == 261
+                            && self.bg_rendering_enabled()
+                        {
                             self.internal.borrow_mut().transfer_y();
+                            if self.cycle == 304 {
+                                // v has the scroll of the first scanline now,
+                                // until the next tiles are prefetched
+                                let internal = self.internal.borrow();
+                                self.next_scroll = Some(ScrollRect::from_registers(
+                                    internal.vram_addr.value(),
+                                    internal.fine_x_scroll,
+                                ));
+                            }
                         }
+
+                        // Sprite tile fetches reuse the background fetch
+                        // schedule, but both the nametable and attribute
+                        // bytes are garbage nametable fetches. Then the 8
+                        // sprites of secondary OAM get their patterns fetched,
+                        // even empty slots. MMC3 relies on these A12 toggles
+                        if self.rendering_enabled() {
+                            let slot = (self.cycle - 257) as usize / 8;
+                            match (self.cycle - 1) % 8 {
+                                1 | 3 => {
+                                    self.nametable_fetch();
+                                }
+                                5 => self.fetch_sprite_pattern_plane(slot, 0),
+                                7 => self.fetch_sprite_pattern_plane(slot, 1),
+                                _ => {}
+                            }
+                        }
+                    }
+
+                    337 => {
+                        self.pixel_producer.load_shifters();
                     }
 
                     338 | 340 => {
@@ -256,10 +563,7 @@ impl Ppu {
                         self.pixel_producer.buffers.next_tile_number = self.nametable_fetch();
                     }
 
-                    _ => {
-                        // There are some unimplemented cycles with garbage NT,
-                        // ignore them
-                    }
+                    _ => {}
                 }
             }
 
@@ -270,7 +574,7 @@ impl Ppu {
             241 if self.cycle == 1 => {
                 self.registers.set_vertical_blank();
                 if self.registers.nmi_enabled() {
-                    self.event_bus.access().emit(Event::NMI)
+                    self.nmi_line.assert();
                 }
             }
 
@@ -282,23 +586,46 @@ impl Ppu {
             _ => panic!("Internal PPU error. Scanline is {}!", self.scan_line),
         }
 
-        if self.bg_rendering_enabled() {
-            self.render_pixel();
-        }
+        self.render_pixel();
 
         self.cycle += 1;
         if self.cycle > 340 {
             self.cycle = 0;
-            self.render_scanline_sprites();
+            if (self.scan_line as usize) < SCREEN_HEIGHT {
+                self.compose_scanline();
+                self.report_scanlines();
+            }
             self.scan_line += 1;
 
             if self.scan_line > 261 {
                 self.scan_line = 0;
-                self.event_bus.access().emit(Event::FrameReady);
+                self.start_frame_scroll();
+                self.frame.info = self.frame_info();
+                self.frame_index += 1;
+                self.event_bus.emit(Event::FrameReady);
             }
         }
     }
 
+    /// Keep the scroll of the frame just completed and take the one of the
+    /// frame starting. With background rendering disabled during the
+    /// pre-render scanline, `t` and fine X are the best guess
+    fn start_frame_scroll(&mut self) {
+        let scroll = self.next_scroll.take().unwrap_or_else(|| {
+            let internal = self.internal.borrow();
+            ScrollRect::from_registers(internal.temp_vram_addr.value(), internal.fine_x_scroll)
+        });
+        self.last_scroll = std::mem::replace(&mut self.scroll, scroll);
+    }
+
+    /// Visible area of the nametables in the last complete frame, i.e., the
+    /// viewport debug UIs draw over the nametables. It's the scroll of its
+    /// first scanline: games changing it mid-frame (status bars...) show other
+    /// areas below
+    pub fn scroll_rect(&self) -> ScrollRect {
+        self.last_scroll
+    }
+
     /// Fetch next tile ID to render using internal state: loopy v register and
     /// PPU configuration.
     ///
@@ -316,7 +643,7 @@ impl Ppu {
         // bits from v. High bits of v are used for fine Y during rendering, so
         // we aren't interested in them during nametable fetch
         let tile_number_address = 0x2000 | (self.internal.borrow().vram_addr.value() & 0x0FFF);
-        self.bus.borrow().read(tile_number_address)
+        self.render_fetch(tile_number_address)
     }
 
     /// Fetch the attributes data corresponding to the next tile to render
@@ -335,11 +662,12 @@ impl Ppu {
             let v = self.internal.borrow().vram_addr.value();
             0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07)
         };
-        self.bus.borrow().read(attributes_address)
+        self.render_fetch(attributes_address)
     }
 
-    /// Fetch background pattern planes corresponding to the next tile to render
-    fn fetch_pattern_planes(&self, tile_number: u8) -> (u8, u8) {
+    /// Fetch a background pattern `plane` (0 low, 1 high) of the next tile to
+    /// render
+    fn fetch_pattern_plane(&self, tile_number: u8, plane: u8) -> u8 {
         let pattern_table = self.registers.background_pattern_table();
         let fine_y = self
             .internal
@@ -350,22 +678,147 @@ impl Ppu {
         let mut pattern_table_address = PatternTableAddress::new(pattern_table);
         pattern_table_address.set(PatternTableAddress::TILE_NUMBER, tile_number);
         pattern_table_address.set(PatternTableAddress::FINE_Y_OFFSET, fine_y);
+        pattern_table_address.set(PatternTableAddress::BIT_PLANE, plane);
 
-        pattern_table_address.set(PatternTableAddress::BIT_PLANE, 0);
-        let low = self.bus.borrow().read(pattern_table_address.into());
+        self.render_fetch(pattern_table_address.into())
+    }
 
-        pattern_table_address.set(PatternTableAddress::BIT_PLANE, 1);
-        let high = self.bus.borrow().read(pattern_table_address.into());
+    /// Fetch a pattern `plane` (0 low, 1 high) of the sprite in secondary OAM
+    /// `slot` for the next scanline. Empty slots fetch tile $FF, whose data is
+    /// discarded
+    fn fetch_sprite_pattern_plane(&mut self, slot: usize, plane: u8) {
+        let address = self.pixel_producer.sprite_pattern_address(
+            slot,
+            plane,
+            self.registers.sprite_size(),
+            self.scan_line + 1,
+        );
+        let data = self.render_fetch(address);
+        self.pixel_producer.set_sprite_pattern(slot, plane, data);
+    }
+
+    /// Read `address` from PPU memory for rendering. The address is driven
+    /// on the PPU address bus only while rendering is enabled, as the PPU
+    /// doesn't fetch anything otherwise
+    fn render_fetch(&self, address: u16) -> u8 {
+        if self.rendering_enabled() {
+            self.drive_address(address);
+        }
+        self.bus.borrow().read(address)
+    }
+
+    /// Put `address` on the PPU address bus, recording rises of the A12 line.
+    /// Mappers like MMC3 count them to know which scanline is being rendered
+    fn drive_address(&self, address: u16) {
+        let high = address & 0x1000 != 0;
+        match (self.a12_high.get(), high) {
+            (false, true) => {
+                let low_dots = self.dots.saturating_sub(self.a12_low_since.get());
+                self.a12_rises
+                    .borrow_mut()
+                    .push(low_dots.min(u32::MAX as u64) as u32);
+            }
+            (true, false) => self.a12_low_since.set(self.dots),
+            _ => {}
+        }
+        self.a12_high.set(high);
+    }
 
-        (high, low)
+    /// Rises of the PPU A12 address line since the last call, as the number
+    /// of dots the line was low before each one. Mappers filter out the short
+    /// ones, e.g., MMC3 ignores rises after less than ~3 CPU cycles low, so
+    /// it sees a single rise per scanline while sprites are fetched
+    pub fn take_a12_rises(&mut self) -> std::vec::Drain<'_, u32> {
+        self.a12_rises.get_mut().drain(..)
     }
 
     fn render_pixel(&mut self) {
-        let col = self.cycle as usize;
+        // Pixel output is delayed a cycle, so pixel 0 is output at cycle 1
+        let Some(col) = (self.cycle as usize).checked_sub(1) else {
+            return;
+        };
+        let row = self.scan_line as usize;
+
+        if !self.rendering_enabled() {
+            self.output_direct_color(col, row);
+            return;
+        }
+
+        let palette_offset = self.pixel_producer.produce_pixel(
+            col,
+            row,
+            self.bg_rendering_enabled(),
+            self.registers.sprite_rendering_enabled(),
+        );
+        if let Some(palette_offset) = palette_offset {
+            self.scanline_palette_offsets[col] = Some(palette_offset);
+        }
+
+        if self.pixel_producer.sprite_zero_hit {
+            self.pixel_producer.sprite_zero_hit = false;
+            if self.registers.sprite_rendering_enabled() {
+                self.registers.set_sprite_zero_hit(true);
+            }
+        }
+    }
+
+    /// With rendering disabled, the PPU outputs the backdrop color ($3F00), or
+    /// the palette entry `v` points to if it's in palette space. Some demos use
+    /// it to draw colors without rendering (background palette hack).
+    ///
+    /// Palettes can be written meanwhile, so the color is resolved right away
+    ///
+    /// See more information: https://www.nesdev.org/wiki/PPU_palettes#The_background_palette_hack
+    fn output_direct_color(&mut self, col: usize, row: usize) {
+        if col >= SCREEN_WIDTH || row >= SCREEN_HEIGHT {
+            return;
+        }
+
+        let vram_address = self.internal.borrow().vram_addr.value() & 0x3FFF;
+        let address = if vram_address >= PALETTE_MEMORY_START {
+            vram_address
+        } else {
+            PALETTE_MEMORY_START
+        };
+        let color = self.bus.borrow().read(address) & 0x3F;
+        self.scanline_palette_offsets[col] = None;
+        self.frame.set_color_index(color, FramePixel { col, row });
+    }
+
+    /// Resolve color indices for all pixels produced in the current scanline
+    /// and draw them in the frame.
+    ///
+    /// Doing it once per scanline avoids a palette memory access per dot.
+    /// Palette memory can't be written while rendering, so reading it at the
+    /// end of the scanline gives the same result
+    fn compose_scanline(&mut self) {
+        let mut palettes = [0; PALETTE_MEMORY_SIZE as usize];
+        {
+            let bus = self.bus.borrow();
+            for (offset, color) in palettes.iter_mut().enumerate() {
+                *color = bus.read(PALETTE_MEMORY_START + offset as u16);
+            }
+        }
+
         let row = self.scan_line as usize;
-        let pixel = self.pixel_producer.produce_pixel(col, row);
-        if let Some(pixel) = pixel {
-            self.frame.set_pixel(pixel, FramePixel { col, row });
+        for (col, palette_offset) in self.scanline_palette_offsets.iter_mut().enumerate() {
+            if let Some(offset) = palette_offset.take() {
+                let color = palettes[offset as usize] & 0x3F;
+                self.frame.set_color_index(color, FramePixel { col, row });
+            }
+        }
+    }
+
+    /// Emit `ScanlinesReady` once every `beam_racing` rows are rendered, and
+    /// for the last rows of the frame
+    fn report_scanlines(&self) {
+        let Some(scanlines) = self.beam_racing else {
+            return;
+        };
+        let end = self.scan_line as usize + 1;
+        if end.is_multiple_of(scanlines) || end == SCREEN_HEIGHT {
+            let start = (end - 1) / scanlines * scanlines;
+            self.event_bus.emit(Event::ScanlinesReady(start..end));
         }
     }
 
@@ -379,16 +832,83 @@ impl Ppu {
         self.registers.background_rendering_enabled()
     }
 
+    fn frame_info(&self) -> FrameInfo {
+        let system_clocks = self.dots as u128 * PPU_CLOCK_DIVIDER as u128;
+        FrameInfo {
+            index: self.frame_index,
+            emulated_time_ns: (system_clocks * 1_000_000_000 / MASTER_CLOCK_HZ as u128) as u64,
+            odd: self.frame_index % 2 == 1,
+        }
+    }
+
+    pub fn state(&self) -> PpuState {
+        let internal = self.internal.borrow();
+        PpuState {
+            ctrl: self.registers.ctrl.bits(),
+            mask: self.registers.mask.bits(),
+            status: self.registers.status.get().bits(),
+            oam_addr: self.registers.oam_addr,
+            vram_addr: internal.vram_addr.value(),
+            temp_vram_addr: internal.temp_vram_addr.value(),
+            fine_x_scroll: internal.fine_x_scroll,
+            write_toggle: internal.write_toggle == WriteToggle::Second,
+            scan_line: self.scan_line,
+            cycle: self.cycle,
+            frame_index: self.frame_index,
+        }
+    }
+
     /// Get the current frame being rendered by the PPU. Once the PPU signals
     /// `FrameReady` event through the event bus, this Frame is complete.
-    pub fn take_frame(&mut self) -> Frame {
-        let frame = self.frame.clone();
-        self.frame = Frame::black();
-        frame
+    ///
+    /// The frame is moved out, not copied, and shared from then on. Color
+    /// indices are converted to RGB here, in a single pass
+    pub fn take_frame(&mut self) -> Arc<Frame> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.colorize(&self.color_lookup);
+        Arc::new(frame)
     }
 
-    pub fn oam_dma_write(&mut self, address: u8, data: u8) {
-        self.oam.write(address as u16, data);
+    pub fn snapshot(&self) -> PpuSnapshot {
+        PpuSnapshot {
+            registers: self.registers.clone(),
+            internal: self.internal.borrow().clone(),
+            oam: self.oam.clone(),
+            cycle: self.cycle,
+            scan_line: self.scan_line,
+            frame_index: self.frame_index,
+            dots: self.dots,
+            pixel_producer: self.pixel_producer.clone(),
+            scanline_palette_offsets: self.scanline_palette_offsets,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &PpuSnapshot) {
+        self.registers = snapshot.registers.clone();
+        *self.internal.borrow_mut() = snapshot.internal.clone();
+        self.oam = snapshot.oam.clone();
+        self.cycle = snapshot.cycle;
+        self.scan_line = snapshot.scan_line;
+        self.frame_index = snapshot.frame_index;
+        self.dots = snapshot.dots;
+        self.pixel_producer.restore(&snapshot.pixel_producer);
+        self.scanline_palette_offsets = snapshot.scanline_palette_offsets;
+        self.a12_rises.get_mut().clear();
+    }
+
+    /// Write a byte transferred by OAM DMA. DMA writes through OAMDATA, so
+    /// the byte goes to OAMADDR, which is incremented and wraps around. A
+    /// transfer started with OAMADDR other than 0 ends up rotated in OAM.
+    /// Returns the OAM address written
+    pub fn oam_dma_write(&mut self, data: u8) -> u8 {
+        let address = self.registers.oam_addr;
+        self.write_oam_data(data);
+        address
+    }
+
+    fn write_oam_data(&mut self, data: u8) {
+        self.oam.write(self.registers.oam_addr as u16, data);
+        self.registers.oam_addr = self.registers.oam_addr.wrapping_add(1);
     }
 
     pub fn dump_oam(&self, path: &str) -> std::io::Result<()> {
@@ -397,15 +917,110 @@ impl Ppu {
         Ok(())
     }
 
-    // Sprite rendering per scanline. This is still non functional, as sprites
-    // are completely over background and not in the correct scanline (sprites
-    // should be one scanline below). But it's a better approach
-    fn render_scanline_sprites(&mut self) {
-        if self.scan_line >= 240 {
-            // only render in visible scanlines
-            return;
+    /// Build debug views of the graphics memory, drawing pattern tables with
+    /// `pattern_table_palette` (0 to 7, higher values wrap around). Graphics
+    /// bus reads have no side effects, so this doesn't alter emulation
+    pub fn debug_view(&self, pattern_table_palette: u8) -> GraphicsDebugView {
+        let pattern_table_palette = pattern_table_palette & 0x07;
+        let bus = self.bus.borrow();
+        let mut palettes = [Pixel::BLACK; 32];
+        for (offset, color) in palettes.iter_mut().enumerate() {
+            let index = bus.read(PALETTE_MEMORY_START + offset as u16) & 0x3F;
+            *color = self.color_lookup[index as usize];
+        }
+        // Draw a row of 8 pixels of a tile
+        let draw_tile_row = |image: &mut DebugImage,
+                             table: u16,
+                             tile: u8,
+                             row: u8,
+                             palette: u8,
+                             (x, y): (usize, usize)| {
+            let address = table * 0x1000 + tile as u16 * 16 + row as u16;
+            let (low, high) = (bus.read(address), bus.read(address + 8));
+            for column in 0..8 {
+                let bit = 7 - column as u8;
+                let pattern = utils::bv(high, bit) << 1 | utils::bv(low, bit);
+                image.set_pixel(x + column, y, palettes[(palette * 4 + pattern) as usize]);
+            }
+        };
+
+        let pattern_tables = [0, 1].map(|table| {
+            let mut image = DebugImage::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+            for tile in 0..=255u8 {
+                for row in 0..8 {
+                    let position = (
+                        tile as usize % 16 * 8,
+                        tile as usize / 16 * 8 + row as usize,
+                    );
+                    draw_tile_row(
+                        &mut image,
+                        table,
+                        tile,
+                        row,
+                        pattern_table_palette,
+                        position,
+                    );
+                }
+            }
+            image
+        });
+
+        let background_table = self
+            .registers
+            .ctrl
+            .contains(PpuCtrl::BACKGROUND_PATTERN_TABLE) as u16;
+        let mut nametables = DebugImage::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+        for nametable in 0..4 {
+            let base = 0x2000 + nametable as u16 * 0x0400;
+            for coarse_y in 0..30 {
+                for coarse_x in 0..32 {
+                    let tile = bus.read(base + coarse_y * 32 + coarse_x);
+                    let attribute = bus.read(base + 0x03C0 + coarse_y / 4 * 8 + coarse_x / 4);
+                    let shift = (coarse_y % 4 / 2) * 4 + (coarse_x % 4 / 2) * 2;
+                    let palette = (attribute >> shift) & 0b11;
+                    for row in 0..8 {
+                        let position = (
+                            nametable % 2 * SCREEN_WIDTH + coarse_x as usize * 8,
+                            nametable / 2 * SCREEN_HEIGHT + coarse_y as usize * 8 + row as usize,
+                        );
+                        draw_tile_row(
+                            &mut nametables,
+                            background_table,
+                            tile,
+                            row,
+                            palette,
+                            position,
+                        );
+                    }
+                }
+            }
+        }
+
+        GraphicsDebugView {
+            frame_index: self.frame_index,
+            pattern_tables,
+            pattern_table_palette,
+            nametables,
+            scroll: self.last_scroll,
+            palettes,
+            sprites: (0..64)
+                .map(|index| {
+                    let sprite = self.oam.read_sprite(index);
+                    SpriteInfo::new(index, sprite.x, sprite.y, sprite.tile, sprite.attributes)
+                })
+                .collect(),
+            tall_sprites: self.registers.ctrl.contains(PpuCtrl::SPRITE_SIZE),
         }
+    }
 
+    /// Sprite evaluation and fetch for the next scanline. The PPU evaluates
+    /// sprites in cycles 65-256 of scanline N and fetches their patterns in
+    /// cycles 257-320, so they're drawn in scanline N+1.
+    ///
+    /// Sprite Y coordinates in OAM are one less than the scanline where their
+    /// first row is drawn. No sprites are evaluated in the pre-render scanline,
+    /// so they can't be drawn in the first visible one
+    fn evaluate_sprites(&mut self) {
         // Cycles 1-64: secondary OAM initialization, all to 0xFF as if Y
         // coordinate is out of screen, we won't paint the sprite
         let mut secondary_oam = [OamSprite {
@@ -415,33 +1030,44 @@ impl Ppu {
             attributes: 0xFF,
         }; 8];
 
-        // Cycles 65-256: read 8 sprites from OAM and write them into secondary
-        // OAM if they are in screen
-        let mut sprites_in_screen = 0;
-        let mut sprite_overflow = false;
-        for s in 0..64 {
-            let sprite = self.oam.read_sprite(s);
-
-            let sprite_y = sprite.y as u16;
-            let in_screen = (self.scan_line >= sprite_y) && (self.scan_line < (sprite_y + 8));
-            if !in_screen {
-                // sprite hidden out of screen, skip
-                continue;
+        let next_scan_line = self.scan_line + 1;
+        let sprite_height = self.registers.sprite_size() as u16;
+        let mut sprite_zero = false;
+
+        if self.scan_line != 261 {
+            // Cycles 65-256: read 8 sprites from OAM and write them into
+            // secondary OAM if they're in the next scanline
+            let mut sprites_found = 0;
+            let mut sprite_overflow = false;
+            for s in 0..64 {
+                let sprite = self.oam.read_sprite(s);
+
+                let top = sprite.y as u16 + 1;
+                if next_scan_line < top || next_scan_line >= top + sprite_height {
+                    continue;
+                }
+
+                if sprites_found < 8 {
+                    sprite_zero |= s == 0;
+                    secondary_oam[sprites_found] = sprite;
+                    sprites_found += 1
+                } else {
+                    sprite_overflow = true;
+                    break;
+                }
             }
 
-            if sprites_in_screen < 8 {
-                secondary_oam[sprites_in_screen] = sprite;
-                sprites_in_screen += 1
-            } else {
-                sprite_overflow = true;
-                break;
+            if sprite_overflow {
+                self.registers.set_sprite_overflow(true);
             }
         }
 
-        self.registers.set_sprite_overflow(sprite_overflow);
-
-        self.pixel_producer.sprites = secondary_oam;
-        self.pixel_producer.sprite_pattern_table = self.registers.sprite_pattern_table();
+        // Cycles 257-320 fetch their patterns, see `clock`
+        self.pixel_producer.load_sprites(
+            secondary_oam,
+            sprite_zero,
+            self.registers.sprite_pattern_table(),
+        );
     }
 
     // TODO: move to example?
@@ -522,6 +1148,30 @@ impl Ppu {
     }
 }
 
+impl Ppu {
+    /// Auto-increment the VRAM address after a PPUDATA access, horizontally or
+    /// vertically. While rendering, the PPU is using the address to fetch
+    /// tiles, so the access glitches and increments both coarse X and Y
+    /// instead
+    fn increment_vram_addr(&self, internal: &mut PpuInternalRegisters) {
+        let rendering_scan_line = self.scan_line < SCREEN_HEIGHT as u16 || self.scan_line == 261;
+        if self.rendering_enabled() && rendering_scan_line {
+            internal.vram_addr.increment_x();
+            internal.vram_addr.increment_y();
+        } else {
+            let increment = self.registers.vram_address_increment();
+            internal.vram_addr = RenderAddress::from(internal.vram_addr.value() + increment);
+        }
+    }
+
+    /// Whether the NMI for this VBL has been raised so recently the CPU
+    /// hasn't detected it yet. VBL starts at dot 1 of scanline 241 and the CPU
+    /// samples the NMI line once per cycle (3 dots)
+    fn nmi_just_raised(&self) -> bool {
+        self.scan_line == 241 && self.cycle <= 1 + NMI_DETECTION_DOTS
+    }
+}
+
 impl Memory for Ppu {
     fn read(&self, address: u16) -> u8 {
         // PPU registers are mirrored every 8 bytes
@@ -532,21 +1182,23 @@ impl Memory for Ppu {
                 let mut internal = self.internal.borrow_mut();
                 internal.write_toggle = WriteToggle::First;
 
-                // The 5 lower bits reflect the PPU bus contents. Although
+                // The 5 lower bits reflect the I/O latch contents. Although
                 // emulated, no games should relay on this behaviour
                 let ppustatus = (self.registers.status.get().bits() & 0xE0)
-                    | (self.registers.data_buffer.get() & 0x1F);
+                    | (self.registers.io_latch.read(self.dots) & 0x1F);
+                self.registers.io_latch.refresh(ppustatus, 0xE0, self.dots);
 
                 // Reading PPU status clears VBL flag and the address latch
                 self.registers.unset_vertical_blank();
-                self.registers.data_buffer.set(0);
 
-                ppustatus | 0b1000_0000
+                ppustatus
             }
 
             OAMDATA => {
                 let oam_addr = self.registers.oam_addr as u16;
-                self.oam.read(oam_addr)
+                let data = self.oam.read(oam_addr);
+                self.registers.io_latch.refresh(data, 0xFF, self.dots);
+                data
             }
 
             PPUDATA => {
@@ -557,23 +1209,36 @@ impl Memory for Ppu {
 
                 // Update buffer for next read
                 let vram_address = internal.vram_addr.value();
-                let vram_data = self.bus.borrow().read(vram_address);
-                self.registers.data_buffer.set(vram_data);
+                self.drive_address(vram_address);
+                let bus_address = resolve_graphics_address(vram_address);
+                let vram_data = self.bus.borrow().read(bus_address);
+
+                if bus_address >= PALETTE_MEMORY_START {
+                    // The buffer is filled with the nametable byte "under" the
+                    // palette ($2F00-$2FFF) instead
+                    let underneath = resolve_graphics_address(bus_address - 0x1000);
+                    self.registers
+                        .data_buffer
+                        .set(self.bus.borrow().read(underneath));
 
-                if vram_address >= 0x3F00 {
                     // some addresses used combinatory logic to avoid one clock
                     // delay between reading and having data available (palettes
-                    // for example)
-                    data = vram_data;
+                    // for example). Palette entries are 6-bit, the 2 high bits
+                    // come from the I/O latch
+                    data = (self.registers.io_latch.read(self.dots) & 0xC0) | (vram_data & 0x3F);
+                    self.registers.io_latch.refresh(data, 0x3F, self.dots);
+                } else {
+                    self.registers.data_buffer.set(vram_data);
+                    self.registers.io_latch.refresh(data, 0xFF, self.dots);
                 }
 
-                // Auto-increment vram address horizontally or vertically
-                let increment = self.registers.vram_address_increment();
-                internal.vram_addr = RenderAddress::from(vram_address + increment);
+                self.increment_vram_addr(&mut internal);
 
                 data
             }
-            _ => unimplemented!("PPU read not implemented for address: {address:0>4X}"),
+
+            // Write-only registers return the I/O latch contents
+            _ => self.registers.io_latch.read(self.dots),
         };
         trace!("PPU read from: {address:0>4X} <- {data:0>2X}");
         data
@@ -582,7 +1247,20 @@ impl Memory for Ppu {
     fn write(&mut self, address: u16, data: u8) {
         trace!("PPU write to: {address:0>4X} -> {data:0>2X}");
 
+        // Writes to any register fill the I/O latch
+        self.registers.io_latch.refresh(data, 0xFF, self.dots);
+
         let address = address + 0x2000;
+        if self.warming_up() && matches!(address, PPUCTRL | PPUMASK | PPUSCROLL | PPUADDR) {
+            debug!("PPU write to {address:0>4X} ignored during warm-up");
+            self.warm_up_writes.push(WarmUpWrite {
+                register: address,
+                data,
+                pc: None,
+            });
+            return;
+        }
+
         match address {
             PPUCTRL => {
                 let mut internal = self.internal.borrow_mut();
@@ -591,7 +1269,20 @@ impl Memory for Ppu {
                     .set(RenderAddress::NAMETABLES_SELECT, data & 0b00000011);
 
                 // Registers
+                let nmi_was_enabled = self.registers.nmi_enabled();
                 self.registers.ctrl = PpuCtrl::from_bits_truncate(data);
+
+                // NMI output is the AND of VBL flag and NMI enable, the CPU
+                // reacts to its rising edge. Enabling NMI during VBL triggers
+                // an NMI right away, while disabling it right after VBL starts
+                // suppresses the NMI just raised
+                if self.registers.vertical_blank() {
+                    match (nmi_was_enabled, self.registers.nmi_enabled()) {
+                        (false, true) => self.nmi_line.assert(),
+                        (true, false) if self.nmi_just_raised() => self.nmi_line.release(),
+                        _ => {}
+                    }
+                }
             }
             PPUMASK => {
                 // Registers
@@ -603,8 +1294,7 @@ impl Memory for Ppu {
             }
 
             OAMDATA => {
-                let oam_addr = self.registers.oam_addr as u16;
-                self.oam.write(oam_addr, data);
+                self.write_oam_data(data);
             }
 
             PPUSCROLL => {
@@ -615,6 +1305,7 @@ impl Memory for Ppu {
                             .temp_vram_addr
                             .set(RenderAddress::COARSE_X_SCROLL, data >> 3);
                         internal.fine_x_scroll = data & 0b0000_0111;
+                        self.pixel_producer.fine_x = internal.fine_x_scroll;
                         internal.write_toggle = WriteToggle::Second;
                     }
                     WriteToggle::Second => {
@@ -650,6 +1341,8 @@ impl Memory for Ppu {
                         );
                         internal.vram_addr = internal.temp_vram_addr;
                         internal.write_toggle = WriteToggle::First;
+                        // v is put on the address bus outside rendering
+                        self.drive_address(internal.vram_addr.value());
                     }
                 }
             }
@@ -658,11 +1351,12 @@ impl Memory for Ppu {
                 let mut internal = self.internal.borrow_mut();
 
                 let vram_address = internal.vram_addr.value();
-                self.bus.borrow_mut().write(vram_address, data);
+                self.drive_address(vram_address);
+                self.bus
+                    .borrow_mut()
+                    .write(resolve_graphics_address(vram_address), data);
 
-                // Auto-increment vram address horizontally or vertically
-                let increment = self.registers.vram_address_increment();
-                internal.vram_addr = RenderAddress::from(vram_address + increment);
+                self.increment_vram_addr(&mut internal);
             }
 
             _ => unimplemented!("PPU write not implemented for address: {address:0>4X}"),
@@ -732,8 +1426,14 @@ impl PpuInternalRegisters {
 mod tests {
     use std::rc::Rc;
 
+    use crate::graphics::palette_memory::PaletteMemory;
+    use crate::graphics::ppu_registers::IO_LATCH_DECAY_DOTS;
+    use crate::hardware::PALETTE_MIRRORS;
     use crate::hardware::PPU_REGISTERS_START;
+    use crate::interfaces::{AddressRange, Bus as _};
     use crate::processor::bus::Bus;
+    use crate::processor::memory::{Ciram, MirroredMemory, Mirroring, Ram};
+    use crate::types::SharedMemory;
 
     use super::*;
 
@@ -743,6 +1443,152 @@ mod tests {
         Ppu::new(graphics_bus, event_bus)
     }
 
+    #[test]
+    fn test_sprite_evaluation_8x16() {
+        let mut ppu = test_ppu();
+
+        // Pattern tables with the row number as low plane
+        let chr = Rc::new(RefCell::new(Ram::new(0x2000)));
+        for address in 0..0x2000 {
+            chr.borrow_mut().write(address, (address % 16) as u8);
+        }
+        ppu.bus
+            .borrow_mut()
+            .attach(
+                "CHR",
+                chr,
+                AddressRange {
+                    start: 0,
+                    end: 0x1FFF,
+                },
+            )
+            .unwrap();
+
+        // 8x16 sprite with tiles $02-$03 of pattern table 1 drawn from
+        // scanline 10
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b0010_0000);
+        ppu.write(PPUMASK - PPU_REGISTERS_START, 0b0001_0000);
+        for data in [9, 0x03, 0, 20] {
+            ppu.oam_dma_write(data);
+        }
+
+        let evaluate = |ppu: &mut Ppu, scan_line| {
+            ppu.scan_line = scan_line;
+            ppu.evaluate_sprites();
+            ppu.fetch_sprite_pattern_plane(0, 0);
+            (
+                ppu.pixel_producer.sprite_zero_loaded,
+                ppu.pixel_producer.sprite_patterns[0].0,
+            )
+        };
+
+        assert!(!evaluate(&mut ppu, 8).0);
+        assert_eq!(evaluate(&mut ppu, 9), (true, 0));
+        assert_eq!(evaluate(&mut ppu, 16), (true, 7));
+        assert_eq!(evaluate(&mut ppu, 17), (true, 0));
+        assert_eq!(evaluate(&mut ppu, 24), (true, 7));
+        assert!(!evaluate(&mut ppu, 25).0);
+
+        // Vertical flip swaps both halves
+        ppu.write(OAMADDR - PPU_REGISTERS_START, 2);
+        ppu.oam_dma_write(0b1000_0000);
+        assert_eq!(evaluate(&mut ppu, 9), (true, 7));
+        assert_eq!(evaluate(&mut ppu, 24), (true, 0));
+    }
+
+    #[test]
+    fn test_dots_until_event() {
+        let mut ppu = test_ppu_with_memory();
+        // Position after the event dot, i.e., VBL set or a new frame
+        let event = |ppu: &Ppu, frame_index| {
+            ppu.registers.vertical_blank() || ppu.frame_index != frame_index
+        };
+
+        for (scan_line, cycle) in [(0, 0), (100, 50), (240, 340), (241, 0), (241, 1), (250, 3)] {
+            ppu.seek(scan_line, cycle);
+            ppu.registers.unset_vertical_blank();
+            let frame_index = ppu.frame_index;
+            for _ in 1..ppu.dots_until_event() {
+                ppu.clock();
+            }
+            assert!(
+                !event(&ppu, frame_index),
+                "early event from {scan_line},{cycle}"
+            );
+            ppu.clock();
+            assert!(
+                event(&ppu, frame_index),
+                "no event from {scan_line},{cycle}"
+            );
+        }
+
+        // Beam racing reports every scanline
+        ppu.set_beam_racing(Some(8));
+        ppu.seek(100, 50);
+        assert_eq!(ppu.dots_until_event(), 291);
+    }
+
+    #[test]
+    fn test_nmi_enable_during_vertical_blank() {
+        let mut ppu = test_ppu();
+        let nmi = InterruptLine::new();
+        ppu.connect_nmi_line(nmi.clone());
+
+        // Enabling NMI outside VBL doesn't trigger it
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b1000_0000);
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0);
+        assert!(!nmi.take());
+
+        // Late NMI enable during VBL, and again after toggling it
+        ppu.scan_line = 250;
+        ppu.registers.set_vertical_blank();
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b1000_0000);
+        assert!(nmi.take());
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b1000_0000);
+        assert!(!nmi.take());
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0);
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b1000_0000);
+        assert!(nmi.take());
+        assert!(!nmi.take());
+
+        // Disabling NMI right after VBL starts suppresses it
+        ppu.registers.unset_vertical_blank();
+        ppu.scan_line = 241;
+        ppu.cycle = 1;
+        ppu.clock();
+        assert!(nmi.is_asserted());
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0);
+        assert!(!nmi.is_asserted());
+
+        // but not once the CPU has seen it
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b1000_0000);
+        assert!(nmi.is_asserted());
+        ppu.cycle = 10;
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0);
+        assert!(nmi.is_asserted());
+    }
+
+    #[test]
+    fn test_io_latch_open_bus() {
+        let mut ppu = test_ppu();
+
+        // Write-only registers return the last value written to any register
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 0b1011_0101);
+        assert_eq!(ppu.read(PPUMASK - PPU_REGISTERS_START), 0b1011_0101);
+        assert_eq!(ppu.read(PPUADDR - PPU_REGISTERS_START), 0b1011_0101);
+
+        // PPUSTATUS low bits come from the latch while high bits refresh it
+        ppu.registers.set_vertical_blank();
+        assert_eq!(ppu.read(PPUSTATUS - PPU_REGISTERS_START), 0b1001_0101);
+        assert_eq!(ppu.read(OAMADDR - PPU_REGISTERS_START), 0b1001_0101);
+
+        // Bits decay if they aren't refreshed
+        ppu.dots += IO_LATCH_DECAY_DOTS - 1;
+        ppu.read(PPUSTATUS - PPU_REGISTERS_START);
+        ppu.dots += 1;
+        assert_eq!(ppu.read(PPUCTRL - PPU_REGISTERS_START), 0);
+    }
+
     #[test]
     fn test_loopy_scrolling_registers_read_and_write() {
         // Test inspired by example in:
@@ -966,4 +1812,313 @@ mod tests {
     fn test_ppudata_reads_and_writes_TEST_NOT_IMPLEMENTED() {
         // TODO
     }
+
+    /// PPU with CHR RAM, vertically mirrored nametables and palettes
+    fn test_ppu_with_memory() -> Ppu {
+        let ppu = test_ppu();
+        let chr = Rc::new(RefCell::new(Ram::new(0x2000)));
+        let nametables = Rc::new(RefCell::new(Ciram::new(0x0400)));
+        nametables.borrow_mut().set_mirroring(Mirroring::Vertical);
+        let palettes = Rc::new(RefCell::new(MirroredMemory::new(
+            PaletteMemory::new(),
+            PALETTE_MIRRORS.into(),
+        )));
+        let devices: [(&'static str, SharedMemory, u16, u16); 3] = [
+            ("CHR", chr, 0x0000, 0x1FFF),
+            ("Nametables", nametables, 0x2000, 0x2FFF),
+            ("Palettes", palettes, 0x3F00, 0x3FFF),
+        ];
+        for (id, memory, start, end) in devices {
+            ppu.bus
+                .borrow_mut()
+                .attach(id, memory, AddressRange { start, end })
+                .unwrap();
+        }
+        ppu
+    }
+
+    #[test]
+    fn test_ppudata_address_mirroring() {
+        let mut ppu = test_ppu_with_memory();
+
+        let set_address = |ppu: &mut Ppu, address: u16| {
+            ppu.write(PPUADDR - PPU_REGISTERS_START, (address >> 8) as u8);
+            ppu.write(PPUADDR - PPU_REGISTERS_START, address as u8);
+        };
+
+        // Every address reachable through PPUADDR, written and read back from
+        // the device it's mapped to
+        for address in 0..=0x3FFF_u16 {
+            let data = (address ^ address >> 8) as u8 & 0x3F;
+            set_address(&mut ppu, address);
+            ppu.write(PPUDATA - PPU_REGISTERS_START, data);
+
+            let resolved = resolve_graphics_address(address);
+            assert_eq!(ppu.bus.borrow().read(resolved), data);
+
+            // Buffered reads, except for palettes
+            set_address(&mut ppu, address);
+            let mut read = ppu.read(PPUDATA - PPU_REGISTERS_START);
+            if address < PALETTE_MEMORY_START {
+                set_address(&mut ppu, 0x0000);
+                read = ppu.read(PPUDATA - PPU_REGISTERS_START);
+            }
+            assert_eq!(read & 0x3F, data, "PPU address ${address:0>4X}");
+        }
+
+        // Nametable mirrors alias the nametables
+        set_address(&mut ppu, 0x3123);
+        ppu.write(PPUDATA - PPU_REGISTERS_START, 0xAB);
+        assert_eq!(ppu.bus.borrow().read(0x2123), 0xAB);
+
+        // Palette reads fill the buffer with the nametable byte underneath
+        set_address(&mut ppu, 0x3F05);
+        ppu.read(PPUDATA - PPU_REGISTERS_START);
+        set_address(&mut ppu, 0x0000);
+        assert_eq!(
+            ppu.read(PPUDATA - PPU_REGISTERS_START),
+            ppu.bus.borrow().read(0x2F05)
+        );
+
+        // Incrementing past $3FFF wraps to the pattern tables
+        set_address(&mut ppu, 0x3FFF);
+        ppu.write(PPUDATA - PPU_REGISTERS_START, 0x01);
+        ppu.write(PPUDATA - PPU_REGISTERS_START, 0xCD);
+        assert_eq!(ppu.bus.borrow().read(0x0000), 0xCD);
+    }
+
+    #[test]
+    fn test_power_up_warm_up() {
+        let mut ppu = test_ppu_with_memory();
+        ppu.power_up();
+        assert!(!ppu.warming_up());
+        assert_eq!(ppu.state().status, 0);
+
+        let mut ppu = test_ppu_with_memory();
+        ppu.set_accuracy(AccuracyProfile::Accurate);
+        ppu.power_up();
+        assert!(ppu.warming_up());
+        assert_eq!(ppu.state().status, 0b1010_0000);
+
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0x10);
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 0x20);
+        ppu.write(PPUADDR - PPU_REGISTERS_START, 0x21);
+        ppu.write(OAMADDR - PPU_REGISTERS_START, 0x08);
+        let state = ppu.state();
+        assert_eq!(
+            (state.ctrl, state.temp_vram_addr, state.write_toggle),
+            (0, 0, false)
+        );
+        assert_eq!(state.oam_addr, 0x08);
+        let registers: Vec<u16> = ppu
+            .take_warm_up_writes()
+            .iter()
+            .map(|write| write.register)
+            .collect();
+        assert_eq!(registers, [PPUCTRL, PPUSCROLL, PPUADDR]);
+        assert!(ppu.take_warm_up_writes().is_empty());
+
+        while ppu.warming_up() {
+            ppu.clock();
+        }
+        assert_eq!(ppu.state().scan_line, 261);
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0x10);
+        assert_eq!(ppu.state().ctrl, 0x10);
+    }
+
+    #[test]
+    fn test_seek_vertical_blank_start() {
+        let mut ppu = test_ppu_with_memory();
+        ppu.seek(241, 1);
+        assert_eq!(ppu.state().status & 0x80, 0);
+        ppu.clock();
+        assert_eq!(ppu.state().status & 0x80, 0x80);
+
+        // Seeking backwards continues in the next frame
+        ppu.seek(10, 0);
+        let state = ppu.state();
+        assert_eq!(
+            (state.scan_line, state.cycle, state.frame_index),
+            (10, 0, 1)
+        );
+    }
+
+    #[test]
+    fn test_ppudata_increment_while_rendering() {
+        let mut ppu = test_ppu_with_memory();
+        ppu.write(PPUMASK - PPU_REGISTERS_START, 0b0000_1000);
+        ppu.seek(100, 50);
+
+        // Coarse X and Y are incremented instead of adding 1 or 32
+        let mut expected = RenderAddress::from(ppu.state().vram_addr);
+        expected.increment_x();
+        expected.increment_y();
+        ppu.write(PPUDATA - PPU_REGISTERS_START, 0x12);
+        assert_eq!(ppu.state().vram_addr, expected.value());
+
+        // VBL accesses increment as usual
+        ppu.seek(250, 0);
+        let vram_addr = ppu.state().vram_addr;
+        ppu.read(PPUDATA - PPU_REGISTERS_START);
+        assert_eq!(ppu.state().vram_addr, vram_addr + 1);
+    }
+
+    #[test]
+    fn test_a12_rises() {
+        let mut ppu = test_ppu_with_memory();
+
+        // Background at $0000 and sprites at $1000, as most MMC3 games do.
+        // Every sprite slot is fetched, even without sprites in the scanline,
+        // but only the first rise follows a long time with A12 low
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b0000_1000);
+        ppu.write(PPUMASK - PPU_REGISTERS_START, 0b0001_1000);
+        for _ in 0..341 {
+            ppu.clock();
+        }
+        let rises: Vec<u32> = ppu.take_a12_rises().collect();
+        assert_eq!(rises.len(), 8);
+        assert!(rises[0] > 200);
+        assert!(rises[1..].iter().all(|low_dots| *low_dots == 4));
+
+        // The pre-render scanline fetches sprites too
+        ppu.seek(261, 0);
+        ppu.take_a12_rises();
+        for _ in 0..341 {
+            ppu.clock();
+        }
+        assert_eq!(ppu.take_a12_rises().len(), 8);
+
+        // Nothing is fetched with rendering disabled
+        ppu.write(PPUMASK - PPU_REGISTERS_START, 0);
+        for _ in 0..341 {
+            ppu.clock();
+        }
+        assert_eq!(ppu.take_a12_rises().len(), 0);
+    }
+
+    #[test]
+    fn test_direct_color_output() {
+        let mut ppu = test_ppu_with_memory();
+        ppu.bus.borrow_mut().write(0x3F00, 0x0F);
+        ppu.bus.borrow_mut().write(0x3F05, 0x16);
+        let set_address = |ppu: &mut Ppu, address: u16| {
+            ppu.write(PPUADDR - PPU_REGISTERS_START, (address >> 8) as u8);
+            ppu.write(PPUADDR - PPU_REGISTERS_START, address as u8);
+        };
+
+        // Rendering is disabled: backdrop color until v points to a palette
+        set_address(&mut ppu, 0x2000);
+        for _ in 0..128 {
+            ppu.clock();
+        }
+        set_address(&mut ppu, 0x3F05);
+        for _ in 0..341 {
+            ppu.clock();
+        }
+
+        let rgb = |pixel: Pixel| (pixel.red(), pixel.green(), pixel.blue());
+        let frame = ppu.take_frame();
+        assert_eq!(rgb(frame[0][0]), rgb(ppu.color_lookup[0x0F]));
+        assert_eq!(rgb(frame[0][127]), rgb(ppu.color_lookup[0x0F]));
+        assert_eq!(rgb(frame[0][128]), rgb(ppu.color_lookup[0x16]));
+        assert_eq!(rgb(frame[1][0]), rgb(ppu.color_lookup[0x16]));
+
+        let indices = frame.indices().unwrap();
+        assert_eq!(indices.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(indices[127], 0x0F);
+        assert_eq!(indices[128], 0x16);
+        assert_eq!(indices[SCREEN_WIDTH], 0x16);
+
+        let mut frame = (*frame).clone();
+        frame.colorize(&[Pixel::WHITE; 64]);
+        assert_eq!(rgb(frame[0][0]), rgb(Pixel::WHITE));
+    }
+
+    #[test]
+    fn test_scroll_rect() {
+        let mut ppu = test_ppu_with_memory();
+        let run_frame = |ppu: &mut Ppu| {
+            let frame_index = ppu.frame_index;
+            while ppu.frame_index == frame_index {
+                ppu.clock();
+            }
+        };
+
+        // Second nametable, 100 pixels right and 50 down
+        ppu.write(PPUCTRL - PPU_REGISTERS_START, 0b0000_0001);
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 100);
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 50);
+        ppu.write(PPUMASK - PPU_REGISTERS_START, 0b0000_1010);
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+        let expected = ScrollRect {
+            x: 356,
+            y: 50,
+            width: 256,
+            height: 240,
+        };
+        assert_eq!(ppu.scroll_rect(), expected);
+
+        // Changing the scroll mid-frame doesn't move the top of the frame
+        ppu.seek(120, 0);
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 0);
+        ppu.write(PPUSCROLL - PPU_REGISTERS_START, 0);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.scroll_rect(), expected);
+        assert_eq!(ppu.debug_view(0).scroll, expected);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.scroll_rect().x, 256);
+    }
+
+    #[test]
+    fn test_debug_view() {
+        let mut ppu = test_ppu_with_memory();
+        {
+            let bus = ppu.bus.borrow_mut();
+            // Tile 1, first row: leftmost pixel uses color 3
+            bus.write(0x0010, 0b1000_0000);
+            bus.write(0x0018, 0b1000_0000);
+            // Tile 1 at the top left of nametables 0 and 1, the latter with
+            // palette 1
+            bus.write(0x2000, 1);
+            bus.write(0x2400, 1);
+            bus.write(0x27C0, 0b01);
+            bus.write(0x3F03, 0x30);
+            bus.write(0x3F07, 0x16);
+        }
+        for data in [0x20, 0x01, 0b1100_0010, 0x40] {
+            ppu.oam_dma_write(data);
+        }
+
+        let view = ppu.debug_view(0);
+        let rgb = |pixel: Pixel| (pixel.red(), pixel.green(), pixel.blue());
+        let white = rgb(ppu.color_lookup[0x30]);
+        let red = rgb(ppu.color_lookup[0x16]);
+        assert_eq!(rgb(view.palettes[3]), white);
+        assert_eq!(rgb(view.pattern_tables[0].pixel(8, 0)), white);
+        assert_eq!(
+            rgb(view.pattern_tables[0].pixel(9, 0)),
+            rgb(view.palettes[0])
+        );
+        assert_eq!(rgb(view.nametables.pixel(0, 0)), white);
+        assert_eq!(rgb(view.nametables.pixel(256, 0)), red);
+        // Vertical mirroring
+        assert_eq!(rgb(view.nametables.pixel(256, 240)), red);
+
+        let sprite = view.sprites[0];
+        assert_eq!(
+            (sprite.x, sprite.y, sprite.tile, sprite.palette),
+            (0x40, 0x20, 1, 2)
+        );
+        assert!(sprite.flip_horizontally && sprite.flip_vertically && !sprite.behind_background);
+        assert_eq!(sprite.to_string(), "#00 ( 64,  32) tile $01 palette 2 HV-");
+
+        // Sprite palette 1 (palette 5)
+        let view = ppu.debug_view(13);
+        assert_eq!(view.pattern_table_palette, 5);
+        assert_eq!(
+            rgb(view.pattern_tables[0].pixel(8, 0)),
+            rgb(view.palettes[23])
+        );
+    }
 }
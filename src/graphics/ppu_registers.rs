@@ -7,12 +7,17 @@ use std::cell::Cell;
 
 use bitflags::bitflags;
 
+use crate::errors::StateError;
+use crate::snapshot::{StateReader, StateWriter};
+
+#[derive(Clone)]
 pub struct PpuRegisters {
     pub ctrl: PpuCtrl,
     pub mask: PpuMask,
     pub status: Cell<PpuStatus>,
     pub oam_addr: u8,
     pub data_buffer: Cell<u8>,
+    pub io_latch: IoLatch,
 }
 
 impl Default for PpuRegisters {
@@ -23,15 +28,86 @@ impl Default for PpuRegisters {
             status: Cell::new(PpuStatus::empty()),
             oam_addr: 0,
             data_buffer: Cell::new(0),
+            io_latch: IoLatch::default(),
         }
     }
 }
 
+/// PPU dots a bit of the I/O latch keeps its value after being refreshed,
+/// around 600 ms
+pub const IO_LATCH_DECAY_DOTS: u64 = 3_200_000;
+
+/// PPU I/O latch (open bus)
+///
+/// The data bus between the CPU and the PPU registers acts as a latch: it
+/// keeps the last value written to or read from any PPU register. Reading a
+/// write-only register returns its contents, as do the unused bits of
+/// PPUSTATUS and palette reads.
+///
+/// As it's a capacitor, each bit decays to 0 if it's not refreshed for a while.
+/// Time is measured in PPU dots
+///
+/// See more information: https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+#[derive(Clone, Default)]
+pub struct IoLatch {
+    value: Cell<u8>,
+    refreshed_at: Cell<[u64; 8]>,
+}
+
+impl IoLatch {
+    /// Latch value at `dot`, with decayed bits cleared
+    pub fn read(&self, dot: u64) -> u8 {
+        let refreshed_at = self.refreshed_at.get();
+        (0..8)
+            .filter(|&bit| dot.saturating_sub(refreshed_at[bit]) < IO_LATCH_DECAY_DOTS)
+            .fold(0, |value, bit| value | (self.value.get() & (1 << bit)))
+    }
+
+    /// Drive the bits selected by `mask` with `value` at `dot`
+    pub fn refresh(&self, value: u8, mask: u8, dot: u64) {
+        let mut refreshed_at = self.refreshed_at.get();
+        for (bit, refreshed_at) in refreshed_at.iter_mut().enumerate() {
+            if mask & (1 << bit) > 0 {
+                *refreshed_at = dot;
+            }
+        }
+        self.refreshed_at.set(refreshed_at);
+        self.value.set((self.value.get() & !mask) | (value & mask));
+    }
+}
+
 impl PpuRegisters {
     pub fn reset(&mut self) {
         *self = Self::default();
     }
 
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl.bits());
+        state.u8(self.mask.bits());
+        state.u8(self.status.get().bits());
+        state.u8(self.oam_addr);
+        state.u8(self.data_buffer.get());
+        state.u8(self.io_latch.value.get());
+        for refreshed_at in self.io_latch.refreshed_at.get() {
+            state.u64(refreshed_at);
+        }
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = PpuCtrl::from_bits_truncate(state.u8()?);
+        self.mask = PpuMask::from_bits_truncate(state.u8()?);
+        self.status.set(PpuStatus::from_bits_truncate(state.u8()?));
+        self.oam_addr = state.u8()?;
+        self.data_buffer.set(state.u8()?);
+        self.io_latch.value.set(state.u8()?);
+        let mut refreshed_at = [0; 8];
+        for refreshed_at in refreshed_at.iter_mut() {
+            *refreshed_at = state.u64()?;
+        }
+        self.io_latch.refreshed_at.set(refreshed_at);
+        Ok(())
+    }
+
     // PPUCTRL
 
     #[inline]
@@ -96,6 +172,18 @@ impl PpuRegisters {
         self.status.set(status);
     }
 
+    #[inline]
+    pub fn set_sprite_zero_hit(&self, value: bool) {
+        let mut status = self.status.get();
+        status.set(PpuStatus::SPRITE_ZERO_HIT, value);
+        self.status.set(status);
+    }
+
+    #[inline]
+    pub fn vertical_blank(&self) -> bool {
+        self.status.get().contains(PpuStatus::VERTICAL_BLANK)
+    }
+
     #[inline]
     pub fn set_vertical_blank(&self) {
         let mut status = self.status.get();
@@ -152,6 +240,10 @@ bitflags! {
         /// PPU is in vertical blank (VBL) status
         const VERTICAL_BLANK = 0b1000_0000;
 
+        /// An opaque pixel of sprite 0 has overlapped an opaque background
+        /// pixel. Cleared at the pre-render scanline
+        const SPRITE_ZERO_HIT = 0b0100_0000;
+
         /// Sprite overflow is active whenever more than 8 sprites appear on a
         /// scanline. The real NES had a hardware bug that generate false
         /// positives and negatives
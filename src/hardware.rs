@@ -1,4 +1,34 @@
 //! NES hardware constants
+//!
+//! Public address map of the NES, so tools built on top of the emulator don't
+//! need to hardcode magic numbers.
+//!
+//! Main bus (CPU address space):
+//!
+//! | Range           | Contents                                       |
+//! |-----------------|------------------------------------------------|
+//! | `$0000-$07FF`   | 2 kB internal RAM                              |
+//! | `$0800-$1FFF`   | Mirrors of `$0000-$07FF`                       |
+//! | `$2000-$2007`   | PPU registers                                  |
+//! | `$2008-$3FFF`   | Mirrors of `$2000-$2007` (every 8 bytes)       |
+//! | `$4000-$4017`   | APU and I/O registers                          |
+//! | `$4018-$401F`   | APU and I/O test mode (normally disabled)      |
+//! | `$4020-$5FFF`   | Cartidge expansion ROM                         |
+//! | `$6000-$7FFF`   | Cartidge RAM (PRG RAM), usually battery backed |
+//! | `$8000-$FFFF`   | Cartidge ROM (PRG ROM) and mapper registers    |
+//!
+//! Graphics bus (PPU address space):
+//!
+//! | Range           | Contents                                       |
+//! |-----------------|------------------------------------------------|
+//! | `$0000-$1FFF`   | Pattern tables (CHR ROM/RAM)                   |
+//! | `$2000-$2FFF`   | Nametables                                     |
+//! | `$3000-$3EFF`   | Mirrors of `$2000-$2EFF`                       |
+//! | `$3F00-$3F1F`   | Palette RAM                                    |
+//! | `$3F20-$3FFF`   | Mirrors of `$3F00-$3F1F`                       |
+//!
+//! See more information: https://www.nesdev.org/wiki/CPU_memory_map and
+//! https://www.nesdev.org/wiki/PPU_memory_map
 
 // Main bus
 // --------
@@ -8,13 +38,17 @@
 //
 // Cartidges PGR ROM and RAM are mapped to this space
 
-// Memory - 2kB RAM mirrored 3 times (used by the CPU)
+/// Internal RAM, 2 kB mirrored 3 times
 pub const RAM_START: u16 = 0x0000;
 pub const RAM_END: u16 = 0x1FFF;
 pub const RAM_SIZE: u16 = RAM_END - RAM_START + 1;
 pub const RAM_MIRRORS: u16 = 3;
 
-// PPU registers - 8 registers mirrored 1023 times
+/// The second page of RAM is used as the CPU stack
+pub const STACK_START: u16 = 0x0100;
+pub const STACK_END: u16 = 0x01FF;
+
+/// PPU registers, 8 registers mirrored 1023 times
 pub const PPU_REGISTERS_START: u16 = 0x2000;
 pub const PPU_REGISTERS_END: u16 = 0x3FFF;
 
@@ -28,31 +62,57 @@ pub const PPUADDR: u16 = 0x2006;
 pub const PPUDATA: u16 = 0x2007;
 pub const OAMDMA: u16 = 0x4014;
 
-// APU and I/O REGISTERS
+/// APU and I/O registers
 pub const APU_AND_IO_REGISTERS_START: u16 = 0x4000;
 pub const APU_AND_IO_REGISTERS_END: u16 = 0x4015;
 pub const APU_AND_IO_REGISTERS_SIZE: u16 =
     APU_AND_IO_REGISTERS_END - APU_AND_IO_REGISTERS_START + 1;
 
-// DMA
+// APU channels registers
+pub const APU_PULSE_1_START: u16 = 0x4000;
+pub const APU_PULSE_1_END: u16 = 0x4003;
+pub const APU_PULSE_2_START: u16 = 0x4004;
+pub const APU_PULSE_2_END: u16 = 0x4007;
+pub const APU_TRIANGLE_START: u16 = 0x4008;
+pub const APU_TRIANGLE_END: u16 = 0x400B;
+pub const APU_NOISE_START: u16 = 0x400C;
+pub const APU_NOISE_END: u16 = 0x400F;
+pub const APU_DMC_START: u16 = 0x4010;
+pub const APU_DMC_END: u16 = 0x4013;
+
+/// APU channels enable (write) and status (read)
+pub const APU_STATUS: u16 = 0x4015;
+/// APU frame counter (write only, reads go to the second controller port)
+pub const APU_FRAME_COUNTER: u16 = 0x4017;
+
+/// OAM DMA register. Writing a page number copies the 256 bytes of that page
+/// to the PPU OAM
 pub const OAM_DMA: u16 = 0x4014;
 
 // Controllers
 pub const CONTROLLER_PORT_1: u16 = 0x4016;
 pub const CONTROLLER_PORT_2: u16 = 0x4017;
 
-// Cartidge PGR ROM and RAM space
+/// Cartidge expansion ROM, rarely used
 pub const CARTIDGE_EXPANSION_ROM_START: u16 = 0x4020;
 pub const CARTIDGE_EXPANSION_ROM_END: u16 = 0x5FFF;
 pub const CARTIDGE_EXPANSION_ROM_SIZE: u16 =
     CARTIDGE_EXPANSION_ROM_END - CARTIDGE_EXPANSION_ROM_START + 1;
 
+/// Cartidge RAM (PRG RAM or SRAM)
 pub const CARTIDGE_RAM_START: u16 = 0x6000;
 pub const CARTIDGE_RAM_END: u16 = 0x7FFF;
+pub const CARTIDGE_RAM_SIZE: u16 = CARTIDGE_RAM_END - CARTIDGE_RAM_START + 1;
 
+/// Cartidge ROM (PRG ROM)
 pub const CARTIDGE_ROM_START: u16 = 0x8000;
 pub const CARTIDGE_ROM_END: u16 = 0xFFFF;
 
+// Interrupt vectors, stored at the end of the cartidge ROM
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
 // Graphics bus
 // ------------
 //
@@ -61,21 +121,26 @@ pub const CARTIDGE_ROM_END: u16 = 0xFFFF;
 //
 // Cartidges CHR ROM and RAM are usually mapped to this space
 
-// Pattern tables - area of memory that defines the shapes of tiles that make up
-// backgrounds and sprites. It's data is also known as CHR (from "character")
-// and is attached from the cartidges
+/// Pattern tables - area of memory that defines the shapes of tiles that make
+/// up backgrounds and sprites. It's data is also known as CHR (from
+/// "character") and is attached from the cartidges
 pub const PATTERN_TABLES_START: u16 = 0x0000;
 pub const PATTERN_TABLES_END: u16 = 0x1FFF;
 
-// Nametables - also known as VRAM. 1024-byte area used by the PPU to lay out
-// backgrounds
+/// Nametables - also known as VRAM. Four 1024-byte areas used by the PPU to
+/// lay out backgrounds
 pub const NAMETABLES_START: u16 = 0x2000;
 pub const NAMETABLES_END: u16 = 0x2FFF;
+pub const NAMETABLE_SIZE: u16 = 0x0400;
 
-// TODO: nametables mirrors
+/// Mirrors of the nametables, except for the last 256 bytes overlapped by
+/// palettes
+pub const NAMETABLE_MIRRORS_START: u16 = 0x3000;
+pub const NAMETABLE_MIRRORS_END: u16 = 0x3EFF;
 
-// Palettes - 256-byte memory storing which colors should be displayed on the
-// screen when sprites and background are combined
+/// Palettes - 32-byte memory storing which colors should be displayed on the
+/// screen when sprites and background are combined. It's mirrored up to
+/// `$3FFF`
 pub const PALETTE_MEMORY_START: u16 = 0x3F00;
 pub const PALETTE_MEMORY_END: u16 = 0x3F1F;
 pub const PALETTE_MEMORY_SIZE: u16 = PALETTE_MEMORY_END - PALETTE_MEMORY_START + 1;
@@ -87,3 +152,127 @@ pub const PALETTE_MEMORY_MIRRORS_END: u16 = 0x3FFF;
 
 pub const SCREEN_HEIGHT: usize = 240;
 pub const SCREEN_WIDTH: usize = 256;
+
+// Timing
+// ------
+
+/// NTSC master (system) clock frequency: 21.477272 MHz
+pub const MASTER_CLOCK_HZ: u64 = 21_477_272;
+
+/// System clocks per PPU dot
+pub const PPU_CLOCK_DIVIDER: u64 = 4;
+
+/// System clocks per CPU cycle
+pub const CPU_CLOCK_DIVIDER: u64 = 12;
+
+/// Main bus region an address belongs to
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MainBusRegion {
+    Ram,
+    PpuRegisters,
+    ApuAndIoRegisters,
+    ApuAndIoTestMode,
+    CartidgeExpansionRom,
+    CartidgeRam,
+    CartidgeRom,
+}
+
+impl MainBusRegion {
+    pub fn of(address: u16) -> Self {
+        match address {
+            RAM_START..=RAM_END => Self::Ram,
+            PPU_REGISTERS_START..=PPU_REGISTERS_END => Self::PpuRegisters,
+            0x4000..=0x4017 => Self::ApuAndIoRegisters,
+            0x4018..=0x401F => Self::ApuAndIoTestMode,
+            CARTIDGE_EXPANSION_ROM_START..=CARTIDGE_EXPANSION_ROM_END => Self::CartidgeExpansionRom,
+            CARTIDGE_RAM_START..=CARTIDGE_RAM_END => Self::CartidgeRam,
+            CARTIDGE_ROM_START..=CARTIDGE_ROM_END => Self::CartidgeRom,
+        }
+    }
+}
+
+/// The PPU address bus is 14-bit wide, higher address bits are ignored
+pub const GRAPHICS_BUS_MASK: u16 = 0x3FFF;
+
+/// Graphics bus region an address belongs to
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GraphicsBusRegion {
+    PatternTables,
+    Nametables,
+    Palettes,
+}
+
+impl GraphicsBusRegion {
+    pub fn of(address: u16) -> Self {
+        match resolve_graphics_address(address) {
+            PATTERN_TABLES_START..=PATTERN_TABLES_END => Self::PatternTables,
+            NAMETABLES_START..=NAMETABLES_END => Self::Nametables,
+            _ => Self::Palettes,
+        }
+    }
+}
+
+/// Resolve a PPU address to the address where devices are attached in the
+/// graphics bus: high bits are dropped and mirrors of nametables and
+/// palettes map to their first copy. Mirrors inside palette RAM ($3F10 and
+/// friends) are left to [`PaletteMemory`](crate::graphics::palette_memory)
+pub fn resolve_graphics_address(address: u16) -> u16 {
+    match address & GRAPHICS_BUS_MASK {
+        address @ NAMETABLE_MIRRORS_START..=NAMETABLE_MIRRORS_END => {
+            address - NAMETABLE_MIRRORS_START + NAMETABLES_START
+        }
+        address @ PALETTE_MEMORY_START..=PALETTE_MEMORY_MIRRORS_END => {
+            PALETTE_MEMORY_START + (address - PALETTE_MEMORY_START) % PALETTE_MEMORY_SIZE
+        }
+        address => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_main_bus_region() {
+        assert_eq!(MainBusRegion::of(0x07FF), MainBusRegion::Ram);
+        assert_eq!(MainBusRegion::of(0x3FFF), MainBusRegion::PpuRegisters);
+        assert_eq!(
+            MainBusRegion::of(CONTROLLER_PORT_2),
+            MainBusRegion::ApuAndIoRegisters
+        );
+        assert_eq!(MainBusRegion::of(0x401A), MainBusRegion::ApuAndIoTestMode);
+        assert_eq!(MainBusRegion::of(0x6000), MainBusRegion::CartidgeRam);
+        assert_eq!(MainBusRegion::of(RESET_VECTOR), MainBusRegion::CartidgeRom);
+    }
+
+    #[test]
+    fn test_graphics_address_mirroring() {
+        for address in 0..=u16::MAX {
+            let resolved = resolve_graphics_address(address);
+            let base = address & GRAPHICS_BUS_MASK;
+            let expected = match base {
+                0x0000..=0x2FFF => base,
+                0x3000..=0x3EFF => base - 0x1000,
+                _ => 0x3F00 | (base & 0x1F),
+            };
+            assert_eq!(resolved, expected, "PPU address ${address:0>4X}");
+
+            // Always an address with a device attached, already resolved
+            assert!(resolved <= NAMETABLES_END || resolved >= PALETTE_MEMORY_START);
+            assert!(resolved <= PALETTE_MEMORY_END);
+            assert_eq!(resolve_graphics_address(resolved), resolved);
+        }
+
+        assert_eq!(
+            GraphicsBusRegion::of(0x1FFF),
+            GraphicsBusRegion::PatternTables
+        );
+        assert_eq!(GraphicsBusRegion::of(0x3EFF), GraphicsBusRegion::Nametables);
+        assert_eq!(GraphicsBusRegion::of(0x3F20), GraphicsBusRegion::Palettes);
+        assert_eq!(
+            GraphicsBusRegion::of(0x4000),
+            GraphicsBusRegion::PatternTables
+        );
+        assert_eq!(GraphicsBusRegion::of(0x7F00), GraphicsBusRegion::Palettes);
+    }
+}
@@ -0,0 +1,477 @@
+//! Input devices
+//!
+//! The NES has two input ports, read at $4016 and $4017. Whatever is plugged
+//! in a port implements [`InputDevice`]: the standard
+//! [`Controller`](crate::Controller), the [`Zapper`] light gun, the
+//! [`FourScore`] adapter or the [`ArkanoidPaddle`]. New peripherals only need
+//! to implement the trait and be plugged with [`Nes::plug`](crate::Nes::plug).
+//!
+//! Devices see the OUT lines written by the CPU (bit 0 is the strobe) and
+//! return the data lines D0-D4 when the port is read. The rest of the bits are
+//! open bus, so reading a standard controller returns $40 or $41.
+//!
+//! Both ports share their OUT lines, written at $4016. Writes to $4017 don't
+//! reach the ports but the APU frame counter, see [`IoRegisters`].
+//!
+//! On the Famicom, the second controller has a microphone instead of Select
+//! and Start. Its level is read in bit 2 of $4016, regardless of the device
+//! plugged, and set with [`Nes::set_microphone`](crate::Nes::set_microphone).
+//!
+//! See more information: https://www.nesdev.org/wiki/Input_devices
+
+use std::any::Any;
+use std::cell::Cell;
+
+use crate::controller::ControllerState;
+use crate::graphics::Frame;
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::interfaces::Memory;
+use crate::types::{SharedInputPort, SharedMemory};
+use crate::utils;
+
+/// Port bits driven by input devices (D0-D4)
+const DATA_LINES: u8 = 0b0001_1111;
+
+/// Value of the undriven port bits, left on the bus by the high byte of the
+/// port addresses ($40xx)
+const OPEN_BUS: u8 = 0x40;
+
+/// Port bit with the Famicom microphone level, in port one ($4016)
+const MICROPHONE: u8 = 0b0000_0100;
+
+pub trait InputDevice: Any {
+    /// Read the data lines (D0-D4) of the port
+    fn read(&self) -> u8;
+
+    /// The CPU wrote the OUT lines. Bit 0 is the strobe
+    fn write(&mut self, data: u8);
+
+    /// A frame has been completed
+    fn end_frame(&mut self, frame: &Frame) {}
+
+    /// Serial interface state of the device. See
+    /// [`Snapshot`](crate::snapshot::Snapshot)
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore a snapshot taken from the same kind of device. Snapshots from
+    /// other devices must be ignored
+    fn restore(&mut self, snapshot: &[u8]) {}
+}
+
+impl dyn InputDevice {
+    pub fn downcast_ref<T: InputDevice>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+
+    pub fn downcast_mut<T: InputDevice>(&mut self) -> Option<&mut T> {
+        (self as &mut dyn Any).downcast_mut()
+    }
+}
+
+/// Input port with a device plugged in, attached to the main bus
+pub struct InputPort {
+    device: Box<dyn InputDevice>,
+    microphone: bool,
+}
+
+impl InputPort {
+    pub fn new(device: Box<dyn InputDevice>) -> Self {
+        Self {
+            device,
+            microphone: false,
+        }
+    }
+
+    /// Plug `device` in the port and return the device previously plugged
+    pub fn plug(&mut self, device: Box<dyn InputDevice>) -> Box<dyn InputDevice> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn device(&self) -> &dyn InputDevice {
+        self.device.as_ref()
+    }
+
+    pub fn device_mut(&mut self) -> &mut dyn InputDevice {
+        self.device.as_mut()
+    }
+
+    /// Drive the Famicom microphone bit. Only port one has a microphone
+    pub fn set_microphone(&mut self, active: bool) {
+        self.microphone = active;
+    }
+
+    pub fn microphone(&self) -> bool {
+        self.microphone
+    }
+}
+
+impl Memory for InputPort {
+    fn read(&self, _address: u16) -> u8 {
+        // Only D0-D4 are driven, upper bits keep the last value on the bus:
+        // the high byte of the port address
+        let microphone = if self.microphone { MICROPHONE } else { 0 };
+        OPEN_BUS | ((self.device.read() | microphone) & DATA_LINES)
+    }
+
+    fn write(&mut self, _address: u16, data: u8) {
+        self.device.write(data);
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+}
+
+/// Registers at $4016-$4017, where the input ports and the APU frame counter
+/// meet. Reads return the data lines of each port, while writes to $4016
+/// drive the OUT lines of both ports and writes to $4017 go to the frame
+/// counter
+pub struct IoRegisters {
+    ports: [SharedInputPort; 2],
+    frame_counter: SharedMemory,
+}
+
+impl IoRegisters {
+    pub fn new(ports: [SharedInputPort; 2], frame_counter: SharedMemory) -> Self {
+        Self {
+            ports,
+            frame_counter,
+        }
+    }
+}
+
+impl Memory for IoRegisters {
+    fn read(&self, address: u16) -> u8 {
+        self.ports[address as usize & 0x01].borrow().read(0)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if address & 0x01 == 0 {
+            for port in &self.ports {
+                port.borrow_mut().write(0, data);
+            }
+        } else {
+            self.frame_counter.borrow_mut().write(0, data);
+        }
+    }
+
+    fn size(&self) -> usize {
+        2
+    }
+}
+
+/// NES Zapper light gun, usually plugged in port two. The photodiode senses
+/// the last completed frame, so games checking for light on the same frame
+/// they draw the targets see them one frame late.
+///
+/// See more information: https://www.nesdev.org/wiki/Zapper
+#[derive(Default)]
+pub struct Zapper {
+    aim: Option<(usize, usize)>,
+    trigger: bool,
+    light: bool,
+}
+
+impl Zapper {
+    /// Minimum brightness (0 to 1) of the aimed pixels for light to be sensed
+    pub const LIGHT_THRESHOLD: f64 = 0.8;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aim at the screen pixel `(x, y)` or off-screen with `None`
+    pub fn aim(&mut self, position: Option<(usize, usize)>) {
+        self.aim = position.filter(|&(x, y)| x < SCREEN_WIDTH && y < SCREEN_HEIGHT);
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    fn senses_light(&self, frame: &Frame) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+
+        // The photodiode sees a small area around the aimed pixel
+        let rows = y.saturating_sub(1)..(y + 2).min(SCREEN_HEIGHT);
+        let cols = x.saturating_sub(1)..(x + 2).min(SCREEN_WIDTH);
+        rows.flat_map(|row| cols.clone().map(move |col| (row, col)))
+            .any(|(row, col)| {
+                let pixel = &frame[row][col];
+                (pixel.red() + pixel.green() + pixel.blue()) / 3.0 >= Self::LIGHT_THRESHOLD
+            })
+    }
+}
+
+impl InputDevice for Zapper {
+    fn read(&self) -> u8 {
+        // D3 is low while light is sensed, D4 is high while the trigger is
+        // pulled
+        let light = if self.light { 0 } else { 1 << 3 };
+        let trigger = if self.trigger { 1 << 4 } else { 0 };
+        light | trigger
+    }
+
+    fn write(&mut self, _data: u8) {
+        // the Zapper doesn't use the OUT lines
+    }
+
+    fn end_frame(&mut self, frame: &Frame) {
+        self.light = self.senses_light(frame);
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.light as u8]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        if let [light] = snapshot {
+            self.light = *light == 1;
+        }
+    }
+}
+
+/// Four Score adapter, plugging two controllers in a single port. Plug one in
+/// each port to play with four controllers: port one reads controllers 1 and
+/// 3 and port two controllers 2 and 4. A signature follows the controllers
+/// so games can detect the adapter.
+///
+/// See more information: https://www.nesdev.org/wiki/Four_Score
+pub struct FourScore {
+    signature: u8,
+    states: [ControllerState; 2],
+    // 24 bits read in order: both controllers and the signature
+    shift_register: Cell<u32>,
+    strobe: bool,
+}
+
+impl FourScore {
+    /// Create the adapter for `port` (0 or 1), as each port has its own
+    /// signature
+    pub fn new(port: usize) -> Self {
+        Self {
+            signature: if port == 0 { 0b0001_0000 } else { 0b0010_0000 },
+            states: [ControllerState::empty(); 2],
+            shift_register: Cell::new(0),
+            strobe: false,
+        }
+    }
+
+    /// Set the pressed buttons of the first (0) or second (1) controller
+    /// plugged in the adapter
+    pub fn set_state(&mut self, controller: usize, state: ControllerState) {
+        self.states[controller] = state;
+    }
+
+    fn latch(&self) -> u32 {
+        (self.states[0].bits() as u32) << 16
+            | (self.states[1].bits() as u32) << 8
+            | self.signature as u32
+    }
+}
+
+impl InputDevice for FourScore {
+    fn read(&self) -> u8 {
+        if self.strobe {
+            return utils::bv(self.states[0].bits(), 7);
+        }
+
+        // Once the 24 bits have been read, the adapter returns 1s
+        let register = self.shift_register.get();
+        self.shift_register.set(((register << 1) | 1) & 0xFF_FFFF);
+        (register >> 23) as u8 & 1
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift_register.set(self.latch());
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = self.shift_register.get().to_le_bytes().to_vec();
+        snapshot.push(self.strobe as u8);
+        snapshot
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        if let [b0, b1, b2, b3, strobe] = *snapshot {
+            self.shift_register
+                .set(u32::from_le_bytes([b0, b1, b2, b3]));
+            self.strobe = strobe == 1;
+        }
+    }
+}
+
+/// Arkanoid "Vaus" paddle controller (NES version), plugged in port two. The
+/// knob position is read serially on D3, inverted and most significant bit
+/// first, and the button on D4.
+///
+/// See more information: https://www.nesdev.org/wiki/Arkanoid_controller
+pub struct ArkanoidPaddle {
+    position: u8,
+    button: bool,
+    shift_register: Cell<u8>,
+    strobe: bool,
+}
+
+impl ArkanoidPaddle {
+    /// Knob range of original paddles. Games calibrate against it
+    pub const MIN_POSITION: u8 = 0x62;
+    pub const MAX_POSITION: u8 = 0xF2;
+
+    pub fn new() -> Self {
+        Self {
+            position: Self::MIN_POSITION,
+            button: false,
+            shift_register: Cell::new(0),
+            strobe: false,
+        }
+    }
+
+    /// Set the knob position, clamped to the paddle range
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.clamp(Self::MIN_POSITION, Self::MAX_POSITION);
+    }
+
+    pub fn set_button(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+}
+
+impl Default for ArkanoidPaddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputDevice for ArkanoidPaddle {
+    fn read(&self) -> u8 {
+        let register = self.shift_register.get();
+        if !self.strobe {
+            self.shift_register.set(register << 1);
+        }
+        let knob = utils::bv(!register, 7) << 3;
+        let button = if self.button { 1 << 4 } else { 0 };
+        knob | button
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift_register.set(self.position);
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.shift_register.get(), self.strobe as u8]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        if let [shift_register, strobe] = *snapshot {
+            self.shift_register.set(shift_register);
+            self.strobe = strobe == 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::events::KeyboardChannel;
+    use crate::graphics::Pixel;
+    use crate::processor::memory::Ram;
+    use crate::Controller;
+
+    fn poll(device: &mut dyn InputDevice, reads: usize) -> Vec<u8> {
+        device.write(1);
+        device.write(0);
+        (0..reads).map(|_| device.read()).collect()
+    }
+
+    #[test]
+    fn test_microphone() {
+        let mut port = InputPort::new(Box::new(Zapper::new()));
+        assert_eq!(port.read(0), 0x48);
+
+        port.set_microphone(true);
+        assert_eq!(port.read(0), 0x4C);
+        port.set_microphone(false);
+        assert_eq!(port.read(0), 0x48);
+    }
+
+    #[test]
+    fn test_io_registers() {
+        let keyboard = KeyboardChannel::new();
+        let ports = [(), ()].map(|_| {
+            let mut controller = Controller::new(keyboard.listener());
+            controller.set_state(ControllerState::A);
+            Rc::new(RefCell::new(InputPort::new(Box::new(controller))))
+        });
+        let frame_counter = Rc::new(RefCell::new(Ram::new(1)));
+        let mut registers = IoRegisters::new(ports, frame_counter.clone());
+
+        // Writing the frame counter doesn't strobe the second controller
+        registers.write(1, 0x01);
+        registers.write(1, 0x00);
+        assert_eq!(frame_counter.borrow().read(0), 0x00);
+        assert_eq!(registers.read(1), 0x40);
+
+        // Both controllers are strobed through $4016
+        registers.write(0, 0x01);
+        registers.write(0, 0x00);
+        assert_eq!(registers.read(0), 0x41);
+        assert_eq!(registers.read(1), 0x41);
+    }
+
+    #[test]
+    fn test_four_score_signature() {
+        let mut four_score = FourScore::new(1);
+        four_score.set_state(0, ControllerState::A);
+        four_score.set_state(1, ControllerState::RIGHT);
+
+        let bits = poll(&mut four_score, 25);
+        assert_eq!(bits[0], 1);
+        assert_eq!(bits[1..15], [0; 14]);
+        assert_eq!(bits[15], 1);
+        assert_eq!(bits[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[24], 1);
+    }
+
+    #[test]
+    fn test_zapper_light() {
+        let mut zapper = Zapper::new();
+        let mut frame = Frame::black();
+        frame.inner[100][50] = Pixel::WHITE;
+
+        zapper.aim(Some((50, 100)));
+        zapper.set_trigger(true);
+        zapper.end_frame(&frame);
+        assert_eq!(zapper.read(), 0b1_0000);
+
+        zapper.aim(Some((10, 10)));
+        zapper.set_trigger(false);
+        zapper.end_frame(&frame);
+        assert_eq!(zapper.read(), 0b0_1000);
+    }
+
+    #[test]
+    fn test_arkanoid_paddle_position() {
+        let mut paddle = ArkanoidPaddle::new();
+        paddle.set_position(0xA5);
+
+        let bits = poll(&mut paddle, 8);
+        let position = bits
+            .iter()
+            .fold(0, |position, bit| (position << 1) | (bit >> 3));
+        assert_eq!(!position, 0xA5);
+    }
+}
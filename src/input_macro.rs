@@ -0,0 +1,85 @@
+//! Input macros
+//!
+//! A macro is a sequence of controller states, one per frame. They can be
+//! recorded from the player input and replayed on demand, useful to practice
+//! tricky maneuvers or automate menu navigation in tests.
+//!
+
+use crate::controller::ControllerState;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InputMacro {
+    frames: Vec<ControllerState>,
+}
+
+impl InputMacro {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold `state` during `frames` frames
+    pub fn hold(mut self, state: ControllerState, frames: usize) -> Self {
+        self.frames.resize(self.frames.len() + frames, state);
+        self
+    }
+
+    /// Press `state` buttons during a frame and release them the next one
+    pub fn tap(self, state: ControllerState) -> Self {
+        self.hold(state, 1).hold(ControllerState::empty(), 1)
+    }
+
+    pub fn push(&mut self, state: ControllerState) {
+        self.frames.push(state);
+    }
+
+    pub fn frames(&self) -> &[ControllerState] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl FromIterator<ControllerState> for InputMacro {
+    fn from_iter<T: IntoIterator<Item = ControllerState>>(iter: T) -> Self {
+        Self {
+            frames: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Playback state of a macro
+pub(crate) struct MacroPlayback {
+    input_macro: InputMacro,
+    position: usize,
+}
+
+impl MacroPlayback {
+    pub fn new(input_macro: InputMacro) -> Self {
+        Self {
+            input_macro,
+            position: 0,
+        }
+    }
+
+    pub fn current(&self) -> ControllerState {
+        self.input_macro
+            .frames
+            .get(self.position)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn advance(&mut self) {
+        self.position += 1;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.position >= self.input_macro.len()
+    }
+}
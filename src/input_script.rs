@@ -0,0 +1,343 @@
+//! Scripted input
+//!
+//! Drive the controllers with text commands read from a pipe, a FIFO, a file
+//! or the standard input, so shell scripts and external programs can play
+//! games without linking against the emulator. One command per line:
+//!
+//! - `P1 A+RIGHT 10`: hold A and RIGHT on controller one during 10 frames.
+//!   Frames are 1 by default
+//! - `P2 NONE 5`: keep controller two released during 5 frames
+//! - `WAIT 30`: wait until both controllers are done and let 30 more frames
+//!   pass, without touching them
+//!
+//! Buttons are A, B, SELECT, START, UP, DOWN, LEFT and RIGHT (case
+//! insensitive). Empty lines and lines starting with `#` are ignored.
+//!
+//! Commands for the same controller run one after another while each
+//! controller runs its own commands, so both players can press buttons at the
+//! same time. Controllers without pending commands are left alone (e.g., the
+//! keyboard keeps working), and disconnected controllers are never connected.
+//!
+//! ```text
+//! $ mkfifo /tmp/nes-input
+//! $ cargo run --example scripted_input game.nes /tmp/nes-input &
+//! $ echo "P1 START" > /tmp/nes-input
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use log::warn;
+
+use crate::controller::ControllerState;
+use crate::errors::InputScriptError;
+
+const BUTTONS: [(&str, ControllerState); 8] = [
+    ("A", ControllerState::A),
+    ("B", ControllerState::B),
+    ("SELECT", ControllerState::SELECT),
+    ("START", ControllerState::START),
+    ("UP", ControllerState::UP),
+    ("DOWN", ControllerState::DOWN),
+    ("LEFT", ControllerState::LEFT),
+    ("RIGHT", ControllerState::RIGHT),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputCommand {
+    /// Hold `state` buttons of the controller in `port` (0 or 1) during
+    /// `frames` frames
+    Hold {
+        port: usize,
+        state: ControllerState,
+        frames: usize,
+    },
+
+    /// Wait for both controllers and let some frames pass
+    Wait(usize),
+}
+
+/// What a script does with a controller in a frame
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScriptedInput {
+    /// Hold these buttons
+    Hold(ControllerState),
+    /// The script is done with the controller for now, give it back to the
+    /// keyboard or the host application
+    Release,
+    /// Leave the controller alone
+    Idle,
+}
+
+impl InputCommand {
+    /// Parse a command line. Empty lines and comments have no command
+    pub fn parse(line: &str) -> Result<Option<Self>, InputScriptError> {
+        let error = |details: &str| InputScriptError {
+            command: line.trim().to_string(),
+            details: details.to_string(),
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let frames = |word: Option<&&str>| match word {
+            Some(word) => word.parse().map_err(|_| error("invalid frame count")),
+            None => Ok(1),
+        };
+
+        let name = words[0].to_uppercase();
+        let command = match name.as_str() {
+            "WAIT" if words.len() <= 2 => InputCommand::Wait(frames(words.get(1))?),
+            "P1" | "P2" if (2..=3).contains(&words.len()) => InputCommand::Hold {
+                port: if name == "P1" { 0 } else { 1 },
+                state: parse_buttons(words[1]).ok_or_else(|| error("unknown button"))?,
+                frames: frames(words.get(2))?,
+            },
+            "WAIT" | "P1" | "P2" => return Err(error("wrong number of arguments")),
+            _ => return Err(error("unknown command")),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Parse buttons joined by `+`, like `A+RIGHT`, or `NONE`
+fn parse_buttons(text: &str) -> Option<ControllerState> {
+    if text.eq_ignore_ascii_case("NONE") {
+        return Some(ControllerState::empty());
+    }
+
+    text.split('+')
+        .try_fold(ControllerState::empty(), |state, name| {
+            let (_, button) = BUTTONS
+                .iter()
+                .find(|(button, _)| button.eq_ignore_ascii_case(name))?;
+            Some(state | *button)
+        })
+}
+
+/// Controller input read from a text stream. Commands are read in a background
+/// thread, so a stream waiting for input never blocks the emulator. Play it
+/// with [`Nes::play_input_script`](crate::Nes::play_input_script)
+pub struct InputScript {
+    // Commands from the reader thread, until the stream is closed
+    commands: Option<Receiver<InputCommand>>,
+    // Controller states of the upcoming frames, `None` when not driven
+    queues: [VecDeque<Option<ControllerState>>; 2],
+    // Whether the controller has been driven by the script the last frame
+    active: [bool; 2],
+}
+
+impl InputScript {
+    /// Script without a stream, only fed with [`InputScript::push`]
+    pub fn new() -> Self {
+        Self {
+            commands: None,
+            queues: Default::default(),
+            active: [false; 2],
+        }
+    }
+
+    /// Read commands from `reader` until it's closed. Invalid lines are
+    /// logged and skipped
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || read_commands(reader, sender));
+        Self {
+            commands: Some(commands),
+            ..Self::new()
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Self::from_reader(io::stdin())
+    }
+
+    /// Read commands from the file at `path`. Opening a FIFO waits for a
+    /// writer, so it's opened in the reader thread too
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || match File::open(&path) {
+            Ok(file) => read_commands(file, sender),
+            Err(error) => warn!("Unable to open input script {path:?}: {error}"),
+        });
+        Self {
+            commands: Some(commands),
+            ..Self::new()
+        }
+    }
+
+    pub fn push(&mut self, command: InputCommand) {
+        match command {
+            InputCommand::Hold {
+                port,
+                state,
+                frames,
+            } => {
+                let queue = &mut self.queues[port];
+                queue.resize(queue.len() + frames, Some(state));
+            }
+            InputCommand::Wait(frames) => {
+                let length = self.queues.iter().map(VecDeque::len).max().unwrap() + frames;
+                for queue in self.queues.iter_mut() {
+                    queue.resize(length, None);
+                }
+            }
+        }
+    }
+
+    /// What to do with each controller the next frame. Controllers are
+    /// released after their last command, and then left alone
+    pub fn next_frame(&mut self) -> [ScriptedInput; 2] {
+        while let Some(commands) = self.commands.as_ref() {
+            match commands.try_recv() {
+                Ok(command) => self.push(command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.commands = None,
+            }
+        }
+
+        [0, 1].map(|port| match self.queues[port].pop_front().flatten() {
+            Some(state) => {
+                self.active[port] = true;
+                ScriptedInput::Hold(state)
+            }
+            None if self.active[port] => {
+                self.active[port] = false;
+                ScriptedInput::Release
+            }
+            None => ScriptedInput::Idle,
+        })
+    }
+
+    /// Whether the controller in `port` is being driven by the script
+    pub fn is_driving(&self, port: usize) -> bool {
+        self.active[port]
+    }
+
+    /// Frames until both controllers run out of commands received so far
+    pub fn pending_frames(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).max().unwrap()
+    }
+
+    /// Whether the stream has been closed, all its commands have run and the
+    /// controllers have been released
+    pub fn is_finished(&self) -> bool {
+        self.commands.is_none() && self.pending_frames() == 0 && self.active == [false; 2]
+    }
+}
+
+impl Default for InputScript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_commands(reader: impl Read, sender: Sender<InputCommand>) {
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                warn!("Unable to read input script: {error}");
+                return;
+            }
+        };
+        match InputCommand::parse(&line) {
+            Ok(Some(command)) => {
+                if sender.send(command).is_err() {
+                    // Script dropped
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(error) => warn!("{error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            InputCommand::parse("P1 A+right 10").unwrap(),
+            Some(InputCommand::Hold {
+                port: 0,
+                state: ControllerState::A | ControllerState::RIGHT,
+                frames: 10
+            })
+        );
+        assert_eq!(
+            InputCommand::parse("  p2 NONE").unwrap(),
+            Some(InputCommand::Hold {
+                port: 1,
+                state: ControllerState::empty(),
+                frames: 1
+            })
+        );
+        assert_eq!(
+            InputCommand::parse("WAIT 3").unwrap(),
+            Some(InputCommand::Wait(3))
+        );
+        assert_eq!(InputCommand::parse("# comment").unwrap(), None);
+        assert_eq!(InputCommand::parse("").unwrap(), None);
+
+        for line in ["P3 A", "P1 JUMP", "P1 A x", "P1", "WAIT 1 2"] {
+            assert!(InputCommand::parse(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_script_frames() {
+        let mut script = InputScript::new();
+        for line in ["P1 A 2", "P2 B", "WAIT 1", "P2 START"] {
+            script.push(InputCommand::parse(line).unwrap().unwrap());
+        }
+        assert_eq!(script.pending_frames(), 4);
+
+        use ScriptedInput::{Hold, Idle, Release};
+        let a = Hold(ControllerState::A);
+        assert_eq!(script.next_frame(), [a, Hold(ControllerState::B)]);
+        assert!(script.is_driving(1));
+        assert_eq!(script.next_frame(), [a, Release]);
+        // Waiting doesn't touch the controllers
+        assert_eq!(script.next_frame(), [Release, Idle]);
+        assert_eq!(script.next_frame(), [Idle, Hold(ControllerState::START)]);
+        assert_eq!(script.next_frame(), [Idle, Release]);
+        assert_eq!(script.next_frame(), [Idle, Idle]);
+        assert!(script.is_finished());
+    }
+
+    #[test]
+    fn test_script_from_reader() {
+        let mut script = InputScript::from_reader(&b"P1 START 2\nbad command\nP1 SELECT\n"[..]);
+        let mut states = vec![];
+        while !script.is_finished() {
+            match script.next_frame() {
+                [ScriptedInput::Hold(state), ScriptedInput::Idle] => states.push(state),
+                [ScriptedInput::Release, ScriptedInput::Idle] => {}
+                frame => assert_eq!(frame, [ScriptedInput::Idle; 2]),
+            }
+        }
+
+        // Commands may arrive in different frames, with the controller
+        // released in between
+        assert_eq!(
+            states,
+            [
+                ControllerState::START,
+                ControllerState::START,
+                ControllerState::SELECT
+            ]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::errors::{BusError, NesError};
 use crate::types::SharedMemory;
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct AddressRange {
     pub start: u16,
     pub end: u16,
@@ -9,6 +9,50 @@ pub struct AddressRange {
 
 pub type DeviceId = &'static str;
 
+/// How buses react to accesses they can't attend (no device attached or the
+/// device failed)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BusFaultPolicy {
+    /// Panic on faults
+    #[default]
+    Strict,
+    /// Log faults and continue. Reads return the open bus value (last value on
+    /// the bus) and writes are dropped
+    Tolerant,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum BusAccess {
+    Read,
+    Write(u8),
+}
+
+/// Bus access recorded in an access trace, see
+/// [`Nes::set_bus_trace`](crate::Nes::set_bus_trace)
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TracedAccess {
+    pub address: u16,
+    /// Device that answered the access. `None` if no device is attached at
+    /// `address`
+    pub device_id: Option<DeviceId>,
+    pub access: BusAccess,
+    /// Value read or written. Faulty reads return the open bus value
+    pub value: u8,
+    /// CPU cycle the access happened in
+    pub cpu_cycle: u64,
+}
+
+/// Bus access that couldn't be attended while running in tolerant mode
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct BusFault {
+    pub bus_id: &'static str,
+    pub address: u16,
+    pub access: BusAccess,
+    /// Program counter of the instruction that caused the fault (when known)
+    pub pc: Option<u16>,
+}
+
 pub trait Bus {
     /// Attach a new device to the bus to further read/write from
     /// it. Return an UUID to uniquely refer to `device`.
@@ -35,6 +79,24 @@ pub trait Bus {
     /// Panics if an address doesn't correspond to any attached
     /// device.
     fn write(&self, address: u16, data: u8);
+
+    /// Read consecutive bytes starting at `address` into `buffer`, wrapping
+    /// around at the end of the address space. Same as reading them one by
+    /// one, but buses can serve each device at once
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            *data = self.read(address.wrapping_add(offset as u16));
+        }
+    }
+
+    /// Write `data` to consecutive addresses starting at `address`, wrapping
+    /// around at the end of the address space. Same as writing them one by
+    /// one, but buses can serve each device at once
+    fn write_block(&self, address: u16, data: &[u8]) {
+        for (offset, data) in data.iter().enumerate() {
+            self.write(address.wrapping_add(offset as u16), *data);
+        }
+    }
 }
 
 pub trait Memory {
@@ -42,8 +104,7 @@ pub trait Memory {
     fn read(&self, address: u16) -> u8;
 
     fn try_read(&self, address: u16) -> Result<u8, NesError> {
-        let size = self.size() as u16;
-        if address > size {
+        if address as usize > self.size() {
             return Err(NesError::MemoryAccessError {
                 address,
                 memory_size: self.size(),
@@ -56,8 +117,7 @@ pub trait Memory {
     fn write(&mut self, address: u16, data: u8);
 
     fn try_write(&mut self, address: u16, data: u8) -> Result<(), NesError> {
-        let size = self.size() as u16;
-        if address > size {
+        if address as usize > self.size() {
             return Err(NesError::MemoryAccessError {
                 address,
                 memory_size: self.size(),
@@ -69,6 +129,22 @@ pub trait Memory {
 
     /// Memory size in bytes
     fn size(&self) -> usize;
+
+    /// Read `buffer.len()` bytes starting at `address`. Memories backed by
+    /// arrays override it to copy the whole block at once
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            *data = self.read(address.wrapping_add(offset as u16));
+        }
+    }
+
+    /// Write `data` starting at `address`. Memories backed by arrays override
+    /// it to copy the whole block at once
+    fn write_block(&mut self, address: u16, data: &[u8]) {
+        for (offset, data) in data.iter().enumerate() {
+            self.write(address.wrapping_add(offset as u16), *data);
+        }
+    }
 }
 
 pub trait LoadableMemory {
@@ -0,0 +1,86 @@
+//! Keyboard keys as understood by the emulator
+//!
+//! UIs translate their native key events into [`Key`]s before publishing them
+//! through the keyboard channel. This decouples controller bindings from any
+//! specific UI toolkit and allows binding keys without a character
+//! representation, like arrows or modifiers.
+//!
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Key {
+    /// Any key with a character representation. Characters are normalized to
+    /// uppercase, so `Key::Char('a')` and `Key::Char('A')` are the same key
+    Char(char),
+
+    Up,
+    Down,
+    Left,
+    Right,
+
+    Enter,
+    Space,
+    Tab,
+    Backspace,
+    Escape,
+
+    LeftShift,
+    RightShift,
+    LeftControl,
+    RightControl,
+    LeftAlt,
+    RightAlt,
+}
+
+impl Key {
+    /// Build a key from a character. Whitespace characters with a dedicated
+    /// key are mapped to them
+    pub fn from_char(c: char) -> Self {
+        match c {
+            ' ' => Key::Space,
+            '\n' | '\r' => Key::Enter,
+            '\t' => Key::Tab,
+            c => Key::Char(c.to_uppercase().next().unwrap_or(c)),
+        }
+    }
+
+    /// Return the key with its character (if any) normalized
+    pub fn normalized(self) -> Self {
+        match self {
+            Key::Char(c) => Key::from_char(c),
+            key => key,
+        }
+    }
+}
+
+impl From<char> for Key {
+    fn from(c: char) -> Self {
+        Key::from_char(c)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Char(c) => write!(f, "{c}"),
+            key => write!(f, "{key:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_from_char() {
+        assert_eq!(Key::from('a'), Key::Char('A'));
+        assert_eq!(Key::from('A'), Key::Char('A'));
+        assert_eq!(Key::from(' '), Key::Space);
+        assert_eq!(Key::from('\n'), Key::Enter);
+        assert_eq!(Key::Char('j').normalized(), Key::Char('J'));
+        assert_eq!(Key::Up.normalized(), Key::Up);
+    }
+}
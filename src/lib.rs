@@ -1,25 +1,79 @@
 //! NES emulator
 //!
+//! Build a [`Nes`], insert a [`Cartidge`] and run it:
+//!
+//! ```
+//! use nes_emulator::settings::UiKind;
+//! use nes_emulator::testing::scroll_split_cartidge;
+//! use nes_emulator::Nes;
+//!
+//! let mut nes = Nes::builder()
+//!     .with_ui(UiKind::None)
+//!     .with_cartidge(scroll_split_cartidge())
+//!     .build();
+//! nes.run_frames(2).unwrap();
+//!
+//! let frame = nes.last_frame().unwrap();
+//! assert_eq!(frame.info.index, 1);
+//! ```
+//!
+//! See the *examples/* folder for more complete programs.
 
 #![allow(dead_code, unused_variables)]
 
+pub mod audio;
+pub mod capture;
 mod cartidge;
+pub mod conditions;
 mod controller;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
 mod dma;
 pub mod errors;
 pub mod events;
+pub mod expression;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod graphics;
 pub mod hardware;
+pub mod input;
+pub mod input_macro;
+pub mod input_script;
 pub mod interfaces;
-mod mappers;
-mod metrics;
+pub mod keyboard;
+pub mod mappers;
+pub mod memory_viewer;
+pub mod metrics;
+pub mod movie;
 mod nes;
+pub mod pipeline;
 mod processor;
+pub mod reference_trace;
+pub mod rng;
+pub mod rom_watcher;
+pub mod scheduler;
+pub mod session;
 pub mod settings;
+pub mod snapshot;
+pub mod symbols;
+pub mod testing;
 mod types;
 pub mod ui;
 pub mod utils;
+pub mod warnings;
+pub mod watchdog;
 
-pub use cartidge::Cartidge;
+pub use cartidge::{Cartidge, CartidgeInfo, RawMapping, Region, RomValidation};
+pub use controller::Controller;
 pub use controller::ControllerButtons;
-pub use nes::Nes;
+pub use controller::ControllerState;
+pub use dma::{DmaState, OamDmaHook, OamDmaTransfer, OamDmaWrite};
+pub use graphics::ppu::PpuState;
+pub use keyboard::Key;
+pub use mappers::boards::MapperState;
+pub use nes::{Nes, NesBuilder};
+pub use processor::cpu::{CpuState, ExecHook};
+pub use processor::instruction::{AddressingMode, Instruction, Opcode};
+pub use processor::interrupt_line::InterruptLine;
+pub use processor::memory::Mirroring;
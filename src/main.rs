@@ -5,13 +5,14 @@ use nes_emulator::{Cartidge, ControllerButtons, Nes};
 fn main() {
     env_logger::init();
 
-    let mut nes = Nes::default();
-    nes.setup_tv();
-    nes.connect_controller_one(ControllerButtons::default());
     // let cartidge = Cartidge::new(Path::new("/path/to/cartidge"));
     let cartidge = Cartidge::new("roms/Super Mario Bros. (World).nes");
     // let cartidge = Cartidge::new("roms/Galaga - Demons of Death (USA).nes");
 
-    nes.load_cartidge(cartidge);
+    let mut nes = Nes::builder()
+        .with_controllers(ControllerButtons::default(), None)
+        .with_cartidge(cartidge)
+        .with_metrics_callback(|metrics| println!("FPS: {:.1}", metrics.frames_per_second))
+        .build();
     nes.run().unwrap();
 }
@@ -0,0 +1,1876 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use log::trace;
+
+use crate::errors::{RomError, StateError};
+use crate::interfaces::{LoadableMemory, Memory};
+use crate::processor::interrupt_line::InterruptLine;
+use crate::processor::memory::{MirroredMemory, Mirroring, Ram, Rom};
+use crate::snapshot::{StateReader, StateWriter, MAPPER_IRQ_VERSION};
+use crate::types::{SharedCiram, SharedMemory, SharedRam, SharedWarnings};
+use crate::warnings::WarningKind;
+
+pub trait Mapper {
+    fn load_program_rom(&mut self, data: &[u8]);
+    fn load_character_memory(&mut self, data: &[u8]);
+
+    fn program_ram_ref(&self) -> SharedMemory;
+
+    /// PRG space ($8000-$FFFF) as seen from the main bus. Reads are served
+    /// from PRG ROM, while every write is delivered to the mapper, which
+    /// decides whether it's a register write or it's ignored. ROM contents
+    /// never change
+    fn program_rom_ref(&self) -> SharedMemory;
+    fn character_memory_ref(&self) -> SharedMemory;
+
+    /// Expansion area ($4020-$5FFF), for mappers with registers or memory in
+    /// it. `None` keeps the default expansion RAM
+    fn expansion_ref(&self) -> Option<SharedMemory> {
+        None
+    }
+
+    /// Nametables ($2000-$2FFF), for mappers mapping them by themselves
+    /// instead of only choosing CIRAM mirroring. `None` keeps the CIRAM
+    fn nametables_ref(&self) -> Option<SharedMemory> {
+        None
+    }
+
+    /// Memory covering the whole CPU address space ($0000-$FFFF), for boards
+    /// replacing the NES memory map. The main bus serves every access with it
+    fn flat_memory_ref(&self) -> Option<SharedMemory> {
+        None
+    }
+
+    /// PRG ROM offset currently mapped at `address` of the PRG space (0 is
+    /// $8000), according to the bank registers. `None` if the mapper doesn't
+    /// know it
+    fn program_rom_offset(&self, address: u16) -> Option<usize> {
+        None
+    }
+
+    /// Connect the CPU IRQ line, for mappers raising IRQs
+    fn connect_irq_line(&mut self, line: InterruptLine) {}
+
+    /// Connect the nametables, for mappers controlling mirroring
+    fn connect_nametables(&mut self, nametables: SharedCiram) {}
+
+    /// Connect the warnings, for mappers with features not emulated
+    fn connect_warnings(&mut self, warnings: SharedWarnings) {}
+
+    /// A CPU cycle has elapsed, for mappers counting them
+    fn clock_cpu(&mut self) {}
+
+    /// PPU address line A12 rose after being low for `low_dots` PPU dots, for
+    /// mappers counting scanlines with it (e.g., MMC3)
+    fn ppu_a12_rise(&mut self, low_dots: u32) {}
+
+    /// Enable or disable bus conflicts emulation. Boards without bus conflicts
+    /// ignore this setting
+    fn set_bus_conflicts(&mut self, enabled: bool) {}
+
+    /// Expansion audio output, from -1.0 to 1.0, for boards with their own
+    /// sound channels. `None` if there are none or they're not emulated
+    fn audio_output(&self) -> Option<f32> {
+        None
+    }
+
+    /// Current mapper state, for debugging purposes
+    fn state(&self) -> MapperState {
+        MapperState::default()
+    }
+
+    /// Capture the mapper writable memories and registers
+    fn snapshot(&self) -> MapperSnapshot;
+
+    /// Restore a snapshot taken from a mapper of the same cartidge
+    fn restore(&mut self, snapshot: &MapperSnapshot);
+}
+
+/// Writable memories and registers of a mapper. ROM is not included, as it
+/// can't change. See [`Snapshot`](crate::snapshot::Snapshot)
+#[derive(Clone)]
+pub struct MapperSnapshot {
+    program_ram: Ram,
+    character_memory: Ram,
+    registers: Vec<u8>,
+    // Whether the mapper was asserting the IRQ line. Always false for mappers
+    // without IRQs
+    irq_asserted: bool,
+}
+
+impl MapperSnapshot {
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.bytes(self.program_ram.as_slice());
+        state.bytes(self.character_memory.as_slice());
+        state.bytes(&self.registers);
+        if state.version >= MAPPER_IRQ_VERSION {
+            state.bool(self.irq_asserted);
+        }
+    }
+
+    /// States of older versions keep the IRQ line state of the snapshot
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(self.program_ram.as_mut_slice(), "PRG RAM")?;
+        state.bytes_into(self.character_memory.as_mut_slice(), "CHR memory")?;
+        state.bytes_into(&mut self.registers, "mapper registers")?;
+        if state.version >= MAPPER_IRQ_VERSION {
+            self.irq_asserted = state.bool()?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of the mapper internal registers. Their meaning depends on the
+/// mapper, e.g., discrete boards have a single bank select register
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapperState {
+    pub registers: Vec<u8>,
+}
+
+pub fn mapper_map(mapper: u8, specs: MapperSpecs) -> Result<Box<dyn Mapper>, RomError> {
+    let mapper: Box<dyn Mapper> = match mapper {
+        0 => Box::new(Mapper0::new(specs)),
+        2 => Box::new(DiscreteMapper::uxrom(specs)),
+        3 => Box::new(DiscreteMapper::cnrom(specs)),
+        19 => Box::new(Namco163Mapper::new(specs)),
+        69 => Box::new(Fme7Mapper::new(specs)),
+        _ => return Err(RomError::UnsupportedMapper(mapper)),
+    };
+    Ok(mapper)
+}
+
+/// How well a mapper is emulated
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapperStatus {
+    /// Every feature games rely on is emulated
+    Full,
+    /// Games run, but some board features are missing (e.g., expansion
+    /// audio). They're reported as [`warnings`](crate::warnings)
+    Partial,
+    /// The mapper is known but not implemented, cartidges using it can't be
+    /// loaded
+    Unsupported,
+}
+
+/// Emulation status of an iNES mapper
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MapperInfo {
+    pub number: u8,
+    pub name: &'static str,
+    pub status: MapperStatus,
+}
+
+const MAPPERS: [MapperInfo; 8] = [
+    mapper_info(0, "NROM", MapperStatus::Full),
+    mapper_info(1, "MMC1", MapperStatus::Unsupported),
+    mapper_info(2, "UxROM", MapperStatus::Full),
+    mapper_info(3, "CNROM", MapperStatus::Full),
+    mapper_info(4, "MMC3", MapperStatus::Unsupported),
+    mapper_info(7, "AxROM", MapperStatus::Unsupported),
+    // Expansion audio is only synthesized with the `namco163-audio` feature,
+    // and it's never heard, as there's no APU to mix it with
+    mapper_info(19, "Namco 163", MapperStatus::Partial),
+    mapper_info(69, "FME-7", MapperStatus::Partial),
+];
+
+const fn mapper_info(number: u8, name: &'static str, status: MapperStatus) -> MapperInfo {
+    MapperInfo {
+        number,
+        name,
+        status,
+    }
+}
+
+/// Mappers known by the emulator, by number, with their emulation status.
+/// Frontends can use it to tell what to expect from a ROM before loading it
+pub fn supported() -> Vec<MapperInfo> {
+    MAPPERS.to_vec()
+}
+
+/// Emulation status of `mapper`. Mappers not even known are
+/// [`Unsupported`](MapperStatus::Unsupported)
+pub fn info(mapper: u8) -> MapperInfo {
+    MAPPERS
+        .iter()
+        .find(|info| info.number == mapper)
+        .copied()
+        .unwrap_or(mapper_info(mapper, "Unknown", MapperStatus::Unsupported))
+}
+
+/// Common name of an iNES `mapper` number
+pub fn mapper_name(mapper: u8) -> &'static str {
+    info(mapper).name
+}
+
+/// Whether a cartidge with `mapper` can have `size` bytes of PRG ROM. NROM
+/// boards mirror PRG ROMs smaller than 32 kB, so their size must divide it.
+/// Other mappers switch banks of at least 8 kB
+pub fn valid_program_rom_size(mapper: u8, size: usize) -> bool {
+    match mapper {
+        0 => size.is_power_of_two() && size <= NROM_PROGRAM_ROM_SIZE,
+        _ => size.is_multiple_of(0x2000) && size <= MAX_PROGRAM_ROM_SIZE,
+    }
+}
+
+/// Size of the NROM PRG ROM window ($8000-$FFFF)
+const NROM_PROGRAM_ROM_SIZE: usize = 0x8000;
+
+/// Largest PRG ROM an iNES header can declare is 255 * 16 kB
+const MAX_PROGRAM_ROM_SIZE: usize = 0x40_0000;
+
+pub struct MapperSpecs {
+    pub program_rom_capacity: usize,
+    pub program_ram_capacity: usize,
+    pub character_memory_capacity: usize,
+}
+
+pub struct Mapper0 {
+    // Program memory (RAM)
+    program_ram: SharedRam,
+
+    // Program memory (ROM)
+    program_rom: Rc<RefCell<NromProgramRom>>,
+
+    // Character memory, stores patterns and graphics for the PPU
+    character_memory: SharedRam,
+}
+
+impl Mapper0 {
+    pub fn new(specs: MapperSpecs) -> Self {
+        // Common boards have 16 or 32 kB, but some homebrew ROMs are smaller.
+        // They're mirrored all over $8000-$FFFF
+        let capacity = specs.program_rom_capacity;
+        assert!(
+            valid_program_rom_size(0, capacity),
+            "Unexpected PGR ROM capacity: {capacity}"
+        );
+        let rom = MirroredMemory::new(Rom::new(capacity), NROM_PROGRAM_ROM_SIZE / capacity - 1);
+
+        Self {
+            program_rom: Rc::new(RefCell::new(NromProgramRom { rom })),
+            program_ram: Rc::new(RefCell::new(Ram::new(specs.program_ram_capacity))),
+            character_memory: Rc::new(RefCell::new(Ram::new(specs.character_memory_capacity))),
+        }
+    }
+}
+
+impl Mapper for Mapper0 {
+    fn load_program_rom(&mut self, data: &[u8]) {
+        self.program_rom.borrow_mut().load(0, data);
+    }
+    fn load_character_memory(&mut self, data: &[u8]) {
+        self.character_memory.borrow_mut().load(0, data);
+    }
+
+    fn program_ram_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_ram) as _
+    }
+
+    fn program_rom_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_rom) as _
+    }
+
+    fn character_memory_ref(&self) -> SharedMemory {
+        Rc::clone(&self.character_memory) as _
+    }
+
+    fn program_rom_offset(&self, address: u16) -> Option<usize> {
+        // ROMs smaller than 32 kB are mirrored
+        Some(address as usize % self.program_rom.borrow().rom.memory().size())
+    }
+
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot {
+            program_ram: self.program_ram.borrow().clone(),
+            character_memory: self.character_memory.borrow().clone(),
+            registers: Vec::new(),
+            irq_asserted: false,
+        }
+    }
+
+    fn restore(&mut self, snapshot: &MapperSnapshot) {
+        *self.program_ram.borrow_mut() = snapshot.program_ram.clone();
+        *self.character_memory.borrow_mut() = snapshot.character_memory.clone();
+    }
+}
+
+/// PRG ROM of NROM boards. They don't have any register, so writes are
+/// ignored
+pub struct NromProgramRom {
+    rom: MirroredMemory<Rom>,
+}
+
+impl Memory for NromProgramRom {
+    fn read(&self, address: u16) -> u8 {
+        self.rom.read(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        trace!("Ignoring write to NROM PRG ROM: 0x{data:0>2X} to 0x{address:0>4X}");
+    }
+
+    fn size(&self) -> usize {
+        self.rom.size()
+    }
+
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        self.rom.read_block(address, buffer);
+    }
+}
+
+impl LoadableMemory for NromProgramRom {
+    fn load(&mut self, address: u16, contents: &[u8]) {
+        self.rom.load(address, contents);
+    }
+}
+
+/// 64 kB of RAM over the whole CPU address space, without NES hardware, for
+/// raw 6502 programs. See [`Cartidge::from_raw_prg`](crate::Cartidge::from_raw_prg)
+pub struct FlatMapper {
+    memory: SharedRam,
+    character_memory: SharedRam,
+}
+
+impl FlatMapper {
+    pub fn new(specs: MapperSpecs) -> Self {
+        Self {
+            memory: Rc::new(RefCell::new(Ram::new(0x10000))),
+            character_memory: Rc::new(RefCell::new(Ram::new(specs.character_memory_capacity))),
+        }
+    }
+}
+
+impl Mapper for FlatMapper {
+    fn load_program_rom(&mut self, data: &[u8]) {
+        self.memory.borrow_mut().load(0, data);
+    }
+    fn load_character_memory(&mut self, data: &[u8]) {
+        self.character_memory.borrow_mut().load(0, data);
+    }
+
+    fn program_ram_ref(&self) -> SharedMemory {
+        Rc::clone(&self.memory) as _
+    }
+
+    fn program_rom_ref(&self) -> SharedMemory {
+        Rc::clone(&self.memory) as _
+    }
+
+    fn character_memory_ref(&self) -> SharedMemory {
+        Rc::clone(&self.character_memory) as _
+    }
+
+    fn flat_memory_ref(&self) -> Option<SharedMemory> {
+        Some(Rc::clone(&self.memory) as _)
+    }
+
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot {
+            program_ram: self.memory.borrow().clone(),
+            character_memory: self.character_memory.borrow().clone(),
+            registers: Vec::new(),
+            irq_asserted: false,
+        }
+    }
+
+    fn restore(&mut self, snapshot: &MapperSnapshot) {
+        *self.memory.borrow_mut() = snapshot.program_ram.clone();
+        *self.character_memory.borrow_mut() = snapshot.character_memory.clone();
+    }
+}
+
+// Discrete mappers
+// ------------------------------------------------------------------------------------------------
+//
+// Discrete boards (UxROM, CNROM...) implement bank switching with a simple
+// latch selected by writing anywhere in PRG ROM space. As the ROM is not
+// disabled during writes, both the CPU and the ROM drive the data bus: a bus
+// conflict. The value latched ends up being the written value ANDed with the
+// ROM byte at that address. Games usually avoid it writing to an address
+// already containing the same value, but some of them rely on it.
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum DiscreteBanking {
+    /// UxROM: switchable 16 kB PRG bank at $8000 and last bank fixed at $C000
+    ProgramBanks,
+    /// CNROM: fixed PRG ROM and switchable 8 kB CHR bank
+    CharacterBanks,
+}
+
+/// PRG ROM of a discrete board. Writes to it update the bank register
+pub struct DiscreteProgramRom {
+    rom: Rom,
+    banking: DiscreteBanking,
+    bank_register: Rc<Cell<u8>>,
+    bus_conflicts: bool,
+}
+
+impl DiscreteProgramRom {
+    fn banks(&self) -> usize {
+        (self.rom.size() / PRG_BANK_SIZE).max(1)
+    }
+
+    fn physical_address(&self, address: u16) -> usize {
+        let address = address as usize;
+        match self.banking {
+            DiscreteBanking::ProgramBanks if address < PRG_BANK_SIZE => {
+                let bank = self.bank_register.get() as usize % self.banks();
+                bank * PRG_BANK_SIZE + address
+            }
+            DiscreteBanking::ProgramBanks => {
+                let last_bank = self.banks() - 1;
+                last_bank * PRG_BANK_SIZE + (address - PRG_BANK_SIZE)
+            }
+            DiscreteBanking::CharacterBanks => address % self.rom.size(),
+        }
+    }
+}
+
+impl Memory for DiscreteProgramRom {
+    fn read(&self, address: u16) -> u8 {
+        self.rom.as_slice()[self.physical_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let value = if self.bus_conflicts {
+            data & self.read(address)
+        } else {
+            data
+        };
+        trace!("Discrete mapper bank select: 0x{value:0>2X} (written 0x{data:0>2X})");
+        self.bank_register.set(value);
+    }
+
+    fn size(&self) -> usize {
+        2 * PRG_BANK_SIZE
+    }
+}
+
+impl LoadableMemory for DiscreteProgramRom {
+    fn load(&mut self, address: u16, contents: &[u8]) {
+        self.rom.load(address, contents);
+    }
+}
+
+/// CHR memory of a discrete board, switched in 8 kB banks
+pub struct DiscreteCharacterMemory {
+    memory: Ram,
+    banking: DiscreteBanking,
+    bank_register: Rc<Cell<u8>>,
+}
+
+impl DiscreteCharacterMemory {
+    fn physical_address(&self, address: u16) -> usize {
+        let address = address as usize;
+        match self.banking {
+            DiscreteBanking::ProgramBanks => address,
+            DiscreteBanking::CharacterBanks => {
+                let banks = (self.memory.size() / CHR_BANK_SIZE).max(1);
+                let bank = self.bank_register.get() as usize % banks;
+                bank * CHR_BANK_SIZE + address
+            }
+        }
+    }
+}
+
+impl Memory for DiscreteCharacterMemory {
+    fn read(&self, address: u16) -> u8 {
+        self.memory.as_slice()[self.physical_address(address)]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let address = self.physical_address(address);
+        self.memory.as_mut_slice()[address] = data;
+    }
+
+    fn size(&self) -> usize {
+        CHR_BANK_SIZE
+    }
+}
+
+impl LoadableMemory for DiscreteCharacterMemory {
+    fn load(&mut self, address: u16, contents: &[u8]) {
+        self.memory.load(address, contents);
+    }
+}
+
+/// UxROM (mapper 2) and CNROM (mapper 3) boards. Bus conflicts are emulated by
+/// default
+pub struct DiscreteMapper {
+    program_ram: SharedRam,
+    program_rom: Rc<RefCell<DiscreteProgramRom>>,
+    character_memory: Rc<RefCell<DiscreteCharacterMemory>>,
+}
+
+impl DiscreteMapper {
+    pub fn uxrom(specs: MapperSpecs) -> Self {
+        Self::new(specs, DiscreteBanking::ProgramBanks)
+    }
+
+    pub fn cnrom(specs: MapperSpecs) -> Self {
+        Self::new(specs, DiscreteBanking::CharacterBanks)
+    }
+
+    fn new(specs: MapperSpecs, banking: DiscreteBanking) -> Self {
+        let bank_register = Rc::new(Cell::new(0));
+
+        // Boards without CHR ROM come with 8 kB of CHR RAM
+        let character_memory_capacity = if specs.character_memory_capacity == 0 {
+            CHR_BANK_SIZE
+        } else {
+            specs.character_memory_capacity
+        };
+
+        Self {
+            program_ram: Rc::new(RefCell::new(Ram::new(specs.program_ram_capacity))),
+            program_rom: Rc::new(RefCell::new(DiscreteProgramRom {
+                rom: Rom::new(specs.program_rom_capacity),
+                banking,
+                bank_register: Rc::clone(&bank_register),
+                bus_conflicts: true,
+            })),
+            character_memory: Rc::new(RefCell::new(DiscreteCharacterMemory {
+                memory: Ram::new(character_memory_capacity),
+                banking,
+                bank_register,
+            })),
+        }
+    }
+}
+
+impl Mapper for DiscreteMapper {
+    fn load_program_rom(&mut self, data: &[u8]) {
+        self.program_rom.borrow_mut().load(0, data);
+    }
+    fn load_character_memory(&mut self, data: &[u8]) {
+        self.character_memory.borrow_mut().load(0, data);
+    }
+
+    fn program_ram_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_ram) as _
+    }
+
+    fn program_rom_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_rom) as _
+    }
+
+    fn character_memory_ref(&self) -> SharedMemory {
+        Rc::clone(&self.character_memory) as _
+    }
+
+    fn program_rom_offset(&self, address: u16) -> Option<usize> {
+        Some(self.program_rom.borrow().physical_address(address))
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.program_rom.borrow_mut().bus_conflicts = enabled;
+    }
+
+    fn state(&self) -> MapperState {
+        MapperState {
+            registers: vec![self.program_rom.borrow().bank_register.get()],
+        }
+    }
+
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot {
+            program_ram: self.program_ram.borrow().clone(),
+            character_memory: self.character_memory.borrow().memory.clone(),
+            registers: self.state().registers,
+            irq_asserted: false,
+        }
+    }
+
+    fn restore(&mut self, snapshot: &MapperSnapshot) {
+        *self.program_ram.borrow_mut() = snapshot.program_ram.clone();
+        self.character_memory.borrow_mut().memory = snapshot.character_memory.clone();
+        self.program_rom
+            .borrow()
+            .bank_register
+            .set(snapshot.registers[0]);
+    }
+}
+
+// Sunsoft FME-7
+// ------------------------------------------------------------------------------------------------
+//
+// FME-7 (mapper 69) is configured through a command port ($8000-$9FFF), which
+// selects one of its 16 internal registers, and a parameter port
+// ($A000-$BFFF), which writes the selected register:
+//
+// | Register | Function                                                    |
+// |----------|-------------------------------------------------------------|
+// | $0-$7    | 1 kB CHR banks at PPU $0000-$1FFF                           |
+// | $8       | 8 kB bank at CPU $6000: bits 0-5 bank, 6 RAM select, 7 RAM  |
+// |          | enable                                                      |
+// | $9-$B    | 8 kB PRG ROM banks at CPU $8000, $A000 and $C000            |
+// | $C       | Mirroring: vertical, horizontal, single-screen lower or     |
+// |          | upper                                                       |
+// | $D       | IRQ control: bit 0 IRQ enable, bit 7 counter enable         |
+// | $E-$F    | IRQ counter low and high bytes                              |
+//
+// The last PRG ROM bank is fixed at $E000. The 16-bit IRQ counter decrements
+// every CPU cycle and raises an IRQ when it wraps from $0000 to $FFFF. Any
+// write to the IRQ control register acknowledges the IRQ.
+//
+// Boards with the Sunsoft 5B chip add expansion audio, configured through an
+// address port ($C000-$DFFF) and a data port ($E000-$FFFF). Only its
+// registers are implemented, as there's no APU to mix it with yet.
+//
+// See more information: https://www.nesdev.org/wiki/Sunsoft_FME-7
+
+const FME7_PRG_BANK_SIZE: usize = 8 * 1024;
+const FME7_CHR_BANK_SIZE: usize = 1024;
+const FME7_PRG_RAM_SIZE: usize = 8 * 1024;
+
+const FME7_MIRRORING: usize = 0xC;
+const FME7_IRQ_CONTROL: usize = 0xD;
+const FME7_IRQ_COUNTER_LOW: usize = 0xE;
+const FME7_IRQ_COUNTER_HIGH: usize = 0xF;
+
+/// Memory and registers of an FME-7 board, shared by the CPU and PPU views
+struct Fme7Board {
+    program_rom: Vec<u8>,
+    program_ram: Ram,
+    character_memory: Ram,
+    character_ram: bool,
+
+    command: u8,
+    registers: [u8; 16],
+    irq_counter: u16,
+    audio_address: u8,
+    audio_registers: [u8; 16],
+
+    irq_line: Option<InterruptLine>,
+    nametables: Option<SharedCiram>,
+    warnings: Option<SharedWarnings>,
+}
+
+impl Fme7Board {
+    fn program_rom_offset(&self, bank: u8, address: u16) -> usize {
+        let banks = (self.program_rom.len() / FME7_PRG_BANK_SIZE).max(1);
+        let bank = (bank & 0x3F) as usize % banks;
+        bank * FME7_PRG_BANK_SIZE + address as usize % FME7_PRG_BANK_SIZE
+    }
+
+    /// PRG ROM offset mapped at `address` of the PRG space ($8000-$FFFF)
+    fn program_rom_window(&self, address: u16) -> usize {
+        let bank = match address as usize / FME7_PRG_BANK_SIZE {
+            window @ 0..=2 => self.registers[0x9 + window],
+            _ => u8::MAX, // last bank
+        };
+        self.program_rom_offset(bank, address)
+    }
+
+    fn character_offset(&self, address: u16) -> usize {
+        let banks = (self.character_memory.size() / FME7_CHR_BANK_SIZE).max(1);
+        let bank = self.registers[address as usize / FME7_CHR_BANK_SIZE] as usize % banks;
+        bank * FME7_CHR_BANK_SIZE + address as usize % FME7_CHR_BANK_SIZE
+    }
+
+    fn write_register(&mut self, data: u8) {
+        let register = self.command as usize;
+        self.registers[register] = data;
+        match register {
+            FME7_MIRRORING => {
+                let mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                if let Some(nametables) = self.nametables.as_ref() {
+                    nametables.borrow_mut().set_mirroring(mirroring);
+                }
+            }
+            FME7_IRQ_CONTROL => {
+                if let Some(irq_line) = self.irq_line.as_ref() {
+                    irq_line.release();
+                }
+            }
+            FME7_IRQ_COUNTER_LOW => {
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+            }
+            FME7_IRQ_COUNTER_HIGH => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let control = self.registers[FME7_IRQ_CONTROL];
+        if control & 0x80 == 0 {
+            return;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && control & 0x01 != 0 {
+            if let Some(irq_line) = self.irq_line.as_ref() {
+                irq_line.assert();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Fme7Window {
+    /// CPU $6000-$7FFF: PRG RAM or a PRG ROM bank
+    ProgramRam,
+    /// CPU $8000-$FFFF: PRG ROM banks and mapper registers
+    ProgramRom,
+    /// PPU $0000-$1FFF
+    Character,
+}
+
+/// A view of an FME-7 board as seen from one of the buses
+pub struct Fme7Memory {
+    board: Rc<RefCell<Fme7Board>>,
+    window: Fme7Window,
+}
+
+impl Memory for Fme7Memory {
+    fn read(&self, address: u16) -> u8 {
+        let board = self.board.borrow();
+        match self.window {
+            Fme7Window::ProgramRam => {
+                let bank = board.registers[0x8];
+                let ram_select = bank & 0x40 != 0;
+                let ram_enable = bank & 0x80 != 0;
+                match (ram_select, ram_enable) {
+                    (false, _) => board.program_rom[board.program_rom_offset(bank, address)],
+                    (true, true) => board.program_ram.read(address),
+                    // Open bus, usually the high byte of the address
+                    (true, false) => 0x60 + (address >> 8) as u8,
+                }
+            }
+            Fme7Window::ProgramRom => board.program_rom[board.program_rom_window(address)],
+            Fme7Window::Character => {
+                board.character_memory.as_slice()[board.character_offset(address)]
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let mut board = self.board.borrow_mut();
+        match self.window {
+            Fme7Window::ProgramRam => {
+                // Only RAM, if selected and enabled, can be written
+                if board.registers[0x8] & 0xC0 == 0xC0 {
+                    board.program_ram.write(address, data);
+                }
+            }
+            Fme7Window::ProgramRom => match address {
+                0x0000..=0x1FFF => board.command = data & 0x0F,
+                0x2000..=0x3FFF => board.write_register(data),
+                0x4000..=0x5FFF => board.audio_address = data,
+                _ => {
+                    trace!("Sunsoft 5B audio register write (not emulated)");
+                    if let Some(warnings) = board.warnings.as_ref() {
+                        warnings.borrow_mut().report(
+                            WarningKind::ExpansionAudioNotEmulated,
+                            Some(0x8000 + address),
+                        );
+                    }
+                    let register = (board.audio_address & 0x0F) as usize;
+                    board.audio_registers[register] = data;
+                }
+            },
+            Fme7Window::Character => {
+                if board.character_ram {
+                    let offset = board.character_offset(address);
+                    board.character_memory.as_mut_slice()[offset] = data;
+                }
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self.window {
+            Fme7Window::ProgramRam => FME7_PRG_RAM_SIZE,
+            Fme7Window::ProgramRom => 4 * FME7_PRG_BANK_SIZE,
+            Fme7Window::Character => 8 * FME7_CHR_BANK_SIZE,
+        }
+    }
+}
+
+/// Sunsoft FME-7 boards (mapper 69), also used with the Sunsoft 5B audio chip
+pub struct Fme7Mapper {
+    board: Rc<RefCell<Fme7Board>>,
+    program_ram: Rc<RefCell<Fme7Memory>>,
+    program_rom: Rc<RefCell<Fme7Memory>>,
+    character_memory: Rc<RefCell<Fme7Memory>>,
+}
+
+impl Fme7Mapper {
+    pub fn new(specs: MapperSpecs) -> Self {
+        // Boards without CHR ROM come with 8 kB of CHR RAM
+        let character_ram = specs.character_memory_capacity == 0;
+        let character_memory_capacity = if character_ram {
+            8 * FME7_CHR_BANK_SIZE
+        } else {
+            specs.character_memory_capacity
+        };
+
+        let board = Rc::new(RefCell::new(Fme7Board {
+            program_rom: vec![0; specs.program_rom_capacity],
+            program_ram: Ram::new(FME7_PRG_RAM_SIZE),
+            character_memory: Ram::new(character_memory_capacity),
+            character_ram,
+            command: 0,
+            registers: [0; 16],
+            irq_counter: 0,
+            audio_address: 0,
+            audio_registers: [0; 16],
+            irq_line: None,
+            nametables: None,
+            warnings: None,
+        }));
+        let view = |window| {
+            Rc::new(RefCell::new(Fme7Memory {
+                board: Rc::clone(&board),
+                window,
+            }))
+        };
+
+        Self {
+            program_ram: view(Fme7Window::ProgramRam),
+            program_rom: view(Fme7Window::ProgramRom),
+            character_memory: view(Fme7Window::Character),
+            board,
+        }
+    }
+}
+
+impl Mapper for Fme7Mapper {
+    fn load_program_rom(&mut self, data: &[u8]) {
+        self.board.borrow_mut().program_rom = data.to_vec();
+    }
+    fn load_character_memory(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.board.borrow_mut().character_memory = Ram::from(data.to_vec());
+        }
+    }
+
+    fn program_ram_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_ram) as _
+    }
+
+    fn program_rom_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_rom) as _
+    }
+
+    fn character_memory_ref(&self) -> SharedMemory {
+        Rc::clone(&self.character_memory) as _
+    }
+
+    fn program_rom_offset(&self, address: u16) -> Option<usize> {
+        Some(self.board.borrow().program_rom_window(address))
+    }
+
+    fn connect_irq_line(&mut self, line: InterruptLine) {
+        self.board.borrow_mut().irq_line = Some(line);
+    }
+
+    fn connect_nametables(&mut self, nametables: SharedCiram) {
+        self.board.borrow_mut().nametables = Some(nametables);
+    }
+
+    fn connect_warnings(&mut self, warnings: SharedWarnings) {
+        self.board.borrow_mut().warnings = Some(warnings);
+    }
+
+    fn clock_cpu(&mut self) {
+        self.board.borrow_mut().clock_irq_counter();
+    }
+
+    /// Command followed by the 16 internal registers
+    fn state(&self) -> MapperState {
+        let board = self.board.borrow();
+        let mut registers = vec![board.command];
+        registers.extend_from_slice(&board.registers);
+        MapperState { registers }
+    }
+
+    fn snapshot(&self) -> MapperSnapshot {
+        let board = self.board.borrow();
+        let mut registers = self.state().registers;
+        registers.extend_from_slice(&board.irq_counter.to_le_bytes());
+        registers.push(board.audio_address);
+        registers.extend_from_slice(&board.audio_registers);
+
+        MapperSnapshot {
+            program_ram: board.program_ram.clone(),
+            character_memory: if board.character_ram {
+                board.character_memory.clone()
+            } else {
+                Ram::new(0)
+            },
+            registers,
+            irq_asserted: board
+                .irq_line
+                .as_ref()
+                .is_some_and(|line| line.is_asserted()),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &MapperSnapshot) {
+        let mut board = self.board.borrow_mut();
+        board.program_ram = snapshot.program_ram.clone();
+        if board.character_ram {
+            board.character_memory = snapshot.character_memory.clone();
+        }
+
+        let registers = &snapshot.registers;
+        board.command = registers[0];
+        board.registers.copy_from_slice(&registers[1..17]);
+        board.irq_counter = u16::from_le_bytes([registers[17], registers[18]]);
+        board.audio_address = registers[19];
+        board.audio_registers.copy_from_slice(&registers[20..36]);
+
+        // A pending IRQ is part of the state, as much as the counter
+        if let Some(irq_line) = board.irq_line.as_ref() {
+            if snapshot.irq_asserted {
+                irq_line.assert();
+            } else {
+                irq_line.release();
+            }
+        }
+    }
+}
+
+// Namco 163
+// ------------------------------------------------------------------------------------------------
+//
+// Namco 163 (mapper 19) registers are spread over the expansion area and PRG
+// space:
+//
+// | Address       | Function                                                |
+// |---------------|---------------------------------------------------------|
+// | $4800-$4FFF   | Internal RAM data port                                  |
+// | $5000-$57FF   | IRQ counter low byte                                    |
+// | $5800-$5FFF   | IRQ counter high bits (0-6) and IRQ enable (7)          |
+// | $8000-$BFFF   | 1 kB CHR banks at PPU $0000-$1FFF                       |
+// | $C000-$DFFF   | 1 kB nametable banks at PPU $2000-$2FFF                 |
+// | $E000-$F7FF   | 8 kB PRG ROM banks at CPU $8000, $A000 and $C000        |
+// | $F800-$FFFF   | Internal RAM address port and PRG RAM write protection  |
+//
+// The last PRG ROM bank is fixed at $E000. CHR and nametable banks $E0-$FF
+// select a CIRAM page (even banks the first one, odd banks the second one)
+// instead of CHR ROM. For pattern tables, this is disabled by bits 6 ($0000)
+// and 7 ($1000) of the $A000 PRG bank register.
+//
+// The 15-bit IRQ counter increments every CPU cycle while enabled and raises
+// an IRQ when it reaches $7FFF, where it stops. Writing any of its bytes
+// acknowledges the IRQ.
+//
+// The 128 bytes of internal RAM are accessed through the address port (bits
+// 0-6 address, bit 7 auto-increment after every access) and the data port.
+// Games use them as battery backed RAM and to hold the wavetable audio
+// channels. Audio synthesis is only built with the `namco163-audio` feature,
+// as there's no APU to mix it with yet.
+//
+// See more information: https://www.nesdev.org/wiki/Namco_163
+
+const N163_PRG_BANK_SIZE: usize = 8 * 1024;
+const N163_CHR_BANK_SIZE: usize = 1024;
+const N163_PRG_RAM_SIZE: usize = 8 * 1024;
+const N163_INTERNAL_RAM_SIZE: usize = 128;
+/// Internal RAM from this address holds the sound channel registers
+const N163_SOUND_REGISTERS_START: usize = 0x40;
+
+/// Banks from this value select a CIRAM page instead of CHR ROM
+const N163_CIRAM_BANKS: u8 = 0xE0;
+const N163_IRQ_COUNTER_MAX: u16 = 0x7FFF;
+
+/// Memory and registers of a Namco 163 board, shared by the CPU and PPU views
+struct Namco163Board {
+    program_rom: Vec<u8>,
+    program_ram: Ram,
+    character_memory: Ram,
+    character_ram: bool,
+    internal_ram: [u8; N163_INTERNAL_RAM_SIZE],
+
+    /// 8 CHR banks followed by 4 nametable banks
+    character_banks: [u8; 12],
+    program_banks: [u8; 3],
+    /// Internal RAM address port and PRG RAM write protection
+    address_port: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+
+    irq_line: Option<InterruptLine>,
+    nametables: Option<SharedCiram>,
+    warnings: Option<SharedWarnings>,
+
+    #[cfg(feature = "namco163-audio")]
+    audio: Namco163Audio,
+}
+
+impl Namco163Board {
+    /// PRG ROM offset mapped at `address` of the PRG space ($8000-$FFFF)
+    fn program_rom_window(&self, address: u16) -> usize {
+        let bank = match address as usize / N163_PRG_BANK_SIZE {
+            window @ 0..=2 => self.program_banks[window] & 0x3F,
+            _ => u8::MAX, // last bank
+        };
+        let banks = (self.program_rom.len() / N163_PRG_BANK_SIZE).max(1);
+        let bank = bank as usize % banks;
+        bank * N163_PRG_BANK_SIZE + address as usize % N163_PRG_BANK_SIZE
+    }
+
+    /// Where the 1 kB `window` of PPU space ($0000-$2FFF) is mapped to: a
+    /// CIRAM page or a CHR memory offset
+    fn locate_character(&self, window: usize) -> Namco163Page {
+        let bank = self.character_banks[window];
+        let ciram_allowed = match window {
+            0..=3 => self.program_banks[1] & 0x40 == 0,
+            4..=7 => self.program_banks[1] & 0x80 == 0,
+            _ => true,
+        };
+        if bank >= N163_CIRAM_BANKS && ciram_allowed {
+            Namco163Page::Ciram(bank as usize & 1)
+        } else {
+            let banks = (self.character_memory.size() / N163_CHR_BANK_SIZE).max(1);
+            Namco163Page::Character(bank as usize % banks * N163_CHR_BANK_SIZE)
+        }
+    }
+
+    fn read_character(&self, address: u16) -> u8 {
+        let offset = address as usize % N163_CHR_BANK_SIZE;
+        match self.locate_character(address as usize / N163_CHR_BANK_SIZE) {
+            Namco163Page::Ciram(cell) => match self.nametables.as_ref() {
+                Some(nametables) => nametables.borrow().read_cell(cell, offset as u16),
+                None => 0,
+            },
+            Namco163Page::Character(base) => self.character_memory.as_slice()[base + offset],
+        }
+    }
+
+    fn write_character(&mut self, address: u16, data: u8) {
+        let offset = address as usize % N163_CHR_BANK_SIZE;
+        match self.locate_character(address as usize / N163_CHR_BANK_SIZE) {
+            Namco163Page::Ciram(cell) => {
+                if let Some(nametables) = self.nametables.as_ref() {
+                    nametables
+                        .borrow_mut()
+                        .write_cell(cell, offset as u16, data);
+                }
+            }
+            Namco163Page::Character(base) => {
+                if self.character_ram {
+                    self.character_memory.as_mut_slice()[base + offset] = data;
+                }
+            }
+        }
+    }
+
+    /// Whether the 2 kB window of PRG RAM containing `address` is writable
+    fn program_ram_writable(&self, address: u16) -> bool {
+        let protection = self.address_port;
+        let window = address as usize / (N163_PRG_RAM_SIZE / 4);
+        protection & 0xF0 == 0x40 && protection & (1 << window) == 0
+    }
+
+    /// Internal RAM address selected by the address port, incremented after
+    /// the access if auto-increment is enabled
+    fn internal_ram_address(&mut self) -> usize {
+        let address = (self.address_port & 0x7F) as usize;
+        if self.address_port & 0x80 != 0 {
+            self.address_port = 0x80 | (self.address_port.wrapping_add(1) & 0x7F);
+        }
+        address
+    }
+
+    /// Report writes to the sound channel registers at the end of the
+    /// internal RAM while sound is enabled, as nothing plays them
+    fn report_audio_write(&self, address: usize) {
+        if address < N163_SOUND_REGISTERS_START || self.program_banks[0] & 0x40 != 0 {
+            return;
+        }
+        trace!("Namco 163 sound register write (not heard)");
+        if let Some(warnings) = self.warnings.as_ref() {
+            warnings
+                .borrow_mut()
+                .report(WarningKind::ExpansionAudioNotEmulated, Some(0x4800));
+        }
+    }
+
+    fn acknowledge_irq(&self) {
+        if let Some(irq_line) = self.irq_line.as_ref() {
+            irq_line.release();
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if !self.irq_enabled || self.irq_counter == N163_IRQ_COUNTER_MAX {
+            return;
+        }
+        self.irq_counter += 1;
+        if self.irq_counter == N163_IRQ_COUNTER_MAX {
+            if let Some(irq_line) = self.irq_line.as_ref() {
+                irq_line.assert();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Namco163Page {
+    /// CIRAM cell
+    Ciram(usize),
+    /// CHR memory offset of the bank
+    Character(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Namco163Window {
+    /// CPU $4020-$5FFF: internal RAM data port and IRQ counter
+    Expansion,
+    /// CPU $6000-$7FFF
+    ProgramRam,
+    /// CPU $8000-$FFFF: PRG ROM banks and mapper registers
+    ProgramRom,
+    /// PPU $0000-$1FFF
+    Character,
+    /// PPU $2000-$2FFF
+    Nametables,
+}
+
+/// A view of a Namco 163 board as seen from one of the buses
+pub struct Namco163Memory {
+    board: Rc<RefCell<Namco163Board>>,
+    window: Namco163Window,
+}
+
+impl Memory for Namco163Memory {
+    fn read(&self, address: u16) -> u8 {
+        match self.window {
+            Namco163Window::Expansion => {
+                let mut board = self.board.borrow_mut();
+                // Relative to $4020
+                match address + 0x20 {
+                    0x0800..=0x0FFF => {
+                        let address = board.internal_ram_address();
+                        board.internal_ram[address]
+                    }
+                    0x1000..=0x17FF => board.irq_counter as u8,
+                    0x1800..=0x1FFF => {
+                        (board.irq_counter >> 8) as u8 | (board.irq_enabled as u8) << 7
+                    }
+                    // Open bus, usually the high byte of the address
+                    address => 0x40 + (address >> 8) as u8,
+                }
+            }
+            Namco163Window::ProgramRam => self.board.borrow().program_ram.read(address),
+            Namco163Window::ProgramRom => {
+                let board = self.board.borrow();
+                board.program_rom[board.program_rom_window(address)]
+            }
+            Namco163Window::Character => self.board.borrow().read_character(address),
+            Namco163Window::Nametables => self.board.borrow().read_character(0x2000 + address),
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        let mut board = self.board.borrow_mut();
+        match self.window {
+            Namco163Window::Expansion => match address + 0x20 {
+                0x0800..=0x0FFF => {
+                    let address = board.internal_ram_address();
+                    board.internal_ram[address] = data;
+                    board.report_audio_write(address);
+                }
+                0x1000..=0x17FF => {
+                    board.irq_counter = (board.irq_counter & 0x7F00) | data as u16;
+                    board.acknowledge_irq();
+                }
+                0x1800..=0x1FFF => {
+                    board.irq_counter = (board.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                    board.irq_enabled = data & 0x80 != 0;
+                    board.acknowledge_irq();
+                }
+                _ => {}
+            },
+            Namco163Window::ProgramRam => {
+                if board.program_ram_writable(address) {
+                    board.program_ram.write(address, data);
+                }
+            }
+            Namco163Window::ProgramRom => match address {
+                0x0000..=0x5FFF => {
+                    let register = address as usize / 0x0800;
+                    board.character_banks[register] = data;
+                }
+                0x6000..=0x77FF => {
+                    let register = (address as usize - 0x6000) / 0x0800;
+                    board.program_banks[register] = data;
+                }
+                _ => board.address_port = data,
+            },
+            Namco163Window::Character => board.write_character(address, data),
+            Namco163Window::Nametables => board.write_character(0x2000 + address, data),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self.window {
+            Namco163Window::Expansion => 0x2000 - 0x20,
+            Namco163Window::ProgramRam => N163_PRG_RAM_SIZE,
+            Namco163Window::ProgramRom => 4 * N163_PRG_BANK_SIZE,
+            Namco163Window::Character => 8 * N163_CHR_BANK_SIZE,
+            Namco163Window::Nametables => 4 * N163_CHR_BANK_SIZE,
+        }
+    }
+}
+
+/// Namco 163 wavetable audio. Up to 8 channels, configured in the internal
+/// RAM, play 4-bit samples also stored in it. A single channel is updated
+/// every 15 CPU cycles, so the more channels enabled, the lower their rate
+#[cfg(feature = "namco163-audio")]
+#[derive(Clone, Default)]
+struct Namco163Audio {
+    // CPU cycles since the last channel update
+    cycles: u8,
+    // Channel updated next, counting down from 7
+    channel: usize,
+    // Last output of every channel, from -120 to 105
+    outputs: [i16; 8],
+}
+
+#[cfg(feature = "namco163-audio")]
+impl Namco163Audio {
+    const CYCLES_PER_CHANNEL: u8 = 15;
+
+    fn enabled_channels(ram: &[u8; N163_INTERNAL_RAM_SIZE]) -> usize {
+        (ram[0x7F] >> 4 & 0x07) as usize + 1
+    }
+
+    fn clock(&mut self, ram: &mut [u8; N163_INTERNAL_RAM_SIZE]) {
+        self.cycles += 1;
+        if self.cycles < Self::CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycles = 0;
+
+        let enabled = Self::enabled_channels(ram);
+        if self.channel < 8 - enabled {
+            self.channel = 7;
+        }
+        let base = 0x40 + self.channel * 8;
+        let registers = &mut ram[base..base + 8];
+
+        let frequency = u32::from_le_bytes([registers[0], registers[2], registers[4] & 0x03, 0]);
+        let length = (256 - (registers[4] & 0xFC) as u32) << 16;
+        let mut phase = u32::from_le_bytes([registers[1], registers[3], registers[5], 0]);
+        phase = (phase + frequency) % length;
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let sample_address = ((phase >> 16) as usize + registers[6] as usize) & 0xFF;
+        let volume = (registers[7] & 0x0F) as i16;
+        let byte = ram[sample_address / 2];
+        let sample = if sample_address & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        self.outputs[self.channel] = (sample as i16 - 8) * volume;
+
+        self.channel = self.channel.checked_sub(1).unwrap_or(7);
+    }
+
+    /// Mix of the enabled channels, from -1.0 to 1.0
+    fn output(&self, ram: &[u8; N163_INTERNAL_RAM_SIZE]) -> f32 {
+        let enabled = Self::enabled_channels(ram);
+        let sum: i16 = self.outputs[8 - enabled..].iter().sum();
+        sum as f32 / (enabled as f32 * 120.0)
+    }
+}
+
+/// Namco 163 boards (mapper 19)
+pub struct Namco163Mapper {
+    board: Rc<RefCell<Namco163Board>>,
+    expansion: Rc<RefCell<Namco163Memory>>,
+    program_ram: Rc<RefCell<Namco163Memory>>,
+    program_rom: Rc<RefCell<Namco163Memory>>,
+    character_memory: Rc<RefCell<Namco163Memory>>,
+    nametables: Rc<RefCell<Namco163Memory>>,
+}
+
+impl Namco163Mapper {
+    pub fn new(specs: MapperSpecs) -> Self {
+        // Boards without CHR ROM come with 8 kB of CHR RAM
+        let character_ram = specs.character_memory_capacity == 0;
+        let character_memory_capacity = if character_ram {
+            8 * N163_CHR_BANK_SIZE
+        } else {
+            specs.character_memory_capacity
+        };
+
+        let board = Rc::new(RefCell::new(Namco163Board {
+            program_rom: vec![0; specs.program_rom_capacity],
+            program_ram: Ram::new(N163_PRG_RAM_SIZE),
+            character_memory: Ram::new(character_memory_capacity),
+            character_ram,
+            internal_ram: [0; N163_INTERNAL_RAM_SIZE],
+            character_banks: [0; 12],
+            program_banks: [0; 3],
+            address_port: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_line: None,
+            nametables: None,
+            warnings: None,
+            #[cfg(feature = "namco163-audio")]
+            audio: Namco163Audio::default(),
+        }));
+        let view = |window| {
+            Rc::new(RefCell::new(Namco163Memory {
+                board: Rc::clone(&board),
+                window,
+            }))
+        };
+
+        Self {
+            expansion: view(Namco163Window::Expansion),
+            program_ram: view(Namco163Window::ProgramRam),
+            program_rom: view(Namco163Window::ProgramRom),
+            character_memory: view(Namco163Window::Character),
+            nametables: view(Namco163Window::Nametables),
+            board,
+        }
+    }
+}
+
+impl Mapper for Namco163Mapper {
+    fn load_program_rom(&mut self, data: &[u8]) {
+        self.board.borrow_mut().program_rom = data.to_vec();
+    }
+    fn load_character_memory(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.board.borrow_mut().character_memory = Ram::from(data.to_vec());
+        }
+    }
+
+    fn program_ram_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_ram) as _
+    }
+
+    fn program_rom_ref(&self) -> SharedMemory {
+        Rc::clone(&self.program_rom) as _
+    }
+
+    fn character_memory_ref(&self) -> SharedMemory {
+        Rc::clone(&self.character_memory) as _
+    }
+
+    fn expansion_ref(&self) -> Option<SharedMemory> {
+        Some(Rc::clone(&self.expansion) as _)
+    }
+
+    fn nametables_ref(&self) -> Option<SharedMemory> {
+        Some(Rc::clone(&self.nametables) as _)
+    }
+
+    fn program_rom_offset(&self, address: u16) -> Option<usize> {
+        Some(self.board.borrow().program_rom_window(address))
+    }
+
+    fn connect_irq_line(&mut self, line: InterruptLine) {
+        self.board.borrow_mut().irq_line = Some(line);
+    }
+
+    fn connect_nametables(&mut self, nametables: SharedCiram) {
+        self.board.borrow_mut().nametables = Some(nametables);
+    }
+
+    fn connect_warnings(&mut self, warnings: SharedWarnings) {
+        self.board.borrow_mut().warnings = Some(warnings);
+    }
+
+    fn clock_cpu(&mut self) {
+        let mut board = self.board.borrow_mut();
+        board.clock_irq_counter();
+
+        #[cfg(feature = "namco163-audio")]
+        {
+            let board = &mut *board;
+            board.audio.clock(&mut board.internal_ram);
+        }
+    }
+
+    // Wavetable output, silent while sound is disabled (bit 6 of the $8000
+    // PRG bank register)
+    #[cfg(feature = "namco163-audio")]
+    fn audio_output(&self) -> Option<f32> {
+        let board = self.board.borrow();
+        if board.program_banks[0] & 0x40 != 0 {
+            return Some(0.0);
+        }
+        Some(board.audio.output(&board.internal_ram))
+    }
+
+    /// CHR and nametable banks, PRG banks and the address port
+    fn state(&self) -> MapperState {
+        let board = self.board.borrow();
+        let mut registers = board.character_banks.to_vec();
+        registers.extend_from_slice(&board.program_banks);
+        registers.push(board.address_port);
+        MapperState { registers }
+    }
+
+    fn snapshot(&self) -> MapperSnapshot {
+        let board = self.board.borrow();
+        let mut registers = self.state().registers;
+        registers.extend_from_slice(&board.irq_counter.to_le_bytes());
+        registers.push(board.irq_enabled as u8);
+        registers.extend_from_slice(&board.internal_ram);
+
+        MapperSnapshot {
+            program_ram: board.program_ram.clone(),
+            character_memory: if board.character_ram {
+                board.character_memory.clone()
+            } else {
+                Ram::new(0)
+            },
+            registers,
+            irq_asserted: board
+                .irq_line
+                .as_ref()
+                .is_some_and(|line| line.is_asserted()),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &MapperSnapshot) {
+        let mut board = self.board.borrow_mut();
+        board.program_ram = snapshot.program_ram.clone();
+        if board.character_ram {
+            board.character_memory = snapshot.character_memory.clone();
+        }
+
+        let registers = &snapshot.registers;
+        board.character_banks.copy_from_slice(&registers[0..12]);
+        board.program_banks.copy_from_slice(&registers[12..15]);
+        board.address_port = registers[15];
+        board.irq_counter = u16::from_le_bytes([registers[16], registers[17]]);
+        board.irq_enabled = registers[18] != 0;
+        board
+            .internal_ram
+            .copy_from_slice(&registers[19..19 + N163_INTERNAL_RAM_SIZE]);
+
+        // A pending IRQ is part of the state, as much as the counter
+        if let Some(irq_line) = board.irq_line.as_ref() {
+            if snapshot.irq_asserted {
+                irq_line.assert();
+            } else {
+                irq_line.release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::memory::Ciram;
+
+    fn uxrom() -> DiscreteMapper {
+        let mut mapper = DiscreteMapper::uxrom(MapperSpecs {
+            program_rom_capacity: 4 * PRG_BANK_SIZE,
+            program_ram_capacity: 8 * 1024,
+            character_memory_capacity: 0,
+        });
+
+        // each bank filled with its number, except the first byte
+        let mut prg = vec![0; 4 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+            chunk[0] = 0xFF;
+        }
+        mapper.load_program_rom(&prg);
+        mapper
+    }
+
+    #[test]
+    fn test_nrom_ignores_program_rom_writes() {
+        let mut mapper = Mapper0::new(MapperSpecs {
+            program_rom_capacity: PRG_BANK_SIZE,
+            program_ram_capacity: 8 * 1024,
+            character_memory_capacity: CHR_BANK_SIZE,
+        });
+        mapper.load_program_rom(&vec![0xEA; PRG_BANK_SIZE]);
+        let rom = mapper.program_rom_ref();
+
+        rom.borrow_mut().write(0x0000, 0x12);
+        rom.borrow_mut().write(0x7FFF, 0x34);
+        assert_eq!(rom.borrow().read(0x0000), 0xEA);
+        assert_eq!(rom.borrow().read(0x7FFF), 0xEA);
+    }
+
+    #[test]
+    fn test_supported_mappers() {
+        let supported = supported();
+        assert_eq!(supported[0], info(0));
+        assert_eq!(info(69).status, MapperStatus::Partial);
+        assert_eq!(info(200).name, "Unknown");
+        assert_eq!(info(200).status, MapperStatus::Unsupported);
+
+        // Every mapper not reported as unsupported can be built and loaded.
+        // NROM can't have more than 32 kB of PRG ROM, other mappers get
+        // images with more banks than their registers' lowest bits select
+        for info in supported
+            .iter()
+            .filter(|info| info.status != MapperStatus::Unsupported)
+        {
+            let (program_rom_size, character_memory_size) = match info.number {
+                0 => (2 * PRG_BANK_SIZE, CHR_BANK_SIZE),
+                _ => (8 * PRG_BANK_SIZE, 8 * CHR_BANK_SIZE),
+            };
+            let mut mapper = mapper_map(
+                info.number,
+                MapperSpecs {
+                    program_rom_capacity: program_rom_size,
+                    program_ram_capacity: 8 * 1024,
+                    character_memory_capacity: character_memory_size,
+                },
+            )
+            .unwrap();
+            mapper.load_program_rom(&vec![0xEA; program_rom_size]);
+            mapper.load_character_memory(&vec![0x55; character_memory_size]);
+
+            let rom = mapper.program_rom_ref();
+            assert_eq!(rom.borrow().read(0x0000), 0xEA);
+            assert_eq!(rom.borrow().read(0x7FFF), 0xEA);
+        }
+        assert!(matches!(
+            mapper_map(
+                0xAB,
+                MapperSpecs {
+                    program_rom_capacity: 2 * PRG_BANK_SIZE,
+                    program_ram_capacity: 8 * 1024,
+                    character_memory_capacity: CHR_BANK_SIZE,
+                }
+            ),
+            Err(RomError::UnsupportedMapper(0xAB))
+        ));
+    }
+
+    #[test]
+    fn test_uxrom_bank_switching() {
+        let mapper = uxrom();
+        let rom = mapper.program_rom_ref();
+
+        assert_eq!(rom.borrow().read(0x0001), 0);
+        assert_eq!(rom.borrow().read(0x4001), 3);
+
+        rom.borrow_mut().write(0x0000, 2);
+        assert_eq!(rom.borrow().read(0x0001), 2);
+        assert_eq!(rom.borrow().read(0x4001), 3);
+    }
+
+    #[test]
+    fn test_uxrom_large_program_rom() {
+        let mut mapper = DiscreteMapper::uxrom(MapperSpecs {
+            program_rom_capacity: 8 * PRG_BANK_SIZE,
+            program_ram_capacity: 8 * 1024,
+            character_memory_capacity: 0,
+        });
+        let prg = (0..8)
+            .flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE])
+            .collect::<Vec<u8>>();
+        mapper.load_program_rom(&prg);
+        mapper.set_bus_conflicts(false);
+        let rom = mapper.program_rom_ref();
+
+        // Banks past 64 kB
+        assert_eq!(rom.borrow().read(0x4000), 7);
+        rom.borrow_mut().write(0x0000, 5);
+        assert_eq!(rom.borrow().read(0x0000), 5);
+        rom.borrow_mut().write(0x0000, 7);
+        assert_eq!(rom.borrow().read(0x3FFF), 7);
+        assert_eq!(
+            mapper.program_rom_offset(0x0010),
+            Some(7 * PRG_BANK_SIZE + 0x10)
+        );
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = uxrom();
+        let rom = mapper.program_rom_ref();
+
+        // ROM byte at 0x4001 is 3 (last bank), 2 & 3 = 2
+        rom.borrow_mut().write(0x4001, 2);
+        assert_eq!(rom.borrow().read(0x0001), 2);
+
+        // ROM byte at 0x0001 is 2 (current bank), 1 & 2 = 0
+        rom.borrow_mut().write(0x0001, 1);
+        assert_eq!(rom.borrow().read(0x0001), 0);
+
+        mapper.set_bus_conflicts(false);
+        rom.borrow_mut().write(0x0001, 1);
+        assert_eq!(rom.borrow().read(0x0001), 1);
+    }
+
+    #[test]
+    fn test_cnrom_character_banks() {
+        let mut mapper = DiscreteMapper::cnrom(MapperSpecs {
+            program_rom_capacity: PRG_BANK_SIZE,
+            program_ram_capacity: 8 * 1024,
+            character_memory_capacity: 4 * CHR_BANK_SIZE,
+        });
+        mapper.load_program_rom(&vec![0xFF; PRG_BANK_SIZE]);
+        let mut chr = vec![0; 4 * CHR_BANK_SIZE];
+        for (bank, chunk) in chr.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        mapper.load_character_memory(&chr);
+
+        let rom = mapper.program_rom_ref();
+        let chr = mapper.character_memory_ref();
+
+        // 16 kB PRG ROM is mirrored
+        assert_eq!(rom.borrow().read(0x4000), 0xFF);
+
+        rom.borrow_mut().write(0x0010, 3);
+        assert_eq!(chr.borrow().read(0x0100), 3);
+    }
+
+    fn fme7() -> Fme7Mapper {
+        let mut mapper = Fme7Mapper::new(MapperSpecs {
+            program_rom_capacity: 16 * FME7_PRG_BANK_SIZE,
+            program_ram_capacity: 0,
+            character_memory_capacity: 16 * FME7_CHR_BANK_SIZE,
+        });
+
+        // every bank filled with its number
+        let banks = |count, size| {
+            (0..count)
+                .flat_map(|bank| vec![bank as u8; size])
+                .collect::<Vec<u8>>()
+        };
+        mapper.load_program_rom(&banks(16, FME7_PRG_BANK_SIZE));
+        mapper.load_character_memory(&banks(16, FME7_CHR_BANK_SIZE));
+        mapper
+    }
+
+    fn fme7_write_register(rom: &SharedMemory, register: u8, data: u8) {
+        rom.borrow_mut().write(0x0000, register);
+        rom.borrow_mut().write(0x2000, data);
+    }
+
+    #[test]
+    fn test_fme7_banking() {
+        let mapper = fme7();
+        let ram = mapper.program_ram_ref();
+        let rom = mapper.program_rom_ref();
+        let chr = mapper.character_memory_ref();
+
+        fme7_write_register(&rom, 0x9, 3);
+        fme7_write_register(&rom, 0xB, 5);
+        fme7_write_register(&rom, 0x7, 9);
+        assert_eq!(rom.borrow().read(0x0000), 3);
+        assert_eq!(rom.borrow().read(0x4000), 5);
+        assert_eq!(rom.borrow().read(0x6000), 15);
+        assert_eq!(chr.borrow().read(0x1C00), 9);
+
+        // $6000 maps a ROM bank, then enabled RAM
+        fme7_write_register(&rom, 0x8, 7);
+        assert_eq!(ram.borrow().read(0x0000), 7);
+        ram.borrow_mut().write(0x0000, 0xAB);
+        fme7_write_register(&rom, 0x8, 0xC0);
+        ram.borrow_mut().write(0x0000, 0xCD);
+        assert_eq!(ram.borrow().read(0x0000), 0xCD);
+    }
+
+    #[test]
+    fn test_fme7_irq() {
+        let mut mapper = fme7();
+        let rom = mapper.program_rom_ref();
+        let irq_line = InterruptLine::new();
+        mapper.connect_irq_line(irq_line.clone());
+
+        fme7_write_register(&rom, 0xE, 2);
+        fme7_write_register(&rom, 0xF, 0);
+        fme7_write_register(&rom, 0xD, 0x81);
+        for _ in 0..2 {
+            mapper.clock_cpu();
+        }
+        assert!(!irq_line.is_asserted());
+        mapper.clock_cpu();
+        assert!(irq_line.is_asserted());
+
+        // Acknowledged writing IRQ control
+        fme7_write_register(&rom, 0xD, 0x00);
+        assert!(!irq_line.is_asserted());
+        mapper.clock_cpu();
+        assert_eq!(mapper.board.borrow().irq_counter, 0xFFFF);
+    }
+
+    #[test]
+    fn test_fme7_irq_restore() {
+        let mut mapper = fme7();
+        let rom = mapper.program_rom_ref();
+        let irq_line = InterruptLine::new();
+        mapper.connect_irq_line(irq_line.clone());
+
+        let clear = mapper.snapshot();
+        fme7_write_register(&rom, 0xE, 0);
+        fme7_write_register(&rom, 0xF, 0);
+        fme7_write_register(&rom, 0xD, 0x81);
+        mapper.clock_cpu();
+        assert!(irq_line.is_asserted());
+        let pending = mapper.snapshot();
+
+        mapper.restore(&clear);
+        assert!(!irq_line.is_asserted());
+        mapper.restore(&pending);
+        assert!(irq_line.is_asserted());
+    }
+
+    fn namco163() -> Namco163Mapper {
+        let mut mapper = Namco163Mapper::new(MapperSpecs {
+            program_rom_capacity: 16 * N163_PRG_BANK_SIZE,
+            program_ram_capacity: N163_PRG_RAM_SIZE,
+            character_memory_capacity: 32 * N163_CHR_BANK_SIZE,
+        });
+
+        // every bank filled with its number
+        let banks = |count, size| {
+            (0..count)
+                .flat_map(|bank| vec![bank as u8; size])
+                .collect::<Vec<u8>>()
+        };
+        mapper.load_program_rom(&banks(16, N163_PRG_BANK_SIZE));
+        mapper.load_character_memory(&banks(32, N163_CHR_BANK_SIZE));
+        mapper
+    }
+
+    #[test]
+    fn test_namco163_banking() {
+        let mut mapper = namco163();
+        let ciram = Rc::new(RefCell::new(Ciram::new(N163_CHR_BANK_SIZE)));
+        mapper.connect_nametables(Rc::clone(&ciram));
+        let rom = mapper.program_rom_ref();
+        let chr = mapper.character_memory_ref();
+        let nametables = mapper.nametables_ref().unwrap();
+
+        rom.borrow_mut().write(0x6000, 3);
+        rom.borrow_mut().write(0x7000, 5);
+        assert_eq!(rom.borrow().read(0x0000), 3);
+        assert_eq!(rom.borrow().read(0x4000), 5);
+        assert_eq!(rom.borrow().read(0x6000), 15);
+
+        // CHR banks, one of them mapped to the second CIRAM page
+        ciram.borrow_mut().write_cell(1, 0x10, 0xAB);
+        rom.borrow_mut().write(0x0800, 9);
+        rom.borrow_mut().write(0x3800, 0xE1);
+        assert_eq!(chr.borrow().read(0x0400), 9);
+        assert_eq!(chr.borrow().read(0x1C10), 0xAB);
+
+        // CIRAM disabled for $1000-$1FFF, the bank comes from CHR ROM
+        rom.borrow_mut().write(0x6800, 0x80);
+        assert_eq!(chr.borrow().read(0x1C10), 0xE1 % 32);
+
+        // Nametables from CIRAM and CHR ROM
+        rom.borrow_mut().write(0x4000, 0xE1);
+        rom.borrow_mut().write(0x5800, 7);
+        assert_eq!(nametables.borrow().read(0x0010), 0xAB);
+        nametables.borrow_mut().write(0x0011, 0xCD);
+        assert_eq!(ciram.borrow().read_cell(1, 0x11), 0xCD);
+        assert_eq!(nametables.borrow().read(0x0C00), 7);
+    }
+
+    #[test]
+    fn test_namco163_internal_ram_and_irq() {
+        let mut mapper = namco163();
+        let irq_line = InterruptLine::new();
+        mapper.connect_irq_line(irq_line.clone());
+        let rom = mapper.program_rom_ref();
+        let expansion = mapper.expansion_ref().unwrap();
+
+        // $F800 address port with auto-increment, $4800 data port
+        rom.borrow_mut().write(0x7800, 0x80 | 0x7E);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x12);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x34);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x56);
+        assert_eq!(mapper.board.borrow().internal_ram[0x7E..], [0x12, 0x34]);
+        assert_eq!(mapper.board.borrow().internal_ram[0x00], 0x56);
+
+        expansion.borrow_mut().write(0x5000 - 0x4020, 0xFD);
+        expansion.borrow_mut().write(0x5800 - 0x4020, 0x80 | 0x7F);
+        mapper.clock_cpu();
+        assert!(!irq_line.is_asserted());
+        mapper.clock_cpu();
+        assert!(irq_line.is_asserted());
+
+        // Stopped at $7FFF, acknowledged writing the counter
+        mapper.clock_cpu();
+        assert_eq!(expansion.borrow().read(0x5000 - 0x4020), 0xFF);
+        expansion.borrow_mut().write(0x5000 - 0x4020, 0x00);
+        assert!(!irq_line.is_asserted());
+    }
+
+    #[test]
+    fn test_namco163_irq_restore() {
+        let mut mapper = namco163();
+        let irq_line = InterruptLine::new();
+        mapper.connect_irq_line(irq_line.clone());
+        let expansion = mapper.expansion_ref().unwrap();
+
+        let clear = mapper.snapshot();
+        expansion.borrow_mut().write(0x5000 - 0x4020, 0xFE);
+        expansion.borrow_mut().write(0x5800 - 0x4020, 0x80 | 0x7F);
+        mapper.clock_cpu();
+        assert!(irq_line.is_asserted());
+        let pending = mapper.snapshot();
+
+        mapper.restore(&clear);
+        assert!(!irq_line.is_asserted());
+        mapper.restore(&pending);
+        assert!(irq_line.is_asserted());
+    }
+
+    #[test]
+    fn test_namco163_sound_warnings() {
+        let mut mapper = namco163();
+        let warnings = Rc::new(RefCell::new(crate::warnings::Warnings::new()));
+        mapper.connect_warnings(Rc::clone(&warnings));
+        let rom = mapper.program_rom_ref();
+        let expansion = mapper.expansion_ref().unwrap();
+
+        // Wavetable samples aren't sound registers
+        rom.borrow_mut().write(0x7800, 0x3F);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x12);
+        assert!(warnings.borrow().summaries().is_empty());
+
+        // Nor are channel registers while sound is disabled
+        rom.borrow_mut().write(0x6000, 0x40);
+        rom.borrow_mut().write(0x7800, 0x7F);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x70);
+        assert!(warnings.borrow().summaries().is_empty());
+
+        rom.borrow_mut().write(0x6000, 0x00);
+        expansion.borrow_mut().write(0x4800 - 0x4020, 0x70);
+        let summaries = warnings.borrow().summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].kind, WarningKind::ExpansionAudioNotEmulated);
+    }
+
+    #[test]
+    fn test_expansion_audio_output() {
+        assert_eq!(uxrom().audio_output(), None);
+        assert_eq!(
+            namco163().audio_output().is_some(),
+            cfg!(feature = "namco163-audio")
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! Mappers known by the emulator and how well they're emulated. Mapper
+//! implementations are internal to the emulator
+
+pub(crate) mod boards;
+
+pub(crate) use boards::*;
+pub use boards::{info, supported, MapperInfo, MapperStatus};
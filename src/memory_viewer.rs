@@ -0,0 +1,235 @@
+//! Memory viewer
+//!
+//! Hexdumps of a bus range for debuggers. [`MemoryViewer`] takes a snapshot of
+//! its range every time it's refreshed (e.g., once per frame) and marks the
+//! bytes that changed since the previous snapshot, so UIs can highlight them.
+//! Snapshots are available as rows of cells or formatted as text:
+//!
+//! ```text
+//! $0300  00 1F 20 3C 00 00 00 00  00 00 00 00 -- -- 00 00  |.. <............|
+//! ```
+//!
+//! Memory is read through a function, usually [`Nes::peek`](crate::Nes::peek),
+//! so viewing memory has no side effects. Addresses it can't read (e.g., I/O
+//! registers) are shown as `--`.
+
+use std::fmt::Write;
+
+use crate::interfaces::AddressRange;
+
+/// Bytes per hexdump row
+pub const ROW_SIZE: usize = 16;
+
+// ANSI escape codes used to highlight changed bytes in text hexdumps
+const HIGHLIGHT_START: &str = "\x1b[93m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// A byte of a [`MemoryDump`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HexCell {
+    pub address: u16,
+    /// `None` if the address can't be read
+    pub value: Option<u8>,
+    /// Whether the value is different in the previous snapshot
+    pub changed: bool,
+}
+
+/// Up to [`ROW_SIZE`] consecutive bytes of a [`MemoryDump`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HexRow {
+    pub address: u16,
+    pub cells: Vec<HexCell>,
+}
+
+/// Snapshot of a bus range
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryDump {
+    start: u16,
+    cells: Vec<HexCell>,
+}
+
+impl MemoryDump {
+    /// Read every address in `range` with `read`. No byte is marked as changed
+    pub fn capture(range: AddressRange, mut read: impl FnMut(u16) -> Option<u8>) -> Self {
+        let cells = (range.start..=range.end)
+            .map(|address| HexCell {
+                address,
+                value: read(address),
+                changed: false,
+            })
+            .collect();
+        Self {
+            start: range.start,
+            cells,
+        }
+    }
+
+    pub fn range(&self) -> AddressRange {
+        AddressRange {
+            start: self.start,
+            end: self.start + (self.cells.len() - 1) as u16,
+        }
+    }
+
+    pub fn cells(&self) -> &[HexCell] {
+        &self.cells
+    }
+
+    /// Value at `address`, if it's in range and could be read
+    pub fn value(&self, address: u16) -> Option<u8> {
+        let index = address.checked_sub(self.start)? as usize;
+        self.cells.get(index)?.value
+    }
+
+    /// Addresses whose value changed since the previous snapshot
+    pub fn changes(&self) -> Vec<u16> {
+        self.cells
+            .iter()
+            .filter(|cell| cell.changed)
+            .map(|cell| cell.address)
+            .collect()
+    }
+
+    /// Mark the bytes different in `previous`. Snapshots of other ranges are
+    /// compared where they overlap
+    pub fn compare(&mut self, previous: &MemoryDump) {
+        for cell in self.cells.iter_mut() {
+            let address = cell.address;
+            cell.changed = address >= previous.start
+                && previous
+                    .cells
+                    .get((address - previous.start) as usize)
+                    .is_some_and(|previous| previous.value != cell.value);
+        }
+    }
+
+    pub fn rows(&self) -> Vec<HexRow> {
+        self.cells
+            .chunks(ROW_SIZE)
+            .map(|cells| HexRow {
+                address: cells[0].address,
+                cells: cells.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Hexdump with an address, the bytes and their ASCII representation per
+    /// row. If `highlight`, changed bytes are highlighted with ANSI colors
+    pub fn to_text(&self, highlight: bool) -> String {
+        let mut text = String::new();
+        for row in self.rows() {
+            write!(text, "${:0>4X} ", row.address).unwrap();
+            for index in 0..ROW_SIZE {
+                if index % 8 == 0 {
+                    text.push(' ');
+                }
+                match row.cells.get(index) {
+                    Some(cell) if cell.changed && highlight => {
+                        text.push_str(HIGHLIGHT_START);
+                        text.push_str(&hex(cell.value));
+                        text.push_str(HIGHLIGHT_END);
+                    }
+                    Some(cell) => text.push_str(&hex(cell.value)),
+                    None => text.push_str("  "),
+                }
+                text.push(' ');
+            }
+
+            text.push_str(" |");
+            for cell in row.cells.iter() {
+                text.push(match cell.value {
+                    Some(value) if value.is_ascii_graphic() || value == b' ' => value as char,
+                    _ => '.',
+                });
+            }
+            text.push_str("|\n");
+        }
+        text
+    }
+}
+
+fn hex(value: Option<u8>) -> String {
+    value.map_or("--".to_string(), |value| format!("{value:0>2X}"))
+}
+
+/// Hexdump of a bus range refreshed over time, comparing each snapshot with
+/// the previous one
+#[derive(Debug)]
+pub struct MemoryViewer {
+    range: AddressRange,
+    dump: Option<MemoryDump>,
+}
+
+impl MemoryViewer {
+    pub fn new(range: AddressRange) -> Self {
+        Self { range, dump: None }
+    }
+
+    pub fn range(&self) -> AddressRange {
+        self.range
+    }
+
+    /// View another range. The next snapshot has no changes
+    pub fn set_range(&mut self, range: AddressRange) {
+        self.range = range;
+        self.dump = None;
+    }
+
+    /// Take a new snapshot reading memory with `read` (see
+    /// [`Nes::peek`](crate::Nes::peek)) and compare it with the previous one
+    pub fn refresh(&mut self, read: impl FnMut(u16) -> Option<u8>) -> &MemoryDump {
+        let mut dump = MemoryDump::capture(self.range, read);
+        if let Some(previous) = self.dump.as_ref() {
+            dump.compare(previous);
+        }
+        self.dump.insert(dump)
+    }
+
+    /// Last snapshot taken, if any
+    pub fn dump(&self) -> Option<&MemoryDump> {
+        self.dump.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_viewer_changes() {
+        let mut memory = [0u8; 0x20];
+        let read = |memory: &[u8; 0x20]| {
+            let memory = *memory;
+            move |address: u16| (address != 0x0C).then(|| memory[address as usize])
+        };
+        let mut viewer = MemoryViewer::new(AddressRange {
+            start: 0x00,
+            end: 0x13,
+        });
+
+        let dump = viewer.refresh(read(&memory));
+        assert!(dump.changes().is_empty());
+        assert_eq!(dump.value(0x0C), None);
+
+        memory[0x02] = b'A';
+        memory[0x12] = 0xFF;
+        memory[0x1F] = 0xFF;
+        let dump = viewer.refresh(read(&memory));
+        assert_eq!(dump.changes(), [0x02, 0x12]);
+        assert_eq!(dump.value(0x12), Some(0xFF));
+
+        let rows = dump.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[1].address, rows[1].cells.len()), (0x10, 4));
+
+        let text = dump.to_text(false);
+        assert_eq!(
+            text.lines().next().unwrap(),
+            "$0000  00 00 41 00 00 00 00 00  00 00 00 00 -- 00 00 00  |..A.............|"
+        );
+        assert!(dump.to_text(true).contains("\x1b[93mFF\x1b[0m"));
+
+        let dump = viewer.refresh(read(&memory));
+        assert!(dump.changes().is_empty());
+    }
+}
@@ -1,48 +1,126 @@
 //! This module provides a way to gather metrics for the NES
 //!
+//! Metrics are collected continuously while the NES runs and reported
+//! periodically (every second by default). Frontends can query the last report
+//! with `Nes::metrics` or register a callback to be notified of every new one.
+//!
+//! The time between the last frames is kept too, see [`Collector::frame_times`]
+//!
 
+use std::collections::VecDeque;
 use std::time::Duration;
 use std::time::Instant;
 
 use log::debug;
 
+use crate::hardware::CPU_CLOCK_DIVIDER;
+
+/// Default time between metric reports
+pub const DEFAULT_REPORT_PERIOD: Duration = Duration::from_secs(1);
+
+/// Number of frame times kept, about 2 seconds
+pub const FRAME_TIME_HISTORY: usize = 120;
+
+/// Callback invoked every time a new metrics report is available
+pub type MetricsCallback = Box<dyn FnMut(&Metrics)>;
+
 #[derive(Debug)]
 struct RawMetrics {
     record_start: Instant,
     clocks: u64,
     frames_rendered: usize,
+    frames_dropped: usize,
+    frames_duplicated: usize,
 }
 
-#[derive(Debug)]
+/// Performance report for a period of time
+#[derive(Clone, Debug, Default)]
 pub struct Metrics {
+    /// Period of time this report covers
     pub recorded_time: Duration,
-    pub clock_speed_mhz: usize,
-    pub frames_per_second: usize,
+
+    /// Emulated system clock speed attained
+    pub clock_speed_mhz: f64,
+
+    /// Emulated CPU clock speed attained. A real NTSC NES runs at ~1.79 MHz,
+    /// faster with a non-authentic [`CpuSpeed`](crate::settings::CpuSpeed)
+    pub cpu_clock_speed_mhz: f64,
+
+    /// Frames produced by the PPU per second
+    pub frames_per_second: f64,
+
+    /// Frames produced by the PPU that the UI replaced before presenting them
+    pub dropped_frames: usize,
+
+    /// Frames identical to the previous one, not handed to the UI
+    pub duplicate_frames: usize,
+}
+
+impl Metrics {
+    /// Serialize metrics as a JSON object, so they can be easily exported to
+    /// other tools
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"recorded_time_ms\":{},\"clock_speed_mhz\":{:.3},\"cpu_clock_speed_mhz\":{:.3},\"frames_per_second\":{:.2},\"dropped_frames\":{},\"duplicate_frames\":{}}}",
+            self.recorded_time.as_millis(),
+            self.clock_speed_mhz,
+            self.cpu_clock_speed_mhz,
+            self.frames_per_second,
+            self.dropped_frames,
+            self.duplicate_frames,
+        )
+    }
 }
 
 pub struct Collector {
     collecting: RawMetrics,
+    report_period: Duration,
+    // Time between the last frames, oldest first
+    frame_times: VecDeque<Duration>,
+    last_frame: Option<Instant>,
+    // System clocks per CPU cycle
+    cpu_clock_divider: u64,
 }
 
 impl Collector {
-    pub fn new() -> Self {
+    /// Collector for a CPU running a cycle every `cpu_clock_divider` system
+    /// clocks
+    pub fn new(cpu_clock_divider: u64) -> Self {
         Self {
             collecting: RawMetrics::default(),
+            report_period: DEFAULT_REPORT_PERIOD,
+            frame_times: VecDeque::with_capacity(FRAME_TIME_HISTORY),
+            last_frame: None,
+            cpu_clock_divider,
         }
     }
 
     pub fn collect(&mut self) -> Metrics {
+        let recorded_time = self.collecting.record_start.elapsed();
+        self.collect_over(recorded_time)
+    }
+
+    // Report the metrics collected over `recorded_time`
+    fn collect_over(&mut self, recorded_time: Duration) -> Metrics {
         debug!("Raw metrics: {:?}", self.collecting);
-        let recorded_time = Instant::now() - self.collecting.record_start;
-        let clock_speed_mhz =
-            (self.collecting.clocks as u128) * 1_000_000 / recorded_time.as_micros() / 1_000_000;
-        let frames_per_second =
-            (self.collecting.frames_rendered as u128) * 1_000_000 / recorded_time.as_micros();
+        let seconds = recorded_time.as_secs_f64();
+
+        let (clock_speed_mhz, frames_per_second) = if seconds > 0.0 {
+            (
+                self.collecting.clocks as f64 / seconds / 1_000_000.0,
+                self.collecting.frames_rendered as f64 / seconds,
+            )
+        } else {
+            (0.0, 0.0)
+        };
 
         let metrics = Metrics {
             recorded_time,
-            clock_speed_mhz: clock_speed_mhz as usize,
-            frames_per_second: frames_per_second as usize,
+            clock_speed_mhz,
+            cpu_clock_speed_mhz: clock_speed_mhz / self.cpu_clock_divider as f64,
+            frames_per_second,
+            dropped_frames: self.collecting.frames_dropped,
+            duplicate_frames: self.collecting.frames_duplicated,
         };
         debug!("Metrics: {:?}", metrics);
 
@@ -51,12 +129,50 @@ impl Collector {
         metrics
     }
 
+    /// Whether enough time has passed since the last report to generate a new
+    /// one
+    pub fn should_report(&self) -> bool {
+        self.collecting.record_start.elapsed() >= self.report_period
+    }
+
     pub fn observe_system_clocks(&mut self, clocks: u64) {
         self.collecting.clocks += clocks;
     }
 
     pub fn observe_frame_ready(&mut self) {
+        self.observe_frame_ready_at(Instant::now());
+    }
+
+    fn observe_frame_ready_at(&mut self, now: Instant) {
         self.collecting.frames_rendered += 1;
+
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.frame_times.len() == FRAME_TIME_HISTORY {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(now - last_frame);
+        }
+    }
+
+    /// Wall time between each of the last [`FRAME_TIME_HISTORY`] frames and
+    /// the previous one, oldest first. Unlike the other metrics, they're not
+    /// reset by every report
+    pub fn frame_times(&self) -> impl ExactSizeIterator<Item = Duration> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    pub fn observe_dropped_frames(&mut self, frames: usize) {
+        self.collecting.frames_dropped += frames;
+    }
+
+    pub fn observe_duplicate_frame(&mut self) {
+        self.collecting.frames_duplicated += 1;
+    }
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new(CPU_CLOCK_DIVIDER)
     }
 }
 
@@ -65,6 +181,8 @@ impl RawMetrics {
         self.record_start = Instant::now();
         self.clocks = 0;
         self.frames_rendered = 0;
+        self.frames_dropped = 0;
+        self.frames_duplicated = 0;
     }
 }
 
@@ -74,6 +192,92 @@ impl Default for RawMetrics {
             record_start: Instant::now(),
             clocks: 0,
             frames_rendered: 0,
+            frames_dropped: 0,
+            frames_duplicated: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_resets_counters() {
+        let mut collector = Collector::default();
+        collector.observe_system_clocks(21_477_272);
+        collector.observe_frame_ready();
+        collector.observe_dropped_frames(2);
+        collector.observe_duplicate_frame();
+
+        let metrics = collector.collect_over(Duration::from_millis(500));
+        assert_eq!(metrics.clock_speed_mhz, 42.954544);
+        assert_eq!(metrics.cpu_clock_speed_mhz, 42.954544 / 12.0);
+        assert_eq!(metrics.frames_per_second, 2.0);
+        assert_eq!(metrics.dropped_frames, 2);
+        assert_eq!(metrics.duplicate_frames, 1);
+
+        let metrics = collector.collect_over(Duration::from_millis(500));
+        assert_eq!(metrics.dropped_frames, 0);
+        assert_eq!(metrics.frames_per_second, 0.0);
+
+        // Nothing can be measured without time
+        collector.observe_frame_ready();
+        assert_eq!(
+            collector.collect_over(Duration::ZERO).frames_per_second,
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_non_authentic_cpu_speed() {
+        // Overclocked twice
+        let mut collector = Collector::new(CPU_CLOCK_DIVIDER / 2);
+        collector.observe_system_clocks(21_477_272);
+
+        let metrics = collector.collect_over(Duration::from_secs(1));
+        assert_eq!(metrics.clock_speed_mhz, 21.477272);
+        assert_eq!(metrics.cpu_clock_speed_mhz, 21.477272 / 6.0);
+    }
+
+    #[test]
+    fn test_frame_times() {
+        let mut collector = Collector::default();
+        let start = Instant::now();
+        collector.observe_frame_ready_at(start);
+        assert_eq!(collector.frame_times().len(), 0);
+
+        let frame_time = Duration::from_millis(16);
+        let frames = FRAME_TIME_HISTORY as u32 + 10;
+        for frame in 1..=frames {
+            collector.observe_frame_ready_at(start + frame_time * frame);
+        }
+        collector.collect();
+        assert_eq!(collector.frame_times().len(), FRAME_TIME_HISTORY);
+        assert!(collector.frame_times().all(|time| time == frame_time));
+
+        let last_frame = start + frame_time * frames;
+        collector.observe_frame_ready_at(last_frame + Duration::from_millis(2));
+        assert_eq!(
+            collector.frame_times().last(),
+            Some(Duration::from_millis(2))
+        );
+    }
+
+    #[test]
+    fn test_metrics_to_json() {
+        let metrics = Metrics {
+            recorded_time: Duration::from_millis(1000),
+            clock_speed_mhz: 21.477,
+            cpu_clock_speed_mhz: 1.78975,
+            frames_per_second: 60.1,
+            dropped_frames: 3,
+            duplicate_frames: 5,
+        };
+
+        assert_eq!(
+            metrics.to_json(),
+            "{\"recorded_time_ms\":1000,\"clock_speed_mhz\":21.477,\"cpu_clock_speed_mhz\":1.790,\"frames_per_second\":60.10,\"dropped_frames\":3,\"duplicate_frames\":5}"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Movie files
+//!
+//! Movies are input recordings for the whole console: the state of both
+//! controllers and console commands (like resets) for every frame. They're
+//! used by tool-assisted speedruns (TAS) and are exchanged between emulators.
+//!
+//! Supported formats:
+//! - FCEUX `.fm2` text movies. Binary FM2 movies are not supported
+//! - BizHawk `.bk2` input logs. BK2 files are zip archives, only its
+//!   `Input Log.txt` contents are handled here
+//!
+//! See more information: https://fceux.com/web/FM2.html
+//!
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use bitflags::bitflags;
+
+use crate::controller::ControllerState;
+use crate::errors::MovieError;
+use crate::input_macro::InputMacro;
+
+const FM2_VERSION: &str = "3";
+
+const INPUT_DELAY_HEADER: &str = "inputDelay";
+
+// Buttons in FM2 order (RLDUTSBA)
+const FM2_BUTTONS: [ControllerState; 8] = [
+    ControllerState::RIGHT,
+    ControllerState::LEFT,
+    ControllerState::DOWN,
+    ControllerState::UP,
+    ControllerState::START,
+    ControllerState::SELECT,
+    ControllerState::B,
+    ControllerState::A,
+];
+const FM2_MNEMONICS: &str = "RLDUTSBA";
+
+// Buttons in BK2 order (UDLRSsBA)
+const BK2_BUTTONS: [ControllerState; 8] = [
+    ControllerState::UP,
+    ControllerState::DOWN,
+    ControllerState::LEFT,
+    ControllerState::RIGHT,
+    ControllerState::START,
+    ControllerState::SELECT,
+    ControllerState::B,
+    ControllerState::A,
+];
+const BK2_MNEMONICS: &str = "UDLRSsBA";
+const BK2_LOG_KEY: &str = "LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|";
+
+bitflags! {
+    /// Console commands executed at the beginning of a frame
+    #[derive(Default)]
+    pub struct MovieCommands: u8 {
+        const SOFT_RESET = 0b0000_0001;
+        const HARD_RESET = 0b0000_0010;
+        const FDS_INSERT = 0b0000_0100;
+        const FDS_SELECT = 0b0000_1000;
+        const VS_INSERT_COIN = 0b0001_0000;
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MovieFrame {
+    pub commands: MovieCommands,
+    pub port0: ControllerState,
+    pub port1: ControllerState,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Movie {
+    /// Header metadata (key, value) pairs, in file order
+    header: Vec<(String, String)>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a movie from the input macros of both controllers. The shortest
+    /// macro is padded with released buttons
+    pub fn from_macros(port0: &InputMacro, port1: &InputMacro) -> Self {
+        let frames = (0..port0.len().max(port1.len()))
+            .map(|frame| MovieFrame {
+                commands: MovieCommands::empty(),
+                port0: port0.frames().get(frame).copied().unwrap_or_default(),
+                port1: port1.frames().get(frame).copied().unwrap_or_default(),
+            })
+            .collect();
+
+        Self {
+            header: Vec::new(),
+            frames,
+        }
+    }
+
+    /// Frames controllers input was delayed while recording, stored in the
+    /// `inputDelay` header. Movies from other emulators have none
+    pub fn input_delay(&self) -> u8 {
+        self.header(INPUT_DELAY_HEADER)
+            .and_then(|delay| delay.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    pub fn set_input_delay(&mut self, frames: u8) {
+        self.set_header(INPUT_DELAY_HEADER, frames);
+    }
+
+    /// Input of a controller `port` (0 or 1) as a macro
+    pub fn port_macro(&self, port: usize) -> InputMacro {
+        self.frames
+            .iter()
+            .map(|frame| match port {
+                0 => frame.port0,
+                _ => frame.port1,
+            })
+            .collect()
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_header(&mut self, key: &str, value: impl ToString) {
+        match self.header.iter_mut().find(|(k, _)| k == key) {
+            Some((_, current)) => *current = value.to_string(),
+            None => self.header.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn load_fm2(path: impl AsRef<Path>) -> Result<Self, MovieError> {
+        Self::from_fm2(&fs::read_to_string(path)?)
+    }
+
+    pub fn save_fm2(&self, path: impl AsRef<Path>) -> Result<(), MovieError> {
+        fs::write(path, self.to_fm2())?;
+        Ok(())
+    }
+
+    pub fn from_fm2(contents: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('|') {
+                movie.frames.push(parse_fm2_frame(line, number + 1)?);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            if key == "binary" && value.trim() == "1" {
+                return Err(MovieError::Unsupported(
+                    "binary FM2 movies are not supported".to_string(),
+                ));
+            }
+            // Comments and subtitles can appear more than once, so they're
+            // appended instead of replaced
+            movie.header.push((key.to_string(), value.to_string()));
+        }
+
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut fm2 = String::new();
+
+        let defaults = [
+            ("version", FM2_VERSION),
+            ("emuVersion", env!("CARGO_PKG_VERSION")),
+            ("palFlag", "0"),
+            ("port0", "1"),
+            ("port1", "1"),
+            ("port2", "0"),
+            ("FourScore", "0"),
+        ];
+        for (key, value) in defaults {
+            if self.header(key).is_none() {
+                writeln!(fm2, "{key} {value}").unwrap();
+            }
+        }
+        for (key, value) in self.header.iter() {
+            writeln!(fm2, "{key} {value}").unwrap();
+        }
+
+        for frame in self.frames.iter() {
+            writeln!(
+                fm2,
+                "|{}|{}|{}||",
+                frame.commands.bits(),
+                encode_buttons(frame.port0, &FM2_BUTTONS, FM2_MNEMONICS),
+                encode_buttons(frame.port1, &FM2_BUTTONS, FM2_MNEMONICS),
+            )
+            .unwrap();
+        }
+
+        fm2
+    }
+
+    /// Parse the contents of a BK2 `Input Log.txt` file
+    pub fn from_bk2_input_log(contents: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if !line.starts_with('|') {
+                // [Input], [/Input] and LogKey lines
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 4 {
+                return Err(MovieError::InvalidLine {
+                    line: number + 1,
+                    details: "expected console and two controller fields".to_string(),
+                });
+            }
+
+            let mut commands = MovieCommands::empty();
+            let mut console = fields[1].chars();
+            if console.next().is_some_and(is_pressed) {
+                commands.insert(MovieCommands::SOFT_RESET);
+            }
+            if console.next().is_some_and(is_pressed) {
+                commands.insert(MovieCommands::HARD_RESET);
+            }
+
+            movie.frames.push(MovieFrame {
+                commands,
+                port0: decode_buttons(fields[2], &BK2_BUTTONS),
+                port1: decode_buttons(fields[3], &BK2_BUTTONS),
+            });
+        }
+
+        Ok(movie)
+    }
+
+    /// Export the movie as a BK2 `Input Log.txt` file contents
+    pub fn to_bk2_input_log(&self) -> String {
+        let mut log = String::from("[Input]\n");
+        log.push_str(BK2_LOG_KEY);
+        log.push('\n');
+
+        for frame in self.frames.iter() {
+            let reset = if frame.commands.contains(MovieCommands::SOFT_RESET) {
+                'r'
+            } else {
+                '.'
+            };
+            let power = if frame.commands.contains(MovieCommands::HARD_RESET) {
+                'P'
+            } else {
+                '.'
+            };
+            writeln!(
+                log,
+                "|{reset}{power}|{}|{}|",
+                encode_buttons(frame.port0, &BK2_BUTTONS, BK2_MNEMONICS),
+                encode_buttons(frame.port1, &BK2_BUTTONS, BK2_MNEMONICS),
+            )
+            .unwrap();
+        }
+
+        log.push_str("[/Input]\n");
+        log
+    }
+}
+
+fn is_pressed(mnemonic: char) -> bool {
+    mnemonic != '.' && mnemonic != ' '
+}
+
+fn decode_buttons(field: &str, order: &[ControllerState; 8]) -> ControllerState {
+    field
+        .chars()
+        .zip(order.iter())
+        .filter(|(mnemonic, _)| is_pressed(*mnemonic))
+        .fold(ControllerState::empty(), |state, (_, button)| {
+            state | *button
+        })
+}
+
+fn encode_buttons(state: ControllerState, order: &[ControllerState; 8], mnemonics: &str) -> String {
+    order
+        .iter()
+        .zip(mnemonics.chars())
+        .map(|(button, mnemonic)| {
+            if state.contains(*button) {
+                mnemonic
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn parse_fm2_frame(line: &str, number: usize) -> Result<MovieFrame, MovieError> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 4 {
+        return Err(MovieError::InvalidLine {
+            line: number,
+            details: "expected commands and two port fields".to_string(),
+        });
+    }
+
+    let commands = match fields[1].trim() {
+        "" => 0,
+        commands => commands.parse().map_err(|_| MovieError::InvalidLine {
+            line: number,
+            details: format!("invalid commands '{commands}'"),
+        })?,
+    };
+
+    Ok(MovieFrame {
+        commands: MovieCommands::from_bits_truncate(commands),
+        port0: decode_buttons(fields[2], &FM2_BUTTONS),
+        port1: decode_buttons(fields[3], &FM2_BUTTONS),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FM2: &str = "version 3
+emuVersion 22020
+romFilename smb
+comment author someone
+|2|........|........||
+|0|....T...|........||
+|0|R......A|.L......||
+";
+
+    #[test]
+    fn test_fm2_roundtrip() {
+        let movie = Movie::from_fm2(FM2).unwrap();
+        assert_eq!(movie.header("romFilename"), Some("smb"));
+        assert_eq!(movie.header("comment"), Some("author someone"));
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[0].commands, MovieCommands::HARD_RESET);
+        assert_eq!(movie.frames[1].port0, ControllerState::START);
+        assert_eq!(
+            movie.frames[2].port0,
+            ControllerState::RIGHT | ControllerState::A
+        );
+        assert_eq!(movie.frames[2].port1, ControllerState::LEFT);
+
+        let exported = Movie::from_fm2(&movie.to_fm2()).unwrap();
+        assert_eq!(exported.frames, movie.frames);
+        assert_eq!(exported.header("romFilename"), Some("smb"));
+        assert_eq!(exported.header("port1"), Some("1"));
+        assert_eq!(exported.input_delay(), 0);
+
+        let mut delayed = movie.clone();
+        delayed.set_input_delay(2);
+        assert_eq!(Movie::from_fm2(&delayed.to_fm2()).unwrap().input_delay(), 2);
+
+        let bk2 = Movie::from_bk2_input_log(&movie.to_bk2_input_log()).unwrap();
+        assert_eq!(bk2.frames, movie.frames);
+
+        assert!(Movie::from_fm2("binary 1\n").is_err());
+    }
+}
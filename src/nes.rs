@@ -7,37 +7,88 @@
 /// start playing!
 ///
 ///
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
-use log::info;
+use log::{debug, info, warn};
 
+use crate::audio::ApuPlaceholder;
+use crate::capture::{BurstSettings, ScreenshotBurst};
 use crate::cartidge::Cartidge;
+use crate::conditions::{Condition, ConditionEngine, ConditionId};
 use crate::controller::Controller;
 use crate::controller::ControllerButtons;
-use crate::dma::DmaController;
-use crate::errors::NesError;
+use crate::controller::ControllerState;
+use crate::coverage::{Access, CoverageMap};
+use crate::debugger::CallStack;
+use crate::disassembler::Disassembler;
+use crate::dma::{DmaController, DmaState, OamDmaHook, OamDmaTransfer, OamDmaWrite};
+use crate::errors::{MovieError, NesError, StateError};
 use crate::events::Event;
+use crate::events::EventSubscriber;
 use crate::events::KeyboardChannel;
 use crate::events::SharedEventBus;
-use crate::graphics::palette_memory::PaletteMemory;
-use crate::graphics::ppu::Ppu;
+use crate::graphics::debug_views::{GraphicsDebugView, ScrollRect};
+use crate::graphics::filters;
+use crate::graphics::frame_time_graph;
+use crate::graphics::input_overlay;
+use crate::graphics::palette_memory::{PaletteMemory, PaletteRam};
+use crate::graphics::ppu::{Ppu, PpuState};
+use crate::graphics::{Frame, PartialFrame, ScanlinesHook};
 use crate::hardware::*;
+use crate::input::{InputDevice, InputPort, IoRegisters};
+use crate::input_macro::InputMacro;
+use crate::input_script::{InputScript, ScriptedInput};
 use crate::interfaces::AddressRange;
 use crate::interfaces::Bus as BusTrait;
-use crate::metrics::Collector;
+use crate::interfaces::BusAccess;
+use crate::interfaces::Memory;
+use crate::interfaces::TracedAccess;
+use crate::keyboard::Key;
+use crate::mappers::MapperState;
+use crate::memory_viewer::MemoryDump;
+use crate::metrics::{Collector, Metrics, MetricsCallback};
+use crate::movie::{Movie, MovieCommands};
 use crate::processor::bus::Bus;
-use crate::processor::cpu::{Cpu, Interrupt};
+use crate::processor::cpu::{Cpu, CpuState};
+use crate::processor::instruction::Instruction;
 use crate::processor::memory::MirroredMemory;
 use crate::processor::memory::{Ciram, Ram};
-use crate::settings::NesSettings;
-use crate::settings::UiKind;
-use crate::types::{SharedBus, SharedCiram, SharedController, SharedMemory, SharedPpu};
-use crate::ui::{GtkUi, Ui};
+use crate::rng::Rng;
+use crate::rom_watcher::RomWatcher;
+use crate::scheduler::{needs_catch_up, Component, Scheduler};
+use crate::session::SessionStore;
+use crate::settings::MAX_CPU_PPU_ALIGNMENT;
+use crate::settings::{ClockGranularity, ColorSettings, NesSettings};
+use crate::settings::{ScreenCorner, UiKind, VideoFilterKind};
+use crate::snapshot::{CartidgeSnapshot, Snapshot, SnapshotData, StateMetadata};
+use crate::types::{
+    SharedBus, SharedCiram, SharedInputPort, SharedPpu, SharedRam, SharedRng, SharedWarnings,
+};
+#[cfg(feature = "egui")]
+use crate::ui::EguiUi;
+use crate::ui::{GtkUi, Inspection, Ui};
+use crate::warnings::{WarningSummary, Warnings};
+use crate::watchdog::Watchdog;
+
+// Instructions disassembled from PC for UI debuggers
+const INSPECTED_INSTRUCTIONS: usize = 16;
 
 pub struct Nes {
     // XXX: change to u128 if overflow occur
     system_clock: u64,
+    // System clocks the PPU runs ahead of the CPU (CPU/PPU alignment)
+    cpu_clock_offset: u64,
+    // System clock of the next CPU cycle
+    next_cpu_clock: u64,
+    // System clocks per CPU cycle, see `CpuSpeed`
+    cpu_clock_divider: u64,
 
     cartidge: Option<Cartidge>,
 
@@ -47,22 +98,48 @@ pub struct Nes {
     pub ppu: SharedPpu,
     pub graphics_bus: SharedBus,
 
-    ram: SharedMemory,
+    ram: Rc<RefCell<MirroredMemory<Ram>>>,
+    cartidge_expansion_rom: SharedRam,
     nametable: SharedCiram,
-    palettes: SharedMemory,
+    palettes: Rc<RefCell<MirroredMemory<PaletteMemory>>>,
 
     dma_controller: Rc<RefCell<DmaController>>,
+    scheduler: Scheduler,
+
+    rng: SharedRng,
+    warnings: SharedWarnings,
 
-    pub ui: Option<GtkUi>,
+    pub ui: Option<Box<dyn Ui>>,
 
-    controller_one: SharedController,
-    controller_two: SharedController,
+    input_ports: [SharedInputPort; 2],
 
     event_bus: SharedEventBus,
+    events: EventSubscriber,
+    switched_off: bool,
     keyboard_channel: KeyboardChannel,
 
     settings: NesSettings,
     metrics: Collector,
+    last_metrics: Metrics,
+
+    frame_count: u64,
+    // Last frame produced while running without UI
+    last_frame: Option<Arc<Frame>>,
+    // Hash of the last frame handed to the UI, to skip repeated ones
+    presented_hash: Option<u64>,
+    metrics_callback: Option<MetricsCallback>,
+    oam_dma_hook: Option<OamDmaHook>,
+    scanlines_hook: Option<ScanlinesHook>,
+
+    // Console commands of the movie being played, one per frame
+    movie_commands: VecDeque<MovieCommands>,
+
+    conditions: ConditionEngine,
+    watchdog: Option<Watchdog>,
+    coverage: Option<CoverageMap>,
+    screenshot_burst: Option<ScreenshotBurst>,
+    rom_watcher: Option<RomWatcher>,
+    input_script: Option<InputScript>,
 }
 
 impl Default for Nes {
@@ -72,18 +149,51 @@ impl Default for Nes {
 }
 
 impl Nes {
+    pub fn builder() -> NesBuilder {
+        NesBuilder::new()
+    }
+
     pub fn new(settings: NesSettings) -> Self {
+        assert!(
+            settings.cpu_ppu_alignment <= MAX_CPU_PPU_ALIGNMENT,
+            "CPU/PPU alignment must be between 0 and {MAX_CPU_PPU_ALIGNMENT}"
+        );
+
+        let cpu_clock_divider = settings
+            .cpu_speed
+            .clock_divider()
+            .unwrap_or_else(|| panic!("Invalid CPU speed {:?}", settings.cpu_speed));
+        if !settings.cpu_speed.is_authentic() {
+            warn!("Non-authentic CPU speed: a CPU cycle every {cpu_clock_divider} system clocks");
+        }
+
         let event_bus = SharedEventBus::new();
+        let events = event_bus.subscribe();
         let keyboard_channel = KeyboardChannel::default();
+        let warnings = Rc::new(RefCell::new(Warnings::new()));
 
         let main_bus = Rc::new(RefCell::new(Bus::new("CPU")));
         let graphics_bus = Rc::new(RefCell::new(Bus::new("PPU")));
+        main_bus
+            .borrow_mut()
+            .set_fault_policy(settings.bus_fault_policy);
+        graphics_bus
+            .borrow_mut()
+            .set_fault_policy(settings.bus_fault_policy);
 
         let main_bus_ptr = Rc::clone(&main_bus);
-        let cpu = Cpu::new(main_bus_ptr);
+        let mut cpu = Cpu::new(main_bus_ptr);
+        cpu.set_accuracy(settings.accuracy);
 
         let graphics_bus_ptr = Rc::clone(&graphics_bus);
         let ppu = Rc::new(RefCell::new(Ppu::new(graphics_bus_ptr, event_bus.clone())));
+        ppu.borrow_mut().set_color_settings(&settings.colors);
+        ppu.borrow_mut().set_accuracy(settings.accuracy);
+        ppu.borrow_mut().set_beam_racing(settings.beam_racing);
+        ppu.borrow_mut().power_up();
+        ppu.borrow_mut().connect_nmi_line(cpu.nmi_line());
+
+        let rng = Rc::new(RefCell::new(Rng::new(settings.rng_seed)));
 
         // Main Bus
         // ----------------------------------------------------------------------------------------
@@ -92,6 +202,11 @@ impl Nes {
             Ram::new((RAM_SIZE / (RAM_MIRRORS + 1)).into()),
             RAM_MIRRORS.into(),
         )));
+        if settings.accuracy.is_accurate() {
+            // RAM contents at power-up are unpredictable, games must clear it
+            rng.borrow_mut()
+                .fill_bytes(ram.borrow_mut().memory_mut().as_mut_slice());
+        }
         let ram_ptr = Rc::clone(&ram);
         main_bus
             .borrow_mut()
@@ -124,7 +239,11 @@ impl Nes {
             .borrow_mut()
             .attach(
                 "Fake APU (1)",
-                Rc::new(RefCell::new(Ram::new(0x4014 - 0x4000))),
+                Rc::new(RefCell::new(ApuPlaceholder::new(
+                    0x4000,
+                    0x4013,
+                    Rc::clone(&warnings),
+                ))),
                 AddressRange {
                     start: 0x4000,
                     end: 0x4013,
@@ -135,39 +254,41 @@ impl Nes {
             .borrow_mut()
             .attach(
                 "Fake APU (2)",
-                Rc::new(RefCell::new(Ram::new(0x4015 - 0x4014))),
+                Rc::new(RefCell::new(ApuPlaceholder::new(
+                    APU_STATUS,
+                    APU_STATUS,
+                    Rc::clone(&warnings),
+                ))),
                 AddressRange {
-                    start: 0x4015,
-                    end: 0x4015,
+                    start: APU_STATUS,
+                    end: APU_STATUS,
                 },
             )
             .unwrap();
 
-        let keyboard_listener_one = keyboard_channel.listener();
-        let controller_one = Rc::new(RefCell::new(Controller::new(keyboard_listener_one)));
-        let controller_one_ptr = Rc::clone(&controller_one);
-        main_bus
-            .borrow_mut()
-            .attach(
-                "Controller 1",
-                controller_one_ptr,
-                AddressRange {
-                    start: CONTROLLER_PORT_1,
-                    end: CONTROLLER_PORT_1,
-                },
-            )
-            .unwrap();
+        let input_ports = [(), ()].map(|_| {
+            let mut controller = Controller::new(keyboard_channel.listener());
+            controller.set_input_delay(settings.input_delay);
+            Rc::new(RefCell::new(InputPort::new(Box::new(controller))))
+        });
 
-        let keyboard_listener_two = keyboard_channel.listener();
-        let controller_two = Rc::new(RefCell::new(Controller::new(keyboard_listener_two)));
-        let controller_two_ptr = Rc::clone(&controller_two);
+        // Input ports share $4017 with the APU frame counter, which is as fake
+        // as the rest of APU registers
+        let apu_frame_counter = Rc::new(RefCell::new(ApuPlaceholder::new(
+            APU_FRAME_COUNTER,
+            APU_FRAME_COUNTER,
+            Rc::clone(&warnings),
+        )));
         main_bus
             .borrow_mut()
             .attach(
-                "Controller 2",
-                controller_two_ptr,
+                "Input ports",
+                Rc::new(RefCell::new(IoRegisters::new(
+                    input_ports.clone(),
+                    apu_frame_counter,
+                ))),
                 AddressRange {
-                    start: CONTROLLER_PORT_2,
+                    start: CONTROLLER_PORT_1,
                     end: CONTROLLER_PORT_2,
                 },
             )
@@ -237,24 +358,70 @@ impl Nes {
 
         // ----------------------------------------------------------------------------------------
 
+        // The watchdog checks whether PPU, APU and I/O registers are accessed
+        let watchdog = settings.watchdog.map(Watchdog::new);
+        if watchdog.is_some() {
+            main_bus.borrow_mut().watch(AddressRange {
+                start: PPU_REGISTERS_START,
+                end: CONTROLLER_PORT_2,
+            });
+        }
+
+        // The clock granularity can't change after this point, so the hook is
+        // only installed here
+        let scheduler = Scheduler::new(Rc::clone(&ppu));
+        if settings.clock_granularity == ClockGranularity::CatchUp {
+            let catch_up = scheduler.catch_up().clone();
+            main_bus
+                .borrow_mut()
+                .set_access_hook(Some(Box::new(move |address, access| {
+                    if needs_catch_up(address, access) {
+                        catch_up.run();
+                    }
+                })));
+        }
+
         Self {
             system_clock: 0,
+            cpu_clock_offset: settings.cpu_ppu_alignment as u64 * PPU_CLOCK_DIVIDER,
+            next_cpu_clock: settings.cpu_ppu_alignment as u64 * PPU_CLOCK_DIVIDER
+                + cpu_clock_divider,
+            cpu_clock_divider,
             cartidge: None,
             cpu,
             main_bus,
             ppu,
             graphics_bus,
             ram,
+            cartidge_expansion_rom,
             nametable,
             palettes: palette_memory,
             dma_controller,
+            scheduler,
+            rng,
+            warnings,
             ui: None,
-            controller_one,
-            controller_two,
+            input_ports,
             event_bus,
+            events,
+            switched_off: false,
             keyboard_channel,
             settings,
-            metrics: Collector::new(),
+            metrics: Collector::new(cpu_clock_divider),
+            last_metrics: Metrics::default(),
+            metrics_callback: None,
+            oam_dma_hook: None,
+            scanlines_hook: None,
+            conditions: ConditionEngine::new(),
+            watchdog,
+            coverage: None,
+            screenshot_burst: None,
+            rom_watcher: None,
+            input_script: None,
+            movie_commands: VecDeque::new(),
+            frame_count: 0,
+            last_frame: None,
+            presented_hash: None,
         }
     }
 
@@ -263,8 +430,19 @@ impl Nes {
     ///
     /// Remember, to run the NES, you must insert a cartidge on it. What would
     /// you play otherwise?
-    pub fn load_cartidge(&mut self, cartidge: Cartidge) {
+    pub fn load_cartidge(&mut self, mut cartidge: Cartidge) {
         info!("Cartidge inserted: {}", cartidge);
+        if let Some(ui) = self.ui.as_mut() {
+            ui.set_title(&cartidge.info().title);
+        }
+
+        if self.cartidge.take().is_some() {
+            self.main_bus.borrow_mut().detach("Cartidge RAM");
+            self.main_bus.borrow_mut().detach("Cartidge ROM");
+            self.graphics_bus
+                .borrow_mut()
+                .detach("CHR ROM (pattern memories)");
+        }
 
         let ram = cartidge.mapper.program_ram_ref();
         let rom = cartidge.mapper.program_rom_ref();
@@ -282,6 +460,8 @@ impl Nes {
             )
             .unwrap();
 
+        // All writes to PRG space go to the mapper, which decides whether
+        // they're register writes or are ignored
         self.main_bus
             .borrow_mut()
             .attach(
@@ -316,19 +496,336 @@ impl Nes {
             .borrow_mut()
             .set_mirroring(cartidge.mirroring());
 
+        // An IRQ raised by the previous cartidge would never be acknowledged
+        let irq_line = self.cpu.irq_line();
+        irq_line.release();
+        cartidge.mapper.connect_irq_line(irq_line);
+        cartidge
+            .mapper
+            .connect_nametables(Rc::clone(&self.nametable));
+        cartidge.mapper.connect_warnings(Rc::clone(&self.warnings));
+
+        // Raw 6502 programs may replace the whole memory map
+        self.main_bus
+            .borrow_mut()
+            .set_flat_memory(cartidge.mapper.flat_memory_ref());
+
+        // Some mappers replace the expansion area and nametables with their
+        // own views
+        self.main_bus.borrow_mut().detach("Cartidge Expansion ROM");
+        self.graphics_bus.borrow_mut().detach("Nametables");
+        let expansion = cartidge
+            .mapper
+            .expansion_ref()
+            .unwrap_or_else(|| Rc::clone(&self.cartidge_expansion_rom) as _);
+        let nametables = cartidge
+            .mapper
+            .nametables_ref()
+            .unwrap_or_else(|| Rc::clone(&self.nametable) as _);
+        self.main_bus
+            .borrow_mut()
+            .attach(
+                "Cartidge Expansion ROM",
+                expansion,
+                AddressRange {
+                    start: CARTIDGE_EXPANSION_ROM_START,
+                    end: CARTIDGE_EXPANSION_ROM_END,
+                },
+            )
+            .unwrap();
+        self.graphics_bus
+            .borrow_mut()
+            .attach(
+                "Nametables",
+                nametables,
+                AddressRange {
+                    start: NAMETABLES_START,
+                    end: NAMETABLES_END,
+                },
+            )
+            .unwrap();
+
         self.cartidge = Some(cartidge);
-        self.cpu.reset();
+        if self.coverage.is_some() {
+            self.start_coverage();
+        }
+        self.cpu.power_up();
+        self.resume_session();
+    }
+
+    /// Restore the session of the inserted cartidge, if sessions are enabled
+    /// and there's one. See [`SessionStore`]
+    fn resume_session(&mut self) {
+        let (Some(directory), Some(cartidge)) = (
+            self.settings.session_directory.as_ref(),
+            self.cartidge.as_ref(),
+        ) else {
+            return;
+        };
+        let store = SessionStore::new(directory);
+        let path = store.path(cartidge.checksum());
+        let state = match store.load(cartidge.checksum()) {
+            Ok(Some(state)) => state,
+            Ok(None) => return,
+            Err(error) => {
+                warn!("Unable to read session {path:?}: {error}");
+                return;
+            }
+        };
+
+        let restored = self
+            .load_snapshot(&state)
+            .map_err(|error| error.to_string())
+            .and_then(|snapshot| self.restore(&snapshot).map_err(|error| error.to_string()));
+        match restored {
+            Ok(()) => info!("Session resumed from {path:?}"),
+            Err(error) => warn!("Unable to resume session {path:?}: {error}"),
+        }
+    }
+
+    /// Save the session of the inserted cartidge, if sessions are enabled
+    fn save_session(&self) {
+        let Some(directory) = self.settings.session_directory.as_ref() else {
+            return;
+        };
+        if self.cartidge.is_none() {
+            return;
+        }
+        let store = SessionStore::new(directory);
+        match store.save(&self.snapshot()) {
+            Ok(()) => info!("Session saved in {:?}", store.directory()),
+            Err(error) => warn!("Unable to save session in {:?}: {error}", store.directory()),
+        }
+    }
+
+    /// Change the color adjustments used to draw the next frames
+    pub fn set_color_settings(&mut self, colors: ColorSettings) {
+        self.ppu.borrow_mut().set_color_settings(&colors);
+        self.settings.colors = colors;
+    }
+
+    /// Plug an input device in `port` (0 or 1), replacing the device
+    /// plugged before, which is returned. Ports have a standard
+    /// [`Controller`] plugged at power-on
+    pub fn plug(&mut self, port: usize, device: Box<dyn InputDevice>) -> Box<dyn InputDevice> {
+        self.input_ports[port].borrow_mut().plug(device)
+    }
+
+    /// Blow into (or shout at) the microphone of the Famicom second
+    /// controller, read by games in bit 2 of $4016. Frontends usually bind it
+    /// to a key, as a handful of games (e.g., The Legend of Zelda, to scare
+    /// Pols Voice) check it
+    pub fn set_microphone(&mut self, active: bool) {
+        self.input_ports[0].borrow_mut().set_microphone(active);
+    }
+
+    /// Whether the Famicom microphone is active
+    pub fn microphone(&self) -> bool {
+        self.input_ports[0].borrow().microphone()
+    }
+
+    /// Device of type `T` plugged in `port`, if any
+    pub fn input_device<T: InputDevice>(&self, port: usize) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.input_ports[port].borrow_mut(), |port| {
+            port.device_mut().downcast_mut::<T>()
+        })
+        .ok()
+    }
+
+    /// Standard controller plugged in `port`. If another device was plugged,
+    /// it's replaced by a new controller
+    fn controller(&mut self, port: usize) -> RefMut<'_, Controller> {
+        if self.input_device::<Controller>(port).is_none() {
+            let mut controller = Controller::new(self.keyboard_channel.listener());
+            controller.set_input_delay(self.settings.input_delay);
+            self.plug(port, Box::new(controller));
+        }
+        self.input_device::<Controller>(port).unwrap()
     }
 
     /// Connect controller one to the NES and define its configuration
     pub fn connect_controller_one(&mut self, buttons: ControllerButtons) {
-        self.controller_one.borrow_mut().connect(buttons);
+        self.controller(0).connect(buttons);
     }
 
     /// Diconnect controller one from the NES. After this action, the controls
     /// defined for this controller won't do anything anymore
     pub fn disconnect_controller_one(&mut self) {
-        self.controller_one.borrow_mut().disconnect();
+        if let Some(mut controller) = self.input_device::<Controller>(0) {
+            controller.disconnect();
+        }
+    }
+
+    /// Connect controller two to the NES and define its configuration
+    pub fn connect_controller_two(&mut self, buttons: ControllerButtons) {
+        self.controller(1).connect(buttons);
+    }
+
+    /// Diconnect controller two from the NES
+    pub fn disconnect_controller_two(&mut self) {
+        if let Some(mut controller) = self.input_device::<Controller>(1) {
+            controller.disconnect();
+        }
+    }
+
+    /// Set the pressed buttons of controller one, overriding keyboard input
+    pub fn set_controller_one_state(&mut self, state: ControllerState) {
+        self.controller(0).set_state(state);
+    }
+
+    /// Set the pressed buttons of controller two, overriding keyboard input
+    pub fn set_controller_two_state(&mut self, state: ControllerState) {
+        self.controller(1).set_state(state);
+    }
+
+    /// Delay controllers input `frames` frames (see
+    /// [`NesSettings::input_delay`](crate::settings::NesSettings::input_delay))
+    pub fn set_input_delay(&mut self, frames: u8) {
+        self.settings.input_delay = frames;
+        for port in 0..2 {
+            if let Some(mut controller) = self.input_device::<Controller>(port) {
+                controller.set_input_delay(frames);
+            }
+        }
+    }
+
+    pub fn input_delay(&self) -> u8 {
+        self.settings.input_delay
+    }
+
+    /// Start recording controller one input as a macro
+    pub fn start_macro_recording(&mut self) {
+        self.controller(0).start_macro_recording();
+    }
+
+    /// Stop recording controller one input and return the recorded macro
+    pub fn stop_macro_recording(&mut self) -> Option<InputMacro> {
+        self.input_device::<Controller>(0)?.stop_macro_recording()
+    }
+
+    /// Replay an input macro in controller one
+    pub fn play_macro(&mut self, input_macro: InputMacro) {
+        self.controller(0).play_macro(input_macro);
+    }
+
+    /// Replay `input_macro` in controller one every time `key` is pressed
+    pub fn bind_macro(&mut self, key: Key, input_macro: InputMacro) {
+        self.controller(0).bind_macro(key, input_macro);
+    }
+
+    /// Start recording both controllers input as a movie. Only standard
+    /// controllers are recorded
+    pub fn start_movie_recording(&mut self) {
+        for port in 0..2 {
+            if let Some(mut controller) = self.input_device::<Controller>(port) {
+                controller.start_macro_recording();
+            }
+        }
+    }
+
+    /// Stop recording and return the recorded movie, if any. The input delay
+    /// is kept in its header
+    pub fn stop_movie_recording(&mut self) -> Option<Movie> {
+        let [port0, port1] = [0, 1].map(|port| {
+            self.input_device::<Controller>(port)
+                .and_then(|mut controller| controller.stop_macro_recording())
+        });
+        match (port0, port1) {
+            (None, None) => None,
+            (port0, port1) => {
+                let mut movie =
+                    Movie::from_macros(&port0.unwrap_or_default(), &port1.unwrap_or_default());
+                movie.set_input_delay(self.settings.input_delay);
+                Some(movie)
+            }
+        }
+    }
+
+    /// Replay a movie from the next frame on: controllers input and console
+    /// commands. The movie input delay replaces the current one
+    ///
+    /// Power cycles are not emulated, so movies with hard resets are rejected.
+    /// A hard reset on the first frame only tells the movie was recorded from
+    /// power-up: play it on a console just powered up, e.g., right after
+    /// inserting the cartidge
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<(), MovieError> {
+        if let Some(number) = movie
+            .frames
+            .iter()
+            .skip(1)
+            .position(|frame| frame.commands.contains(MovieCommands::HARD_RESET))
+        {
+            return Err(MovieError::Unsupported(format!(
+                "hard reset on frame {}, power cycles are not emulated",
+                number + 1
+            )));
+        }
+
+        self.set_input_delay(movie.input_delay());
+        self.controller(0).play_macro(movie.port_macro(0));
+        self.controller(1).play_macro(movie.port_macro(1));
+        self.movie_commands = movie.frames.iter().map(|frame| frame.commands).collect();
+        self.execute_movie_commands();
+        Ok(())
+    }
+
+    /// Drive the controllers with a text [`InputScript`], replacing the one
+    /// being played, if any
+    pub fn play_input_script(&mut self, script: InputScript) {
+        self.input_script = Some(script);
+        self.apply_input_script();
+    }
+
+    pub fn input_script(&self) -> Option<&InputScript> {
+        self.input_script.as_ref()
+    }
+
+    /// Stop the input script, giving the controllers it drives back to the
+    /// keyboard
+    pub fn stop_input_script(&mut self) -> Option<InputScript> {
+        let script = self.input_script.take()?;
+        for port in [0, 1] {
+            if script.is_driving(port) {
+                self.script_input(port, ScriptedInput::Release);
+            }
+        }
+        Some(script)
+    }
+
+    fn apply_input_script(&mut self) {
+        let Some(script) = self.input_script.as_mut() else {
+            return;
+        };
+        for (port, input) in script.next_frame().into_iter().enumerate() {
+            self.script_input(port, input);
+        }
+    }
+
+    // Only connected controllers are driven, other devices are left alone
+    fn script_input(&self, port: usize, input: ScriptedInput) {
+        let Some(mut controller) = self.input_device::<Controller>(port) else {
+            return;
+        };
+        if !controller.is_connected() {
+            return;
+        }
+        match input {
+            ScriptedInput::Hold(state) => controller.set_state(state),
+            ScriptedInput::Release => controller.clear_state(),
+            ScriptedInput::Idle => {}
+        }
+    }
+
+    fn execute_movie_commands(&mut self) {
+        let Some(commands) = self.movie_commands.pop_front() else {
+            return;
+        };
+
+        // Hard resets are only left on the first frame, where the console was
+        // just powered up
+        if commands.contains(MovieCommands::SOFT_RESET) {
+            self.cpu.reset();
+        }
     }
 
     /// Blocking NES run
@@ -348,18 +845,13 @@ impl Nes {
             })?;
         }
 
+        self.switched_off = false;
         loop {
-            if self.event_bus.access().emitted(Event::SwitchOff) {
+            if self.switched_off {
                 break;
             }
 
-            if self.system_clock % (2_u64.pow(25)) == 0 {
-                self.metrics.observe_system_clocks(2_u64.pow(25));
-                let metrics = self.metrics.collect();
-                println!("FPS: {}", metrics.frames_per_second);
-            }
-
-            self.clock()
+            self.step()
                 .map_err(|error| NesError::NesInternalError(error))?;
         }
 
@@ -373,6 +865,294 @@ impl Nes {
         Ok(())
     }
 
+    /// Run the NES until the CPU starts executing a new instruction (or
+    /// attends an interrupt). Instructions are executed at once, so the CPU
+    /// state already reflects its effects afterwards. Useful to implement
+    /// debuggers
+    pub fn step_instruction(&mut self) -> Result<(), NesError> {
+        if self.cartidge.is_none() {
+            return Err(NesError::NoCartidgeInserted);
+        }
+
+        loop {
+            let next_cycle_starts_instruction = self.cpu.cycles_before_next_instruction() == 1;
+            self.step().map_err(NesError::NesInternalError)?;
+            if next_cycle_starts_instruction && self.cpu.cycles_before_next_instruction() != 1 {
+                self.catch_up_ppu();
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run the NES without UI until `frames` new frames have been produced.
+    /// Frames can be retrieved afterwards with [`Nes::last_frame`]
+    pub fn run_frames(&mut self, frames: u64) -> Result<(), NesError> {
+        if self.cartidge.is_none() {
+            return Err(NesError::NoCartidgeInserted);
+        }
+
+        let target = self.frame_count + frames;
+        while self.frame_count < target {
+            self.step().map_err(NesError::NesInternalError)?;
+        }
+        self.catch_up_ppu();
+        Ok(())
+    }
+
+    /// CPU cycles run since power-on, including cycles the CPU was halted by
+    /// DMA. It increases monotonically, so the difference between two reads
+    /// measures the timing of the code run in between
+    pub fn cpu_cycles(&self) -> u64 {
+        (self.next_cpu_clock - self.cpu_clock_offset) / self.cpu_clock_divider - 1
+    }
+
+    /// Contents of palette RAM: NES color indices of the 4 background and 4
+    /// sprite palettes. See [`palette_memory`](crate::graphics::palette_memory)
+    pub fn palette_ram(&self) -> PaletteRam {
+        self.palettes.borrow().memory().entries()
+    }
+
+    /// Pattern tables, nametables, palettes and sprites as they are now.
+    /// Pattern tables are drawn with `pattern_table_palette` (0 to 7, higher
+    /// values wrap around)
+    pub fn graphics_debug_view(&self, pattern_table_palette: u8) -> GraphicsDebugView {
+        self.ppu.borrow().debug_view(pattern_table_palette)
+    }
+
+    /// Position of the PPU: scanline (0 to 261, 261 being the pre-render
+    /// one), cycle within the scanline (0 to 340) and frame index
+    pub fn ppu_dot(&self) -> (u16, u16, u64) {
+        let state = self.ppu.borrow().state();
+        (state.scan_line, state.cycle, state.frame_index)
+    }
+
+    /// Call `hook` after every instruction the CPU executes, with the CPU
+    /// state it left. Useful for profilers, coverage tools or custom tracers.
+    /// It replaces the current hook, if any
+    pub fn set_exec_hook(&mut self, hook: impl FnMut(&CpuState, &Instruction) + 'static) {
+        self.cpu.set_exec_hook(Some(Box::new(hook)));
+    }
+
+    pub fn clear_exec_hook(&mut self) {
+        self.cpu.set_exec_hook(None);
+    }
+
+    /// Call `hook` on every byte OAM DMA writes to OAM, with its source
+    /// address and the OAMADDR it went to. It replaces the current hook, if
+    /// any
+    pub fn set_oam_dma_hook(&mut self, hook: impl FnMut(&OamDmaWrite) + 'static) {
+        self.oam_dma_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_oam_dma_hook(&mut self) {
+        self.oam_dma_hook = None;
+    }
+
+    /// Call `hook` with the rows rendered so far every time the PPU reports
+    /// them, with beam racing enabled (see
+    /// [`NesSettings::beam_racing`](crate::settings::NesSettings::beam_racing)).
+    /// It replaces the current hook, if any
+    pub fn set_scanlines_hook(&mut self, hook: impl FnMut(&PartialFrame) + 'static) {
+        self.scanlines_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_scanlines_hook(&mut self) {
+        self.scanlines_hook = None;
+    }
+
+    /// Record the last `capacity` main bus accesses, with the device that
+    /// answered each one and the CPU cycle it happened in, or stop recording
+    /// with `None`. See [`Nes::bus_trace`]
+    pub fn set_bus_trace(&mut self, capacity: Option<usize>) {
+        self.main_bus.borrow_mut().set_access_trace(capacity);
+    }
+
+    /// Main bus accesses recorded so far, oldest first
+    pub fn bus_trace(&self) -> Vec<TracedAccess> {
+        self.main_bus.borrow().access_trace()
+    }
+
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    /// Read main bus `address` without side effects, for debugging tools.
+    /// Only internal RAM, cartidge RAM and PRG ROM can be read this way,
+    /// other addresses (registers) return `None`
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        let cartidge = self.cartidge.as_ref();
+        if let Some(memory) = cartidge.and_then(|cartidge| cartidge.mapper.flat_memory_ref()) {
+            let data = memory.borrow().read(address);
+            return Some(data);
+        }
+        match address {
+            RAM_START..=RAM_END => Some(self.ram.borrow().read(address - RAM_START)),
+            CARTIDGE_RAM_START..=CARTIDGE_RAM_END => cartidge.and_then(|cartidge| {
+                let ram = cartidge.mapper.program_ram_ref();
+                let data = ram.borrow().try_read(address - CARTIDGE_RAM_START).ok();
+                data
+            }),
+            CARTIDGE_ROM_START..=CARTIDGE_ROM_END => cartidge.map(|cartidge| {
+                let rom = cartidge.mapper.program_rom_ref();
+                let data = rom.borrow().read(address - CARTIDGE_ROM_START);
+                data
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn ppu_state(&self) -> PpuState {
+        self.ppu.borrow().state()
+    }
+
+    /// Area of the nametables visible in the last frame, in nametable pixel
+    /// coordinates (0-511, 0-479). See [`ScrollRect`]
+    pub fn scroll_rect(&self) -> ScrollRect {
+        self.ppu.borrow().scroll_rect()
+    }
+
+    /// Whether the PPU is still ignoring register writes after power-up (only
+    /// with the accurate profile). See [`Ppu::warming_up`]
+    pub fn ppu_warming_up(&self) -> bool {
+        self.ppu.borrow().warming_up()
+    }
+
+    /// State of the inserted cartidge mapper, if any
+    pub fn mapper_state(&self) -> Option<MapperState> {
+        self.cartidge
+            .as_ref()
+            .map(|cartidge| cartidge.mapper.state())
+    }
+
+    /// Number of frames produced by the PPU since power-on
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Last frame produced. Frames are only kept when the NES runs without UI,
+    /// otherwise they're sent to the UI for rendering
+    pub fn last_frame(&self) -> Option<&Frame> {
+        self.last_frame.as_deref()
+    }
+
+    /// Request the DMC sample byte at `address` through DMA. The CPU is
+    /// stalled while it's read from the main bus. This is the hook the APU
+    /// DMC channel uses when its sample buffer empties
+    pub fn request_dmc_read(&mut self, address: u16) {
+        self.dma_controller.borrow_mut().request_dmc_read(address);
+    }
+
+    /// Collect the last sample byte read for the DMC, if any
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dma_controller.borrow_mut().take_dmc_sample()
+    }
+
+    /// Source of the emulated randomness, shared with the components needing
+    /// it. See [`rng`](crate::rng)
+    pub fn rng(&self) -> SharedRng {
+        Rc::clone(&self.rng)
+    }
+
+    /// Total CPU cycles stolen by DMC DMA since power-on
+    pub fn dmc_stalled_cycles(&self) -> u64 {
+        self.dma_controller.borrow().dmc_stalled_cycles()
+    }
+
+    /// Current state of the DMA controller: OAM DMA progress and pending DMC
+    /// reads
+    pub fn dma_state(&self) -> DmaState {
+        self.dma_controller.borrow().state()
+    }
+
+    /// Capture the complete NES state in memory. See [`Snapshot`]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            data: Rc::new(SnapshotData {
+                system_clock: self.system_clock,
+                cpu_clock_offset: self.cpu_clock_offset,
+                next_cpu_clock: self.next_cpu_clock,
+                frame_count: self.frame_count,
+                rng: self.rng.borrow().clone(),
+                input_delay: self.settings.input_delay,
+                metadata: StateMetadata::now(
+                    self.cartidge.as_ref().map(|cartidge| cartidge.checksum()),
+                ),
+                cpu: self.cpu.snapshot(),
+                ppu: self.ppu.borrow().snapshot(),
+                dma_controller: self.dma_controller.borrow().clone(),
+                input_devices: self
+                    .input_ports
+                    .each_ref()
+                    .map(|port| port.borrow().device().snapshot()),
+                ram: self.ram.borrow().clone(),
+                nametable: self.nametable.borrow().clone(),
+                palettes: self.palettes.borrow().clone(),
+                cartidge: self.cartidge.as_ref().map(|cartidge| CartidgeSnapshot {
+                    name: cartidge.name().to_string(),
+                    checksum: cartidge.checksum(),
+                    mapper: cartidge.mapper.snapshot(),
+                }),
+            }),
+        }
+    }
+
+    /// Restore a [`Snapshot`] taken from a NES with the same cartidge
+    /// inserted, matched by checksum. Events pending to be processed are
+    /// discarded
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), NesError> {
+        let data = &snapshot.data;
+
+        let inserted = self.cartidge.as_ref().map(|cartidge| cartidge.checksum());
+        if inserted != snapshot.cartidge_checksum() {
+            return Err(NesError::SnapshotMismatch {
+                snapshot: data.cartidge.as_ref().map(|cartidge| cartidge.name.clone()),
+                inserted: self
+                    .cartidge
+                    .as_ref()
+                    .map(|cartidge| cartidge.name().to_string()),
+            });
+        }
+
+        self.system_clock = data.system_clock;
+        self.cpu_clock_offset = data.cpu_clock_offset;
+        self.next_cpu_clock = data.next_cpu_clock;
+        self.frame_count = data.frame_count;
+        *self.rng.borrow_mut() = data.rng.clone();
+        self.set_input_delay(data.input_delay);
+
+        self.cpu.restore(&data.cpu);
+        self.ppu.borrow_mut().restore(&data.ppu);
+        *self.dma_controller.borrow_mut() = data.dma_controller.clone();
+        for (port, snapshot) in self.input_ports.iter().zip(&data.input_devices) {
+            port.borrow_mut().device_mut().restore(snapshot);
+        }
+
+        *self.ram.borrow_mut() = data.ram.clone();
+        *self.nametable.borrow_mut() = data.nametable.clone();
+        *self.palettes.borrow_mut() = data.palettes.clone();
+
+        if let (Some(cartidge), Some(snapshot)) = (self.cartidge.as_mut(), &data.cartidge) {
+            cartidge.mapper.restore(&snapshot.mapper);
+        }
+
+        self.events.drain();
+        self.last_frame = None;
+        self.presented_hash = None;
+
+        Ok(())
+    }
+
+    /// Decode a snapshot saved with [`Snapshot::to_bytes`]. It must have been
+    /// taken with the same ROM inserted, matched by checksum
+    pub fn load_snapshot(&self, bytes: &[u8]) -> Result<Snapshot, StateError> {
+        Snapshot::from_bytes(bytes, self.snapshot())
+    }
+
+    /// Take the last frame produced, if it hasn't been taken yet
+    pub fn take_last_frame(&mut self) -> Option<Arc<Frame>> {
+        self.last_frame.take()
+    }
+
     /// Execute a NES simulated system clock.
     ///
     /// In the NES NTSC (2C02), this clock runs at ~21.47 MHz.
@@ -381,53 +1161,577 @@ impl Nes {
     /// - CPU clocks every 12 system clocks
     /// - PPU clocks every 4 system clocks
     ///
+    /// The CPU starts `cpu_ppu_alignment` PPU cycles after the PPU, as
+    /// configured in [`NesSettings`]. A non-authentic CPU speed changes the
+    /// CPU division, running several CPU cycles per PPU dot if needed.
+    ///
     /// See more information:
     /// https://www.nesdev.org/wiki/Cycle_reference_chart#Clock_rates
     pub fn clock(&mut self) -> Result<(), String> {
-        self.system_clock += 4;
+        self.system_clock += PPU_CLOCK_DIVIDER;
+        self.metrics.observe_system_clocks(PPU_CLOCK_DIVIDER);
 
         // PPU clock runs every 4 system clocks
-        if self.system_clock % 4 == 0 {
-            let mut ppu = self.ppu.borrow_mut();
-            ppu.clock();
+        self.ppu.borrow_mut().clock();
+        if self.events.has_pending() {
+            self.process_events();
+        }
+
+        // CPU clock runs every 12 system clocks
+        while self.next_cpu_clock <= self.system_clock {
+            self.cpu_cycle()?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the NES a step of the configured [`ClockGranularity`]
+    fn step(&mut self) -> Result<(), String> {
+        match self.settings.clock_granularity {
+            ClockGranularity::Dot => self.clock(),
+            ClockGranularity::CpuCycle => self.clock_cpu_cycle(),
+            ClockGranularity::CatchUp => self.clock_scheduled(),
+        }
+    }
+
+    /// Run a CPU cycle while the PPU runs behind, catching it up only if
+    /// something could observe it. See [`scheduler`](crate::scheduler)
+    fn clock_scheduled(&mut self) -> Result<(), String> {
+        let catch_up = self.scheduler.catch_up().clone();
+        catch_up.reset(self.system_clock, self.next_cpu_clock);
+
+        let dots = self.ppu.borrow().dots_until_event();
+        let ppu_event = self.system_clock + (dots - 1) * PPU_CLOCK_DIVIDER;
+        self.scheduler.schedule(Component::Cpu, self.next_cpu_clock);
+        self.scheduler.schedule(Component::Ppu, ppu_event);
 
-            if self.event_bus.access().emitted(Event::NMI) {
-                self.cpu.interrupt(Interrupt::NonMaskableInterrupt);
-                self.event_bus.access().mark_as_processed(Event::NMI);
+        let dma_active = {
+            let dma_controller = self.dma_controller.borrow();
+            dma_controller.is_dmc_dma_active()
+                || dma_controller.is_oam_dma_active(self.next_cpu_cycle())
+        };
+        if self.scheduler.next() == Component::Ppu || dma_active || self.events.has_pending() {
+            self.catch_up_ppu();
+        }
+        if self.events.has_pending() {
+            self.process_events();
+            // Events may reset the NES or restore a snapshot
+            catch_up.reset(self.system_clock, self.next_cpu_clock);
+        }
+
+        self.cpu_cycle()?;
+        self.sync_system_clock();
+        Ok(())
+    }
+
+    /// Run the PPU dots a lagging PPU owes until the current CPU cycle. Other
+    /// clock granularities never lag
+    fn catch_up_ppu(&mut self) {
+        if self.settings.clock_granularity == ClockGranularity::CatchUp {
+            self.scheduler.catch_up().run();
+            self.sync_system_clock();
+        }
+    }
+
+    // The system clock follows the PPU, wherever it caught up to
+    fn sync_system_clock(&mut self) {
+        let clock = self.scheduler.catch_up().clock();
+        self.metrics
+            .observe_system_clocks(clock - self.system_clock);
+        self.system_clock = clock;
+    }
+
+    // Index of the CPU cycle at `next_cpu_clock`
+    fn next_cpu_cycle(&self) -> u64 {
+        (self.next_cpu_clock - self.cpu_clock_offset) / self.cpu_clock_divider
+    }
+
+    /// Run PPU dots until the next CPU cycle and execute it. This is equivalent
+    /// to calling [`Nes::clock`] until the CPU clocks, without its per dot
+    /// overhead
+    fn clock_cpu_cycle(&mut self) -> Result<(), String> {
+        // Overclocked CPUs can run several cycles before the next PPU dot
+        let mut clocks = 0;
+        {
+            let mut ppu = self.ppu.borrow_mut();
+            while self.system_clock + clocks < self.next_cpu_clock {
+                ppu.clock();
+                clocks += PPU_CLOCK_DIVIDER;
             }
+        }
+        self.system_clock += clocks;
+        self.metrics.observe_system_clocks(clocks);
+
+        // Events emitted by the PPU don't need to be processed until now
+        if self.events.has_pending() {
+            self.process_events();
+        }
+
+        self.cpu_cycle()
+    }
+
+    fn cpu_cycle(&mut self) -> Result<(), String> {
+        let cpu_clock = self.next_cpu_cycle();
+        self.next_cpu_clock += self.cpu_clock_divider;
+        self.main_bus.borrow().set_cpu_cycle(cpu_clock);
 
-            if self.event_bus.access().emitted(Event::FrameReady) {
-                let frame = ppu.take_frame();
-                self.metrics.observe_frame_ready();
-                self.event_bus.access().mark_as_processed(Event::FrameReady);
+        let ongoing_dmc_dma = self.dma_controller.borrow().is_dmc_dma_active();
+        let ongoing_dma = self.dma_controller.borrow().is_oam_dma_active(cpu_clock);
+        // Address of the instruction started in this cycle, if any
+        let mut instruction_pc = None;
+        if ongoing_dmc_dma {
+            if self.settings.dpcm_controller_conflict
+                && self.dma_controller.borrow().is_dmc_dma_halting()
+            {
+                self.dpcm_controller_conflict();
+            }
+            // DMC DMA has priority over OAM DMA, which pauses meanwhile
+            self.dma_controller
+                .borrow_mut()
+                .dmc_dma_transfer(&self.main_bus);
+        } else if ongoing_dma {
+            let mut dma_controller = self.dma_controller.borrow_mut();
+            let transfer = OamDmaTransfer {
+                page: dma_controller.oam_dma_page(),
+                cpu_cycle: cpu_clock,
+            };
+            if dma_controller.is_oam_dma_starting() {
+                self.event_bus.emit(Event::OamDmaStarted(transfer));
+            }
+            let write = dma_controller.oam_dma_transfer(cpu_clock, &self.main_bus, &self.ppu);
+            if !dma_controller.is_oam_dma_active(cpu_clock) {
+                self.event_bus.emit(Event::OamDmaFinished(transfer));
+            }
+            drop(dma_controller);
 
-                if let Some(ui) = self.ui.as_mut() {
-                    ui.render(frame);
+            if let (Some(write), Some(hook)) = (write, self.oam_dma_hook.as_mut()) {
+                hook(&write);
+            }
+        } else {
+            if self.cpu.cycles_before_next_instruction() == 1 {
+                instruction_pc = Some(self.cpu.state().pc);
+                if let Some(watchdog) = self.watchdog.as_mut() {
+                    watchdog.observe_instruction(self.cpu.state());
                 }
-                // std::thread::sleep(std::time::Duration::from_millis(33)); // ~30 FPS
-                // std::thread::sleep(std::time::Duration::from_millis(16)); // ~60 FPS
-                // std::thread::sleep(std::time::Duration::from_millis(8)); // ~120 FPS
             }
+            self.cpu.clock()?;
         }
-
-        // CPU clock runs every 12 system clocks
-        if self.system_clock % 12 == 0 {
-            let cpu_clock = self.system_clock / 12;
-            let ongoing_dma = self.dma_controller.borrow().is_oam_dma_active(cpu_clock);
-            if ongoing_dma {
-                self.dma_controller.borrow_mut().oam_dma_transfer(
-                    cpu_clock,
-                    &self.main_bus,
-                    &self.ppu,
-                );
-            } else {
-                self.cpu.clock()?;
+        if let Some(cartidge) = self.cartidge.as_mut() {
+            for low_dots in self.ppu.borrow_mut().take_a12_rises() {
+                cartidge.mapper.ppu_a12_rise(low_dots);
             }
+            cartidge.mapper.clock_cpu();
+        }
+        self.report_bus_faults();
+        self.report_warm_up_writes();
+        self.record_coverage(instruction_pc);
+
+        Ok(())
+    }
+
+    /// DMC DMA halts the CPU on a read cycle, which is repeated while halted.
+    /// Reading a controller port twice clocks its shift register twice, so a
+    /// bit is lost. As the CPU executes instructions atomically, the CPU is
+    /// considered to be reading the port if it was its last read
+    fn dpcm_controller_conflict(&mut self) {
+        let main_bus = self.main_bus.borrow();
+        if let Some(address @ (CONTROLLER_PORT_1 | CONTROLLER_PORT_2)) =
+            main_bus.last_read_address()
+        {
+            debug!("DPCM conflict: extra read of controller port ${address:0>4X}");
+            main_bus.read(address);
+        }
+    }
+
+    /// Log faults ignored by the buses in tolerant mode and notify them
+    fn report_bus_faults(&mut self) {
+        let pc = self.cpu.instruction_pc();
+        let faults = self
+            .main_bus
+            .borrow()
+            .take_faults()
+            .into_iter()
+            .chain(self.graphics_bus.borrow().take_faults());
+
+        for mut fault in faults {
+            fault.pc = Some(pc);
+            warn!(
+                "Bus fault at PC ${pc:0>4X}: {:?} on bus '{}' address ${:0>4X}",
+                fault.access, fault.bus_id, fault.address
+            );
+            self.event_bus.emit(Event::BusFault(fault));
+        }
+    }
+
+    /// Log register writes ignored by the PPU while warming up and notify them
+    fn report_warm_up_writes(&mut self) {
+        let pc = self.cpu.instruction_pc();
+        let writes = self.ppu.borrow_mut().take_warm_up_writes();
+        for mut write in writes {
+            write.pc = Some(pc);
+            warn!(
+                "PPU warm-up: write ${:0>2X} to ${:0>4X} at PC ${pc:0>4X} ignored. Wait for two vertical blanks before setting up the PPU",
+                write.data, write.register
+            );
+            self.event_bus.emit(Event::WarmUpWriteIgnored(write));
+        }
+    }
+
+    /// Emulator state for UI debuggers, with the main bus `range`
+    fn inspect(&self, range: AddressRange) -> Inspection {
+        let cpu = self.cpu.state();
+        let disassembly =
+            Disassembler::new().disassemble_many(cpu.pc, INSPECTED_INSTRUCTIONS, |address| {
+                self.peek(address).unwrap_or(0)
+            });
+        Inspection {
+            frame_index: self.frame_count,
+            cpu,
+            ppu: self.ppu_state(),
+            disassembly,
+            memory: MemoryDump::capture(range, |address| self.peek(address)),
+            metrics: self.last_metrics.clone(),
+        }
+    }
+
+    /// Log and notify unsupported features hit by the game, rate-limited
+    fn report_warnings(&mut self) {
+        let frame = self.ppu.borrow().state().frame_index.saturating_sub(1);
+        let warnings = self.warnings.borrow_mut().end_frame(frame);
+        for warning in warnings {
+            warn!("Compatibility warning: {warning}");
+            self.event_bus.emit(Event::EmulatorWarning(warning));
+        }
+    }
+
+    /// Unsupported features the game has hit since power-on, one entry per
+    /// kind. See [`warnings`](crate::warnings)
+    pub fn warnings(&self) -> Vec<WarningSummary> {
+        self.warnings.borrow().summaries()
+    }
+
+    /// Notify palette RAM changes, once per frame at most
+    fn report_palette_changes(&mut self) {
+        let mut palettes = self.palettes.borrow_mut();
+        if palettes.memory_mut().take_changed() {
+            let entries = palettes.memory().entries();
+            self.event_bus.emit(Event::PaletteChanged(entries));
         }
+    }
+
+    /// Start recording which PRG ROM bytes the CPU executes, reads and
+    /// writes. Recording restarts if it was already started, and when another
+    /// cartidge is inserted. See [`coverage`](crate::coverage)
+    pub fn start_coverage(&mut self) {
+        let program_rom_size = self
+            .cartidge
+            .as_ref()
+            .map_or(0, |cartidge| cartidge.info().program_rom_size);
+        self.coverage = Some(CoverageMap::new(program_rom_size));
+        self.main_bus.borrow_mut().set_access_log(true);
+    }
+
+    /// Coverage recorded since [`Nes::start_coverage`]
+    pub fn coverage(&self) -> Option<&CoverageMap> {
+        self.coverage.as_ref()
+    }
 
+    pub fn stop_coverage(&mut self) -> Option<CoverageMap> {
+        self.main_bus.borrow_mut().set_access_log(false);
+        self.coverage.take()
+    }
+
+    /// Start rebuilding the call stack from the calls, returns and interrupts
+    /// executed from now on. See [`CallStack`]
+    pub fn start_call_tracking(&mut self) {
+        self.cpu.set_call_tracking(true);
+    }
+
+    /// Call stack rebuilt since [`Nes::start_call_tracking`]
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.cpu.call_stack()
+    }
+
+    pub fn stop_call_tracking(&mut self) {
+        self.cpu.set_call_tracking(false);
+    }
+
+    /// Save every `interval` frames to a directory for a while, replacing the
+    /// ongoing burst if any. See [`ScreenshotBurst`]
+    pub fn start_screenshot_burst(&mut self, settings: BurstSettings) -> io::Result<()> {
+        self.screenshot_burst = Some(ScreenshotBurst::new(settings)?);
         Ok(())
     }
 
+    /// Ongoing or finished screenshot burst
+    pub fn screenshot_burst(&self) -> Option<&ScreenshotBurst> {
+        self.screenshot_burst.as_ref()
+    }
+
+    /// Stop the screenshot burst, returning it with the files it saved
+    pub fn stop_screenshot_burst(&mut self) -> Option<ScreenshotBurst> {
+        self.screenshot_burst.take()
+    }
+
+    // Frames are captured as emulated, without overlays
+    fn capture_frame(&mut self, frame: &Frame) {
+        let Some(burst) = self.screenshot_burst.as_mut() else {
+            return;
+        };
+        if burst.is_finished() {
+            return;
+        }
+        if let Err(error) = burst.observe_frame(frame) {
+            warn!("Unable to save {:?}: {error}", burst.path(frame));
+            burst.finish();
+        }
+        if burst.is_finished() {
+            info!(
+                "Screenshot burst finished, {} frames saved in {:?}",
+                burst.captured().len(),
+                burst.settings().directory
+            );
+        }
+    }
+
+    /// Record PRG ROM accesses of the last CPU cycle. Reads of the 3 bytes at
+    /// `instruction_pc` are instruction fetches
+    fn record_coverage(&mut self, instruction_pc: Option<u16>) {
+        let (Some(coverage), Some(cartidge)) = (self.coverage.as_mut(), self.cartidge.as_ref())
+        else {
+            return;
+        };
+
+        for (address, access) in self.main_bus.borrow().take_accesses() {
+            let Some(offset) = address
+                .checked_sub(CARTIDGE_ROM_START)
+                .and_then(|address| cartidge.mapper.program_rom_offset(address))
+            else {
+                continue;
+            };
+            let fetch =
+                instruction_pc.is_some_and(|pc| (pc..=pc.saturating_add(2)).contains(&address));
+            let access = match access {
+                BusAccess::Write(_) => Access::WRITE,
+                BusAccess::Read if fetch => Access::EXECUTE,
+                BusAccess::Read => Access::READ,
+            };
+            coverage.record(offset, access);
+        }
+    }
+
+    /// Attend all events emitted since the last call
+    fn process_events(&mut self) {
+        while let Some(event) = self.events.poll() {
+            match event {
+                Event::FrameReady => {
+                    let mut frame = self.ppu.borrow_mut().take_frame();
+                    self.frame_count += 1;
+                    self.report_palette_changes();
+                    self.report_warnings();
+                    for port in &self.input_ports {
+                        port.borrow_mut().device_mut().end_frame(&frame);
+                    }
+                    self.metrics.observe_frame_ready();
+                    self.capture_frame(&frame);
+                    if let Some(corner) = self.settings.input_overlay {
+                        // The frame isn't shared yet, so it's not copied
+                        self.draw_input_overlay(Arc::make_mut(&mut frame), corner);
+                    }
+                    if let Some(corner) = self.settings.frame_time_graph {
+                        let frame_times: Vec<Duration> = self.metrics.frame_times().collect();
+                        frame_time_graph::draw(Arc::make_mut(&mut frame), corner, &frame_times);
+                    }
+                    self.execute_movie_commands();
+                    self.apply_input_script();
+                    self.evaluate_conditions();
+                    self.feed_watchdog();
+                    self.poll_rom_watcher();
+
+                    let inspection = self
+                        .ui
+                        .as_ref()
+                        .and_then(|ui| ui.inspected_memory())
+                        .map(|range| self.inspect(range));
+                    match self.ui.as_mut() {
+                        Some(ui) => {
+                            if ui.wants_debug_view() {
+                                let view = self.ppu.borrow().debug_view(0);
+                                ui.render_debug_view(Arc::new(view));
+                            }
+                            if let Some(inspection) = inspection {
+                                ui.render_inspection(Arc::new(inspection));
+                            }
+                            // Static screens (menus, pauses...) repeat the same
+                            // frame, there's no need to hand it over again
+                            let hash = frame.content_hash();
+                            if self.presented_hash == Some(hash) {
+                                self.metrics.observe_duplicate_frame();
+                            } else {
+                                self.presented_hash = Some(hash);
+                                ui.render(frame);
+                            }
+                        }
+                        None => self.last_frame = Some(frame),
+                    }
+
+                    if self.metrics.should_report() {
+                        self.report_metrics();
+                    }
+                }
+
+                Event::ScanlinesReady(rows) => self.present_scanlines(rows),
+
+                Event::SwitchOff => {
+                    self.save_session();
+                    self.switched_off = true;
+                }
+
+                Event::LoadRom(path) => match Cartidge::load(&path) {
+                    Ok(cartidge) => self.load_cartidge(cartidge),
+                    Err(error) => warn!("Unable to load ROM {path:?}: {error}"),
+                },
+
+                // Already reported
+                Event::BusFault(_)
+                | Event::WatchdogTriggered(_)
+                | Event::WarmUpWriteIgnored(_)
+                | Event::PaletteChanged(_)
+                | Event::EmulatorWarning(_)
+                | Event::OamDmaStarted(_)
+                | Event::OamDmaFinished(_) => {}
+            }
+        }
+    }
+
+    /// Send the UI and the scanlines hook the latest rendered `rows`
+    fn present_scanlines(&mut self, rows: Range<usize>) {
+        if self.scanlines_hook.is_none() && self.ui.is_none() {
+            return;
+        }
+
+        let ppu = self.ppu.borrow();
+        let partial = Arc::new(PartialFrame {
+            frame_index: ppu.state().frame_index,
+            pixels: ppu.frame_rows(rows.clone()),
+            rows,
+        });
+        drop(ppu);
+
+        if let Some(hook) = self.scanlines_hook.as_mut() {
+            hook(&partial);
+        }
+        if let Some(ui) = self.ui.as_mut() {
+            ui.render_scanlines(partial);
+        }
+    }
+
+    /// Reload the cartidge at `path` and reset whenever the file changes, for
+    /// homebrew development. The file is checked every
+    /// [`RomWatcher::POLL_INTERVAL_FRAMES`] frames. It doesn't need to be the
+    /// loaded cartidge, e.g., the first build can be loaded from memory
+    pub fn watch_rom(&mut self, path: impl Into<PathBuf>) {
+        self.rom_watcher = Some(RomWatcher::new(path));
+    }
+
+    pub fn stop_watching_rom(&mut self) {
+        self.rom_watcher = None;
+    }
+
+    fn poll_rom_watcher(&mut self) {
+        let Some(watcher) = self.rom_watcher.as_mut() else {
+            return;
+        };
+        if self
+            .frame_count
+            .is_multiple_of(RomWatcher::POLL_INTERVAL_FRAMES)
+            && watcher.poll()
+        {
+            info!("ROM {:?} changed, reloading it", watcher.path());
+            self.event_bus
+                .emit(Event::LoadRom(watcher.path().to_path_buf()));
+        }
+    }
+
+    fn feed_watchdog(&mut self) {
+        let Some(watchdog) = self.watchdog.as_mut() else {
+            return;
+        };
+
+        let hardware_accessed = self.main_bus.borrow().take_watched_access();
+        if let Some(report) = watchdog.end_frame(hardware_accessed) {
+            warn!(
+                "Watchdog: CPU stuck for {} frames in loop ${:0>4X}-${:0>4X}",
+                report.frames, report.loop_start, report.loop_end
+            );
+            self.event_bus.emit(Event::WatchdogTriggered(report));
+        }
+    }
+
+    /// Register a `condition` evaluated at the end of every frame. `callback`
+    /// is invoked every time the condition goes from unmet to met.
+    ///
+    /// Conditions can inspect internal RAM ($0000-$1FFF) and cartidge RAM
+    /// ($6000-$7FFF). Predicates over other addresses are never met, as reading
+    /// them could have side effects
+    pub fn add_condition(
+        &mut self,
+        condition: Condition,
+        callback: impl FnMut(ConditionId) + 'static,
+    ) -> ConditionId {
+        self.conditions.add(condition, Box::new(callback))
+    }
+
+    pub fn remove_condition(&mut self, id: ConditionId) {
+        self.conditions.remove(id);
+    }
+
+    fn evaluate_conditions(&mut self) {
+        if self.conditions.is_empty() {
+            return;
+        }
+
+        let ram = self.ram.borrow();
+        let cartidge_ram = self
+            .cartidge
+            .as_ref()
+            .map(|cartidge| cartidge.mapper.program_ram_ref());
+        let cartidge_ram = cartidge_ram.as_ref().map(|ram| ram.borrow());
+
+        self.conditions.evaluate(|address| match address {
+            RAM_START..=RAM_END => Some(ram.read(address - RAM_START)),
+            CARTIDGE_RAM_START..=CARTIDGE_RAM_END => cartidge_ram
+                .as_ref()
+                .and_then(|ram| ram.try_read(address - CARTIDGE_RAM_START).ok()),
+            _ => None,
+        });
+    }
+
+    /// Last performance metrics report. Metrics are reported every second
+    /// while the NES is running
+    pub fn metrics(&self) -> &Metrics {
+        &self.last_metrics
+    }
+
+    /// Register a `callback` invoked every time a new metrics report is
+    /// available. Useful for frontends displaying performance HUDs
+    pub fn on_metrics(&mut self, callback: impl FnMut(&Metrics) + 'static) {
+        self.metrics_callback = Some(Box::new(callback));
+    }
+
+    fn report_metrics(&mut self) {
+        if let Some(ui) = self.ui.as_mut() {
+            self.metrics
+                .observe_dropped_frames(ui.take_dropped_frames());
+        }
+
+        self.last_metrics = self.metrics.collect();
+
+        if let Some(callback) = self.metrics_callback.as_mut() {
+            callback(&self.last_metrics);
+        }
+    }
+
     /// Creates a new TV (UI) to render NES picture data and play audio. It must
     /// be called before running if one want to view and listen to the games
     pub fn setup_tv(&mut self) {
@@ -435,18 +1739,197 @@ impl Nes {
             UiKind::None => None,
 
             UiKind::Gtk => {
-                let gtk_ui = GtkUi::builder()
+                let mut builder = GtkUi::builder()
                     .screen_size(SCREEN_WIDTH, SCREEN_HEIGHT)
+                    .pixel_scale_factor(self.settings.pixel_scale_factor)
+                    .with_keyboard_publisher(self.keyboard_channel.publisher())
+                    .with_event_bus(self.event_bus.clone());
+                if let Some(ref settings_file) = self.settings.settings_file {
+                    builder = builder.with_settings_file(settings_file.clone());
+                }
+                if !self.settings.debug_windows.is_empty() {
+                    builder = builder.with_debug_windows(&self.settings.debug_windows);
+                }
+                let gtk_ui = builder.build();
+                Some(Box::new(gtk_ui) as Box<dyn Ui>)
+            }
+
+            #[cfg(feature = "egui")]
+            UiKind::Egui => {
+                let egui_ui = EguiUi::builder()
                     .pixel_scale_factor(self.settings.pixel_scale_factor)
                     .with_keyboard_publisher(self.keyboard_channel.publisher())
                     .with_event_bus(self.event_bus.clone())
                     .build();
-                Some(gtk_ui)
+                Some(Box::new(egui_ui) as Box<dyn Ui>)
             }
         };
 
         if let Some(ui) = ui {
-            self.ui.replace(ui);
+            self.set_ui(ui);
         }
     }
+
+    /// Render frames in a custom [`Ui`] instead of the ones provided. It
+    /// replaces the current UI, if any
+    pub fn set_ui(&mut self, mut ui: Box<dyn Ui>) {
+        if let Some(ref cartidge) = self.cartidge {
+            ui.set_title(&cartidge.info().title);
+        }
+        ui.set_video_filter(filters::build(self.settings.video_filter));
+        self.ui.replace(ui);
+        self.presented_hash = None;
+    }
+
+    /// Draw the controllers input in `corner` of the next frames, or stop
+    /// drawing it with `None`
+    pub fn set_input_overlay(&mut self, corner: Option<ScreenCorner>) {
+        self.settings.input_overlay = corner;
+    }
+
+    /// Draw a graph of the last frame times in `corner` of the next frames, or
+    /// stop drawing it with `None`
+    pub fn set_frame_time_graph(&mut self, corner: Option<ScreenCorner>) {
+        self.settings.frame_time_graph = corner;
+    }
+
+    /// Wall time between the last frames, oldest first. See
+    /// [`Collector::frame_times`](crate::metrics::Collector::frame_times)
+    pub fn frame_times(&self) -> Vec<Duration> {
+        self.metrics.frame_times().collect()
+    }
+
+    fn draw_input_overlay(&self, frame: &mut Frame, corner: ScreenCorner) {
+        let states: Vec<ControllerState> = self
+            .input_ports
+            .iter()
+            .filter_map(|port| {
+                let port = port.borrow();
+                let controller = port.device().downcast_ref::<Controller>()?;
+                controller.is_connected().then(|| controller.state())
+            })
+            .collect();
+        input_overlay::draw(frame, corner, &states);
+    }
+
+    /// Change the filter applied to frames before the UI presents them
+    pub fn set_video_filter(&mut self, kind: VideoFilterKind) {
+        self.settings.video_filter = kind;
+        if let Some(ui) = self.ui.as_mut() {
+            ui.set_video_filter(filters::build(kind));
+        }
+        self.presented_hash = None;
+    }
+
+    /// Event bus of this NES. UIs can use it to emit events, e.g.,
+    /// [`Event::SwitchOff`] to stop [`Nes::run`]
+    pub fn event_bus(&self) -> SharedEventBus {
+        self.event_bus.clone()
+    }
+}
+
+/// Builder returning a fully wired [`Nes`]: TV set up for the chosen UI,
+/// controllers connected and cartidge inserted.
+///
+/// ```no_run
+/// use nes_emulator::settings::UiKind;
+/// use nes_emulator::{Cartidge, ControllerButtons, Nes};
+///
+/// let mut nes = Nes::builder()
+///     .with_ui(UiKind::Gtk)
+///     .with_controllers(ControllerButtons::default(), None)
+///     .with_cartidge(Cartidge::new("game.nes"))
+///     .build();
+/// nes.run().unwrap();
+/// ```
+///
+/// There's no audio sink option yet, as the APU is not emulated
+pub struct NesBuilder {
+    settings: NesSettings,
+    custom_ui: Option<Box<dyn Ui>>,
+    controller_one: Option<ControllerButtons>,
+    controller_two: Option<ControllerButtons>,
+    cartidge: Option<Cartidge>,
+    metrics_callback: Option<MetricsCallback>,
+}
+
+impl NesBuilder {
+    pub fn new() -> Self {
+        Self {
+            settings: NesSettings::default(),
+            custom_ui: None,
+            controller_one: None,
+            controller_two: None,
+            cartidge: None,
+            metrics_callback: None,
+        }
+    }
+
+    pub fn build(self) -> Nes {
+        let mut nes = Nes::new(self.settings);
+        match self.custom_ui {
+            Some(ui) => nes.set_ui(ui),
+            None => nes.setup_tv(),
+        }
+
+        if let Some(buttons) = self.controller_one {
+            nes.connect_controller_one(buttons);
+        }
+        if let Some(buttons) = self.controller_two {
+            nes.connect_controller_two(buttons);
+        }
+        if let Some(cartidge) = self.cartidge {
+            nes.load_cartidge(cartidge);
+        }
+        nes.metrics_callback = self.metrics_callback;
+
+        nes
+    }
+
+    /// Replace all settings. Use it before other options, as it overrides
+    /// the UI kind
+    pub fn with_settings(mut self, settings: NesSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_ui(mut self, ui_kind: UiKind) -> Self {
+        self.settings.ui_kind = ui_kind;
+        self
+    }
+
+    /// Render frames in a custom [`Ui`]. It takes precedence over the UI kind
+    pub fn with_custom_ui(mut self, ui: impl Ui + 'static) -> Self {
+        self.custom_ui = Some(Box::new(ui));
+        self
+    }
+
+    /// Connect controller one and, optionally, controller two with their
+    /// keyboard bindings
+    pub fn with_controllers(
+        mut self,
+        one: ControllerButtons,
+        two: Option<ControllerButtons>,
+    ) -> Self {
+        self.controller_one = Some(one);
+        self.controller_two = two;
+        self
+    }
+
+    pub fn with_cartidge(mut self, cartidge: Cartidge) -> Self {
+        self.cartidge = Some(cartidge);
+        self
+    }
+
+    /// See [`Nes::on_metrics`]
+    pub fn with_metrics_callback(mut self, callback: impl FnMut(&Metrics) + 'static) -> Self {
+        self.metrics_callback = Some(Box::new(callback));
+        self
+    }
+}
+
+impl Default for NesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,284 @@
+//! Emulation and presentation pipeline
+//!
+//! Runs the emulation loop in its own thread, decoupled from whoever presents
+//! the frames (a UI, an encoder...). Frames flow from the emulation thread to
+//! the consumer through a bounded channel, and a [`Backpressure`] policy
+//! decides what happens when the consumer can't keep up.
+//!
+//! As the NES is not `Send`, it's built inside the emulation thread by a
+//! closure. Frames are only produced when it runs without UI, so the closure
+//! should build it with [`UiKind::None`](crate::settings::UiKind::None).
+//!
+//! ```no_run
+//! use nes_emulator::pipeline::{Backpressure, Pipeline};
+//! use nes_emulator::settings::UiKind;
+//! use nes_emulator::{Cartidge, Nes};
+//!
+//! let pipeline = Pipeline::spawn(2, Backpressure::Block, || {
+//!     Nes::builder()
+//!         .with_ui(UiKind::None)
+//!         .with_cartidge(Cartidge::new("game.nes"))
+//!         .build()
+//! });
+//! while let Some(frame) = pipeline.recv_frame() {
+//!     // present the frame
+//! }
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossbeam_channel::{
+    bounded, never, unbounded, Receiver, SendTimeoutError, Sender, TryRecvError, TrySendError,
+};
+use log::info;
+
+use crate::controller::ControllerState;
+use crate::errors::NesError;
+use crate::graphics::Frame;
+use crate::nes::Nes;
+
+// How often an emulation thread waiting for the consumer checks if it must stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What the emulation thread does when the frames channel is full
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Wait until the consumer takes a frame. Emulation runs at the consumer
+    /// pace
+    #[default]
+    Block,
+
+    /// Drop the new frame and keep emulating. Emulation runs as fast as
+    /// possible
+    Drop,
+}
+
+enum Command {
+    SetControllerState(u8, ControllerState),
+}
+
+pub struct Pipeline {
+    frames: Receiver<Arc<Frame>>,
+    commands: Sender<Command>,
+    stop: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+    handle: Option<JoinHandle<Result<(), NesError>>>,
+}
+
+impl Pipeline {
+    /// Spawn the emulation thread. `build` is called inside it to create the
+    /// NES. Up to `capacity` frames can be waiting for the consumer
+    pub fn spawn<F>(capacity: usize, backpressure: Backpressure, build: F) -> Self
+    where
+        F: FnOnce() -> Nes + Send + 'static,
+    {
+        let (frames_sender, frames) = bounded(capacity);
+        // Commands never block the consumer, even if the emulation thread is
+        // waiting for it
+        let (commands, commands_receiver) = unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let dropped_frames = Arc::new(AtomicU64::new(0));
+
+        let channels = EmulationChannels {
+            frames: frames_sender,
+            commands: commands_receiver,
+            stop: Arc::clone(&stop),
+            dropped_frames: Arc::clone(&dropped_frames),
+        };
+        let handle = thread::spawn(move || {
+            let nes = build();
+            emulation_loop(nes, backpressure, channels)
+        });
+
+        Self {
+            frames,
+            commands,
+            stop,
+            dropped_frames,
+            handle: Some(handle),
+        }
+    }
+
+    /// Wait for the next frame. Returns `None` once the emulation has stopped
+    pub fn recv_frame(&self) -> Option<Arc<Frame>> {
+        self.frames.recv().ok()
+    }
+
+    /// Next frame, if one is ready
+    pub fn try_recv_frame(&self) -> Option<Arc<Frame>> {
+        self.frames.try_recv().ok()
+    }
+
+    /// Frames channel, to be used with `select!` or iterators
+    pub fn frames(&self) -> &Receiver<Arc<Frame>> {
+        &self.frames
+    }
+
+    /// Set the pressed buttons of a controller `port` (0 or 1). It's applied
+    /// before emulating the next frame
+    pub fn set_controller_state(&self, port: u8, state: ControllerState) {
+        let _ = self.commands.send(Command::SetControllerState(port, state));
+    }
+
+    /// Frames dropped with [`Backpressure::Drop`] policy
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Stop the emulation thread and wait for it to finish
+    pub fn stop(mut self) -> Result<(), NesError> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), NesError> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+
+        // The emulation thread may be waiting for a frame to be taken and not
+        // reading commands, so stop it with a flag. Dropping the frames
+        // receiver also unblocks it, unless it's been cloned
+        self.stop.store(true, Ordering::Relaxed);
+        self.frames = never();
+
+        handle.join().unwrap_or_else(|_| {
+            Err(NesError::NesInternalError(
+                "Emulation thread panicked".to_string(),
+            ))
+        })
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+struct EmulationChannels {
+    frames: Sender<Arc<Frame>>,
+    commands: Receiver<Command>,
+    stop: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+}
+
+impl EmulationChannels {
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the consumer takes `frame`. Returns whether the emulation
+    /// must stop
+    fn send_frame(&self, mut frame: Arc<Frame>) -> bool {
+        loop {
+            match self.frames.send_timeout(frame, STOP_POLL_INTERVAL) {
+                Ok(()) => return false,
+                Err(SendTimeoutError::Timeout(unsent)) if !self.stopped() => frame = unsent,
+                Err(_) => return true,
+            }
+        }
+    }
+}
+
+fn emulation_loop(
+    mut nes: Nes,
+    backpressure: Backpressure,
+    channels: EmulationChannels,
+) -> Result<(), NesError> {
+    info!("Emulation thread started");
+
+    loop {
+        if channels.stopped() {
+            return Ok(());
+        }
+        loop {
+            match channels.commands.try_recv() {
+                Ok(Command::SetControllerState(0, state)) => nes.set_controller_one_state(state),
+                Ok(Command::SetControllerState(_, state)) => nes.set_controller_two_state(state),
+                Err(TryRecvError::Disconnected) => return Ok(()),
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        nes.run_frames(1)?;
+        let Some(frame) = nes.take_last_frame() else {
+            continue;
+        };
+
+        let stop = match backpressure {
+            Backpressure::Block => channels.send_frame(frame),
+            Backpressure::Drop => match channels.frames.try_send(frame) {
+                Ok(()) => false,
+                Err(TrySendError::Full(_)) => {
+                    channels.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => true,
+            },
+        };
+        if stop {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::settings::UiKind;
+    use crate::testing::scroll_split_cartidge;
+
+    fn headless_nes() -> Nes {
+        Nes::builder()
+            .with_ui(UiKind::None)
+            .with_cartidge(scroll_split_cartidge())
+            .build()
+    }
+
+    #[test]
+    fn test_pipeline_produces_frames() {
+        let pipeline = Pipeline::spawn(1, Backpressure::Block, headless_nes);
+        pipeline.set_controller_state(0, ControllerState::START);
+
+        let first = pipeline.recv_frame().unwrap();
+        let second = pipeline.recv_frame().unwrap();
+        assert_eq!(second.info.index, first.info.index + 1);
+        assert_eq!(pipeline.dropped_frames(), 0);
+
+        pipeline.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_drops_frames() {
+        let pipeline = Pipeline::spawn(1, Backpressure::Drop, headless_nes);
+
+        let start = Instant::now();
+        while pipeline.dropped_frames() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(30));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(pipeline.try_recv_frame().is_some());
+
+        pipeline.stop().unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_stops_without_consumer() {
+        for capacity in [0, 2] {
+            let pipeline = Pipeline::spawn(capacity, Backpressure::Block, headless_nes);
+            // Frames are never taken, the emulation thread waits for the
+            // consumer, even with a clone of the frames receiver around
+            let _frames = pipeline.frames().clone();
+            for _ in 0..100 {
+                pipeline.set_controller_state(0, ControllerState::A);
+            }
+            thread::sleep(Duration::from_millis(500));
+            pipeline.stop().unwrap();
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 
 use log::debug;
 
@@ -7,11 +7,38 @@ use crate::errors::BusError;
 use crate::interfaces::AddressRange;
 use crate::interfaces::Bus as BusTrait;
 use crate::interfaces::DeviceId;
+use crate::interfaces::{BusAccess, BusFault, BusFaultPolicy, TracedAccess};
 use crate::types::SharedMemory;
 
+/// Called before every read and write with the address and access, see
+/// [`Bus::set_access_hook`]
+pub type AccessHook = Box<dyn Fn(u16, BusAccess)>;
+
+const FLAT_MEMORY_ID: DeviceId = "Flat memory";
+
 pub struct Bus {
     id: &'static str,
     devices: RefCell<HashMap<DeviceId, Device>>,
+
+    fault_policy: BusFaultPolicy,
+    // Last value driven on the bus, returned by faulty reads in tolerant mode
+    open_bus: Cell<u8>,
+    last_read_address: Cell<Option<u16>>,
+    faults: RefCell<Vec<BusFault>>,
+
+    watched_range: Option<AddressRange>,
+    watched_accessed: Cell<bool>,
+    access_log: Option<RefCell<Vec<(u16, BusAccess)>>>,
+    access_trace: Option<RefCell<AccessTrace>>,
+    cpu_cycle: Cell<u64>,
+    access_hook: Option<AccessHook>,
+    flat_memory: Option<SharedMemory>,
+}
+
+// Last accesses with the device answering each one, oldest first
+struct AccessTrace {
+    accesses: VecDeque<TracedAccess>,
+    capacity: usize,
 }
 
 struct Device {
@@ -24,8 +51,182 @@ impl Bus {
         Self {
             id,
             devices: RefCell::new(HashMap::new()),
+            fault_policy: BusFaultPolicy::default(),
+            open_bus: Cell::new(0),
+            last_read_address: Cell::new(None),
+            faults: RefCell::new(Vec::new()),
+            watched_range: None,
+            watched_accessed: Cell::new(false),
+            access_log: None,
+            access_trace: None,
+            cpu_cycle: Cell::new(0),
+            access_hook: None,
+            flat_memory: None,
         }
     }
+
+    pub fn set_fault_policy(&mut self, policy: BusFaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    pub fn fault_policy(&self) -> BusFaultPolicy {
+        self.fault_policy
+    }
+
+    /// Return and clear faults occurred since the last call. Faults are only
+    /// recorded in tolerant mode
+    pub fn take_faults(&self) -> Vec<BusFault> {
+        self.faults.take()
+    }
+
+    /// Address of the last read, excluding DMA reads
+    pub fn last_read_address(&self) -> Option<u16> {
+        self.last_read_address.get()
+    }
+
+    /// Record reads and writes to `range`. See [`Bus::take_watched_access`]
+    pub fn watch(&mut self, range: AddressRange) {
+        self.watched_range = Some(range);
+        self.watched_accessed.set(false);
+    }
+
+    /// Return whether the watched range has been read or written since the
+    /// last call, excluding DMA reads
+    pub fn take_watched_access(&self) -> bool {
+        self.watched_accessed.take()
+    }
+
+    /// Start or stop logging every read and write. See [`Bus::take_accesses`]
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.access_log = enabled.then(RefCell::default);
+    }
+
+    /// Return and clear the accesses logged since the last call, in order
+    pub fn take_accesses(&self) -> Vec<(u16, BusAccess)> {
+        self.access_log
+            .as_ref()
+            .map(RefCell::take)
+            .unwrap_or_default()
+    }
+
+    /// Start recording the last `capacity` accesses along with the device
+    /// answering each one, or stop with `None`. Useful to find out which
+    /// device answered a read when devices or mappers conflict. See
+    /// [`Bus::access_trace`]
+    pub fn set_access_trace(&mut self, capacity: Option<usize>) {
+        self.access_trace = capacity.map(|capacity| {
+            RefCell::new(AccessTrace {
+                accesses: VecDeque::with_capacity(capacity),
+                capacity,
+            })
+        });
+    }
+
+    /// Traced accesses, oldest first. The trace is kept
+    pub fn access_trace(&self) -> Vec<TracedAccess> {
+        self.access_trace
+            .as_ref()
+            .map(|trace| trace.borrow().accesses.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Set the CPU cycle traced accesses happen in. The CPU runs instructions
+    /// at once, so all accesses of an instruction share the same cycle
+    pub fn set_cpu_cycle(&self, cycle: u64) {
+        self.cpu_cycle.set(cycle);
+    }
+
+    /// Run `hook` before every read and write, excluding DMA reads, e.g., to
+    /// catch up devices running behind the CPU
+    pub fn set_access_hook(&mut self, hook: Option<AccessHook>) {
+        self.access_hook = hook;
+    }
+
+    /// Serve every access with `memory`, covering the whole address space,
+    /// instead of the attached devices. `None` goes back to the devices
+    pub fn set_flat_memory(&mut self, memory: Option<SharedMemory>) {
+        self.flat_memory = memory;
+    }
+
+    fn trace_access(&self, address: u16, access: BusAccess, value: u8) {
+        let Some(trace) = &self.access_trace else {
+            return;
+        };
+        let mut trace = trace.borrow_mut();
+        if trace.capacity == 0 {
+            return;
+        }
+        if trace.accesses.len() == trace.capacity {
+            trace.accesses.pop_front();
+        }
+        let device_id = self.device_at(address);
+        trace.accesses.push_back(TracedAccess {
+            address,
+            device_id,
+            access,
+            value,
+            cpu_cycle: self.cpu_cycle.get(),
+        });
+    }
+
+    fn device_at(&self, address: u16) -> Option<DeviceId> {
+        if self.flat_memory.is_some() {
+            return Some(FLAT_MEMORY_ID);
+        }
+        self.devices
+            .borrow()
+            .iter()
+            .find(|(_, Device { addr_range, .. })| {
+                (addr_range.start..=addr_range.end).contains(&address)
+            })
+            .map(|(device_id, _)| *device_id)
+    }
+
+    fn log_access(&self, address: u16, access: BusAccess) {
+        if let Some(log) = &self.access_log {
+            log.borrow_mut().push((address, access));
+        }
+    }
+
+    fn observe_access(&self, address: u16) {
+        if let Some(range) = &self.watched_range {
+            if (range.start..=range.end).contains(&address) {
+                self.watched_accessed.set(true);
+            }
+        }
+    }
+
+    /// Read `address` the way DMA units do. Reads from unmapped or write-only
+    /// addresses aren't faults but return the open bus value, i.e., the last
+    /// value driven on the bus
+    pub fn dma_read(&self, address: u16) -> u8 {
+        self.log_access(address, BusAccess::Read);
+        let data = match self.try_read(address) {
+            Ok(data) => {
+                self.open_bus.set(data);
+                data
+            }
+            Err(error) => {
+                debug!("DMA read from ${address:0>4X} returns open bus: {error}");
+                self.open_bus.get()
+            }
+        };
+        self.trace_access(address, BusAccess::Read, data);
+        data
+    }
+
+    fn recover(&self, error: BusError, address: u16, access: BusAccess) {
+        if self.fault_policy == BusFaultPolicy::Strict {
+            panic!("{error}");
+        }
+        debug!("Ignoring bus fault: {error}");
+        self.faults.borrow_mut().push(BusFault {
+            bus_id: self.id,
+            address,
+            access,
+            pc: None,
+        });
+    }
 }
 
 impl BusTrait for Bus {
@@ -78,20 +279,138 @@ impl BusTrait for Bus {
     }
 
     fn read(&self, address: u16) -> u8 {
-        self.try_read(address)
-            .map_err(|error| error.to_string())
-            .unwrap()
+        if let Some(hook) = &self.access_hook {
+            hook(address, BusAccess::Read);
+        }
+        self.last_read_address.set(Some(address));
+        self.observe_access(address);
+        self.log_access(address, BusAccess::Read);
+        let data = match self.try_read(address) {
+            Ok(data) => {
+                self.open_bus.set(data);
+                data
+            }
+            Err(error) => {
+                self.recover(error, address, BusAccess::Read);
+                self.open_bus.get()
+            }
+        };
+        self.trace_access(address, BusAccess::Read, data);
+        data
     }
 
     fn write(&self, address: u16, data: u8) {
-        self.try_write(address, data)
-            .map_err(|error| error.to_string())
-            .unwrap();
+        if let Some(hook) = &self.access_hook {
+            hook(address, BusAccess::Write(data));
+        }
+        self.open_bus.set(data);
+        self.observe_access(address);
+        self.log_access(address, BusAccess::Write(data));
+        self.trace_access(address, BusAccess::Write(data), data);
+        if let Err(error) = self.try_write(address, data) {
+            self.recover(error, address, BusAccess::Write(data));
+        }
+    }
+
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let start = address.wrapping_add(offset as u16);
+            let Some((device, virtual_address, length)) =
+                self.block_device(start, buffer.len() - offset)
+            else {
+                // Let a single read deal with faults
+                buffer[offset] = self.read(start);
+                offset += 1;
+                continue;
+            };
+
+            self.run_access_hook(start, length, |_| BusAccess::Read);
+            let block = &mut buffer[offset..offset + length];
+            device.borrow().read_block(virtual_address, block);
+            for (index, data) in block.iter().enumerate() {
+                let address = start + index as u16;
+                self.observe_access(address);
+                self.log_access(address, BusAccess::Read);
+                self.trace_access(address, BusAccess::Read, *data);
+                self.last_read_address.set(Some(address));
+                self.open_bus.set(*data);
+            }
+            offset += length;
+        }
+    }
+
+    fn write_block(&self, address: u16, data: &[u8]) {
+        let mut offset = 0;
+        while offset < data.len() {
+            let start = address.wrapping_add(offset as u16);
+            let Some((device, virtual_address, length)) =
+                self.block_device(start, data.len() - offset)
+            else {
+                self.write(start, data[offset]);
+                offset += 1;
+                continue;
+            };
+
+            let block = &data[offset..offset + length];
+            self.run_access_hook(start, length, |index| BusAccess::Write(block[index]));
+            device.borrow_mut().write_block(virtual_address, block);
+            for (index, data) in block.iter().enumerate() {
+                let address = start + index as u16;
+                self.observe_access(address);
+                self.log_access(address, BusAccess::Write(*data));
+                self.trace_access(address, BusAccess::Write(*data), *data);
+                self.open_bus.set(*data);
+            }
+            offset += length;
+        }
     }
 }
 
 impl Bus {
+    /// Run the access hook for each of the `length` bytes of a block access
+    /// starting at `address`, before the device serves any of them
+    fn run_access_hook(&self, address: u16, length: usize, access: impl Fn(usize) -> BusAccess) {
+        if let Some(hook) = &self.access_hook {
+            for index in 0..length {
+                hook(address.wrapping_add(index as u16), access(index));
+            }
+        }
+    }
+
+    /// Device serving `address` and its address in the device, along with how
+    /// many of the next `length` bytes it serves. Devices smaller than their
+    /// address range are left out, as single accesses handle their mirroring
+    /// and faults
+    fn block_device(&self, address: u16, length: usize) -> Option<(SharedMemory, u16, usize)> {
+        if let Some(memory) = &self.flat_memory {
+            let length = length.min(0x10000 - address as usize);
+            return Some((SharedMemory::clone(memory), address, length));
+        }
+        let devices = self.devices.borrow();
+        let Device { device, addr_range } =
+            devices.values().find(|Device { addr_range, .. }| {
+                (addr_range.start..=addr_range.end).contains(&address)
+            })?;
+
+        let virtual_address = address - addr_range.start;
+        let length = length.min((addr_range.end - address) as usize + 1);
+        let fits = virtual_address as usize + length <= device.borrow().size();
+        fits.then(|| (SharedMemory::clone(device), virtual_address, length))
+    }
+
     fn try_read(&self, address: u16) -> Result<u8, BusError> {
+        if let Some(memory) = &self.flat_memory {
+            return memory
+                .borrow()
+                .try_read(address)
+                .map_err(|error| BusError::BusReadError {
+                    bus_id: self.id,
+                    device_id: FLAT_MEMORY_ID,
+                    address,
+                    details: error.to_string(),
+                });
+        }
         for (device_id, Device { device, addr_range }) in self.devices.borrow().iter() {
             if address >= addr_range.start && address <= addr_range.end {
                 let virtual_address = address - addr_range.start;
@@ -118,6 +437,17 @@ impl Bus {
 
     fn try_write(&self, address: u16, data: u8) -> Result<(), BusError> {
         debug!("Bus ({0}) write to: {address:0>4X} <- {data:0>2X}", self.id);
+        if let Some(memory) = &self.flat_memory {
+            return memory
+                .borrow_mut()
+                .try_write(address, data)
+                .map_err(|error| BusError::BusWriteError {
+                    bus_id: self.id,
+                    device_id: FLAT_MEMORY_ID,
+                    address,
+                    details: error.to_string(),
+                });
+        }
         for (device_id, Device { device, addr_range }) in self.devices.borrow_mut().iter_mut() {
             if address >= addr_range.start && address <= addr_range.end {
                 let virtual_address = address - addr_range.start;
@@ -159,4 +489,151 @@ mod tests {
 
         bus.write(0x1234, 0xf0);
     }
+
+    #[test]
+    fn test_tolerant_bus_faults() {
+        let mut bus = Bus::new("test-bus");
+        bus.set_fault_policy(BusFaultPolicy::Tolerant);
+
+        bus.write(0x1234, 0xf0);
+        assert_eq!(bus.read(0x4321), 0xf0);
+
+        let faults = bus.take_faults();
+        assert_eq!(faults.len(), 2);
+        assert_eq!(faults[0].access, BusAccess::Write(0xf0));
+        assert_eq!(faults[1].address, 0x4321);
+        assert_eq!(faults[1].access, BusAccess::Read);
+        assert!(bus.take_faults().is_empty());
+    }
+
+    #[test]
+    fn test_block_accesses() {
+        use std::rc::Rc;
+
+        use crate::processor::memory::{MirroredMemory, Ram};
+
+        let mut bus = Bus::new("test-bus");
+        bus.set_fault_policy(BusFaultPolicy::Tolerant);
+        let ram = Rc::new(RefCell::new(Ram::new(0x10)));
+        let mirrored = Rc::new(RefCell::new(MirroredMemory::new(Ram::new(4), 3)));
+        bus.attach(
+            "RAM",
+            ram,
+            AddressRange {
+                start: 0x0000,
+                end: 0x000F,
+            },
+        )
+        .unwrap();
+        bus.attach(
+            "Mirrored RAM",
+            Rc::clone(&mirrored) as _,
+            AddressRange {
+                start: 0x0010,
+                end: 0x001F,
+            },
+        )
+        .unwrap();
+        bus.set_access_log(true);
+
+        let data: Vec<u8> = (1..=12).collect();
+        bus.write_block(0x000C, &data);
+        assert_eq!(mirrored.borrow().memory().as_slice(), [9, 10, 11, 12]);
+
+        let mut buffer = [0; 8];
+        bus.read_block(0x000C, &mut buffer);
+        assert_eq!(buffer, [1, 2, 3, 4, 9, 10, 11, 12]);
+        assert_eq!(bus.take_accesses().len(), 20);
+
+        // Unmapped addresses are faults, as with single accesses
+        let mut buffer = [0; 4];
+        bus.read_block(0x001E, &mut buffer);
+        assert_eq!(buffer, [11, 12, 12, 12]);
+        assert_eq!(bus.take_faults().len(), 2);
+        assert_eq!(bus.last_read_address(), Some(0x0021));
+    }
+
+    #[test]
+    fn test_block_accesses_run_access_hook() {
+        use std::rc::Rc;
+
+        use crate::processor::memory::Ram;
+
+        let mut bus = Bus::new("test-bus");
+        let ram = Rc::new(RefCell::new(Ram::new(0x10)));
+        bus.attach(
+            "RAM",
+            ram,
+            AddressRange {
+                start: 0x0000,
+                end: 0x000F,
+            },
+        )
+        .unwrap();
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let hooked = Rc::clone(&accesses);
+        bus.set_access_hook(Some(Box::new(move |address, access| {
+            hooked.borrow_mut().push((address, access));
+        })));
+
+        bus.write_block(0x0002, &[7, 8]);
+        let mut buffer = [0; 2];
+        bus.read_block(0x0002, &mut buffer);
+        assert_eq!(
+            *accesses.borrow(),
+            [
+                (0x0002, BusAccess::Write(7)),
+                (0x0003, BusAccess::Write(8)),
+                (0x0002, BusAccess::Read),
+                (0x0003, BusAccess::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_access_trace() {
+        use std::rc::Rc;
+
+        use crate::processor::memory::Ram;
+
+        let mut bus = Bus::new("test-bus");
+        bus.set_fault_policy(BusFaultPolicy::Tolerant);
+        bus.attach(
+            "RAM",
+            Rc::new(RefCell::new(Ram::new(0x10))),
+            AddressRange {
+                start: 0x0000,
+                end: 0x000F,
+            },
+        )
+        .unwrap();
+        bus.set_access_trace(Some(2));
+
+        bus.set_cpu_cycle(7);
+        bus.write(0x0001, 0x42);
+        bus.set_cpu_cycle(8);
+        bus.read(0x0001);
+        bus.read(0x0020);
+
+        let trace = bus.access_trace();
+        assert_eq!(
+            trace,
+            [
+                TracedAccess {
+                    address: 0x0001,
+                    device_id: Some("RAM"),
+                    access: BusAccess::Read,
+                    value: 0x42,
+                    cpu_cycle: 8,
+                },
+                TracedAccess {
+                    address: 0x0020,
+                    device_id: None,
+                    access: BusAccess::Read,
+                    value: 0x42,
+                    cpu_cycle: 8,
+                },
+            ]
+        );
+    }
 }
@@ -1,5 +1,8 @@
 use log::{debug, info, warn};
 
+use crate::debugger::{CallKind, CallStack};
+use crate::errors::StateError;
+use crate::hardware::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
 use crate::interfaces::Bus as _;
 use crate::processor::instruction::{
     AddressingMode, Instruction, InstructionKind, MiscInstructionKind,
@@ -7,7 +10,10 @@ use crate::processor::instruction::{
 use crate::processor::instruction_set;
 use crate::processor::instruction_set::InstructionSet;
 use crate::processor::internal_cpu::InternalCpu;
+use crate::processor::interrupt_line::InterruptLine;
 use crate::processor::status_register::StatusRegisterFlag;
+use crate::settings::AccuracyProfile;
+use crate::snapshot::{StateReader, StateWriter};
 use crate::types::SharedBus;
 
 use AddressingMode::*;
@@ -15,6 +21,10 @@ use InstructionKind::*;
 use MiscInstructionKind::*;
 use StatusRegisterFlag::*;
 
+/// Callback invoked after every executed instruction with the resulting CPU
+/// state
+pub type ExecHook = Box<dyn FnMut(&CpuState, &Instruction)>;
+
 pub struct Cpu {
     cpu: InternalCpu,
     instruction_set: InstructionSet,
@@ -24,9 +34,71 @@ pub struct Cpu {
     page_boundary_cross_extra_clocks: u8,
 
     interrupt_request: Option<Interrupt>,
+    nmi_line: InterruptLine,
+    irq_line: InterruptLine,
+
+    // Address of the last instruction started
+    instruction_pc: u16,
+
+    exec_hook: Option<ExecHook>,
+    call_stack: Option<CallStack>,
+    accuracy: AccuracyProfile,
+}
+
+/// Snapshot of the CPU registers
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub acc: u8,
+    pub x_reg: u8,
+    pub y_reg: u8,
+    pub sp: u8,
+    pub pc: u16,
+    /// Status register (P) flags
+    pub sr: u8,
 }
 
-#[derive(Copy, Clone)]
+/// Complete CPU state, including timing and pending interrupts. See
+/// [`Snapshot`](crate::snapshot::Snapshot)
+#[derive(Clone)]
+pub struct CpuSnapshot {
+    cpu: InternalCpu,
+    clocks_before_next_execution: u8,
+    page_boundary_cross_extra_clocks: u8,
+    interrupt_request: Option<Interrupt>,
+    nmi_pending: bool,
+    instruction_pc: u16,
+}
+
+impl CpuSnapshot {
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        self.cpu.save(state);
+        state.u8(self.clocks_before_next_execution);
+        state.u8(self.page_boundary_cross_extra_clocks);
+        state.option(self.interrupt_request, |state, interrupt| {
+            state.u8(interrupt as u8)
+        });
+        state.bool(self.nmi_pending);
+        state.u16(self.instruction_pc);
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.load(state)?;
+        self.clocks_before_next_execution = state.u8()?;
+        self.page_boundary_cross_extra_clocks = state.u8()?;
+        self.interrupt_request = state.option(|state| match state.u8()? {
+            0 => Ok(Interrupt::NonMaskableInterrupt),
+            1 => Ok(Interrupt::Reset),
+            2 => Ok(Interrupt::InterruptRequest),
+            value => Err(StateError::Malformed(format!("invalid interrupt {value}"))),
+        })?;
+        self.nmi_pending = state.bool()?;
+        self.instruction_pc = state.u16()?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(clippy::enum_variant_names)]
 pub enum Interrupt {
     NonMaskableInterrupt, // NMI
@@ -43,26 +115,56 @@ impl Cpu {
             clocks_before_next_execution: 1,
             page_boundary_cross_extra_clocks: 0,
             interrupt_request: None,
+            nmi_line: InterruptLine::new(),
+            irq_line: InterruptLine::new(),
+            instruction_pc: 0,
+            exec_hook: None,
+            call_stack: None,
+            accuracy: AccuracyProfile::default(),
         }
     }
 
-    /// Reset the processor to an init state. After concrete CPU
-    /// initializations, it'll call the Reset vector (RES interrupt) and leave
-    /// further state initialization to it.
+    pub fn set_accuracy(&mut self, accuracy: AccuracyProfile) {
+        self.accuracy = accuracy;
+    }
+
+    /// Power the processor up: registers are cleared and the reset sequence
+    /// runs, so SP ends up at $FD and interrupts are disabled (P reads $34
+    /// with PHP)
+    ///
+    /// See more information: https://www.nesdev.org/wiki/CPU_power_up_state
+    pub fn power_up(&mut self) {
+        info!("CPU power-up");
+        self.cpu = InternalCpu {
+            sp: 0,
+            ..InternalCpu::default()
+        };
+        self.reset();
+    }
+
+    /// Reset the processor, as the reset button does. Reset is an interrupt
+    /// taking 7 clocks whose stack pushes are turned into reads, so registers
+    /// are left untouched but SP is decremented by 3 and interrupts are
+    /// disabled. Execution continues at the reset vector
     pub fn reset(&mut self) {
         info!("CPU reset");
-        self.cpu.acc = 0;
-        self.cpu.x_reg = 0;
-        self.cpu.y_reg = 0;
-        self.cpu.sp = 0xFF;
-        self.cpu.sr.reset();
+        self.interrupt_request = None;
+        self.reset_sequence();
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            call_stack.clear();
+        }
 
-        self.clocks_before_next_execution = 1;
+        self.clocks_before_next_execution = 7;
         self.page_boundary_cross_extra_clocks = 0;
+    }
+
+    fn reset_sequence(&mut self) {
+        self.cpu.sp = self.cpu.sp.wrapping_sub(3);
+        self.cpu.sr.set(InterruptDisable);
 
         // read address provided in the reset vector
-        let pcl = self.bus_read(0xFFFC) as u16;
-        let pch = self.bus_read(0xFFFD) as u16;
+        let pcl = self.bus_read(RESET_VECTOR) as u16;
+        let pch = self.bus_read(RESET_VECTOR + 1) as u16;
         self.cpu.pc = (pch << 8) | pcl;
     }
 
@@ -98,9 +200,16 @@ impl Cpu {
             return Ok(());
         }
 
-        match self.interrupt_request.take() {
+        self.instruction_pc = self.cpu.pc;
+        let stack_pointer = self.cpu.sp;
+        let interrupt = self
+            .interrupt_request
+            .take()
+            .or_else(|| self.poll_interrupt_lines());
+        match interrupt {
             Some(interrupt) => {
                 self.execute_interrupt(interrupt);
+                self.track_interrupt(interrupt, stack_pointer);
                 // Attending an interrupt takes 7 clocks: 2 for internal
                 // operations, 2 to push the return address, 1 for the status
                 // register, and 2 more to get the interrupt begin address
@@ -115,6 +224,17 @@ impl Cpu {
 
                 self.cpu.page_boundary_crossed = false;
                 self.execute_instruction(instruction)?;
+                if self.call_stack.is_some() {
+                    let state = self.state();
+                    if let Some(call_stack) = self.call_stack.as_mut() {
+                        call_stack.observe_instruction(
+                            name,
+                            self.instruction_pc,
+                            stack_pointer,
+                            &state,
+                        );
+                    }
+                }
 
                 if self.cpu.page_boundary_crossed {
                     self.page_boundary_cross_extra_clocks += page_crossing_cost;
@@ -128,6 +248,63 @@ impl Cpu {
         }
     }
 
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            acc: self.cpu.acc,
+            x_reg: self.cpu.x_reg,
+            y_reg: self.cpu.y_reg,
+            sp: self.cpu.sp,
+            pc: self.cpu.pc,
+            sr: self.cpu.sr.into(),
+        }
+    }
+
+    /// Overwrite the registers, e.g., to start running a program somewhere
+    /// other than the reset vector
+    pub fn set_state(&mut self, state: CpuState) {
+        self.cpu.acc = state.acc;
+        self.cpu.x_reg = state.x_reg;
+        self.cpu.y_reg = state.y_reg;
+        self.cpu.sp = state.sp;
+        self.cpu.pc = state.pc;
+        self.cpu.sr = state.sr.into();
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            cpu: self.cpu.clone(),
+            clocks_before_next_execution: self.clocks_before_next_execution,
+            page_boundary_cross_extra_clocks: self.page_boundary_cross_extra_clocks,
+            interrupt_request: self.interrupt_request,
+            nmi_pending: self.nmi_line.is_asserted(),
+            instruction_pc: self.instruction_pc,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.cpu = snapshot.cpu.clone();
+        self.clocks_before_next_execution = snapshot.clocks_before_next_execution;
+        self.page_boundary_cross_extra_clocks = snapshot.page_boundary_cross_extra_clocks;
+        self.interrupt_request = snapshot.interrupt_request;
+        if snapshot.nmi_pending {
+            self.nmi_line.assert();
+        } else {
+            self.nmi_line.release();
+        }
+        self.instruction_pc = snapshot.instruction_pc;
+    }
+
+    /// CPU cycles left until the next instruction (or interrupt) starts. When
+    /// it's 1, the next cycle starts it
+    pub fn cycles_before_next_instruction(&self) -> u8 {
+        self.clocks_before_next_execution
+    }
+
+    /// Address of the last instruction the CPU has started to execute
+    pub fn instruction_pc(&self) -> u16 {
+        self.instruction_pc
+    }
+
     /// Execute a CPU interrupt
     pub fn interrupt(&mut self, interrupt: Interrupt) {
         if self.interrupt_request.is_some() {
@@ -136,6 +313,62 @@ impl Cpu {
         self.interrupt_request.replace(interrupt);
     }
 
+    /// Call `hook` after every executed instruction, or stop calling it with
+    /// `None`. Interrupts aren't instructions and don't call it
+    pub fn set_exec_hook(&mut self, hook: Option<ExecHook>) {
+        self.exec_hook = hook;
+    }
+
+    /// NMI line of the CPU, to be wired to the devices raising NMIs
+    pub fn nmi_line(&self) -> InterruptLine {
+        self.nmi_line.clone()
+    }
+
+    /// IRQ line of the CPU, shared by all devices raising IRQs
+    pub fn irq_line(&self) -> InterruptLine {
+        self.irq_line.clone()
+    }
+
+    /// Enable or disable the call stack tracking. Enabling it starts with an
+    /// empty call stack
+    pub fn set_call_tracking(&mut self, enabled: bool) {
+        self.call_stack = enabled.then(CallStack::new);
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.call_stack.as_ref()
+    }
+
+    fn track_interrupt(&mut self, interrupt: Interrupt, stack_pointer: u8) {
+        let state = self.state();
+        let Some(call_stack) = self.call_stack.as_mut() else {
+            return;
+        };
+        let kind = match interrupt {
+            Interrupt::Reset => {
+                call_stack.clear();
+                return;
+            }
+            // Ignored IRQ, as interrupts are disabled
+            _ if state.sp == stack_pointer => return,
+            Interrupt::NonMaskableInterrupt => CallKind::Nmi,
+            Interrupt::InterruptRequest => CallKind::Irq,
+        };
+        call_stack.observe_interrupt(kind, self.instruction_pc, stack_pointer, &state);
+    }
+
+    /// Interrupt signaled through the interrupt lines, if any. NMI has
+    /// priority over IRQ, which is ignored while interrupts are disabled
+    fn poll_interrupt_lines(&self) -> Option<Interrupt> {
+        if self.nmi_line.take() {
+            Some(Interrupt::NonMaskableInterrupt)
+        } else if self.irq_line.is_asserted() && !self.cpu.sr.get(InterruptDisable) {
+            Some(Interrupt::InterruptRequest)
+        } else {
+            None
+        }
+    }
+
     /// Execute a complete instruction and return the number of clocks used
     pub fn execute(&mut self) -> Result<u8, String> {
         let instruction = self.fetch()?;
@@ -149,7 +382,7 @@ impl Cpu {
     pub fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), String> {
         let previous_cpu_status = self.cpu.clone();
 
-        match instruction.instruction {
+        match &instruction.instruction {
             SingleByte(fun) => {
                 fun(&mut self.cpu);
             }
@@ -162,8 +395,13 @@ impl Cpu {
                 self.store(data, instruction.addressing_mode);
             }
             ReadModifyWrite(fun) => {
-                let (_, data) = self.load(instruction.addressing_mode);
+                let (addr, data) = self.load(instruction.addressing_mode);
                 let result = fun(&mut self.cpu, data);
+                // Hardware writes the unmodified value back while modifying
+                // it. I/O registers and mapper ports see both writes
+                if self.accuracy.is_accurate() {
+                    self.bus_write(addr, data);
+                }
                 self.store(result, instruction.addressing_mode);
             }
             Misc(t) => match t {
@@ -202,7 +440,7 @@ impl Cpu {
         // Increase PC
         match instruction.name {
             "JMP" | "JSR" | "RTS" | "BRK" | "RTI" => {}
-            _ => self.cpu.pc += instruction.bytes as u16,
+            _ => self.cpu.pc = self.cpu.pc.wrapping_add(instruction.bytes as u16),
         }
 
         debug!(
@@ -214,6 +452,11 @@ impl Cpu {
             Self::status_diff(&previous_cpu_status, &self.cpu)
         );
 
+        if let Some(mut hook) = self.exec_hook.take() {
+            hook(&self.state(), &instruction);
+            self.exec_hook = Some(hook);
+        }
+
         Ok(())
     }
 
@@ -221,15 +464,18 @@ impl Cpu {
         let (lb, hb) = match interrupt {
             Interrupt::NonMaskableInterrupt => {
                 // println!("CPU executing NMI");
-                (0xFFFA, 0xFFFB)
+                (NMI_VECTOR, NMI_VECTOR + 1)
+            }
+            Interrupt::Reset => {
+                self.reset_sequence();
+                return;
             }
-            Interrupt::Reset => (0xFFFC, 0xFFFD),
             Interrupt::InterruptRequest => {
                 // IRQ is not executed if Interrupt disable flag is active
                 if self.cpu.sr.get(InterruptDisable) {
                     return;
                 }
-                (0xFFFE, 0xFFFF)
+                (IRQ_VECTOR, IRQ_VECTOR + 1)
             }
         };
 
@@ -252,39 +498,39 @@ impl Cpu {
     fn load(&mut self, addr_mode: AddressingMode) -> (u16, u8) {
         let (addr, data) = match addr_mode {
             Implied => {
-                let addr = self.cpu.pc + 1;
+                let addr = self.cpu.pc.wrapping_add(1);
                 let opcode = self.bus_read(self.cpu.pc);
                 let data = opcode; // discarted
                 (addr, data)
             }
             Accumulator => {
-                let addr = self.cpu.pc + 1;
+                let addr = self.cpu.pc.wrapping_add(1);
                 let data = self.cpu.acc;
                 (addr, data)
             }
             Immediate => {
-                let addr = self.cpu.pc + 1;
+                let addr = self.cpu.pc.wrapping_add(1);
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             ZeroPage => {
                 // Effective address is 00, ADL
-                let adl = self.bus_read(self.cpu.pc + 1) as u16;
+                let adl = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 let addr = adl;
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             Absolute => {
                 // Effective address is ADH, ADL
-                let adl = self.bus_read(self.cpu.pc + 1) as u16;
-                let adh = self.bus_read(self.cpu.pc + 2) as u16;
+                let adl = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let adh = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
                 let addr = (adh << 8) | adl;
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             IndirectX => {
                 // page zero base address
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 let adl = self.bus_read((bal + (self.cpu.x_reg as u16)) & 0x00FF) as u16;
                 let adh = self.bus_read((bal + (self.cpu.x_reg as u16) + 1) & 0x00FF) as u16;
                 let addr = (adh << 8) | adl;
@@ -292,61 +538,63 @@ impl Cpu {
                 (addr, data)
             }
             AbsoluteX => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
-                let bah = self.bus_read(self.cpu.pc + 2) as u16;
-                let addr = ((bah << 8) | bal) + self.cpu.x_reg as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let bah = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
+                let addr = ((bah << 8) | bal).wrapping_add(self.cpu.x_reg as u16);
                 if (addr & 0xFF00) >> 8 != bah {
                     self.cpu.page_boundary_crossed = true;
+                    self.page_cross_dummy_read(bah, addr);
                 }
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             AbsoluteY => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
-                let bah = self.bus_read(self.cpu.pc + 2) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let bah = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
                 // ignore overflow while computing address
-                let addr = (((bah << 8) | bal) as u32 + self.cpu.y_reg as u32) as u16;
+                let addr = ((bah << 8) | bal).wrapping_add(self.cpu.y_reg as u16);
                 if (addr & 0xFF00) >> 8 != bah {
                     self.cpu.page_boundary_crossed = true;
+                    self.page_cross_dummy_read(bah, addr);
                 }
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             ZeroPageX => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 // Zero page indexing can't cross page boundaries
                 let addr = (bal + (self.cpu.x_reg as u16)) & 0x00FF;
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             ZeroPageY => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 // Zero page indexing can't cross page boundaries
                 let addr = (bal + (self.cpu.y_reg as u16)) & 0x00FF;
                 let data = self.bus_read(addr);
                 (addr, data)
             }
             IndirectY => {
-                let ial = self.bus_read(self.cpu.pc + 1) as u16;
+                let ial = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 let bal = self.bus_read(ial) as u16;
                 let bah = self.bus_read((ial + 1) & 0x00FF) as u16;
                 let base_addr = (bah << 8) | bal;
                 // ignore overflow while computing address
-                let addr = (base_addr as u32 + self.cpu.y_reg as u32) as u16;
-                let data = self.bus_read(addr);
-                // Hardware CPU behaviour would be doing a fetch of the wrong
-                // address and then another for the correct page. We don't need
-                // that as we are directly fetching the correct address
+                let addr = base_addr.wrapping_add(self.cpu.y_reg as u16);
                 self.cpu.page_boundary_crossed = (addr & 0xFF00) != (bah << 8);
+                if self.cpu.page_boundary_crossed {
+                    self.page_cross_dummy_read(bah, addr);
+                }
+                let data = self.bus_read(addr);
                 (addr, data)
             }
             Relative => {
-                let offset = self.bus_read(self.cpu.pc + 1) as i8 as u8;
-                (self.cpu.pc + 2, offset)
+                let offset = self.bus_read(self.cpu.pc.wrapping_add(1)) as i8 as u8;
+                (self.cpu.pc.wrapping_add(2), offset)
             }
             Indirect => {
-                let ind_l = self.bus_read(self.cpu.pc + 1) as u16;
-                let ind_h = self.bus_read(self.cpu.pc + 2) as u16;
+                let ind_l = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let ind_h = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
                 let addr_l = self.bus_read((ind_h << 8) | ind_l) as u16;
                 let addr_h = self.bus_read((ind_h << 8) | ((ind_l + 1) & 0x00FF)) as u16;
                 let address = (addr_h << 8) | addr_l;
@@ -359,50 +607,50 @@ impl Cpu {
 
     fn store(&mut self, data: u8, addr_mode: AddressingMode) {
         let addr = match addr_mode {
-            ZeroPage => self.bus_read(self.cpu.pc + 1) as u16,
+            ZeroPage => self.bus_read(self.cpu.pc.wrapping_add(1)) as u16,
             Absolute => {
-                let adl = self.bus_read(self.cpu.pc + 1) as u16;
-                let adh = self.bus_read(self.cpu.pc + 2) as u16;
+                let adl = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let adh = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
                 (adh << 8) | adl
             }
             IndirectX => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 let adl = self.bus_read((bal + (self.cpu.x_reg as u16)) & 0x00FF) as u16;
                 let adh = self.bus_read((bal + (self.cpu.x_reg as u16) + 1) & 0x00FF) as u16;
                 (adh << 8) | adl
             }
             AbsoluteX => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
-                let bah = self.bus_read(self.cpu.pc + 2) as u16;
-                let addr = ((bah << 8) | bal) + (self.cpu.x_reg as u16);
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let bah = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
+                let addr = ((bah << 8) | bal).wrapping_add(self.cpu.x_reg as u16);
                 if (addr & 0xFF00) >> 8 != bah {
                     self.cpu.page_boundary_crossed = true;
                 }
                 addr
             }
             AbsoluteY => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
-                let bah = self.bus_read(self.cpu.pc + 2) as u16;
-                let addr = ((bah << 8) | bal) + (self.cpu.y_reg as u16);
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
+                let bah = self.bus_read(self.cpu.pc.wrapping_add(2)) as u16;
+                let addr = ((bah << 8) | bal).wrapping_add(self.cpu.y_reg as u16);
                 if (addr & 0xFF00) >> 8 != bah {
                     self.cpu.page_boundary_crossed = true;
                 }
                 addr
             }
             ZeroPageX => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 (bal + (self.cpu.x_reg as u16)) & 0x00FF
             }
             ZeroPageY => {
-                let bal = self.bus_read(self.cpu.pc + 1) as u16;
+                let bal = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 (bal + (self.cpu.y_reg as u16)) & 0x00FF
             }
             IndirectY => {
-                let ial = self.bus_read(self.cpu.pc + 1) as u16;
+                let ial = self.bus_read(self.cpu.pc.wrapping_add(1)) as u16;
                 let bal = self.bus_read(ial) as u16;
                 let bah = self.bus_read((ial + 1) & 0x00FF) as u16;
                 let base_addr = (bah << 8) | bal;
-                let addr = base_addr + self.cpu.y_reg as u16;
+                let addr = base_addr.wrapping_add(self.cpu.y_reg as u16);
                 self.cpu.page_boundary_crossed = (addr & 0xFF00) != (bah << 8);
                 addr
             }
@@ -422,6 +670,16 @@ impl Cpu {
         Ok(instruction)
     }
 
+    /// Indexed reads crossing a page first read the address without the page
+    /// carry, as hardware adds the high byte a cycle later. It only matters
+    /// for read-sensitive registers (e.g., PPUDATA), so it's only emulated by
+    /// the accurate profile
+    fn page_cross_dummy_read(&self, base_page: u16, address: u16) {
+        if self.accuracy.is_accurate() {
+            self.bus_read((base_page << 8) | (address & 0x00FF));
+        }
+    }
+
     fn bus_read(&self, address: u16) -> u8 {
         self.bus.borrow().read(address)
     }
@@ -479,7 +737,7 @@ mod tests {
     use std::cell::RefCell;
     use std::rc::Rc;
 
-    use crate::interfaces::{AddressRange, LoadableMemory};
+    use crate::interfaces::{AddressRange, BusAccess, LoadableMemory};
     use crate::processor::bus::Bus;
     use crate::processor::memory::Ram;
 
@@ -533,4 +791,194 @@ mod tests {
 
         assert_eq!(cpu.cpu.acc, value * 10);
     }
+
+    /// Bus with the whole address space mapped to RAM, as two memories of
+    /// 32 KiB
+    fn bus_with_ram_halves() -> SharedBus {
+        let bus = Rc::new(RefCell::new(Bus::new("test-bus")));
+        for (name, start) in [("Test RAM", 0x0000), ("Test ROM", 0x8000)] {
+            bus.borrow_mut()
+                .attach(
+                    name,
+                    Rc::new(RefCell::new(Ram::new(0x8000))),
+                    AddressRange {
+                        start,
+                        end: start + 0x7FFF,
+                    },
+                )
+                .unwrap();
+        }
+        bus
+    }
+
+    #[test]
+    fn test_power_up_state() {
+        let bus = bus_with_ram_halves();
+        bus.borrow_mut().write(RESET_VECTOR, 0x34);
+        bus.borrow_mut().write(RESET_VECTOR + 1, 0x82);
+        bus.borrow_mut().write(0x8234, 0xEA); // NOP
+        let mut cpu = Cpu::new(bus);
+
+        cpu.power_up();
+        let state = cpu.state();
+        assert_eq!(
+            (state.acc, state.sp, state.sr, state.pc),
+            (0, 0xFD, 0x04, 0x8234)
+        );
+
+        // Reset keeps the registers
+        cpu.cpu.acc = 0x12;
+        cpu.cpu.sp = 0x01;
+        cpu.reset();
+        let state = cpu.state();
+        assert_eq!(
+            (state.acc, state.sp, state.sr, state.pc),
+            (0x12, 0xFE, 0x04, 0x8234)
+        );
+
+        // The first instruction starts after the 7 reset cycles
+        for _ in 0..6 {
+            cpu.clock().unwrap();
+            assert_eq!(cpu.instruction_pc(), 0);
+        }
+        cpu.clock().unwrap();
+        assert_eq!(cpu.instruction_pc(), 0x8234);
+    }
+
+    #[test]
+    fn test_pc_wrapping() {
+        let bus = bus_with_ram_halves();
+        // LDA $1234 with its operand wrapping to $0000
+        for (address, data) in [
+            (0xFFFE, 0xAD),
+            (0xFFFF, 0x34),
+            (0x0000, 0x12),
+            (0x1234, 0x42),
+        ] {
+            bus.borrow_mut().write(address, data);
+        }
+        bus.borrow_mut().write(0x0001, 0x20); // JSR $FFFE
+        bus.borrow_mut().write(0x0002, 0xFE);
+        bus.borrow_mut().write(0x0003, 0xFF);
+        let mut cpu = Cpu::new(bus);
+
+        cpu.cpu.pc = 0xFFFE;
+        cpu.execute().unwrap();
+        assert_eq!((cpu.cpu.acc, cpu.cpu.pc), (0x42, 0x0001));
+
+        cpu.execute().unwrap();
+        assert_eq!(cpu.cpu.pc, 0xFFFE);
+
+        // BRK at the top of memory pushes a wrapped return address
+        cpu.bus.borrow_mut().write(0xFFFF, 0x00);
+        cpu.cpu.pc = 0xFFFF;
+        let sp = cpu.cpu.sp;
+        cpu.execute().unwrap();
+        let stack = |offset: u8| {
+            cpu.bus
+                .borrow()
+                .read(0x0100 + sp.wrapping_sub(offset) as u16)
+        };
+        assert_eq!((stack(0), stack(1)), (0x00, 0x01));
+    }
+
+    #[test]
+    fn test_stack_wrapping() {
+        let bus = bus_with_ram_halves();
+        // JSR $9000, PHA, PLA, RTS
+        for (address, data) in [(0x8000, 0x20), (0x8001, 0x00), (0x8002, 0x90)] {
+            bus.borrow_mut().write(address, data);
+        }
+        for (address, data) in [(0x9000, 0x48), (0x9001, 0x68), (0x9002, 0x60)] {
+            bus.borrow_mut().write(address, data);
+        }
+        let mut cpu = Cpu::new(bus);
+        cpu.cpu.pc = 0x8000;
+
+        // The return address is split between both ends of the stack page
+        cpu.cpu.sp = 0x00;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.cpu.sp, 0xFE);
+        assert_eq!(cpu.bus.borrow().read(0x0100), 0x80);
+        assert_eq!(cpu.bus.borrow().read(0x01FF), 0x02);
+
+        cpu.cpu.acc = 0x42;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.cpu.sp, 0xFD);
+        cpu.cpu.acc = 0;
+        cpu.execute().unwrap();
+        assert_eq!((cpu.cpu.acc, cpu.cpu.sp), (0x42, 0xFE));
+
+        // Pulling wraps back to $0100
+        cpu.execute().unwrap();
+        assert_eq!((cpu.cpu.pc, cpu.cpu.sp), (0x8003, 0x00));
+    }
+
+    #[test]
+    fn test_page_cross_dummy_read() {
+        let bus = bus_with_ram_halves();
+        // LDA $80F0,X
+        for (address, data) in [(0x8000, 0xBD), (0x8001, 0xF0), (0x8002, 0x80)] {
+            bus.borrow_mut().write(address, data);
+        }
+        bus.borrow_mut().set_access_log(true);
+        let mut cpu = Cpu::new(bus);
+
+        let mut reads = |accuracy| {
+            cpu.set_accuracy(accuracy);
+            cpu.cpu.pc = 0x8000;
+            cpu.cpu.x_reg = 0x20;
+            cpu.execute().unwrap();
+            cpu.bus
+                .borrow()
+                .take_accesses()
+                .into_iter()
+                .filter(|(_, access)| *access == BusAccess::Read)
+                .map(|(address, _)| address)
+                .collect::<Vec<u16>>()
+        };
+
+        assert_eq!(
+            reads(AccuracyProfile::Standard),
+            [0x8000, 0x8001, 0x8002, 0x8110]
+        );
+        // The address without the carry is read first
+        assert_eq!(
+            reads(AccuracyProfile::Accurate),
+            [0x8000, 0x8001, 0x8002, 0x8010, 0x8110]
+        );
+    }
+
+    #[test]
+    fn test_read_modify_write_double_write() {
+        let bus = bus_with_ram_halves();
+        // INC $0234
+        for (address, data) in [(0x8000, 0xEE), (0x8001, 0x34), (0x8002, 0x02)] {
+            bus.borrow_mut().write(address, data);
+        }
+        bus.borrow_mut().set_access_log(true);
+        let mut cpu = Cpu::new(bus);
+
+        let mut writes = |accuracy| {
+            cpu.set_accuracy(accuracy);
+            cpu.cpu.pc = 0x8000;
+            cpu.execute().unwrap();
+            cpu.bus
+                .borrow()
+                .take_accesses()
+                .into_iter()
+                .filter(|(_, access)| *access != BusAccess::Read)
+                .collect::<Vec<(u16, BusAccess)>>()
+        };
+
+        assert_eq!(
+            writes(AccuracyProfile::Standard),
+            [(0x0234, BusAccess::Write(1))]
+        );
+        // The original value is written first
+        assert_eq!(
+            writes(AccuracyProfile::Accurate),
+            [(0x0234, BusAccess::Write(1)), (0x0234, BusAccess::Write(2))]
+        );
+    }
 }
@@ -3,11 +3,12 @@ use crate::types::SharedBus;
 
 pub type Opcode = u8;
 
+/// 6502 instruction, as decoded from its opcode
 #[derive(Clone)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub name: &'static str,
-    pub instruction: InstructionKind,
+    pub(crate) instruction: InstructionKind,
     pub addressing_mode: AddressingMode,
     pub bytes: u8,
     pub cycles: u8,
@@ -411,15 +411,17 @@ pub fn tya(cpu: &mut InternalCpu) {
 
 // Stack instructions
 
+/// The stack lives in page 1 and SP wraps around it, so pushing with SP at
+/// $00 continues at $01FF and pulling with SP at $FF continues at $0100
 pub fn push(cpu: &mut InternalCpu, data: u8, memory: &SharedBus) {
     let address = 0x0100 + (cpu.sp as u16);
     trace!("Push to SP 0x{:X} - 0x{:X}", cpu.sp, data);
     memory.borrow_mut().write(address, data);
-    cpu.sp -= 1;
+    cpu.sp = cpu.sp.wrapping_sub(1);
 }
 
 pub fn pull(cpu: &mut InternalCpu, memory: &SharedBus) -> u8 {
-    cpu.sp += 1;
+    cpu.sp = cpu.sp.wrapping_add(1);
     let address = 0x0100 + (cpu.sp as u16);
     let data = memory.borrow().read(address);
     trace!("Pull from SP 0x{:X} - 0x{:X}", cpu.sp, data);
@@ -1029,7 +1031,7 @@ pub fn jmp(cpu: &mut InternalCpu, address: u16) {
 /// N Z C I D V
 /// - - - - - -
 pub fn jsr(cpu: &mut InternalCpu, address: u16, memory: &SharedBus) {
-    let pc = cpu.pc + 2;
+    let pc = cpu.pc.wrapping_add(2);
     let pch = (pc >> 8) as u8;
     let pcl = (pc & 0x00FF) as u8;
     push(cpu, pch, memory);
@@ -1048,7 +1050,7 @@ pub fn jsr(cpu: &mut InternalCpu, address: u16, memory: &SharedBus) {
 pub fn rts(cpu: &mut InternalCpu, memory: &SharedBus) {
     let pcl = pull(cpu, memory) as u16;
     let pch = pull(cpu, memory) as u16;
-    cpu.pc = ((pch << 8) | pcl) + 1;
+    cpu.pc = ((pch << 8) | pcl).wrapping_add(1);
 }
 
 // Interrupts
@@ -1073,7 +1075,7 @@ pub fn rts(cpu: &mut InternalCpu, memory: &SharedBus) {
 /// N Z C I D V
 /// - - - 1 - -
 pub fn brk(cpu: &mut InternalCpu, memory: &SharedBus) {
-    let return_address = cpu.pc + 2;
+    let return_address = cpu.pc.wrapping_add(2);
     let pch = (return_address >> 8) as u8;
     let pcl = (return_address & 0x00FF) as u8;
     push(cpu, pch, memory);
@@ -1,4 +1,6 @@
+use crate::errors::StateError;
 use crate::processor::status_register::StatusRegister;
+use crate::snapshot::{StateReader, StateWriter};
 
 #[derive(Clone)]
 pub struct InternalCpu {
@@ -30,3 +32,28 @@ impl Default for InternalCpu {
         }
     }
 }
+
+impl InternalCpu {
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.u8(self.acc);
+        state.u8(self.x_reg);
+        state.u8(self.y_reg);
+        state.u8(self.sp);
+        state.u16(self.pc);
+        state.u8(self.sr.into());
+        state.bool(self.page_boundary_crossed);
+        state.option(self.branch_crossed_page_boundary, StateWriter::bool);
+    }
+
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.acc = state.u8()?;
+        self.x_reg = state.u8()?;
+        self.y_reg = state.u8()?;
+        self.sp = state.u8()?;
+        self.pc = state.u16()?;
+        self.sr = state.u8()?.into();
+        self.page_boundary_crossed = state.bool()?;
+        self.branch_crossed_page_boundary = state.option(StateReader::bool)?;
+        Ok(())
+    }
+}
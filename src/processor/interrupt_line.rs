@@ -0,0 +1,48 @@
+//! Interrupt lines
+//!
+//! Devices signal interrupts to the CPU through wires instead of the event
+//! bus, so raising an interrupt is a single store and the CPU sees it on its
+//! very next instruction boundary.
+//!
+//! - NMI is edge triggered. The device asserts the line on the rising edge of
+//!   its NMI output and the CPU releases it when it starts servicing the
+//!   interrupt. Releasing it before the CPU has seen it suppresses the NMI.
+//!
+//! - IRQ is level triggered. Devices keep the line asserted until the
+//!   program acknowledges the interrupt, and the CPU services it while the
+//!   interrupt disable flag is clear.
+//!
+//! See more information: https://www.nesdev.org/wiki/CPU_interrupts
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Interrupt wire shared by a device and the CPU. Clones are connected to the
+/// same wire
+#[derive(Clone, Debug, Default)]
+pub struct InterruptLine {
+    asserted: Rc<Cell<bool>>,
+}
+
+impl InterruptLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn assert(&self) {
+        self.asserted.set(true);
+    }
+
+    pub fn release(&self) {
+        self.asserted.set(false);
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted.get()
+    }
+
+    /// Release the line, returning whether it was asserted
+    pub fn take(&self) -> bool {
+        self.asserted.replace(false)
+    }
+}
@@ -1,4 +1,6 @@
+use crate::errors::StateError;
 use crate::interfaces::{LoadableMemory, Memory};
+use crate::snapshot::{StateReader, StateWriter};
 
 const RAM_SIZE: usize = 2 * 1024; // 2 kB RAM
 
@@ -13,6 +15,20 @@ impl Ram {
             memory: vec![0; size],
         }
     }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+}
+
+impl From<Vec<u8>> for Ram {
+    fn from(memory: Vec<u8>) -> Self {
+        Self { memory }
+    }
 }
 
 impl Memory for Ram {
@@ -27,14 +43,21 @@ impl Memory for Ram {
     fn size(&self) -> usize {
         self.memory.len()
     }
+
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        let start = address as usize;
+        buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+    }
+
+    fn write_block(&mut self, address: u16, data: &[u8]) {
+        let start = address as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+    }
 }
 
 impl LoadableMemory for Ram {
     fn load(&mut self, address: u16, contents: &[u8]) {
-        for (i, byte) in contents.iter().enumerate() {
-            let i = i as u16;
-            self.write(address + i, *byte);
-        }
+        self.write_block(address, contents);
     }
 }
 
@@ -53,6 +76,10 @@ impl Rom {
             write_count: 0,
         }
     }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
 }
 
 impl Memory for Rom {
@@ -71,6 +98,11 @@ impl Memory for Rom {
     fn size(&self) -> usize {
         self.memory.len()
     }
+
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        let start = address as usize;
+        buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+    }
 }
 
 impl LoadableMemory for Rom {
@@ -82,10 +114,8 @@ impl LoadableMemory for Rom {
             panic!("ROM memory can be written only once");
         }
 
-        for (i, byte) in contents.iter().enumerate() {
-            let i = i as u16;
-            self.memory[(address + i) as usize] = *byte;
-        }
+        let start = address as usize;
+        self.memory[start..start + contents.len()].copy_from_slice(contents);
         self.write_count += 1;
     }
 }
@@ -100,6 +130,15 @@ impl<T: Memory> MirroredMemory<T> {
     pub fn new(memory: T, mirrors: usize) -> Self {
         Self { memory, mirrors }
     }
+
+    /// Mirrored memory
+    pub fn memory(&self) -> &T {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut T {
+        &mut self.memory
+    }
 }
 
 impl<T: Memory> Memory for MirroredMemory<T> {
@@ -116,6 +155,40 @@ impl<T: Memory> Memory for MirroredMemory<T> {
     fn size(&self) -> usize {
         self.memory.size() * (self.mirrors + 1)
     }
+
+    fn read_block(&self, address: u16, buffer: &mut [u8]) {
+        let size = self.memory.size();
+        let mut address = address as usize % size;
+        // Split the block where it wraps around the mirrored memory
+        for chunk in split_at_wraps(buffer.len(), address, size) {
+            self.memory.read_block(address as u16, &mut buffer[chunk]);
+            address = 0;
+        }
+    }
+
+    fn write_block(&mut self, address: u16, data: &[u8]) {
+        let size = self.memory.size();
+        let mut address = address as usize % size;
+        for chunk in split_at_wraps(data.len(), address, size) {
+            self.memory.write_block(address as u16, &data[chunk]);
+            address = 0;
+        }
+    }
+}
+
+/// Ranges of a block of `length` bytes starting at `start` in a memory of
+/// `size` bytes that don't wrap around
+fn split_at_wraps(length: usize, start: usize, size: usize) -> Vec<core::ops::Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    let mut chunk_size = size - start;
+    while offset < length {
+        let end = length.min(offset + chunk_size);
+        chunks.push(offset..end);
+        offset = end;
+        chunk_size = size;
+    }
+    chunks
 }
 
 impl<T: LoadableMemory> LoadableMemory for MirroredMemory<T> {
@@ -124,20 +197,34 @@ impl<T: LoadableMemory> LoadableMemory for MirroredMemory<T> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Mirroring {
     /// Vertical arrangement (CIRAM A10 = PPU A11)
     Horizontal,
 
     /// Horizontal arrangement (CIRAM A10 = PPU A10)
     Vertical,
+
+    /// No mirroring at all. The cartidge provides 2 kB of extra VRAM so each
+    /// nametable has its own memory
+    FourScreen,
+
+    /// All nametables map to the first CIRAM cell. Only selectable by mappers
+    SingleScreenLower,
+
+    /// All nametables map to the second CIRAM cell. Only selectable by mappers
+    SingleScreenUpper,
 }
 
 /// CIRAM memory is divided in 4 logical cells where the half is a mirror of the
 /// other half.
+///
+/// Four-screen cartidges provide extra VRAM for the two remaining cells, in
+/// that case no mirroring is done.
 #[derive(Clone)]
 pub struct Ciram {
     memory: Ram,
+    cartidge_vram: Option<Ram>,
     mirroring: Mirroring,
     cell_size: usize,
 }
@@ -146,16 +233,71 @@ impl Ciram {
     pub fn new(cell_size: usize) -> Self {
         Self {
             memory: Ram::new(cell_size * 2),
+            cartidge_vram: None,
             mirroring: Mirroring::Horizontal,
             cell_size,
         }
     }
 
+    /// Set nametable mirroring. Four-screen mirroring allocates the extra
+    /// cartidge VRAM, while any other mirroring releases it
     pub fn set_mirroring(&mut self, mirroring: Mirroring) {
         self.mirroring = mirroring;
+        self.cartidge_vram = match mirroring {
+            Mirroring::FourScreen => Some(Ram::new(self.cell_size * 2)),
+            _ => None,
+        };
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    pub(crate) fn save(&self, state: &mut StateWriter) {
+        state.bytes(self.memory.as_slice());
+        state.u8(match self.mirroring {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        });
+        state.option(self.cartidge_vram.as_ref(), |state, vram| {
+            state.bytes(vram.as_slice())
+        });
     }
 
-    fn compute_offset(&self, address: u16) -> u16 {
+    pub(crate) fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(self.memory.as_mut_slice(), "CIRAM")?;
+        self.mirroring = match state.u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            value => return Err(StateError::Malformed(format!("invalid mirroring {value}"))),
+        };
+        self.cartidge_vram = state.option(|state| Ok(Ram::from(state.bytes()?.to_vec())))?;
+        Ok(())
+    }
+
+    /// Read `offset` of a CIRAM `cell` (0 or 1), ignoring mirroring. Used by
+    /// mappers selecting nametable pages by themselves
+    pub fn read_cell(&self, cell: usize, offset: u16) -> u8 {
+        let base = (cell % 2 * self.cell_size) as u16;
+        self.memory.read(base + offset % self.cell_size as u16)
+    }
+
+    /// Write `offset` of a CIRAM `cell` (0 or 1), ignoring mirroring
+    pub fn write_cell(&mut self, cell: usize, offset: u16, data: u8) {
+        let base = (cell % 2 * self.cell_size) as u16;
+        self.memory
+            .write(base + offset % self.cell_size as u16, data);
+    }
+
+    /// Map an address to the physical cell (0 and 1 live in the CIRAM, 2 and 3
+    /// in the cartidge VRAM) and the offset inside it
+    fn locate(&self, address: u16) -> (usize, u16) {
         // Nametables
         // (0,0)     (256,0)     (511,0)
         //        +-----------+-----------+
@@ -173,26 +315,18 @@ impl Ciram {
         //        +-----------+-----------+
         //      (0,479)   (256,479)   (511,479)
 
-        let cell_size = self.cell_size as u16;
-        let cell = match address as usize {
-            a if a < self.cell_size => 0,
-            a if self.cell_size <= a && a < 2 * self.cell_size => 1,
-            a if self.cell_size * 2 <= a && a < 3 * self.cell_size => 2,
-            a if self.cell_size * 3 <= a && a < 4 * self.cell_size => 3,
-            _ => panic!("Impossible CIRAM address {}", address),
-        };
+        let cell = address as usize / self.cell_size;
+        let offset = address % self.cell_size as u16;
 
-        match (cell, self.mirroring) {
+        let physical_cell = match (cell, self.mirroring) {
             // Horizontal
             // +---+---+
             // | A | A |
             // +---+---+
             // | B | B |
             // +---+---+
-            (0, Mirroring::Horizontal) => 0,
-            (1, Mirroring::Horizontal) => cell_size,
-            (2, Mirroring::Horizontal) => cell_size,
-            (3, Mirroring::Horizontal) => 2 * cell_size,
+            (0 | 1, Mirroring::Horizontal) => 0,
+            (2 | 3, Mirroring::Horizontal) => 1,
 
             // Vertical
             // +---+---+
@@ -200,28 +334,58 @@ impl Ciram {
             // +---+---+
             // | A | B |
             // +---+---+
-            (0, Mirroring::Vertical) => 0,
-            (1, Mirroring::Vertical) => 0,
-            (2, Mirroring::Vertical) => 2 * cell_size,
-            (3, Mirroring::Vertical) => 2 * cell_size,
-
-            _ => panic!(
-                "Impossible CIRAM cell-mirroring combination: {} {:?}",
-                cell, self.mirroring
-            ),
-        }
+            (0 | 2, Mirroring::Vertical) => 0,
+            (1 | 3, Mirroring::Vertical) => 1,
+
+            // Four-screen
+            // +---+---+
+            // | A | B |
+            // +---+---+
+            // | C | D |
+            // +---+---+
+            (0..=3, Mirroring::FourScreen) => cell,
+
+            // Single-screen
+            // +---+---+
+            // | A | A |
+            // +---+---+
+            // | A | A |
+            // +---+---+
+            (0..=3, Mirroring::SingleScreenLower) => 0,
+            (0..=3, Mirroring::SingleScreenUpper) => 1,
+
+            _ => panic!("Impossible CIRAM address {}", address),
+        };
+
+        (physical_cell, offset)
     }
 }
 
 impl Memory for Ciram {
     fn read(&self, address: u16) -> u8 {
-        let offset = self.compute_offset(address);
-        self.memory.read(address - offset)
+        let (cell, offset) = self.locate(address);
+        let base = (cell as u16 % 2) * self.cell_size as u16;
+        let memory = match cell {
+            0 | 1 => &self.memory,
+            _ => self
+                .cartidge_vram
+                .as_ref()
+                .expect("Four-screen mirroring should have cartidge VRAM"),
+        };
+        memory.read(base + offset)
     }
 
     fn write(&mut self, address: u16, data: u8) {
-        let offset = self.compute_offset(address);
-        self.memory.write(address - offset, data);
+        let (cell, offset) = self.locate(address);
+        let base = (cell as u16 % 2) * self.cell_size as u16;
+        let memory = match cell {
+            0 | 1 => &mut self.memory,
+            _ => self
+                .cartidge_vram
+                .as_mut()
+                .expect("Four-screen mirroring should have cartidge VRAM"),
+        };
+        memory.write(base + offset, data);
     }
 
     fn size(&self) -> usize {
@@ -250,3 +414,40 @@ pub struct Palettes {
 pub struct ProgramRom {
     // 0x4020 - 0xFFFF (main bus)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill_cells(ciram: &mut Ciram) {
+        for cell in 0..4 {
+            ciram.write(cell * 0x0400 + 0x10, cell as u8 + 1);
+        }
+    }
+
+    fn read_cells(ciram: &Ciram) -> [u8; 4] {
+        [0, 1, 2, 3].map(|cell| ciram.read(cell * 0x0400 + 0x10))
+    }
+
+    #[test]
+    fn test_ciram_mirroring() {
+        let mut ciram = Ciram::new(0x0400);
+
+        ciram.set_mirroring(Mirroring::Horizontal);
+        fill_cells(&mut ciram);
+        assert_eq!(read_cells(&ciram), [2, 2, 4, 4]);
+
+        ciram.set_mirroring(Mirroring::Vertical);
+        fill_cells(&mut ciram);
+        assert_eq!(read_cells(&ciram), [3, 4, 3, 4]);
+    }
+
+    #[test]
+    fn test_ciram_four_screen() {
+        let mut ciram = Ciram::new(0x0400);
+        ciram.set_mirroring(Mirroring::FourScreen);
+
+        fill_cells(&mut ciram);
+        assert_eq!(read_cells(&ciram), [1, 2, 3, 4]);
+    }
+}
@@ -1,9 +1,10 @@
 pub mod bus;
 pub mod cpu;
+pub mod instruction;
+pub mod interrupt_line;
 pub mod memory;
 
-mod instruction;
-mod instruction_set;
+pub(crate) mod instruction_set;
 mod internal_cpu;
 mod status_register;
 
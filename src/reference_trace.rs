@@ -0,0 +1,282 @@
+//! Reference trace comparison
+//!
+//! Find CPU bugs by running a program in lockstep with a trace recorded by a
+//! reference emulator, instruction by instruction, until they diverge. Traces
+//! use the nestest log format most emulators can write, one line per
+//! instruction with the CPU state before running it:
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//! ```
+//!
+//! Registers, flags and the instruction bytes in memory are compared. The
+//! disassembly, PPU position and cycle count are ignored. Unused and B flags
+//! don't exist in the status register, so they're ignored too.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::TraceError;
+use crate::processor::cpu::CpuState;
+use crate::Nes;
+
+// Status register bits which are only meaningful when pushed to the stack
+const IGNORED_FLAGS: u8 = 0b0011_0000;
+
+/// CPU state before an instruction of a reference trace
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceLine {
+    /// Line in the trace file, starting at 1
+    pub line_number: usize,
+    pub state: CpuState,
+    /// Instruction bytes at PC
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:0>2X}"))
+            .collect();
+        write!(f, "{:0>4X}  {:<8}  ", self.state.pc, bytes.join(" "))?;
+        write_registers(f, &self.state)
+    }
+}
+
+fn write_registers(f: &mut fmt::Formatter<'_>, state: &CpuState) -> fmt::Result {
+    write!(
+        f,
+        "A:{:0>2X} X:{:0>2X} Y:{:0>2X} P:{:0>2X} SP:{:0>2X}",
+        state.acc, state.x_reg, state.y_reg, state.sr, state.sp
+    )
+}
+
+/// First instruction where the NES and the reference trace disagree
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    pub expected: TraceLine,
+    pub actual: CpuState,
+    /// Bytes at the actual PC. Addresses that can't be read without side
+    /// effects are missing
+    pub actual_bytes: Vec<Option<u8>>,
+    /// Last instruction both agreed on, the one that probably went wrong
+    pub previous: Option<TraceLine>,
+    /// Names of the differing fields: PC, A, X, Y, P, SP or opcode
+    pub fields: Vec<&'static str>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Divergence at trace line {} ({})",
+            self.expected.line_number,
+            self.fields.join(", ")
+        )?;
+        if let Some(previous) = self.previous.as_ref() {
+            writeln!(f, "  after:    {previous}")?;
+        }
+        writeln!(f, "  expected: {}", self.expected)?;
+
+        let bytes: Vec<String> = self
+            .actual_bytes
+            .iter()
+            .map(|byte| byte.map_or("--".to_string(), |byte| format!("{byte:0>2X}")))
+            .collect();
+        write!(
+            f,
+            "  actual:   {:0>4X}  {:<8}  ",
+            self.actual.pc,
+            bytes.join(" ")
+        )?;
+        write_registers(f, &self.actual)
+    }
+}
+
+/// Instructions recorded by a reference emulator
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReferenceTrace {
+    lines: Vec<TraceLine>,
+}
+
+impl ReferenceTrace {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TraceError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a nestest-like log. Empty lines are ignored
+    pub fn parse(contents: &str) -> Result<Self, TraceError> {
+        let mut lines = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            lines.push(parse_line(index + 1, line)?);
+        }
+        Ok(Self { lines })
+    }
+
+    pub fn lines(&self) -> &[TraceLine] {
+        &self.lines
+    }
+
+    /// Run `nes` along the trace from its first line, whose registers are
+    /// loaded into the CPU first (e.g., nestest automation mode starts at
+    /// $C000). Returns the number of instructions that matched, or the first
+    /// divergence
+    pub fn compare(&self, nes: &mut Nes) -> Result<usize, TraceError> {
+        let Some(first) = self.lines.first() else {
+            return Ok(0);
+        };
+        nes.cpu.set_state(first.state);
+
+        let mut previous = None;
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                nes.step_instruction()?;
+            }
+
+            let actual = nes.cpu_state();
+            let actual_bytes: Vec<Option<u8>> = (0..line.bytes.len() as u16)
+                .map(|offset| nes.peek(actual.pc.wrapping_add(offset)))
+                .collect();
+            let fields = differences(line, &actual, &actual_bytes);
+            if !fields.is_empty() {
+                return Err(TraceError::Divergence(Box::new(Divergence {
+                    expected: line.clone(),
+                    actual,
+                    actual_bytes,
+                    previous,
+                    fields,
+                })));
+            }
+            previous = Some(line.clone());
+        }
+        Ok(self.lines.len())
+    }
+}
+
+fn differences(expected: &TraceLine, actual: &CpuState, bytes: &[Option<u8>]) -> Vec<&'static str> {
+    let state = &expected.state;
+    let mut fields = Vec::new();
+    for (name, differ) in [
+        ("PC", state.pc != actual.pc),
+        ("A", state.acc != actual.acc),
+        ("X", state.x_reg != actual.x_reg),
+        ("Y", state.y_reg != actual.y_reg),
+        ("P", (state.sr ^ actual.sr) & !IGNORED_FLAGS != 0),
+        ("SP", state.sp != actual.sp),
+    ] {
+        if differ {
+            fields.push(name);
+        }
+    }
+
+    let opcode_differs = expected
+        .bytes
+        .iter()
+        .zip(bytes)
+        .any(|(expected, actual)| actual.is_some_and(|actual| actual != *expected));
+    if opcode_differs {
+        fields.push("opcode");
+    }
+    fields
+}
+
+fn parse_line(line_number: usize, line: &str) -> Result<TraceLine, TraceError> {
+    let invalid = |details: &str| TraceError::InvalidLine {
+        line: line_number,
+        details: details.to_string(),
+    };
+    let hex_u8 = |text: &str| u8::from_str_radix(text, 16).ok();
+
+    let mut words = line.split_whitespace();
+    let pc = words
+        .next()
+        .and_then(|word| u16::from_str_radix(word, 16).ok())
+        .ok_or_else(|| invalid("missing PC"))?;
+    let bytes: Vec<u8> = words
+        .take(3)
+        .map_while(|word| (word.len() == 2).then(|| hex_u8(word)).flatten())
+        .collect();
+    if bytes.is_empty() {
+        return Err(invalid("missing instruction bytes"));
+    }
+
+    let register = |name: &str| {
+        line.split_whitespace()
+            .find_map(|word| word.strip_prefix(name))
+            .and_then(hex_u8)
+            .ok_or_else(|| invalid(&format!("missing register {}", name.trim_end_matches(':'))))
+    };
+    Ok(TraceLine {
+        line_number,
+        state: CpuState {
+            acc: register("A:")?,
+            x_reg: register("X:")?,
+            y_reg: register("Y:")?,
+            sp: register("SP:")?,
+            pc,
+            sr: register("P:")?,
+        },
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::UiKind;
+    use crate::testing::ines_image;
+    use crate::Cartidge;
+
+    fn nes() -> Nes {
+        // LDX #$05, DEX, BNE -3, JMP $8002
+        let mut prg = vec![0xA2, 0x05, 0xCA, 0xD0, 0xFD, 0x4C, 0x02, 0x80];
+        prg.resize(0x4000, 0);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        Nes::builder()
+            .with_ui(UiKind::None)
+            .with_cartidge(Cartidge::from_bytes(
+                "trace.nes",
+                &ines_image(0, false, &prg, &[0; 8 * 1024]),
+            ))
+            .build()
+    }
+
+    const TRACE: &str = "\
+8000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+8002  CA        DEX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+
+8003  D0 FD     BNE $8002                       A:00 X:04 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+8002  CA        DEX                             A:00 X:04 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14
+";
+
+    #[test]
+    fn test_trace_matches() {
+        let trace = ReferenceTrace::parse(TRACE).unwrap();
+        assert_eq!(trace.lines().len(), 4);
+        assert_eq!(trace.lines()[2].bytes, [0xD0, 0xFD]);
+        assert_eq!(trace.compare(&mut nes()).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_trace_divergence() {
+        let trace = ReferenceTrace::parse(&TRACE.replace(
+            "X:04 Y:00 P:24 SP:FD PPU:  0, 42",
+            "X:03 Y:00 P:A4 SP:FD PPU:  0, 42",
+        ))
+        .unwrap();
+        let Err(TraceError::Divergence(divergence)) = trace.compare(&mut nes()) else {
+            panic!("Trace should diverge");
+        };
+        assert_eq!(divergence.expected.line_number, 5);
+        assert_eq!(divergence.fields, ["X", "P"]);
+        assert_eq!(divergence.previous.unwrap().state.pc, 0x8003);
+
+        assert!(ReferenceTrace::parse("8000  LDX #$05  A:00").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! ROM file hot-reload
+//!
+//! Homebrew development loop: while a [`RomWatcher`] is active, rebuilding
+//! the ROM (e.g., with ca65/ld65) reloads it in the running emulator and
+//! resets the console. Start it with [`Nes::watch_rom`](crate::Nes::watch_rom).
+//!
+//! The file is polled for modification instead of using OS notifications.
+//! A change is only reported once the file has stopped changing between two
+//! polls and holds a complete iNES image, so assemblers still writing it
+//! don't crash the emulator.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::debug;
+
+use crate::cartidge::Cartidge;
+
+pub struct RomWatcher {
+    path: PathBuf,
+    // Modification time of the loaded version
+    loaded: Option<SystemTime>,
+    // Modification time of a new version seen in the last poll
+    pending: Option<SystemTime>,
+}
+
+impl RomWatcher {
+    /// Frames between polls, around half a second
+    pub const POLL_INTERVAL_FRAMES: u64 = 30;
+
+    /// Watch the ROM at `path`. Its current version is considered loaded
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let loaded = modified(&path);
+        Self {
+            path,
+            loaded,
+            pending: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check the file for a new, complete version. Returns true once per
+    /// version, when it should be reloaded
+    pub fn poll(&mut self) -> bool {
+        let current = modified(&self.path);
+        if current.is_none() || current == self.loaded {
+            self.pending = None;
+            return false;
+        }
+        if current != self.pending {
+            // Still being written, wait until the next poll
+            self.pending = current;
+            return false;
+        }
+
+        let complete = fs::read(&self.path)
+            .map(|contents| Cartidge::is_complete_image(&contents))
+            .unwrap_or(false);
+        if !complete {
+            debug!(
+                "ROM {:?} changed but it's not a valid iNES image",
+                self.path
+            );
+            return false;
+        }
+
+        self.loaded = current;
+        self.pending = None;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rom_watcher_waits_for_complete_roms() {
+        let path = std::env::temp_dir().join(format!("rom-watcher-{}.nes", std::process::id()));
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0, 0, 0];
+        image.resize(16 + 16 * 1024, 0xEA);
+        fs::write(&path, &image).unwrap();
+
+        let touch = |contents: &[u8], seconds| {
+            fs::write(&path, contents).unwrap();
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+
+        let mut watcher = RomWatcher::new(&path);
+        assert!(!watcher.poll());
+
+        // Truncated image, even when stable
+        touch(&image[..100], 1_000);
+        assert!(!watcher.poll());
+        assert!(!watcher.poll());
+
+        touch(&image, 2_000);
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll());
+    }
+}
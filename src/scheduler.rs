@@ -0,0 +1,161 @@
+//! Catch-up scheduling
+//!
+//! With [`ClockGranularity::CatchUp`](crate::settings::ClockGranularity::CatchUp)
+//! the PPU doesn't run in lockstep with the CPU. Components register the
+//! system clock of their next event and the main loop runs the soonest one:
+//! the CPU runs ahead while nothing can observe the PPU, which catches up in
+//! batches. The PPU catches up:
+//!
+//! - before the CPU cycle following a PPU dot that interacts with the system
+//!   by itself, see [`Ppu::dots_until_event`](crate::graphics::ppu::Ppu::dots_until_event)
+//! - before the CPU accesses anything the PPU reads or is read along with:
+//!   PPU registers, OAM DMA, controller ports (light guns look at the screen)
+//!   and cartidge writes (mappers switch CHR banks and mirroring)
+//! - before DMA cycles and before events are processed
+//!
+//! Catching up runs the same dots in the same order, so the emulation is the
+//! same as with [`ClockGranularity::CpuCycle`](crate::settings::ClockGranularity::CpuCycle).
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::hardware::PPU_CLOCK_DIVIDER;
+use crate::interfaces::BusAccess;
+use crate::types::SharedPpu;
+
+/// Components with events on the system clock
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Component {
+    Cpu,
+    Ppu,
+}
+
+pub(crate) struct Scheduler {
+    // System clock of the next event of each component, in `Component` order
+    next_events: [u64; 2],
+    catch_up: PpuCatchUp,
+}
+
+impl Scheduler {
+    pub fn new(ppu: SharedPpu) -> Self {
+        Self {
+            next_events: [0; 2],
+            catch_up: PpuCatchUp {
+                ppu,
+                clock: Rc::new(Cell::new(0)),
+                target: Rc::new(Cell::new(0)),
+            },
+        }
+    }
+
+    /// Register the system clock of the next event of `component`
+    pub fn schedule(&mut self, component: Component, clock: u64) {
+        self.next_events[component as usize] = clock;
+    }
+
+    /// Component with the soonest event. The CPU goes first on ties, as PPU
+    /// dots only run before a CPU cycle when they're strictly earlier
+    pub fn next(&self) -> Component {
+        if self.next_events[Component::Ppu as usize] < self.next_events[Component::Cpu as usize] {
+            Component::Ppu
+        } else {
+            Component::Cpu
+        }
+    }
+
+    pub fn catch_up(&self) -> &PpuCatchUp {
+        &self.catch_up
+    }
+}
+
+/// PPU running behind the CPU. Clones share the PPU and its clocks, so a
+/// clone can catch it up from the main bus
+#[derive(Clone)]
+pub(crate) struct PpuCatchUp {
+    ppu: SharedPpu,
+    // System clock of the next PPU dot
+    clock: Rc<Cell<u64>>,
+    // System clock of the CPU cycle running, dots before it are due
+    target: Rc<Cell<u64>>,
+}
+
+impl PpuCatchUp {
+    pub fn clock(&self) -> u64 {
+        self.clock.get()
+    }
+
+    /// Start catching up from the PPU dot at `clock` up to the CPU cycle at
+    /// `target`
+    pub fn reset(&self, clock: u64, target: u64) {
+        self.clock.set(clock);
+        self.target.set(target);
+    }
+
+    /// Run the PPU dots due before the CPU cycle
+    pub fn run(&self) {
+        let target = self.target.get();
+        let mut clock = self.clock.get();
+        if clock >= target {
+            return;
+        }
+
+        let mut ppu = self.ppu.borrow_mut();
+        while clock < target {
+            ppu.clock();
+            clock += PPU_CLOCK_DIVIDER;
+        }
+        self.clock.set(clock);
+    }
+}
+
+/// Whether the CPU accessing `address` can observe or change what the PPU
+/// does, so the PPU must catch up first
+pub(crate) fn needs_catch_up(address: u16, access: BusAccess) -> bool {
+    match address {
+        // PPU registers and controller ports
+        0x2000..=0x3FFF | 0x4016..=0x4017 => true,
+        // OAM DMA and mapper registers
+        0x4014 | 0x4020..=0xFFFF => matches!(access, BusAccess::Write(_)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::SharedEventBus;
+    use crate::graphics::ppu::Ppu;
+    use crate::interfaces::BusFaultPolicy;
+    use crate::processor::bus::Bus;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_scheduler() {
+        // Dots can run without CHR or nametables
+        let mut graphics_bus = Bus::new("PPU");
+        graphics_bus.set_fault_policy(BusFaultPolicy::Tolerant);
+        let graphics_bus = Rc::new(RefCell::new(graphics_bus));
+        let ppu = Rc::new(RefCell::new(Ppu::new(graphics_bus, SharedEventBus::new())));
+        ppu.borrow_mut().seek(10, 0);
+        let mut scheduler = Scheduler::new(ppu.clone());
+        scheduler.schedule(Component::Cpu, 24);
+        scheduler.schedule(Component::Ppu, 24);
+        assert_eq!(scheduler.next(), Component::Cpu);
+        scheduler.schedule(Component::Ppu, 20);
+        assert_eq!(scheduler.next(), Component::Ppu);
+
+        // Dots before the CPU cycle run, i.e., 0, 4, ..., 20
+        let catch_up = scheduler.catch_up().clone();
+        catch_up.reset(0, 24);
+        catch_up.run();
+        assert_eq!(catch_up.clock(), 24);
+        assert_eq!(ppu.borrow().state().cycle, 6);
+        catch_up.run();
+        assert_eq!(catch_up.clock(), 24);
+
+        assert!(needs_catch_up(0x2002, BusAccess::Read));
+        assert!(needs_catch_up(0x8000, BusAccess::Write(0)));
+        assert!(!needs_catch_up(0x8000, BusAccess::Read));
+        assert!(!needs_catch_up(0x0200, BusAccess::Write(0)));
+    }
+}
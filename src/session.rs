@@ -0,0 +1,81 @@
+//! Emulation sessions
+//!
+//! Console-like "sleep": with
+//! [`NesSettings::session_directory`](crate::settings::NesSettings::session_directory)
+//! set, the NES saves its state when it's switched off and restores it the
+//! next time the same ROM is loaded. Sessions are matched by ROM checksum, so
+//! renaming or moving the ROM file keeps them.
+//!
+//! Sessions are plain saved states (see [`Snapshot::to_bytes`]) named after
+//! the ROM checksum, e.g., `1A2B3C4D.state`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::snapshot::Snapshot;
+
+pub struct SessionStore {
+    directory: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Default sessions location: `$XDG_DATA_HOME/jotare-nes-emulator/sessions`
+    /// or `~/.local/share/jotare-nes-emulator/sessions`
+    pub fn default_directory() -> Option<PathBuf> {
+        let data_dir = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })?;
+        Some(data_dir.join("jotare-nes-emulator").join("sessions"))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// File of the session of the ROM with `checksum`
+    pub fn path(&self, checksum: u32) -> PathBuf {
+        self.directory.join(format!("{checksum:08X}.state"))
+    }
+
+    /// Save `snapshot` as the session of its cartidge. Snapshots without
+    /// cartidge are ignored
+    pub fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let Some(checksum) = snapshot.cartidge_checksum() else {
+            return Ok(());
+        };
+        fs::create_dir_all(&self.directory)?;
+
+        // Write to a temporary file first, so a crash while saving doesn't
+        // leave a truncated session behind
+        let path = self.path(checksum);
+        let temporary = path.with_extension("state.tmp");
+        fs::write(&temporary, snapshot.to_bytes())?;
+        fs::rename(temporary, path)
+    }
+
+    /// Saved state of the ROM with `checksum`, if there's a session
+    pub fn load(&self, checksum: u32) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(checksum)) {
+            Ok(state) => Ok(Some(state)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Forget the session of the ROM with `checksum`
+    pub fn remove(&self, checksum: u32) -> io::Result<()> {
+        match fs::remove_file(self.path(checksum)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
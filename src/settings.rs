@@ -1,4 +1,17 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::hardware::CPU_CLOCK_DIVIDER;
+use crate::interfaces::BusFaultPolicy;
+
 /// NES configuration options
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct NesSettings {
     /// UI setting: scale factor applied to screen pixels to increase image
     /// size. A scale factor of 2 will make original pixels render as 2x2 pixel
@@ -6,13 +19,279 @@ pub struct NesSettings {
     pub pixel_scale_factor: usize,
 
     pub ui_kind: UiKind,
+
+    /// CPU/PPU phase alignment at power-on, in PPU cycles (0 to 3). Real
+    /// consoles power-on with a random alignment, this allows timing sensitive
+    /// programs to be tested against all of them
+    pub cpu_ppu_alignment: u8,
+
+    /// File where UI state (like window geometry) is persisted across runs.
+    /// `None` disables persistence
+    pub settings_file: Option<PathBuf>,
+
+    /// What to do when a game accesses an address without device. Tolerant
+    /// mode allows playing buggy ROMs instead of stopping the emulator
+    pub bus_fault_policy: BusFaultPolicy,
+
+    /// How much emulated time runs in a single step of the main loop. It's
+    /// read when the [`Nes`](crate::Nes) is built and can't change afterwards
+    pub clock_granularity: ClockGranularity,
+
+    /// CPU speed relative to the PPU. Only [`CpuSpeed::Authentic`] matches
+    /// the real hardware
+    pub cpu_speed: CpuSpeed,
+
+    /// Hardware quirks emulated besides the ones every game relies on
+    pub accuracy: AccuracyProfile,
+
+    /// Seed of the emulated randomness (see [`Rng`](crate::rng::Rng)). The
+    /// same seed always produces the same emulation
+    pub rng_seed: u64,
+
+    /// Adjustments applied to the NES palette colors
+    pub colors: ColorSettings,
+
+    /// Emulate the DPCM conflict glitch: a DMC DMA halting the CPU while it
+    /// reads a controller port clocks the port twice, so a bit is lost. Games
+    /// reading controllers while DPCM samples play work around it
+    pub dpcm_controller_conflict: bool,
+
+    /// Filter applied to frames before the UI presents them
+    pub video_filter: VideoFilterKind,
+
+    /// Detect games stuck in a loop and emit a diagnostic
+    /// [`Event::WatchdogTriggered`](crate::events::Event::WatchdogTriggered).
+    /// `None` disables the watchdog
+    pub watchdog: Option<WatchdogSettings>,
+
+    /// Draw the controllers input in this corner of the frames. `None`
+    /// disables the overlay
+    pub input_overlay: Option<ScreenCorner>,
+
+    /// Draw a graph of the last frame times in this corner of the frames.
+    /// `None` disables it. See
+    /// [`frame_time_graph`](crate::graphics::frame_time_graph)
+    pub frame_time_graph: Option<ScreenCorner>,
+
+    /// Secondary windows showing graphics debug views, refreshed every frame.
+    /// Ignored by UIs without windows
+    pub debug_windows: Vec<DebugWindow>,
+
+    /// Directory where the state is saved when the NES is switched off, to
+    /// resume the game the next time the same ROM is loaded. `None` disables
+    /// sessions. See [`SessionStore`](crate::session::SessionStore)
+    pub session_directory: Option<PathBuf>,
+
+    /// Beam racing: send the UI the rows rendered so far every this many
+    /// scanlines, instead of waiting for the whole frame, to reduce input
+    /// latency. Video filters and the input overlay only apply to complete
+    /// frames. `None` presents complete frames only
+    pub beam_racing: Option<usize>,
+
+    /// Frames between pressing a button and the game seeing it, applied to
+    /// both controllers. Local play can mimic netplay timing this way. It's
+    /// kept in saved states and movies, as replaying them depends on it
+    pub input_delay: u8,
 }
 
 pub const DEFAULT_PIXEL_SCALE_FACTOR: usize = 4;
 
+pub const MAX_CPU_PPU_ALIGNMENT: u8 = 3;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UiKind {
     None,
     Gtk,
+    /// Developer frontend with debugger panels, see [`EguiUi`](crate::ui::EguiUi)
+    #[cfg(feature = "egui")]
+    Egui,
+}
+
+/// Granularity of the NES main loop steps. Coarser steps have less overhead
+/// and produce the same results, as the CPU only handles interrupts between its
+/// cycles
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClockGranularity {
+    /// One PPU dot (4 system clocks) per step. Events are processed after
+    /// every dot
+    Dot,
+
+    /// One CPU cycle (12 system clocks) per step: PPU dots run in a tight loop
+    /// until the next CPU cycle. Events are only processed before CPU cycles,
+    /// and only if some have been emitted
+    #[default]
+    CpuCycle,
+
+    /// One CPU cycle per step, like [`ClockGranularity::CpuCycle`], but the
+    /// PPU runs behind the CPU and catches up in batches when something could
+    /// observe it, e.g., a PPU register access or an NMI. See
+    /// [`scheduler`](crate::scheduler). Mappers counting PPU A12 rises (e.g.,
+    /// MMC3) get them late
+    CatchUp,
+}
+
+/// Hardware quirks emulated. Games work the same with both profiles, but
+/// homebrew developers want their programs to fail as they would on a real
+/// console
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccuracyProfile {
+    /// Idealized hardware, e.g., the PPU is ready right away
+    #[default]
+    Standard,
+
+    /// Documented hardware behavior, e.g., PPU power-up register values and
+    /// warm-up period or CPU dummy reads and writes
+    Accurate,
+}
+
+impl AccuracyProfile {
+    pub fn is_accurate(&self) -> bool {
+        *self == AccuracyProfile::Accurate
+    }
+}
+
+/// Speed of the CPU. The PPU always runs at its real speed, so video timing is
+/// kept while the CPU gets more (or less) time per frame. Non-authentic speeds
+/// are useful to find race conditions in homebrew or to fast-forward turn-based
+/// games, but timing sensitive games will break
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuSpeed {
+    /// A CPU cycle every 3 PPU dots, like the real hardware
+    #[default]
+    Authentic,
+
+    /// Non-authentic: the CPU runs this many times faster. The factor must
+    /// divide 12 (1, 2, 3, 4, 6 or 12)
+    Overclocked(u8),
+
+    /// Non-authentic: a CPU cycle every this many system clocks, overriding
+    /// the CPU:PPU clock ratio. A PPU dot takes 4 system clocks and a real CPU
+    /// cycle 12
+    SystemClockDivider(u64),
+}
+
+impl CpuSpeed {
+    /// System clocks per CPU cycle, or `None` if the speed is invalid
+    pub fn clock_divider(&self) -> Option<u64> {
+        match *self {
+            CpuSpeed::Authentic => Some(CPU_CLOCK_DIVIDER),
+            CpuSpeed::Overclocked(factor)
+                if factor > 0 && CPU_CLOCK_DIVIDER.is_multiple_of(factor as u64) =>
+            {
+                Some(CPU_CLOCK_DIVIDER / factor as u64)
+            }
+            CpuSpeed::SystemClockDivider(divider) if divider > 0 => Some(divider),
+            _ => None,
+        }
+    }
+
+    pub fn is_authentic(&self) -> bool {
+        self.clock_divider() == Some(CPU_CLOCK_DIVIDER)
+    }
+}
+
+/// Built-in video filters. See [`filters`](crate::graphics::filters)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VideoFilterKind {
+    /// Frames are presented as produced, the UI scales them
+    #[default]
+    None,
+
+    /// Nearest neighbor upscaling by an integer factor
+    Nearest(u8),
+
+    Scale2x,
+    Scale3x,
+
+    /// 3x upscaling with CRT-like scanlines
+    Crt,
+}
+
+/// Graphics debug views shown in their own window. See
+/// [`debug_views`](crate::graphics::debug_views)
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DebugWindow {
+    PatternTables,
+
+    /// Nametables with the visible area outlined
+    Nametables,
+
+    Palettes,
+
+    /// List of sprites in OAM
+    Sprites,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScreenCorner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+/// Runaway loop detection. See [`watchdog`](crate::watchdog)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WatchdogSettings {
+    /// Consecutive frames in the loop before reporting it
+    pub frames: u64,
+
+    /// Loops spanning this many bytes of code or more aren't considered
+    /// runaway loops
+    pub max_loop_size: u16,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            frames: 180,
+            max_loop_size: 16,
+        }
+    }
+}
+
+/// Color adjustments applied when the palette is built, so they have no cost
+/// while rendering. Defaults leave the palette untouched.
+///
+/// Palette colors are sRGB. Saturation is adjusted in linear light, while
+/// contrast, brightness and gamma work on the sRGB encoded values
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ColorSettings {
+    /// Added to every color component (-1.0 to 1.0)
+    pub brightness: f64,
+
+    /// Scale of the distance to mid gray. 1.0 keeps the original contrast
+    pub contrast: f64,
+
+    /// 0.0 produces grayscale, 1.0 keeps the original colors and bigger values
+    /// produce more vivid colors
+    pub saturation: f64,
+
+    /// Gamma correction: components are raised to `1 / gamma`. Values bigger
+    /// than 1.0 brighten dark colors
+    pub gamma: f64,
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            gamma: 1.0,
+        }
+    }
 }
 
 impl Default for NesSettings {
@@ -20,6 +299,117 @@ impl Default for NesSettings {
         Self {
             pixel_scale_factor: DEFAULT_PIXEL_SCALE_FACTOR,
             ui_kind: UiKind::Gtk,
+            cpu_ppu_alignment: 0,
+            settings_file: SettingsFile::default_path(),
+            bus_fault_policy: BusFaultPolicy::default(),
+            clock_granularity: ClockGranularity::default(),
+            cpu_speed: CpuSpeed::default(),
+            accuracy: AccuracyProfile::default(),
+            rng_seed: 0,
+            colors: ColorSettings::default(),
+            dpcm_controller_conflict: false,
+            video_filter: VideoFilterKind::default(),
+            watchdog: None,
+            input_overlay: None,
+            frame_time_graph: None,
+            debug_windows: Vec::new(),
+            session_directory: None,
+            beam_racing: None,
+            input_delay: 0,
         }
     }
 }
+
+/// Settings persisted in a plain text file with one `key = value` pair per
+/// line. Lines starting with `#` are comments
+#[derive(Debug)]
+pub struct SettingsFile {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl SettingsFile {
+    /// Default settings file location: `$XDG_CONFIG_HOME/jotare-nes-emulator/settings.conf`
+    /// or `~/.config/jotare-nes-emulator/settings.conf`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("jotare-nes-emulator").join("settings.conf"))
+    }
+
+    /// Load settings from `path`. A missing file is not an error, it'll be
+    /// created when saving
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    warn!("Unable to read settings file {path:?}: {error}");
+                }
+                String::new()
+            }
+        };
+
+        let mut values = BTreeMap::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    values.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                None => warn!("Ignoring malformed settings line: {line}"),
+            }
+        }
+
+        Self { path, values }
+    }
+
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.get(key)?.parse().ok()
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents: String = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}\n"))
+            .collect();
+        fs::write(&self.path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_file_roundtrip() {
+        let path = std::env::temp_dir()
+            .join(format!("nes-settings-{}", std::process::id()))
+            .join("settings.conf");
+
+        let mut settings = SettingsFile::load(&path);
+        assert_eq!(settings.get::<i32>("window.width"), None);
+
+        settings.set("window.width", 1024);
+        settings.set("window.fullscreen", true);
+        settings.save().unwrap();
+
+        let settings = SettingsFile::load(&path);
+        assert_eq!(settings.get::<i32>("window.width"), Some(1024));
+        assert_eq!(settings.get::<bool>("window.fullscreen"), Some(true));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+//! In-memory NES snapshots
+//!
+//! A [`Snapshot`] is the complete state of a running NES kept in memory, so it
+//! can be restored any number of times with
+//! [`Nes::restore`](crate::Nes::restore). It's meant for tree-search AIs,
+//! rollback netplay or "what if" debugging, where the state is branched
+//! thousands of times per second. Cloning a snapshot is cheap, as its contents
+//! are shared.
+//!
+//! The pixels of the frame being drawn are not part of it. Taking snapshots at
+//! frame boundaries, e.g., after [`Nes::run_frames`](crate::Nes::run_frames),
+//! avoids partial frames after restoring.
+//!
+//! Input configuration (keyboard bindings, macros, movies...) and settings are
+//! not part of the snapshot either, except for the input delay, which changes
+//! what games read.
+//!
+//! Snapshots can be saved as bytes with [`Snapshot::to_bytes`] and loaded back
+//! with [`Nes::load_snapshot`](crate::Nes::load_snapshot), e.g., to keep them
+//! in a file. Saved states are tied to the ROM they were taken with.
+//!
+//! The saved state format is versioned. States saved by older versions of the
+//! crate keep loading, the fields they lack get defaults, and
+//! [`Snapshot::to_bytes_version`] saves states older versions can load. Their
+//! [`StateMetadata`] can be read without loading them, e.g., to list save
+//! slots.
+
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dma::DmaController;
+use crate::errors::StateError;
+use crate::graphics::palette_memory::PaletteMemory;
+use crate::graphics::ppu::PpuSnapshot;
+use crate::mappers::MapperSnapshot;
+use crate::processor::cpu::CpuSnapshot;
+use crate::processor::memory::{Ciram, MirroredMemory, Ram};
+use crate::rng::Rng;
+
+/// Saved states start with these bytes
+const STATE_MAGIC: &[u8; 4] = b"NESS";
+
+/// Version of the saved state format, increased on every change. Each
+/// version adds something to the previous one:
+///
+/// 1. First version
+/// 2. RNG state
+/// 3. Controllers input delay
+/// 4. OAM DMA alignment cycles
+/// 5. Crate version and timestamp
+/// 6. Mapper IRQ line state
+pub const STATE_VERSION: u8 = 6;
+
+/// Oldest saved state format version that can be loaded
+pub const MIN_STATE_VERSION: u8 = 1;
+
+// First version of each addition, to migrate older states
+pub(crate) const RNG_VERSION: u8 = 2;
+pub(crate) const INPUT_DELAY_VERSION: u8 = 3;
+pub(crate) const DMA_ALIGNMENT_VERSION: u8 = 4;
+pub(crate) const METADATA_VERSION: u8 = 5;
+pub(crate) const MAPPER_IRQ_VERSION: u8 = 6;
+
+/// Information about a saved state, see [`StateMetadata::from_bytes`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateMetadata {
+    /// Saved state format version
+    pub version: u8,
+    /// ROM checksum of the inserted cartidge, see
+    /// [`Cartidge::checksum`](crate::Cartidge::checksum)
+    pub rom_checksum: Option<u32>,
+    /// Version of the crate that saved the state. Unknown before version 5
+    pub crate_version: Option<String>,
+    /// When the snapshot was taken, to the millisecond. Unknown before
+    /// version 5
+    pub timestamp: Option<SystemTime>,
+}
+
+impl StateMetadata {
+    /// Metadata of a snapshot taken right now
+    pub(crate) fn now(rom_checksum: Option<u32>) -> Self {
+        Self {
+            version: STATE_VERSION,
+            rom_checksum,
+            crate_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            timestamp: Some(SystemTime::now()),
+        }
+    }
+
+    /// Read the metadata of a saved state without loading it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        Self::load(&mut StateReader { bytes, version: 0 })
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.raw(STATE_MAGIC);
+        state.u8(state.version);
+        state.option(self.rom_checksum, StateWriter::u32);
+        if state.version >= METADATA_VERSION {
+            let crate_version = self
+                .crate_version
+                .as_deref()
+                .unwrap_or(env!("CARGO_PKG_VERSION"));
+            state.bytes(crate_version.as_bytes());
+            let timestamp = self.timestamp.unwrap_or_else(SystemTime::now);
+            let millis = timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            state.u64(millis as u64);
+        }
+    }
+
+    /// Read the header of a saved state, which sets the version of `state`
+    fn load(state: &mut StateReader) -> Result<Self, StateError> {
+        if state.raw(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::InvalidFormat);
+        }
+        let version = state.u8()?;
+        if !(MIN_STATE_VERSION..=STATE_VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        state.version = version;
+
+        let rom_checksum = state.option(StateReader::u32)?;
+        let (crate_version, timestamp) = if version >= METADATA_VERSION {
+            let crate_version = String::from_utf8(state.bytes()?.to_vec())
+                .map_err(|_| StateError::Malformed("invalid crate version".to_string()))?;
+            let timestamp = UNIX_EPOCH + Duration::from_millis(state.u64()?);
+            (Some(crate_version), Some(timestamp))
+        } else {
+            (None, None)
+        };
+        Ok(Self {
+            version,
+            rom_checksum,
+            crate_version,
+            timestamp,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub(crate) data: Rc<SnapshotData>,
+}
+
+#[derive(Clone)]
+pub(crate) struct SnapshotData {
+    pub system_clock: u64,
+    pub cpu_clock_offset: u64,
+    pub next_cpu_clock: u64,
+    pub frame_count: u64,
+    pub rng: Rng,
+    pub input_delay: u8,
+    pub metadata: StateMetadata,
+
+    pub cpu: CpuSnapshot,
+    pub ppu: PpuSnapshot,
+    pub dma_controller: DmaController,
+    pub input_devices: [Vec<u8>; 2],
+
+    pub ram: MirroredMemory<Ram>,
+    pub nametable: Ciram,
+    pub palettes: MirroredMemory<PaletteMemory>,
+
+    pub cartidge: Option<CartidgeSnapshot>,
+}
+
+/// Inserted cartidge and its mapper state
+#[derive(Clone)]
+pub(crate) struct CartidgeSnapshot {
+    pub name: String,
+    /// ROM checksum, see [`Cartidge::checksum`](crate::Cartidge::checksum)
+    pub checksum: u32,
+    pub mapper: MapperSnapshot,
+}
+
+impl Snapshot {
+    /// Number of frames produced by the NES when the snapshot was taken
+    pub fn frame_count(&self) -> u64 {
+        self.data.frame_count
+    }
+
+    /// Controllers input delay when the snapshot was taken, in frames
+    pub fn input_delay(&self) -> u8 {
+        self.data.input_delay
+    }
+
+    /// Version, ROM checksum and time of the snapshot. Loaded snapshots keep
+    /// the metadata of their saved state
+    pub fn metadata(&self) -> &StateMetadata {
+        &self.data.metadata
+    }
+
+    /// ROM checksum of the cartidge inserted when the snapshot was taken
+    pub fn cartidge_checksum(&self) -> Option<u32> {
+        self.data
+            .cartidge
+            .as_ref()
+            .map(|cartidge| cartidge.checksum)
+    }
+
+    /// Encode the snapshot in a compact binary format, to save it in a file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(STATE_VERSION)
+    }
+
+    /// Encode the snapshot in the format of an older `version`, for older
+    /// versions of the crate to load it. What the format lacks is lost
+    pub fn to_bytes_version(&self, version: u8) -> Result<Vec<u8>, StateError> {
+        if !(MIN_STATE_VERSION..=STATE_VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(self.encode(version))
+    }
+
+    fn encode(&self, version: u8) -> Vec<u8> {
+        let mut state = StateWriter::new(version);
+        let metadata = StateMetadata {
+            rom_checksum: self.cartidge_checksum(),
+            ..self.data.metadata.clone()
+        };
+        metadata.save(&mut state);
+        self.data.save(&mut state);
+        state.bytes
+    }
+
+    /// Decode a saved state. Memory sizes and devices depend on the hardware
+    /// and cartidge, so the state is loaded over `template`, a snapshot of the
+    /// NES it'll be restored into
+    pub(crate) fn from_bytes(bytes: &[u8], template: Snapshot) -> Result<Self, StateError> {
+        let mut state = StateReader { bytes, version: 0 };
+        let metadata = StateMetadata::load(&mut state)?;
+        let inserted = template.cartidge_checksum();
+        let saved = metadata.rom_checksum;
+        if saved != inserted {
+            return Err(StateError::CartidgeMismatch { saved, inserted });
+        }
+
+        let mut data = Rc::unwrap_or_clone(template.data);
+        data.load(&mut state)?;
+        data.metadata = metadata;
+        if !state.bytes.is_empty() {
+            return Err(StateError::Malformed(
+                "unexpected trailing data".to_string(),
+            ));
+        }
+        Ok(Self {
+            data: Rc::new(data),
+        })
+    }
+}
+
+impl SnapshotData {
+    fn save(&self, state: &mut StateWriter) {
+        state.u64(self.system_clock);
+        state.u64(self.cpu_clock_offset);
+        state.u64(self.next_cpu_clock);
+        state.u64(self.frame_count);
+        if state.version >= RNG_VERSION {
+            self.rng.save(state);
+        }
+        if state.version >= INPUT_DELAY_VERSION {
+            state.u8(self.input_delay);
+        }
+
+        self.cpu.save(state);
+        self.ppu.save(state);
+        self.dma_controller.save(state);
+        for device in self.input_devices.iter() {
+            state.bytes(device);
+        }
+
+        state.bytes(self.ram.memory().as_slice());
+        self.nametable.save(state);
+        self.palettes.memory().save(state);
+
+        if let Some(cartidge) = self.cartidge.as_ref() {
+            cartidge.mapper.save(state);
+        }
+    }
+
+    /// Overwrite the snapshot with a saved state. The cartidge is kept, as the
+    /// saved state checksum has already been checked. States of older versions
+    /// keep what they lack from the snapshot, except the input delay, which
+    /// didn't exist
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.system_clock = state.u64()?;
+        self.cpu_clock_offset = state.u64()?;
+        self.next_cpu_clock = state.u64()?;
+        self.frame_count = state.u64()?;
+        if state.version >= RNG_VERSION {
+            self.rng.load(state)?;
+        }
+        self.input_delay = if state.version >= INPUT_DELAY_VERSION {
+            state.u8()?
+        } else {
+            0
+        };
+
+        self.cpu.load(state)?;
+        self.ppu.load(state)?;
+        self.dma_controller.load(state)?;
+        for device in self.input_devices.iter_mut() {
+            *device = state.bytes()?.to_vec();
+        }
+
+        state.bytes_into(self.ram.memory_mut().as_mut_slice(), "RAM")?;
+        self.nametable.load(state)?;
+        self.palettes.memory_mut().load(state)?;
+
+        if let Some(cartidge) = self.cartidge.as_mut() {
+            cartidge.mapper.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Saved state encoder. Numbers are stored in little endian
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+    /// Format version written, older ones leave out what they lack
+    pub version: u8,
+}
+
+impl StateWriter {
+    pub fn new(version: u8) -> Self {
+        Self {
+            bytes: Vec::new(),
+            version,
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.raw(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.raw(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.raw(&value.to_le_bytes());
+    }
+
+    /// Bytes prefixed by their length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    /// Optional value prefixed by whether it's present
+    pub fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// Saved state decoder, see [`StateWriter`]
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    /// Format version read, set once the header is read
+    pub version: u8,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(StateError::Malformed(format!("invalid boolean {value}"))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.raw(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.raw(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.raw(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let length = self.u32()? as usize;
+        self.raw(length)
+    }
+
+    /// Read bytes into a memory of the same size. `what` names the memory in
+    /// errors
+    pub fn bytes_into(&mut self, memory: &mut [u8], what: &str) -> Result<(), StateError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != memory.len() {
+            return Err(StateError::Malformed(format!(
+                "{what} has {} bytes but {} were expected",
+                bytes.len(),
+                memory.len()
+            )));
+        }
+        memory.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, StateError>,
+    ) -> Result<Option<T>, StateError> {
+        if self.bool()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn raw(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < length {
+            return Err(StateError::Malformed("unexpected end of data".to_string()));
+        }
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,222 @@
+//! Debug symbols
+//!
+//! A [`SymbolTable`] names CPU addresses, so debugging tools can show
+//! `reset` or `nmi_handler` instead of raw addresses. It's loaded from the
+//! label files assemblers and other emulators produce:
+//!
+//! - FCEUX name lists (`.nl`): one `$ADDR#NAME#COMMENT` line per label
+//! - ca65/ld65 debug info (`.dbg`, `ld65 --dbgfile`): `sym` lines of label
+//!   type
+//!
+//! Addresses are CPU addresses. Bank switched games have different labels at
+//! the same address, only the last one loaded is kept.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::errors::SymbolError;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a symbol file, in ca65 debug info format if its extension is
+    /// `.dbg` or as an FCEUX name list otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SymbolError> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("dbg") => Self::from_ca65_dbg(&contents),
+            _ => Self::from_nl(&contents),
+        }
+    }
+
+    /// Parse an FCEUX name list
+    pub fn from_nl(contents: &str) -> Result<Self, SymbolError> {
+        let mut table = Self::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |details: &str| SymbolError::InvalidLine {
+                line: index + 1,
+                details: details.to_string(),
+            };
+
+            let mut fields = line.splitn(3, '#');
+            let address = fields.next().unwrap_or_default();
+            let name = fields.next().ok_or_else(|| invalid("missing name"))?;
+            // Array labels, e.g. `$0300/10`, name their first address
+            let address = address.split('/').next().unwrap_or_default();
+            let address = parse_hex(address).ok_or_else(|| invalid("invalid address"))?;
+            if !name.is_empty() {
+                table.insert(address, name);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Parse ca65/ld65 debug info. Only labels are loaded, as other symbols
+    /// (equates) are usually constants rather than addresses
+    pub fn from_ca65_dbg(contents: &str) -> Result<Self, SymbolError> {
+        let mut table = Self::new();
+        for (index, line) in contents.lines().enumerate() {
+            let Some(attributes) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let invalid = |details: &str| SymbolError::InvalidLine {
+                line: index + 1,
+                details: details.to_string(),
+            };
+
+            let attributes: HashMap<&str, &str> = attributes
+                .split(',')
+                .filter_map(|attribute| attribute.split_once('='))
+                .collect();
+            if attributes.get("type") != Some(&"lab") {
+                continue;
+            }
+            let name = attributes
+                .get("name")
+                .map(|name| name.trim_matches('"'))
+                .ok_or_else(|| invalid("missing name"))?;
+            let value = attributes
+                .get("val")
+                .and_then(|value| value.strip_prefix("0x"))
+                .ok_or_else(|| invalid("missing value"))?;
+            let address = parse_hex(value).ok_or_else(|| invalid("invalid value"))?;
+            table.insert(address, name);
+        }
+        Ok(table)
+    }
+
+    /// Name `address`, replacing its previous name if any
+    pub fn insert(&mut self, address: u16, name: impl Into<String>) {
+        let name = name.into();
+        if let Some(previous) = self.names.insert(address, name.clone()) {
+            self.addresses.remove(&previous);
+        }
+        self.addresses.insert(name, address);
+    }
+
+    /// Name of `address`, if any
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Address named `name`, if any
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Merge the symbols of `other`, which take precedence
+    pub fn extend(&mut self, other: SymbolTable) {
+        for (address, name) in other.names {
+            self.insert(address, name);
+        }
+    }
+
+    /// `address` as shown in debugging tools: its name if it has one, its
+    /// hexadecimal value (`$C000`) otherwise
+    pub fn format(&self, address: u16) -> String {
+        match self.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("${address:0>4X}"),
+        }
+    }
+
+    /// `address` with the closest symbol at or before it in the same page,
+    /// e.g., `$C005 <reset+5>`. Useful to locate the code being run
+    pub fn describe(&self, address: u16) -> String {
+        let page_start = address & 0xFF00;
+        match self.names.range(page_start..=address).next_back() {
+            Some((&start, name)) if start == address => format!("${address:0>4X} <{name}>"),
+            Some((&start, name)) => format!("${address:0>4X} <{name}+{}>", address - start),
+            None => format!("${address:0>4X}"),
+        }
+    }
+
+    /// Parse an address typed by a user: a symbol name, optionally with an
+    /// offset (`reset+2`), or a hexadecimal value with an optional `$`
+    pub fn parse_address(&self, text: &str) -> Option<u16> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, offset.parse::<u16>().ok()?),
+            None => (text, 0),
+        };
+        self.address(name)
+            .or_else(|| parse_hex(name.trim_start_matches('$')))
+            .map(|address| address.wrapping_add(offset))
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_files() {
+        let nl = "$C000#reset#Entry point\n$C010#nmi#\n$0300/10#oam_buffer#\n$C020##\n";
+        let table = SymbolTable::from_nl(nl).unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.name(0xC000), Some("reset"));
+        assert_eq!(table.address("oam_buffer"), Some(0x0300));
+
+        let dbg = "version\tmajor=2,minor=0\n\
+            sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=1,val=0x8012,seg=0,type=lab\n\
+            sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=2,val=0x3,type=equ\n";
+        let table = SymbolTable::from_ca65_dbg(dbg).unwrap();
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.name(0x8012), Some("main"));
+
+        assert!(matches!(
+            SymbolTable::from_nl("C000"),
+            Err(SymbolError::InvalidLine { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        let mut table = SymbolTable::new();
+        table.insert(0xC000, "reset");
+        table.insert(0xC100, "loop");
+
+        assert_eq!(table.format(0xC000), "reset");
+        assert_eq!(table.format(0xC001), "$C001");
+        assert_eq!(table.describe(0xC005), "$C005 <reset+5>");
+        assert_eq!(table.describe(0xC100), "$C100 <loop>");
+        assert_eq!(table.describe(0x8000), "$8000");
+
+        assert_eq!(table.parse_address("loop"), Some(0xC100));
+        assert_eq!(table.parse_address("reset+3"), Some(0xC003));
+        assert_eq!(table.parse_address("$80FF"), Some(0x80FF));
+        assert_eq!(table.parse_address("nmi"), None);
+
+        // Renaming drops the old name
+        table.insert(0xC000, "start");
+        assert_eq!(table.address("reset"), None);
+    }
+}
@@ -0,0 +1,245 @@
+//! Helpers to build end-to-end tests
+//!
+//! Programs are run headlessly (without UI) for a number of frames and the
+//! resulting picture is compared against a golden hash. Hashes are computed
+//! over the 8-bit RGB values of every pixel, so they're stable across
+//! platforms and Rust versions.
+//!
+//! When a golden hash doesn't match, the assertion reports the actual hash so
+//! it can be reviewed and updated.
+//!
+
+use std::sync::Arc;
+
+use crate::graphics::Frame;
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::settings::UiKind;
+use crate::Cartidge;
+use crate::Nes;
+
+const INES_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const PRG_ROM_UNIT: usize = 16 * 1024;
+const CHR_ROM_UNIT: usize = 8 * 1024;
+
+// FNV-1a 64-bit constants
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// Compute a stable hash of a frame contents
+pub fn frame_hash(frame: &Frame) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in frame.to_rgb24() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Run a cartidge headlessly and return the frame produced after `frames`
+/// frames
+///
+/// *Panic*
+///
+/// Panics if the NES fails while running
+pub fn run_headless(cartidge: Cartidge, frames: u64) -> Arc<Frame> {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(cartidge)
+        .build();
+    nes.run_frames(frames).unwrap();
+    nes.take_last_frame()
+        .expect("NES should have produced at least a frame")
+}
+
+/// Run a cartidge headlessly for `frames` frames and assert the last frame
+/// hash matches the `expected` golden value
+pub fn assert_frame_hash(cartidge: Cartidge, frames: u64, expected: u64) {
+    let frame = run_headless(cartidge, frames);
+    let actual = frame_hash(&frame);
+    assert_eq!(
+        actual, expected,
+        "Frame hash mismatch after {frames} frames: got 0x{actual:016X}, expected 0x{expected:016X}"
+    );
+}
+
+/// Scanline where the sprite 0 used by [`scroll_split_cartidge`] is. The
+/// scroll split happens right below it
+pub const SCROLL_SPLIT_SCANLINE: usize = 31;
+
+/// Fine X scroll applied below the split
+pub const SCROLL_SPLIT_FINE_X: usize = 4;
+
+// Status bar split like Super Mario Bros.: every frame, resets the scroll in
+// vertical blank, waits for sprite 0 hit and then scrolls 4 pixels to the
+// right. The nametable is filled with vertical stripes, 4 pixels on and 4 off
+#[rustfmt::skip]
+const SCROLL_SPLIT_PROGRAM: [u8; 140] = [
+    0x78,                   // $8000  SEI
+    0xD8,                   // $8001  CLD
+    0xA2, 0xFF,             // $8002  LDX #$FF
+    0x9A,                   // $8004  TXS
+    0x2C, 0x02, 0x20,       // $8005  BIT $2002
+    0x10, 0xFB,             // $8008  BPL $8005
+    0x2C, 0x02, 0x20,       // $800A  BIT $2002
+    0x10, 0xFB,             // $800D  BPL $800A
+    0xA9, 0x3F,             // $800F  LDA #$3F
+    0x8D, 0x06, 0x20,       // $8011  STA $2006
+    0xA9, 0x00,             // $8014  LDA #$00
+    0x8D, 0x06, 0x20,       // $8016  STA $2006
+    0xA2, 0x00,             // $8019  LDX #$00
+    0xBD, 0x00, 0x81,       // $801B  LDA $8100,X
+    0x8D, 0x07, 0x20,       // $801E  STA $2007
+    0xE8,                   // $8021  INX
+    0xE0, 0x20,             // $8022  CPX #$20
+    0xD0, 0xF5,             // $8024  BNE $801B
+    0xA9, 0x20,             // $8026  LDA #$20
+    0x8D, 0x06, 0x20,       // $8028  STA $2006
+    0xA9, 0x00,             // $802B  LDA #$00
+    0x8D, 0x06, 0x20,       // $802D  STA $2006
+    0xA9, 0x01,             // $8030  LDA #$01
+    0xA2, 0x00,             // $8032  LDX #$00
+    0xA0, 0x04,             // $8034  LDY #$04
+    0x8D, 0x07, 0x20,       // $8036  STA $2007
+    0xE8,                   // $8039  INX
+    0xD0, 0xFA,             // $803A  BNE $8036
+    0x88,                   // $803C  DEY
+    0xD0, 0xF7,             // $803D  BNE $8036
+    0xA9, 0xFF,             // $803F  LDA #$FF
+    0xA2, 0x00,             // $8041  LDX #$00
+    0x9D, 0x00, 0x02,       // $8043  STA $0200,X
+    0xE8,                   // $8046  INX
+    0xD0, 0xFA,             // $8047  BNE $8043
+    0xA9, 0x1E,             // $8049  LDA #30
+    0x8D, 0x00, 0x02,       // $804B  STA $0200
+    0xA9, 0x02,             // $804E  LDA #$02
+    0x8D, 0x01, 0x02,       // $8050  STA $0201
+    0xA9, 0x00,             // $8053  LDA #$00
+    0x8D, 0x02, 0x02,       // $8055  STA $0202
+    0xA9, 0x64,             // $8058  LDA #100
+    0x8D, 0x03, 0x02,       // $805A  STA $0203
+    0xA9, 0x02,             // $805D  LDA #$02
+    0x8D, 0x14, 0x40,       // $805F  STA $4014
+    0xA9, 0x00,             // $8062  LDA #$00
+    0x8D, 0x00, 0x20,       // $8064  STA $2000
+    0xA9, 0x1E,             // $8067  LDA #$1E
+    0x8D, 0x01, 0x20,       // $8069  STA $2001
+    0x2C, 0x02, 0x20,       // $806C  BIT $2002
+    0x10, 0xFB,             // $806F  BPL $806C
+    0xA9, 0x00,             // $8071  LDA #$00
+    0x8D, 0x05, 0x20,       // $8073  STA $2005
+    0x8D, 0x05, 0x20,       // $8076  STA $2005
+    0x2C, 0x02, 0x20,       // $8079  BIT $2002
+    0x70, 0xFB,             // $807C  BVS $8079
+    0x2C, 0x02, 0x20,       // $807E  BIT $2002
+    0x50, 0xFB,             // $8081  BVC $807E
+    0xA9, 0x04,             // $8083  LDA #$04
+    0x8D, 0x05, 0x20,       // $8085  STA $2005
+    0x4C, 0x6C, 0x80,       // $8088  JMP $806C
+    0x40,                   // $808B  RTI
+];
+
+/// Cartidge running a scroll split test program. Rows above
+/// [`SCROLL_SPLIT_SCANLINE`] aren't scrolled while rows below it are scrolled
+/// [`SCROLL_SPLIT_FINE_X`] pixels. Use [`check_scroll_split`] to validate
+/// frames it produces
+pub fn scroll_split_cartidge() -> Cartidge {
+    let mut prg = vec![0; PRG_ROM_UNIT];
+    prg[..SCROLL_SPLIT_PROGRAM.len()].copy_from_slice(&SCROLL_SPLIT_PROGRAM);
+
+    // All background palettes white on black and sprite palettes red
+    for palette in prg[0x100..0x120].chunks_mut(4) {
+        palette.copy_from_slice(&[0x0F, 0x30, 0x30, 0x30]);
+    }
+    for palette in prg[0x110..0x120].chunks_mut(4) {
+        palette.copy_from_slice(&[0x0F, 0x16, 0x16, 0x16]);
+    }
+
+    // NMI, reset and IRQ vectors
+    prg[0x3FFA..].copy_from_slice(&[0x8B, 0x80, 0x00, 0x80, 0x8B, 0x80]);
+
+    // Tile 1: vertical stripes, tile 2: solid block (sprite 0)
+    let mut chr = vec![0; CHR_ROM_UNIT];
+    chr[16..24].fill(0xF0);
+    chr[32..40].fill(0xFF);
+
+    Cartidge::from_bytes("scroll-split.nes", &ines_image(0, false, &prg, &chr))
+}
+
+/// Check a frame produced by [`scroll_split_cartidge`] has the status bar
+/// unscrolled and the playfield scrolled. Sprites are ignored
+pub fn check_scroll_split(frame: &Frame) -> Result<(), String> {
+    let lit = |row: usize, col: usize| {
+        let pixel = frame[row][col];
+        pixel.red() > 0.5 && pixel.green() > 0.5
+    };
+
+    let check_row = |row: usize, scroll: usize| {
+        for col in 0..SCREEN_WIDTH - 8 {
+            let expected = (col + scroll) % 8 < 4;
+            if lit(row, col) != expected {
+                return Err(format!(
+                    "Pixel ({row}, {col}) should be {} with scroll {scroll}",
+                    if expected { "lit" } else { "dark" }
+                ));
+            }
+        }
+        Ok(())
+    };
+
+    check_row(SCROLL_SPLIT_SCANLINE / 2, 0)?;
+    for row in SCROLL_SPLIT_SCANLINE + 10..SCREEN_HEIGHT {
+        check_row(row, SCROLL_SPLIT_FINE_X)?;
+    }
+    Ok(())
+}
+
+/// Build an iNES image in memory from its PRG and CHR contents. PRG and CHR
+/// are padded with zeros up to the next 16 kB or 8 kB unit respectively
+pub fn ines_image(mapper: u8, vertical_mirroring: bool, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+    let prg_units = prg.len().div_ceil(PRG_ROM_UNIT).max(1);
+    let chr_units = chr.len().div_ceil(CHR_ROM_UNIT);
+
+    let mut image = Vec::with_capacity(16 + prg_units * PRG_ROM_UNIT + chr_units * CHR_ROM_UNIT);
+    image.extend_from_slice(&INES_MAGIC);
+    image.push(prg_units as u8);
+    image.push(chr_units as u8);
+    image.push(((mapper & 0x0F) << 4) | vertical_mirroring as u8);
+    image.push(mapper & 0xF0);
+    image.resize(16, 0);
+
+    image.extend_from_slice(prg);
+    image.resize(16 + prg_units * PRG_ROM_UNIT, 0);
+    image.extend_from_slice(chr);
+    image.resize(16 + prg_units * PRG_ROM_UNIT + chr_units * CHR_ROM_UNIT, 0);
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{FramePixel, Pixel};
+
+    #[test]
+    fn test_frame_hash() {
+        let black = Frame::black();
+        let mut frame = Frame::black();
+        assert_eq!(frame_hash(&black), frame_hash(&frame));
+
+        frame.set_pixel(Pixel::RED, FramePixel { row: 10, col: 20 });
+        assert_ne!(frame_hash(&black), frame_hash(&frame));
+    }
+
+    #[test]
+    fn test_ines_image() {
+        let image = ines_image(3, true, &[0xEA; 100], &[0x01; 10]);
+        assert_eq!(image.len(), 16 + PRG_ROM_UNIT + CHR_ROM_UNIT);
+        assert_eq!(image[4..8], [1, 1, 0x31, 0x00]);
+
+        let cartidge = Cartidge::from_bytes("test", &image);
+        assert_eq!(
+            cartidge.mirroring(),
+            crate::processor::memory::Mirroring::Vertical
+        );
+    }
+}
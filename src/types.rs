@@ -1,19 +1,24 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::controller::Controller;
 use crate::graphics::ppu::Ppu;
+use crate::input::InputPort;
 use crate::interfaces::Memory;
 use crate::processor::bus::Bus;
-use crate::processor::memory::{Ciram, MirroredMemory, Ram, Rom};
+use crate::processor::memory::{Ciram, Ram};
+use crate::rng::Rng;
+use crate::warnings::Warnings;
 
 pub type SharedBus = Rc<RefCell<Bus>>;
 
 pub type SharedMemory = Rc<RefCell<dyn Memory>>;
 pub type SharedRam = Rc<RefCell<Ram>>;
 pub type SharedCiram = Rc<RefCell<Ciram>>;
-pub type SharedMirroredRom = Rc<RefCell<MirroredMemory<Rom>>>;
 
 pub type SharedPpu = Rc<RefCell<Ppu>>;
 
-pub type SharedController = Rc<RefCell<Controller>>;
+pub type SharedInputPort = Rc<RefCell<InputPort>>;
+
+pub type SharedRng = Rc<RefCell<Rng>>;
+
+pub type SharedWarnings = Rc<RefCell<Warnings>>;
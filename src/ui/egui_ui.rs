@@ -0,0 +1,760 @@
+/// egui UI
+///
+/// Developer frontend built on egui (through eframe). Besides the screen, it
+/// has panels with the CPU state and disassembly, a memory viewer, the PPU
+/// debug views and the performance metrics. Panels are windows that can be
+/// moved, resized, collapsed and closed, and reopened from the menu bar.
+///
+/// Like the GTK UI, it runs in its own thread. winit only allows that on
+/// Linux and Windows, so this UI is not available on macOS.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+
+use eframe::egui;
+use log::{debug, warn};
+
+use crate::events::{Event, KeyboardPublisher, SharedEventBus};
+use crate::graphics::debug_views::{DebugImage, GraphicsDebugView};
+use crate::graphics::filters::VideoFilter;
+use crate::graphics::{PartialFrame, Pixel};
+use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::interfaces::AddressRange;
+use crate::keyboard::Key;
+use crate::memory_viewer::{MemoryDump, ROW_SIZE};
+use crate::settings::DEFAULT_PIXEL_SCALE_FACTOR;
+use crate::ui::{Frame, Inspection, Ui};
+
+use super::UiError;
+
+const APP_NAME: &str = "NES Emulator (by jotare)";
+
+// Memory shown by the memory viewer until another range is chosen: zero page
+// and stack
+const DEFAULT_INSPECTED_MEMORY: AddressRange = AddressRange {
+    start: 0x0000,
+    end: 0x01FF,
+};
+
+// Debug views pixel scale factors
+const PATTERN_TABLES_SCALE: f32 = 2.0;
+const PALETTE_SWATCH_SIZE: f32 = 20.0;
+
+pub struct EguiUi {
+    pixel_scale_factor: usize,
+    handle: Option<JoinHandle<()>>,
+    keyboard: Option<KeyboardPublisher>,
+    event_bus: Option<SharedEventBus>,
+    shared: Arc<Mutex<SharedState>>,
+    dropped_frames: usize,
+    video_filter: Option<Box<dyn VideoFilter>>,
+    // Frame being drawn while beam racing, rows not rendered yet are the
+    // previous frame ones
+    beam: Option<ScreenImage>,
+}
+
+/// State shared between the emulator and the UI thread
+struct SharedState {
+    screen: Option<ScreenImage>,
+    title: Option<String>,
+    debug_view: Option<Arc<GraphicsDebugView>>,
+    // Debug views are only shown in the PPU panel
+    ppu_panel_open: bool,
+    inspection: Option<Arc<Inspection>>,
+    inspected_memory: AddressRange,
+    // Used to wake up the UI thread when there's something new to show
+    context: Option<egui::Context>,
+}
+
+impl SharedState {
+    fn request_repaint(&self) {
+        if let Some(context) = self.context.as_ref() {
+            context.request_repaint();
+        }
+    }
+}
+
+/// Frame ready to be uploaded as a texture
+#[derive(Clone)]
+struct ScreenImage {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    // Whether all rows belong to the same frame, i.e., it's not being beam
+    // raced
+    complete: bool,
+}
+
+impl ScreenImage {
+    fn from_frame(frame: &Frame) -> Self {
+        let rgba = frame
+            .to_rgb24()
+            .chunks(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], u8::MAX])
+            .collect();
+        Self {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgba,
+            complete: true,
+        }
+    }
+
+    /// Draw the rows of a frame being rendered over the image
+    fn draw_rows(&mut self, partial: &PartialFrame) {
+        for (y, row) in partial.rows.clone().zip(&partial.pixels) {
+            for (x, pixel) in row.iter().enumerate() {
+                let offset = (y * self.width + x) * 4;
+                let channels = [pixel.red(), pixel.green(), pixel.blue()];
+                for (byte, channel) in self.rgba[offset..offset + 3].iter_mut().zip(channels) {
+                    *byte = (channel * u8::MAX as f64).round() as u8;
+                }
+            }
+        }
+        self.complete = false;
+    }
+}
+
+impl EguiUi {
+    pub fn builder() -> EguiUiBuilder {
+        EguiUiBuilder::new()
+    }
+
+    fn render_thread(
+        pixel_scale_factor: usize,
+        event_bus: Option<SharedEventBus>,
+        keyboard: Option<KeyboardPublisher>,
+        shared: Arc<Mutex<SharedState>>,
+    ) {
+        let size = [
+            (SCREEN_WIDTH * pixel_scale_factor) as f32,
+            (SCREEN_HEIGHT * pixel_scale_factor) as f32,
+        ];
+        let options = eframe::NativeOptions {
+            viewport: egui::ViewportBuilder::default()
+                .with_title(APP_NAME)
+                .with_inner_size(size),
+            event_loop_builder: Some(Box::new(|builder| {
+                // The event loop doesn't run in the main thread
+                #[cfg(target_os = "linux")]
+                winit::platform::x11::EventLoopBuilderExtX11::with_any_thread(builder, true);
+                #[cfg(target_os = "windows")]
+                winit::platform::windows::EventLoopBuilderExtWindows::with_any_thread(
+                    builder, true,
+                );
+            })),
+            ..Default::default()
+        };
+
+        let result = eframe::run_native(
+            APP_NAME,
+            options,
+            Box::new(move |creation| {
+                shared.lock().unwrap().context = Some(creation.egui_ctx.clone());
+                Ok(Box::new(EguiApp::new(event_bus, keyboard, shared)))
+            }),
+        );
+        if let Err(error) = result {
+            warn!("egui UI stopped with an error: {error}");
+        }
+    }
+}
+
+impl Ui for EguiUi {
+    /// Start the egui UI in a new thread
+    fn start(&mut self) -> Result<(), UiError> {
+        if self.handle.is_some() {
+            return Err(UiError::AlreadyStarted(
+                "egui UI is already started, can't start it twice".to_string(),
+            ));
+        }
+
+        let pixel_scale_factor = self.pixel_scale_factor;
+        let event_bus = self.event_bus.take();
+        let keyboard = self.keyboard.take();
+        let shared = Arc::clone(&self.shared);
+        let join_handle =
+            spawn(move || Self::render_thread(pixel_scale_factor, event_bus, keyboard, shared));
+        self.handle.replace(join_handle);
+
+        Ok(())
+    }
+
+    fn render(&mut self, frame: Arc<Frame>) {
+        // Filters run here, in the emulation thread, so the UI thread only
+        // has to upload the result
+        let screen = match self.video_filter.as_mut() {
+            Some(filter) => {
+                let filtered = filter.apply(&frame);
+                ScreenImage {
+                    width: filtered.width,
+                    height: filtered.height,
+                    rgba: filtered.rgba,
+                    complete: true,
+                }
+            }
+            None => ScreenImage::from_frame(&frame),
+        };
+
+        let mut shared = self.shared.lock().unwrap();
+        if shared
+            .screen
+            .replace(screen)
+            .is_some_and(|screen| screen.complete)
+        {
+            self.dropped_frames += 1;
+        }
+        shared.request_repaint();
+    }
+
+    fn render_scanlines(&mut self, rows: Arc<PartialFrame>) {
+        // Filters work on complete frames
+        if self.video_filter.is_some() {
+            return;
+        }
+
+        let beam = self
+            .beam
+            .get_or_insert_with(|| ScreenImage::from_frame(&Frame::black()));
+        beam.draw_rows(&rows);
+        let mut shared = self.shared.lock().unwrap();
+        shared.screen = Some(beam.clone());
+        shared.request_repaint();
+    }
+
+    fn take_dropped_frames(&mut self) -> usize {
+        std::mem::take(&mut self.dropped_frames)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.title = Some(title.to_string());
+        shared.request_repaint();
+    }
+
+    fn set_video_filter(&mut self, filter: Option<Box<dyn VideoFilter>>) {
+        self.video_filter = filter;
+    }
+
+    fn wants_debug_view(&self) -> bool {
+        self.shared.lock().unwrap().ppu_panel_open
+    }
+
+    fn render_debug_view(&mut self, view: Arc<GraphicsDebugView>) {
+        self.shared.lock().unwrap().debug_view = Some(view);
+    }
+
+    fn inspected_memory(&self) -> Option<AddressRange> {
+        Some(self.shared.lock().unwrap().inspected_memory)
+    }
+
+    fn render_inspection(&mut self, inspection: Arc<Inspection>) {
+        self.shared.lock().unwrap().inspection = Some(inspection);
+    }
+
+    fn stop(&mut self) -> Result<(), UiError> {
+        let handle = self.handle.take().ok_or(UiError::NotStarted)?;
+        debug!("Waiting UI thread to end...");
+        handle.join().map_err(|_| {
+            UiError::Unhandled("Error waiting UI thread to join (stop)".to_string())
+        })?;
+        debug!("UI thread ended correctly");
+
+        self.shared.lock().unwrap().context = None;
+
+        Ok(())
+    }
+}
+
+pub struct EguiUiBuilder {
+    pixel_scale_factor: usize,
+    keyboard: Option<KeyboardPublisher>,
+    event_bus: Option<SharedEventBus>,
+}
+
+impl EguiUiBuilder {
+    pub fn new() -> Self {
+        Self {
+            pixel_scale_factor: DEFAULT_PIXEL_SCALE_FACTOR,
+            keyboard: None,
+            event_bus: None,
+        }
+    }
+
+    pub fn build(self) -> EguiUi {
+        EguiUi {
+            pixel_scale_factor: self.pixel_scale_factor,
+            handle: None,
+            keyboard: self.keyboard,
+            event_bus: self.event_bus,
+            shared: Arc::new(Mutex::new(SharedState {
+                screen: None,
+                title: None,
+                debug_view: None,
+                ppu_panel_open: false,
+                inspection: None,
+                inspected_memory: DEFAULT_INSPECTED_MEMORY,
+                context: None,
+            })),
+            dropped_frames: 0,
+            video_filter: None,
+            beam: None,
+        }
+    }
+
+    pub fn pixel_scale_factor(mut self, factor: usize) -> Self {
+        self.pixel_scale_factor = factor;
+        self
+    }
+
+    pub fn with_keyboard_publisher(mut self, keyboard_publisher: KeyboardPublisher) -> Self {
+        self.keyboard = Some(keyboard_publisher);
+        self
+    }
+
+    pub fn with_event_bus(mut self, event_bus: SharedEventBus) -> Self {
+        self.event_bus.replace(event_bus);
+        self
+    }
+}
+
+impl Default for EguiUiBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Panels shown, toggled from the menu bar
+struct Panels {
+    debugger: bool,
+    memory: bool,
+    ppu: bool,
+    metrics: bool,
+}
+
+/// egui application running in the UI thread
+struct EguiApp {
+    event_bus: Option<SharedEventBus>,
+    keyboard: Option<KeyboardPublisher>,
+    shared: Arc<Mutex<SharedState>>,
+    panels: Panels,
+
+    screen: Option<egui::TextureHandle>,
+    pattern_tables: Option<egui::TextureHandle>,
+    nametables: Option<egui::TextureHandle>,
+    debug_view: Option<Arc<GraphicsDebugView>>,
+    inspection: Option<Arc<Inspection>>,
+    // Previous memory snapshot, to highlight bytes changed between frames
+    memory: Option<MemoryDump>,
+    memory_range_input: (String, String),
+    // Modifier keys held, as egui reports them as state instead of keys
+    held_modifiers: HashSet<Key>,
+}
+
+impl EguiApp {
+    fn new(
+        event_bus: Option<SharedEventBus>,
+        keyboard: Option<KeyboardPublisher>,
+        shared: Arc<Mutex<SharedState>>,
+    ) -> Self {
+        Self {
+            event_bus,
+            keyboard,
+            shared,
+            panels: Panels {
+                debugger: true,
+                memory: true,
+                ppu: false,
+                metrics: false,
+            },
+            screen: None,
+            pattern_tables: None,
+            nametables: None,
+            debug_view: None,
+            inspection: None,
+            memory: None,
+            memory_range_input: (
+                format!("{:0>4X}", DEFAULT_INSPECTED_MEMORY.start),
+                format!("{:0>4X}", DEFAULT_INSPECTED_MEMORY.end),
+            ),
+            held_modifiers: HashSet::new(),
+        }
+    }
+
+    /// Take what the emulator sent since the last update
+    fn receive(&mut self, ctx: &egui::Context) {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(screen) = shared.screen.take() {
+            let image = egui::ColorImage::from_rgba_unmultiplied(
+                [screen.width, screen.height],
+                &screen.rgba,
+            );
+            upload(ctx, &mut self.screen, "screen", image);
+        }
+        if let Some(title) = shared.title.take() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(format!(
+                "{title} - {APP_NAME}"
+            )));
+        }
+        if let Some(view) = shared.debug_view.take() {
+            if self.panels.ppu {
+                let [left, right] = &view.pattern_tables;
+                upload(
+                    ctx,
+                    &mut self.pattern_tables,
+                    "pattern-tables",
+                    side_by_side(left, right),
+                );
+                let nametables = view.nametables_with_scroll(Pixel::RED);
+                upload(
+                    ctx,
+                    &mut self.nametables,
+                    "nametables",
+                    color_image(&nametables),
+                );
+            }
+            self.debug_view = Some(view);
+        }
+        if let Some(inspection) = shared.inspection.take() {
+            let mut memory = inspection.memory.clone();
+            if let Some(previous) = self.memory.as_ref() {
+                memory.compare(previous);
+            }
+            self.memory = Some(memory);
+            self.inspection = Some(inspection);
+        }
+    }
+
+    /// Publish keyboard input for the controllers
+    fn handle_keys(&mut self, ctx: &egui::Context) {
+        let Some(keyboard) = self.keyboard.as_mut() else {
+            return;
+        };
+        // Typing in a text field doesn't press buttons
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        ctx.input(|input| {
+            for event in &input.events {
+                if let egui::Event::Key {
+                    key,
+                    pressed,
+                    repeat: false,
+                    ..
+                } = event
+                {
+                    let Some(key) = translate_key(*key) else {
+                        continue;
+                    };
+                    if *pressed {
+                        keyboard.press_key(key);
+                    } else {
+                        keyboard.release_key(key);
+                    }
+                }
+            }
+
+            // egui doesn't tell left and right modifiers apart
+            let modifiers = input.modifiers;
+            for (key, held) in [
+                (Key::LeftShift, modifiers.shift),
+                (Key::LeftControl, modifiers.ctrl),
+                (Key::LeftAlt, modifiers.alt),
+            ] {
+                if held && self.held_modifiers.insert(key) {
+                    keyboard.press_key(key);
+                } else if !held && self.held_modifiers.remove(&key) {
+                    keyboard.release_key(key);
+                }
+            }
+        });
+    }
+
+    fn menu_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut self.panels.debugger, "Debugger");
+                ui.toggle_value(&mut self.panels.memory, "Memory");
+                ui.toggle_value(&mut self.panels.ppu, "PPU");
+                ui.toggle_value(&mut self.panels.metrics, "Metrics");
+            });
+        });
+    }
+
+    fn screen(&self, ctx: &egui::Context) {
+        egui::CentralPanel::default()
+            .frame(egui::Frame::NONE.fill(egui::Color32::BLACK))
+            .show(ctx, |ui| {
+                let Some(texture) = self.screen.as_ref() else {
+                    return;
+                };
+                // Biggest integer scale fitting in the panel
+                let available = ui.available_size();
+                let scale = (available.x / SCREEN_WIDTH as f32)
+                    .min(available.y / SCREEN_HEIGHT as f32)
+                    .floor()
+                    .max(1.0);
+                let size = egui::vec2(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32) * scale;
+                ui.centered_and_justified(|ui| {
+                    ui.add(egui::Image::new((texture.id(), size)));
+                });
+            });
+    }
+
+    fn debugger_panel(&mut self, ctx: &egui::Context) {
+        let Some(inspection) = self.inspection.as_ref() else {
+            return;
+        };
+        egui::Window::new("Debugger")
+            .open(&mut self.panels.debugger)
+            .show(ctx, |ui| {
+                let cpu = &inspection.cpu;
+                ui.monospace(format!(
+                    "A:{:0>2X} X:{:0>2X} Y:{:0>2X} P:{:0>2X} SP:{:0>2X} PC:{:0>4X}",
+                    cpu.acc, cpu.x_reg, cpu.y_reg, cpu.sr, cpu.sp, cpu.pc
+                ));
+                ui.monospace(format!("Flags: {}", flags(cpu.sr)));
+                ui.separator();
+                for (index, instruction) in inspection.disassembly.iter().enumerate() {
+                    let text = egui::RichText::new(instruction.to_string()).monospace();
+                    if index == 0 {
+                        ui.label(text.strong());
+                    } else {
+                        ui.label(text);
+                    }
+                }
+            });
+    }
+
+    fn memory_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.memory;
+        egui::Window::new("Memory").open(&mut open).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let (start, end) = &mut self.memory_range_input;
+                ui.label("$");
+                ui.add(egui::TextEdit::singleline(start).desired_width(40.0));
+                ui.label("- $");
+                ui.add(egui::TextEdit::singleline(end).desired_width(40.0));
+                if ui.button("View").clicked() {
+                    self.view_memory_range();
+                }
+            });
+            ui.separator();
+
+            let Some(memory) = self.memory.as_ref() else {
+                return;
+            };
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for row in memory.rows() {
+                    ui.horizontal(|ui| {
+                        ui.spacing_mut().item_spacing.x = 4.0;
+                        ui.monospace(format!("${:0>4X}", row.address));
+                        for cell in row.cells.iter() {
+                            let text = cell
+                                .value
+                                .map_or("--".to_string(), |value| format!("{value:0>2X}"));
+                            let mut text = egui::RichText::new(text).monospace();
+                            if cell.changed {
+                                text = text.color(egui::Color32::YELLOW);
+                            }
+                            ui.label(text);
+                        }
+                        // Keep columns aligned in the last row
+                        for _ in row.cells.len()..ROW_SIZE {
+                            ui.monospace("  ");
+                        }
+                    });
+                }
+            });
+        });
+        self.panels.memory = open;
+    }
+
+    /// Ask the emulator for the range typed in the memory panel
+    fn view_memory_range(&mut self) {
+        let (start, end) = &self.memory_range_input;
+        let parse = |text: &str| u16::from_str_radix(text.trim().trim_start_matches('$'), 16);
+        match (parse(start), parse(end)) {
+            (Ok(start), Ok(end)) if start <= end => {
+                self.shared.lock().unwrap().inspected_memory = AddressRange { start, end };
+                self.memory = None;
+            }
+            _ => warn!("Invalid memory range ${start}-${end}"),
+        }
+    }
+
+    fn ppu_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.ppu;
+        egui::Window::new("PPU").open(&mut open).show(ctx, |ui| {
+            if let Some(inspection) = self.inspection.as_ref() {
+                let ppu = &inspection.ppu;
+                ui.monospace(format!(
+                    "CTRL:{:0>2X} MASK:{:0>2X} STATUS:{:0>2X} OAMADDR:{:0>2X}",
+                    ppu.ctrl, ppu.mask, ppu.status, ppu.oam_addr
+                ));
+                ui.monospace(format!(
+                    "v:{:0>4X} t:{:0>4X} x:{} w:{} scanline:{} cycle:{}",
+                    ppu.vram_addr,
+                    ppu.temp_vram_addr,
+                    ppu.fine_x_scroll,
+                    ppu.write_toggle as u8,
+                    ppu.scan_line,
+                    ppu.cycle
+                ));
+                ui.separator();
+            }
+
+            let Some(view) = self.debug_view.as_ref() else {
+                ui.label("Waiting for the next frame...");
+                return;
+            };
+            ui.label("Palettes");
+            ui.horizontal_wrapped(|ui| {
+                ui.spacing_mut().item_spacing = egui::vec2(1.0, 1.0);
+                for color in view.palettes.iter() {
+                    let (rect, _) = ui.allocate_exact_size(
+                        egui::vec2(PALETTE_SWATCH_SIZE, PALETTE_SWATCH_SIZE),
+                        egui::Sense::hover(),
+                    );
+                    ui.painter().rect_filled(rect, 0.0, color32(*color));
+                }
+            });
+            if let Some(texture) = self.pattern_tables.as_ref() {
+                ui.label("Pattern tables");
+                ui.add(egui::Image::new((
+                    texture.id(),
+                    texture.size_vec2() * PATTERN_TABLES_SCALE,
+                )));
+            }
+            if let Some(texture) = self.nametables.as_ref() {
+                ui.label("Nametables");
+                ui.add(egui::Image::new((texture.id(), texture.size_vec2())));
+            }
+            ui.collapsing("Sprites", |ui| {
+                ui.monospace(view.sprites_table());
+            });
+        });
+        self.panels.ppu = open;
+        self.shared.lock().unwrap().ppu_panel_open = open;
+    }
+
+    fn metrics_panel(&mut self, ctx: &egui::Context) {
+        let Some(inspection) = self.inspection.as_ref() else {
+            return;
+        };
+        egui::Window::new("Metrics")
+            .open(&mut self.panels.metrics)
+            .show(ctx, |ui| {
+                let metrics = &inspection.metrics;
+                ui.monospace(format!("Frame: {}", inspection.frame_index));
+                ui.monospace(format!("FPS: {:.1}", metrics.frames_per_second));
+                ui.monospace(format!(
+                    "Clock: {:.3} MHz (CPU {:.3} MHz)",
+                    metrics.clock_speed_mhz, metrics.cpu_clock_speed_mhz
+                ));
+                ui.monospace(format!("Dropped frames: {}", metrics.dropped_frames));
+                ui.monospace(format!("Duplicate frames: {}", metrics.duplicate_frames));
+            });
+    }
+}
+
+impl eframe::App for EguiApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.receive(ctx);
+        self.handle_keys(ctx);
+
+        self.menu_bar(ctx);
+        self.debugger_panel(ctx);
+        self.memory_panel(ctx);
+        self.ppu_panel(ctx);
+        self.metrics_panel(ctx);
+        // The screen takes the space left by the panels
+        self.screen(ctx);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Closing the window stops the emulator
+        if let Some(event_bus) = self.event_bus.as_ref() {
+            event_bus.emit(Event::SwitchOff);
+        }
+    }
+}
+
+/// Upload `image` to `texture`, creating it the first time
+fn upload(
+    ctx: &egui::Context,
+    texture: &mut Option<egui::TextureHandle>,
+    name: &str,
+    image: egui::ColorImage,
+) {
+    match texture {
+        Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+        None => *texture = Some(ctx.load_texture(name, image, egui::TextureOptions::NEAREST)),
+    }
+}
+
+fn color32(pixel: Pixel) -> egui::Color32 {
+    let channel = |value: f64| (value * 255.0).round() as u8;
+    egui::Color32::from_rgb(
+        channel(pixel.red()),
+        channel(pixel.green()),
+        channel(pixel.blue()),
+    )
+}
+
+fn color_image(image: &DebugImage) -> egui::ColorImage {
+    egui::ColorImage::from_rgb([image.width, image.height], &image.to_rgb24())
+}
+
+/// Both pattern tables in a single image
+fn side_by_side(left: &DebugImage, right: &DebugImage) -> egui::ColorImage {
+    let mut image = DebugImage::new(left.width + right.width, left.height.max(right.height));
+    for (offset, table) in [(0, left), (left.width, right)] {
+        for y in 0..table.height {
+            for x in 0..table.width {
+                image.set_pixel(offset + x, y, table.pixel(x, y));
+            }
+        }
+    }
+    color_image(&image)
+}
+
+/// Status register flags, uppercase when set
+fn flags(sr: u8) -> String {
+    "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(bit, flag)| {
+            if sr & (0x80 >> bit) != 0 {
+                flag
+            } else {
+                flag.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+fn translate_key(key: egui::Key) -> Option<Key> {
+    let key = match key {
+        egui::Key::ArrowUp => Key::Up,
+        egui::Key::ArrowDown => Key::Down,
+        egui::Key::ArrowLeft => Key::Left,
+        egui::Key::ArrowRight => Key::Right,
+        egui::Key::Enter => Key::Enter,
+        egui::Key::Space => Key::Space,
+        egui::Key::Tab => Key::Tab,
+        egui::Key::Backspace => Key::Backspace,
+        egui::Key::Escape => Key::Escape,
+        key => {
+            // Letters and digits are named after their character
+            let mut name = key.name().chars();
+            match (name.next(), name.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => Key::from_char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(key)
+}
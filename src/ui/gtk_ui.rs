@@ -2,6 +2,8 @@
 ///
 /// User Interface built on top of GTK-4 library
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -11,12 +13,18 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gdk, gio, glib, graphene};
 use gtk::{Application, ApplicationWindow, Inhibit};
-use log::debug;
+use log::{debug, warn};
 use once_cell::sync::OnceCell;
 
 use crate::events::KeyboardPublisher;
 use crate::events::SharedEventBus;
+use crate::graphics::debug_views::{DebugImage, GraphicsDebugView};
+use crate::graphics::filters::{FilteredFrame, VideoFilter};
+use crate::graphics::Pixel;
 use crate::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::keyboard::Key;
+use crate::settings::DebugWindow;
+use crate::settings::SettingsFile;
 use crate::settings::DEFAULT_PIXEL_SCALE_FACTOR;
 use crate::ui::{Frame, Ui};
 
@@ -25,9 +33,22 @@ use super::UiError;
 const APP_ID: &str = "jotare-nes-emulator";
 const APP_NAME: &str = "NES Emulator (by jotare)";
 
-static RENDER_SIGNALER: OnceCell<Arc<RwLock<RenderSignaler>>> = OnceCell::new();
+// Persisted settings keys
+const WINDOW_WIDTH_SETTING: &str = "window.width";
+const WINDOW_HEIGHT_SETTING: &str = "window.height";
+const WINDOW_FULLSCREEN_SETTING: &str = "window.fullscreen";
 
-// Used only inside GtkUi thread
+// Debug windows pixel scale factors
+const PATTERN_TABLES_SCALE: usize = 3;
+const PALETTE_SWATCH_SIZE: usize = 24;
+
+type SharedRenderSignaler = Arc<RwLock<RenderSignaler>>;
+
+// Refreshes a debug window with a new debug view
+type DebugWindowUpdate = Box<dyn Fn(&GraphicsDebugView)>;
+
+// Used only inside GtkUi thread. Every UI runs its own thread, so this is not
+// shared between UIs
 thread_local! {
     static RENDER_THREAD_STATE: OnceCell<RenderThreadState> = const { OnceCell::new() };
 }
@@ -39,11 +60,20 @@ pub struct GtkUi {
     handle: Option<JoinHandle<()>>,
     keyboard_channel: Option<KeyboardPublisher>,
     event_bus: Option<SharedEventBus>,
+    settings_file: Option<PathBuf>,
+    dropped_frames: usize,
+    render_signaler: Option<SharedRenderSignaler>,
+    title: Option<String>,
+    video_filter: Option<Box<dyn VideoFilter>>,
+    debug_windows: Vec<DebugWindow>,
 }
 
 #[derive(Debug)]
 struct RenderThreadState {
-    keyboard: Option<KeyboardPublisher>,
+    keyboard: Option<RefCell<KeyboardPublisher>>,
+    // Keys pressed by hardware keycode, so releases match their press even if
+    // modifiers changed meanwhile
+    pressed_keys: RefCell<HashMap<u32, Key>>,
     event_bus: Option<SharedEventBus>,
 }
 
@@ -54,8 +84,8 @@ impl GtkUi {
 
     /// GTK UI is based in a secondary thread that listens for a render event and renders a Frame.
     ///
-    /// Communication is done using a global variable that notifies the thread
-    /// when a new Frame can be drawn
+    /// Communication is done using a render signaler owned by the UI that
+    /// notifies the thread when a new Frame can be drawn
     ///
     /// TODO: Some internal data is hold as thread locals as the current GTK
     /// usage has some limitations on custom state. If a better way to handle
@@ -65,6 +95,9 @@ impl GtkUi {
         pixel_scale_factor: usize,
         event_bus: Option<SharedEventBus>,
         keyboard: Option<KeyboardPublisher>,
+        settings_file: Option<PathBuf>,
+        debug_windows: Vec<DebugWindow>,
+        render_signaler: SharedRenderSignaler,
     ) {
         let (screen_width, screen_height) = screen_size;
 
@@ -72,13 +105,19 @@ impl GtkUi {
         RENDER_THREAD_STATE
             .with(|cell| {
                 cell.set(RenderThreadState {
-                    keyboard,
+                    keyboard: keyboard.map(RefCell::new),
+                    pressed_keys: RefCell::new(HashMap::new()),
                     event_bus,
                 })
             })
             .expect("Unreachable error initializing render thread state");
 
-        let app = Application::builder().application_id(APP_ID).build();
+        // Non unique, so other emulators running in this process or others
+        // don't take over this window
+        let app = Application::builder()
+            .application_id(APP_ID)
+            .flags(gio::ApplicationFlags::NON_UNIQUE)
+            .build();
 
         app.connect_activate(move |app| {
             // Create main window
@@ -96,12 +135,60 @@ impl GtkUi {
                     let state = cell.get()
                         .expect("Thread local once cell should be initialized by now");
                     if let Some(ref event_bus) = state.event_bus {
-                        event_bus.access().emit(crate::events::Event::SwitchOff);
+                        event_bus.emit(crate::events::Event::SwitchOff);
                     }
                 })
             }));
             window.add_action(&quit_action);
 
+            // Fullscreen toggle. The screen is scaled by the biggest integer
+            // factor fitting in the monitor
+            let fullscreen_action = gio::SimpleAction::new("fullscreen", None);
+            fullscreen_action.connect_activate(glib::clone!(@weak window => move |_, _| {
+                if window.is_fullscreen() {
+                    window.unfullscreen();
+                } else {
+                    window.fullscreen();
+                }
+            }));
+            window.add_action(&fullscreen_action);
+
+            let menu = gio::Menu::new();
+            menu.append(Some("Fullscreen"), Some("win.fullscreen"));
+            menu.append(Some("Quit"), Some("win.quit"));
+            let menu_button = gtk::MenuButton::builder()
+                .icon_name("open-menu-symbolic")
+                .menu_model(&menu)
+                .build();
+            let header_bar = gtk::HeaderBar::new();
+            header_bar.pack_end(&menu_button);
+            window.set_titlebar(Some(&header_bar));
+
+            // Restore window geometry from the last run and save it on close
+            if let Some(ref path) = settings_file {
+                let settings = SettingsFile::load(path);
+                if let (Some(width), Some(height)) = (
+                    settings.get::<i32>(WINDOW_WIDTH_SETTING),
+                    settings.get::<i32>(WINDOW_HEIGHT_SETTING),
+                ) {
+                    window.set_default_size(width, height);
+                }
+                if settings
+                    .get::<bool>(WINDOW_FULLSCREEN_SETTING)
+                    .unwrap_or(false)
+                {
+                    window.fullscreen();
+                }
+            }
+
+            let settings_file = settings_file.clone();
+            window.connect_close_request(move |window| {
+                if let Some(ref path) = settings_file {
+                    Self::save_window_geometry(window, path);
+                }
+                Inhibit(false)
+            });
+
             // Keyboard controll so the GUI can forward key presses to the
             // controllers
             let event_controller = gtk::EventControllerKey::builder()
@@ -111,27 +198,53 @@ impl GtkUi {
             event_controller.connect_key_pressed(|event_controller, keyval, keycode, state| {
                 Self::on_key_pressed(event_controller, keyval, keycode, state)
             });
+            event_controller.connect_key_released(|event_controller, keyval, keycode, state| {
+                Self::on_key_released(event_controller, keyval, keycode, state)
+            });
             window.add_controller(event_controller);
 
             // Screen
             let paintable = NesScreen::new();
-            paintable.setup(screen_width, screen_height, pixel_scale_factor);
+            paintable.setup(
+                screen_width,
+                screen_height,
+                pixel_scale_factor,
+                render_signaler.clone(),
+            );
 
             let picture = gtk::Picture::builder()
                 .width_request((screen_width * pixel_scale_factor) as i32)
                 .height_request((screen_height * pixel_scale_factor) as i32)
-                .halign(gtk::Align::Center)
-                .valign(gtk::Align::Center)
+                .halign(gtk::Align::Fill)
+                .valign(gtk::Align::Fill)
+                .hexpand(true)
+                .vexpand(true)
                 .paintable(&paintable)
                 .build();
             window.set_child(Some(&picture));
 
-            // Signal a re-render every time we have a new frame to paint
-            picture.add_tick_callback(|area, _clock| {
-                let signaler = RENDER_SIGNALER.get().unwrap().read().unwrap();
+            let debug_window_updates: Vec<DebugWindowUpdate> = debug_windows
+                .iter()
+                .map(|kind| Self::build_debug_window(app, &window, *kind))
+                .collect();
+
+            // Signal a re-render every time we have a new frame to paint and
+            // show the title of the game being played
+            let render_signaler = render_signaler.clone();
+            let window_ref = window.downgrade();
+            picture.add_tick_callback(move |area, _clock| {
+                let mut signaler = render_signaler.write().unwrap();
                 if signaler.should_render() {
                     area.queue_draw();
                 }
+                if let Some(view) = signaler.take_debug_view() {
+                    for update in debug_window_updates.iter() {
+                        update(&view);
+                    }
+                }
+                if let (Some(title), Some(window)) = (signaler.take_title(), window_ref.upgrade()) {
+                    window.set_title(Some(&format!("{title} - {APP_NAME}")));
+                }
 
                 Continue(true)
             });
@@ -142,10 +255,116 @@ impl GtkUi {
 
         // Standard C-q to quit the GUI window
         app.set_accels_for_action("win.quit", &["<Ctrl>Q"]);
+        app.set_accels_for_action("win.fullscreen", &["F11"]);
 
         app.run();
     }
 
+    /// Create a secondary window showing a graphics debug view and return how
+    /// to refresh it. Debug windows are closed with the main window
+    fn build_debug_window(
+        app: &Application,
+        main_window: &ApplicationWindow,
+        kind: DebugWindow,
+    ) -> DebugWindowUpdate {
+        let title = match kind {
+            DebugWindow::PatternTables => "Pattern tables",
+            DebugWindow::Nametables => "Nametables",
+            DebugWindow::Palettes => "Palettes",
+            DebugWindow::Sprites => "Sprites",
+        };
+        let window = gtk::Window::builder()
+            .application(app)
+            .title(format!("{title} - {APP_NAME}"))
+            .transient_for(main_window)
+            .destroy_with_parent(true)
+            .build();
+
+        let update = match kind {
+            DebugWindow::PatternTables => Self::debug_pictures(&window, 2, |view| {
+                view.pattern_tables
+                    .iter()
+                    .map(|table| table.scaled(PATTERN_TABLES_SCALE))
+                    .collect()
+            }),
+            DebugWindow::Nametables => Self::debug_pictures(&window, 1, |view| {
+                vec![view.nametables_with_scroll(Pixel::RED)]
+            }),
+            DebugWindow::Palettes => Self::debug_pictures(&window, 1, |view| {
+                vec![view.palettes_image(PALETTE_SWATCH_SIZE)]
+            }),
+            DebugWindow::Sprites => {
+                let label = gtk::Label::builder()
+                    .xalign(0.0)
+                    .yalign(0.0)
+                    .selectable(true)
+                    .build();
+                label.add_css_class("monospace");
+                let scrolled_window = gtk::ScrolledWindow::builder()
+                    .child(&label)
+                    .min_content_width(360)
+                    .min_content_height(480)
+                    .build();
+                window.set_child(Some(&scrolled_window));
+                Box::new(move |view: &GraphicsDebugView| label.set_text(&view.sprites_table()))
+                    as DebugWindowUpdate
+            }
+        };
+
+        window.present();
+        update
+    }
+
+    /// Fill `window` with `count` pictures side by side, showing the images
+    /// produced by `images`
+    fn debug_pictures(
+        window: &gtk::Window,
+        count: usize,
+        images: impl Fn(&GraphicsDebugView) -> Vec<DebugImage> + 'static,
+    ) -> DebugWindowUpdate {
+        let container = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        let pictures: Vec<gtk::Picture> = (0..count)
+            .map(|_| {
+                let picture = gtk::Picture::builder()
+                    .hexpand(true)
+                    .vexpand(true)
+                    .can_shrink(true)
+                    .build();
+                container.append(&picture);
+                picture
+            })
+            .collect();
+        window.set_child(Some(&container));
+
+        Box::new(move |view: &GraphicsDebugView| {
+            for (picture, image) in pictures.iter().zip(images(view)) {
+                let texture = gdk::MemoryTexture::new(
+                    image.width as i32,
+                    image.height as i32,
+                    gdk::MemoryFormat::R8g8b8,
+                    &glib::Bytes::from_owned(image.to_rgb24()),
+                    image.width * 3,
+                );
+                picture.set_paintable(Some(&texture));
+            }
+        })
+    }
+
+    fn save_window_geometry(window: &ApplicationWindow, path: &Path) {
+        let mut settings = SettingsFile::load(path);
+
+        // Default size tracks the window size while not fullscreen, so we
+        // restore the windowed geometry
+        let (width, height) = window.default_size();
+        settings.set(WINDOW_WIDTH_SETTING, width);
+        settings.set(WINDOW_HEIGHT_SETTING, height);
+        settings.set(WINDOW_FULLSCREEN_SETTING, window.is_fullscreen());
+
+        if let Err(error) = settings.save() {
+            warn!("Unable to save window geometry in {path:?}: {error}");
+        }
+    }
+
     fn on_key_pressed(
         event_controller: &gtk::EventControllerKey,
         keyval: gdk::Key,
@@ -154,7 +373,7 @@ impl GtkUi {
     ) -> Inhibit {
         println!("KEY PRESSED: {keyval} {modifier_type:?}");
 
-        // We only handle lowercase and uppercase chars and ignore other combinations (C-, M-, ...)
+        // We only handle plain and shifted keys and ignore other combinations (C-, M-, ...)
         if !(modifier_type == gdk::ModifierType::empty()
             || modifier_type == gdk::ModifierType::SHIFT_MASK
             || modifier_type == gdk::ModifierType::LOCK_MASK)
@@ -162,8 +381,8 @@ impl GtkUi {
             return Inhibit(false);
         }
 
-        let character = match keyval.to_unicode() {
-            Some(c) => c,
+        let key = match Self::translate_key(keyval) {
+            Some(key) => key,
             None => return Inhibit(false),
         };
 
@@ -174,37 +393,84 @@ impl GtkUi {
 
             match state.keyboard {
                 Some(ref keyboard_publisher) => {
-                    keyboard_publisher.push_char(character);
+                    // GTK forwards auto-repeated key presses, the publisher
+                    // ignores presses of already held keys
+                    state.pressed_keys.borrow_mut().insert(keycode, key);
+                    keyboard_publisher.borrow_mut().press_key(key);
                     Inhibit(true)
                 }
                 None => Inhibit(false),
             }
         })
     }
+
+    fn on_key_released(
+        event_controller: &gtk::EventControllerKey,
+        keyval: gdk::Key,
+        keycode: u32,
+        modifier_type: gdk::ModifierType,
+    ) {
+        RENDER_THREAD_STATE.with(|cell| {
+            let state = cell
+                .get()
+                .expect("Thread local once cell should be initialized by now");
+
+            let key = state.pressed_keys.borrow_mut().remove(&keycode);
+            if let (Some(key), Some(keyboard_publisher)) = (key, &state.keyboard) {
+                keyboard_publisher.borrow_mut().release_key(key);
+            }
+        })
+    }
+
+    /// Translate a GDK key into an emulator [`Key`]
+    fn translate_key(keyval: gdk::Key) -> Option<Key> {
+        let key = match keyval {
+            gdk::Key::Up => Key::Up,
+            gdk::Key::Down => Key::Down,
+            gdk::Key::Left => Key::Left,
+            gdk::Key::Right => Key::Right,
+            gdk::Key::Return | gdk::Key::KP_Enter => Key::Enter,
+            gdk::Key::space => Key::Space,
+            gdk::Key::Tab => Key::Tab,
+            gdk::Key::BackSpace => Key::Backspace,
+            gdk::Key::Escape => Key::Escape,
+            gdk::Key::Shift_L => Key::LeftShift,
+            gdk::Key::Shift_R => Key::RightShift,
+            gdk::Key::Control_L => Key::LeftControl,
+            gdk::Key::Control_R => Key::RightControl,
+            gdk::Key::Alt_L => Key::LeftAlt,
+            gdk::Key::Alt_R => Key::RightAlt,
+            keyval => Key::from_char(keyval.to_unicode()?),
+        };
+        Some(key)
+    }
 }
 
 impl Ui for GtkUi {
-    /// Starts a GTK running GUI. It should only be called once during the whole
-    /// program. If called more than once, it'll panic.
-    ///
-    /// TODO: overcome the limitation and be able to start/stop the UI more
-    /// times
+    /// Starts a GTK running GUI. Each UI owns its state, so many of them can
+    /// exist in the same process. However, GTK only allows being used from a
+    /// single thread, so only one GTK UI can be started per process.
     fn start(&mut self) -> Result<(), UiError> {
-        let already_initialized = RENDER_SIGNALER
-            .set(Arc::new(RwLock::new(RenderSignaler::default())))
-            .is_err();
-
-        if already_initialized {
+        if self.handle.is_some() {
             return Err(UiError::AlreadyStarted(
                 "GTK UI is already started, can't start it twice".to_string(),
             ));
         }
 
+        let mut signaler = RenderSignaler::default();
+        if let Some(ref title) = self.title {
+            signaler.set_title(title);
+        }
+        let render_signaler = Arc::new(RwLock::new(signaler));
+        self.render_signaler.replace(render_signaler.clone());
+
         let screen_width = self.screen_width;
         let screen_height = self.screen_height;
         let pixel_scale_factor = self.pixel_scale_factor;
         let keyboard_channel = self.keyboard_channel.take();
         let event_bus = self.event_bus.take();
+        let settings_file = self.settings_file.clone();
+        let debug_windows = self.debug_windows.clone();
 
         let join_handle = spawn(move || {
             Self::render_thread(
@@ -212,6 +478,9 @@ impl Ui for GtkUi {
                 pixel_scale_factor,
                 event_bus,
                 keyboard_channel,
+                settings_file,
+                debug_windows,
+                render_signaler,
             )
         });
 
@@ -222,9 +491,43 @@ impl Ui for GtkUi {
 
     /// Signal the GUI to render a new frame. This will be communicated to the
     /// GTK render thread and it'll update the frame as soon as possible
-    fn render(&mut self, frame: Frame) {
-        if let Some(signaler) = RENDER_SIGNALER.get() {
-            signaler.write().unwrap().set_frame(frame);
+    fn render(&mut self, frame: Arc<Frame>) {
+        if let Some(ref signaler) = self.render_signaler {
+            // Filters run here, in the emulation thread, so the GTK thread
+            // only has to upload the result
+            let frame = match self.video_filter.as_mut() {
+                Some(filter) => ScreenFrame::Filtered(filter.apply(&frame)),
+                None => ScreenFrame::Raw(frame),
+            };
+            let replaced = signaler.write().unwrap().set_frame(frame);
+            if replaced {
+                self.dropped_frames += 1;
+            }
+        }
+    }
+
+    fn take_dropped_frames(&mut self) -> usize {
+        std::mem::take(&mut self.dropped_frames)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_string());
+        if let Some(ref signaler) = self.render_signaler {
+            signaler.write().unwrap().set_title(title);
+        }
+    }
+
+    fn set_video_filter(&mut self, filter: Option<Box<dyn VideoFilter>>) {
+        self.video_filter = filter;
+    }
+
+    fn wants_debug_view(&self) -> bool {
+        !self.debug_windows.is_empty()
+    }
+
+    fn render_debug_view(&mut self, view: Arc<GraphicsDebugView>) {
+        if let Some(ref signaler) = self.render_signaler {
+            signaler.write().unwrap().set_debug_view(view);
         }
     }
 
@@ -236,11 +539,7 @@ impl Ui for GtkUi {
         })?;
         debug!("UI thread ended correctly");
 
-        // TODO: cleanup thread local and global variables.
-        //
-        // XXX: Right now, as global variables are not mutable and we don't do
-        // anything to mutate them, we can't clean the state so a second cycle
-        // of start/stop is not possible
+        self.render_signaler = None;
 
         Ok(())
     }
@@ -252,6 +551,8 @@ pub struct GtkUiBuilder {
     pixel_scale_factor: usize,
     keyboard: Option<KeyboardPublisher>,
     event_bus: Option<SharedEventBus>,
+    settings_file: Option<PathBuf>,
+    debug_windows: Vec<DebugWindow>,
 }
 
 impl GtkUiBuilder {
@@ -262,6 +563,8 @@ impl GtkUiBuilder {
             pixel_scale_factor: DEFAULT_PIXEL_SCALE_FACTOR,
             keyboard: None,
             event_bus: None,
+            settings_file: None,
+            debug_windows: Vec::new(),
         }
     }
 
@@ -273,6 +576,12 @@ impl GtkUiBuilder {
             handle: None,
             keyboard_channel: self.keyboard,
             event_bus: self.event_bus,
+            settings_file: self.settings_file,
+            dropped_frames: 0,
+            render_signaler: None,
+            title: None,
+            video_filter: None,
+            debug_windows: self.debug_windows,
         }
     }
 
@@ -296,23 +605,68 @@ impl GtkUiBuilder {
         self.event_bus.replace(event_bus);
         self
     }
+
+    /// Persist window geometry in `path` across runs
+    pub fn with_settings_file(mut self, path: PathBuf) -> Self {
+        self.settings_file.replace(path);
+        self
+    }
+
+    /// Open a secondary window for each graphics debug view in `windows`
+    pub fn with_debug_windows(mut self, windows: &[DebugWindow]) -> Self {
+        self.debug_windows = windows.to_vec();
+        self
+    }
+}
+
+/// Frame to present, as produced by the PPU or already filtered
+enum ScreenFrame {
+    Raw(Arc<Frame>),
+    Filtered(FilteredFrame),
 }
 
 struct RenderSignaler {
-    screen_frame: Option<Frame>,
+    screen_frame: Option<ScreenFrame>,
+    title: Option<String>,
+    debug_view: Option<Arc<GraphicsDebugView>>,
 }
 
 impl RenderSignaler {
     pub fn new() -> Self {
-        Self { screen_frame: None }
+        Self {
+            screen_frame: None,
+            title: None,
+            debug_view: None,
+        }
     }
 
     pub fn should_render(&self) -> bool {
         self.screen_frame.is_some()
     }
 
-    pub fn set_frame(&mut self, frame: Frame) {
-        self.screen_frame.replace(frame);
+    /// Set the next frame to render. Returns `true` if a frame pending to be
+    /// rendered has been replaced
+    pub fn set_frame(&mut self, frame: ScreenFrame) -> bool {
+        self.screen_frame.replace(frame).is_some()
+    }
+
+    /// Set a new window title to show
+    pub fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_string());
+    }
+
+    pub fn take_title(&mut self) -> Option<String> {
+        self.title.take()
+    }
+
+    /// Set the debug view to show, replacing the previous one if it hasn't
+    /// been shown yet
+    pub fn set_debug_view(&mut self, view: Arc<GraphicsDebugView>) {
+        self.debug_view = Some(view);
+    }
+
+    pub fn take_debug_view(&mut self) -> Option<Arc<GraphicsDebugView>> {
+        self.debug_view.take()
     }
 }
 
@@ -331,8 +685,15 @@ impl NesScreen {
         glib::Object::new()
     }
 
-    fn setup(&self, width: usize, height: usize, pixel_scale_factor: usize) {
-        self.imp().setup(width, height, pixel_scale_factor);
+    fn setup(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_scale_factor: usize,
+        render_signaler: SharedRenderSignaler,
+    ) {
+        self.imp()
+            .setup(width, height, pixel_scale_factor, render_signaler);
     }
 }
 
@@ -346,6 +707,7 @@ struct PaintableScreenInner {
     width: usize,
     height: usize,
     pixel_scale_factor: usize,
+    render_signaler: Option<SharedRenderSignaler>,
 }
 
 impl Default for PaintableScreenInner {
@@ -354,6 +716,7 @@ impl Default for PaintableScreenInner {
             width: SCREEN_WIDTH,
             height: SCREEN_HEIGHT,
             pixel_scale_factor: DEFAULT_PIXEL_SCALE_FACTOR,
+            render_signaler: None,
         }
     }
 }
@@ -364,13 +727,52 @@ struct PaintableScreen {
 }
 
 impl PaintableScreen {
-    fn setup(&self, width: usize, height: usize, pixel_scale_factor: usize) {
+    fn setup(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_scale_factor: usize,
+        render_signaler: SharedRenderSignaler,
+    ) {
         *self.inner.borrow_mut() = PaintableScreenInner {
             width,
             height,
             pixel_scale_factor,
+            render_signaler: Some(render_signaler),
         }
     }
+
+    /// Upload a filtered frame as a texture, scaled to fit the available
+    /// space keeping its aspect ratio and centered
+    fn snapshot_filtered(
+        snapshot: &gdk::Snapshot,
+        filtered: FilteredFrame,
+        available_width: f64,
+        available_height: f64,
+    ) {
+        let (width, height) = (filtered.width as f64, filtered.height as f64);
+        let scale = (available_width / width).min(available_height / height);
+        let offset_x = ((available_width - width * scale) / 2.0).max(0.0);
+        let offset_y = ((available_height - height * scale) / 2.0).max(0.0);
+
+        let stride = filtered.width * 4;
+        let texture = gdk::MemoryTexture::new(
+            filtered.width as i32,
+            filtered.height as i32,
+            gdk::MemoryFormat::R8g8b8a8,
+            &glib::Bytes::from_owned(filtered.rgba),
+            stride,
+        );
+        snapshot.append_texture(
+            &texture,
+            &graphene::Rect::new(
+                offset_x as f32,
+                offset_y as f32,
+                (width * scale) as f32,
+                (height * scale) as f32,
+            ),
+        );
+    }
 }
 
 #[glib::object_subclass]
@@ -398,9 +800,18 @@ impl PaintableImpl for PaintableScreen {
         (inner.height * inner.pixel_scale_factor) as i32
     }
 
-    fn snapshot(&self, snapshot: &gdk::Snapshot, _width: f64, _height: f64) {
-        let frame = {
-            let mut writer = RENDER_SIGNALER.get().unwrap().write().unwrap();
+    fn snapshot(&self, snapshot: &gdk::Snapshot, available_width: f64, available_height: f64) {
+        let (width, height, render_signaler) = {
+            let inner = self.inner.borrow();
+            (inner.width, inner.height, inner.render_signaler.clone())
+        };
+
+        let screen_frame = {
+            let Some(render_signaler) = render_signaler else {
+                debug!("Trying to render a screen not set up");
+                return;
+            };
+            let mut writer = render_signaler.write().unwrap();
             match writer.screen_frame.take() {
                 Some(frame) => frame,
                 None => {
@@ -410,15 +821,28 @@ impl PaintableImpl for PaintableScreen {
             }
         };
 
-        let (width, height, pixel_scale_factor) = {
-            let inner = self.inner.borrow();
-            (inner.width, inner.height, inner.pixel_scale_factor)
+        let frame = match screen_frame {
+            ScreenFrame::Raw(frame) => frame,
+            ScreenFrame::Filtered(filtered) => {
+                Self::snapshot_filtered(snapshot, filtered, available_width, available_height);
+                return;
+            }
         };
+
+        // Scale pixels by the biggest integer factor fitting in the available
+        // space (bigger than the intrinsic size in fullscreen) and center them
+        let pixel_scale_factor = (available_width / width as f64)
+            .min(available_height / height as f64)
+            .floor()
+            .max(1.0);
+        let offset_x = ((available_width - width as f64 * pixel_scale_factor) / 2.0).max(0.0);
+        let offset_y = ((available_height - height as f64 * pixel_scale_factor) / 2.0).max(0.0);
+
         let context = snapshot.append_cairo(&graphene::Rect::new(
             0.0,
             0.0,
-            self.intrinsic_width() as f32,
-            self.intrinsic_height() as f32,
+            available_width as f32,
+            available_height as f32,
         ));
         let pixel_size = 0.95;
 
@@ -426,10 +850,10 @@ impl PaintableImpl for PaintableScreen {
             for (w, pixel) in row.iter().enumerate().take(width) {
                 context.set_source_rgb(pixel.red(), pixel.green(), pixel.blue());
                 context.rectangle(
-                    (w * pixel_scale_factor) as f64,
-                    (h * pixel_scale_factor) as f64,
-                    pixel_size * pixel_scale_factor as f64,
-                    pixel_size * pixel_scale_factor as f64,
+                    offset_x + w as f64 * pixel_scale_factor,
+                    offset_y + h as f64 * pixel_scale_factor,
+                    pixel_size * pixel_scale_factor,
+                    pixel_size * pixel_scale_factor,
                 );
                 context.fill().unwrap();
             }
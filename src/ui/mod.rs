@@ -2,19 +2,90 @@
 //!
 //! This module abstract different UIs to render the NES output
 
+#[cfg(feature = "egui")]
+mod egui_ui;
 mod gtk_ui;
 
+#[cfg(feature = "egui")]
+pub use egui_ui::{EguiUi, EguiUiBuilder};
 pub use gtk_ui::GtkUi;
 
+use std::sync::Arc;
+
+use crate::disassembler::DisassembledInstruction;
 use crate::errors::UiError;
-use crate::graphics::Frame;
+use crate::graphics::debug_views::GraphicsDebugView;
+use crate::graphics::filters::VideoFilter;
+use crate::graphics::ppu::PpuState;
+use crate::graphics::{Frame, PartialFrame};
+use crate::interfaces::AddressRange;
+use crate::memory_viewer::MemoryDump;
+use crate::metrics::Metrics;
+use crate::processor::cpu::CpuState;
+
+/// Emulator state after a frame, for UIs with an integrated debugger. See
+/// [`Ui::inspected_memory`]
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    pub frame_index: u64,
+    pub cpu: CpuState,
+    pub ppu: PpuState,
+    /// Next instructions the CPU will execute, from the current PC
+    pub disassembly: Vec<DisassembledInstruction>,
+    /// Snapshot of the memory range requested by the UI. Bytes aren't
+    /// compared with previous snapshots, UIs can do it with
+    /// [`MemoryDump::compare`]
+    pub memory: MemoryDump,
+    /// Last performance metrics report
+    pub metrics: Metrics,
+}
 
 pub trait Ui {
     /// Start the UI. An unstarted UI won't render
     fn start(&mut self) -> Result<(), UiError>;
 
-    /// Trigger a render of a `frame`
-    fn render(&mut self, frame: Frame);
+    /// Trigger a render of a `frame`. Frames are shared, UIs can keep them
+    /// without copying. Frames identical to the last rendered one are not
+    /// rendered again
+    fn render(&mut self, frame: Arc<Frame>);
+
+    /// Present the latest rendered rows of the next frame, with beam racing
+    /// enabled (see
+    /// [`NesSettings::beam_racing`](crate::settings::NesSettings::beam_racing)).
+    /// The complete frame is still rendered with [`Ui::render`]. The egui UI
+    /// presents them, other UIs ignore them
+    fn render_scanlines(&mut self, rows: Arc<PartialFrame>) {}
+
+    /// Return how many frames have been replaced by a newer one before being
+    /// presented since the last call
+    fn take_dropped_frames(&mut self) -> usize {
+        0
+    }
+
+    /// Show `title`, e.g., the game being played, in the UI
+    fn set_title(&mut self, title: &str) {}
+
+    /// Apply `filter` to frames before presenting them, or present them as
+    /// they are with `None`. UIs not supporting filters ignore it
+    fn set_video_filter(&mut self, filter: Option<Box<dyn VideoFilter>>) {}
+
+    /// Whether the UI shows graphics debug views. Building them is costly, so
+    /// the NES only does it for UIs wanting them
+    fn wants_debug_view(&self) -> bool {
+        false
+    }
+
+    /// Show debug views of the frame just rendered
+    fn render_debug_view(&mut self, view: Arc<GraphicsDebugView>) {}
+
+    /// Main bus range shown by the UI debugger, if it has one. The NES
+    /// inspects its state after every frame for UIs returning a range
+    fn inspected_memory(&self) -> Option<AddressRange> {
+        None
+    }
+
+    /// Show the emulator state after the frame just rendered
+    fn render_inspection(&mut self, inspection: Arc<Inspection>) {}
 
     /// Synchronously stop the UI
     fn stop(&mut self) -> Result<(), UiError>;
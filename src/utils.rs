@@ -80,10 +80,29 @@ impl From<BitGroup<u16>> for u16 {
     }
 }
 
+/// CRC-32 (IEEE) checksum, the one used by ROM databases
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
     #[test]
     fn test_bv() {
         assert_eq!(bv(0b0000_0000, 0), 0);
@@ -0,0 +1,182 @@
+//! Compatibility warnings
+//!
+//! Games may use hardware the emulator doesn't implement yet, like the APU.
+//! Instead of failing, components report a [`WarningKind`] and keep running.
+//! The NES emits them as
+//! [`Event::EmulatorWarning`](crate::events::Event::EmulatorWarning), once
+//! per kind every [`WARNING_INTERVAL_FRAMES`] at most, and keeps a summary
+//! frontends can show to the user with
+//! [`Nes::warnings`](crate::Nes::warnings).
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Minimum frames between two warnings of the same kind, about 10 seconds
+pub const WARNING_INTERVAL_FRAMES: u64 = 600;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WarningKind {
+    /// APU registers were written, but there's no APU to play sound
+    ApuNotEmulated,
+    /// Mapper expansion audio registers were written (e.g., Sunsoft 5B)
+    ExpansionAudioNotEmulated,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            WarningKind::ApuNotEmulated => "APU is not emulated, the game plays no sound",
+            WarningKind::ExpansionAudioNotEmulated => {
+                "Expansion audio is not emulated, the game plays no sound"
+            }
+        };
+        f.write_str(description)
+    }
+}
+
+/// Unsupported feature hit by the game
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EmulatorWarning {
+    pub kind: WarningKind,
+    /// Last address accessed when the feature was hit, if any
+    pub address: Option<u16>,
+    /// Times the feature was hit since the last warning of this kind
+    pub occurrences: u64,
+}
+
+impl fmt::Display for EmulatorWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(address) = self.address {
+            write!(f, " (${address:0>4X})")?;
+        }
+        write!(f, ", hit {} times", self.occurrences)
+    }
+}
+
+/// Every time a kind of warning has been hit since power-on
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WarningSummary {
+    pub kind: WarningKind,
+    /// Frame where it was first hit
+    pub first_frame: u64,
+    pub occurrences: u64,
+}
+
+/// Warnings reported by the NES components
+#[derive(Debug, Default)]
+pub struct Warnings {
+    frame: u64,
+    // Warnings not emitted yet
+    pending: BTreeMap<WarningKind, EmulatorWarning>,
+    // Frame where each kind was last emitted
+    emitted: BTreeMap<WarningKind, u64>,
+    summaries: BTreeMap<WarningKind, WarningSummary>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report `kind` has been hit, accessing `address` if any. It's cheap
+    /// enough to be called on every access
+    pub fn report(&mut self, kind: WarningKind, address: Option<u16>) {
+        let warning = self.pending.entry(kind).or_insert(EmulatorWarning {
+            kind,
+            address,
+            occurrences: 0,
+        });
+        warning.address = address;
+        warning.occurrences += 1;
+
+        let frame = self.frame;
+        self.summaries
+            .entry(kind)
+            .or_insert(WarningSummary {
+                kind,
+                first_frame: frame,
+                occurrences: 0,
+            })
+            .occurrences += 1;
+    }
+
+    /// Warnings hit since power-on, one per kind
+    pub fn summaries(&self) -> Vec<WarningSummary> {
+        self.summaries.values().cloned().collect()
+    }
+
+    /// Finish `frame` and return the warnings due, i.e., those whose kind
+    /// hasn't been emitted in the last [`WARNING_INTERVAL_FRAMES`]
+    pub(crate) fn end_frame(&mut self, frame: u64) -> Vec<EmulatorWarning> {
+        self.frame = frame + 1;
+        let due: Vec<WarningKind> = self
+            .pending
+            .keys()
+            .filter(|kind| {
+                self.emitted
+                    .get(kind)
+                    // Restoring a snapshot moves frames back
+                    .is_none_or(|emitted| frame.saturating_sub(*emitted) >= WARNING_INTERVAL_FRAMES)
+            })
+            .copied()
+            .collect();
+
+        due.into_iter()
+            .filter_map(|kind| {
+                self.emitted.insert(kind, frame);
+                self.pending.remove(&kind)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut warnings = Warnings::new();
+        warnings.report(WarningKind::ApuNotEmulated, Some(0x4000));
+        warnings.report(WarningKind::ApuNotEmulated, Some(0x4015));
+        assert_eq!(
+            warnings.end_frame(0),
+            [EmulatorWarning {
+                kind: WarningKind::ApuNotEmulated,
+                address: Some(0x4015),
+                occurrences: 2,
+            }]
+        );
+
+        // Occurrences pile up until the interval elapses
+        for frame in 1..WARNING_INTERVAL_FRAMES {
+            warnings.report(WarningKind::ApuNotEmulated, None);
+            assert!(warnings.end_frame(frame).is_empty());
+        }
+        let due = warnings.end_frame(WARNING_INTERVAL_FRAMES);
+        assert_eq!(due[0].occurrences, WARNING_INTERVAL_FRAMES - 1);
+
+        warnings.report(WarningKind::ExpansionAudioNotEmulated, None);
+        assert_eq!(warnings.end_frame(WARNING_INTERVAL_FRAMES + 1).len(), 1);
+        assert_eq!(
+            warnings.summaries()[0],
+            WarningSummary {
+                kind: WarningKind::ApuNotEmulated,
+                first_frame: 0,
+                occurrences: WARNING_INTERVAL_FRAMES + 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_frames_rewound() {
+        let mut warnings = Warnings::new();
+        warnings.report(WarningKind::ApuNotEmulated, None);
+        assert_eq!(warnings.end_frame(700).len(), 1);
+
+        warnings.report(WarningKind::ApuNotEmulated, None);
+        assert!(warnings.end_frame(3).is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+//! Runaway emulation watchdog
+//!
+//! Games that crash, or that wait for hardware the emulator doesn't support,
+//! usually end up spinning forever in a tight loop. The watchdog detects
+//! frames where the CPU only executed a few bytes of code without touching
+//! PPU, APU or I/O registers. After enough consecutive frames like this, it
+//! reports the loop with a trace of the last executed instructions, which is
+//! most of what is needed to report a compatibility bug.
+//!
+//! Games waiting for NMI in a `JMP *` loop don't trigger it, as their NMI
+//! handler runs outside of the loop and accesses the PPU.
+
+use std::collections::VecDeque;
+
+use crate::processor::cpu::CpuState;
+use crate::settings::WatchdogSettings;
+
+/// Diagnostic of a runaway loop
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct WatchdogReport {
+    /// First and last instruction address of the loop
+    pub loop_start: u16,
+    pub loop_end: u16,
+
+    /// Consecutive frames spent in the loop
+    pub frames: u64,
+
+    /// CPU state before each of the last executed instructions, oldest first
+    pub trace: Vec<CpuState>,
+}
+
+pub struct Watchdog {
+    settings: WatchdogSettings,
+    trace: VecDeque<CpuState>,
+    // Lowest and highest instruction address executed during the frame
+    pc_range: Option<(u16, u16)>,
+    stuck_frames: u64,
+}
+
+impl Watchdog {
+    /// Instructions kept in the trace
+    pub const TRACE_LENGTH: usize = 64;
+
+    pub fn new(settings: WatchdogSettings) -> Self {
+        Self {
+            settings,
+            trace: VecDeque::with_capacity(Self::TRACE_LENGTH),
+            pc_range: None,
+            stuck_frames: 0,
+        }
+    }
+
+    /// The CPU is about to execute an instruction at `state.pc`
+    pub fn observe_instruction(&mut self, state: CpuState) {
+        if self.trace.len() == Self::TRACE_LENGTH {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(state);
+
+        let pc = state.pc;
+        self.pc_range = Some(match self.pc_range {
+            Some((start, end)) => (start.min(pc), end.max(pc)),
+            None => (pc, pc),
+        });
+    }
+
+    /// A frame has been completed. `hardware_accessed` tells whether the CPU
+    /// accessed PPU, APU or I/O registers during it. Returns a report the
+    /// frame the loop is detected, further frames in the same loop don't
+    /// report it again
+    pub fn end_frame(&mut self, hardware_accessed: bool) -> Option<WatchdogReport> {
+        let Some((loop_start, loop_end)) = self.pc_range.take() else {
+            // No instructions executed, e.g., CPU halted by DMA
+            return None;
+        };
+
+        let small_loop = loop_end - loop_start < self.settings.max_loop_size;
+        if hardware_accessed || !small_loop {
+            self.stuck_frames = 0;
+            return None;
+        }
+
+        self.stuck_frames += 1;
+        if self.stuck_frames != self.settings.frames {
+            return None;
+        }
+
+        Some(WatchdogReport {
+            loop_start,
+            loop_end,
+            frames: self.stuck_frames,
+            trace: self.trace.iter().copied().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frame(
+        watchdog: &mut Watchdog,
+        pcs: &[u16],
+        hardware_accessed: bool,
+    ) -> Option<WatchdogReport> {
+        for &pc in pcs {
+            watchdog.observe_instruction(CpuState {
+                pc,
+                ..Default::default()
+            });
+        }
+        watchdog.end_frame(hardware_accessed)
+    }
+
+    #[test]
+    fn test_watchdog_reports_tight_loop_once() {
+        let mut watchdog = Watchdog::new(WatchdogSettings {
+            frames: 3,
+            max_loop_size: 16,
+        });
+        let tight_loop = [0x8010, 0x8012, 0x8014].repeat(100);
+
+        assert_eq!(run_frame(&mut watchdog, &tight_loop, false), None);
+        assert_eq!(run_frame(&mut watchdog, &tight_loop, false), None);
+        let report = run_frame(&mut watchdog, &tight_loop, false).unwrap();
+        assert_eq!((report.loop_start, report.loop_end), (0x8010, 0x8014));
+        assert_eq!(report.frames, 3);
+        assert_eq!(report.trace.len(), Watchdog::TRACE_LENGTH);
+        assert_eq!(report.trace.last().unwrap().pc, 0x8014);
+
+        assert_eq!(run_frame(&mut watchdog, &tight_loop, false), None);
+    }
+
+    #[test]
+    fn test_watchdog_ignores_active_loops() {
+        let mut watchdog = Watchdog::new(WatchdogSettings {
+            frames: 1,
+            max_loop_size: 16,
+        });
+
+        // Polling the PPU
+        assert_eq!(run_frame(&mut watchdog, &[0x8000, 0x8003], true), None);
+        // Waiting for NMI, with the handler far away from the loop
+        assert_eq!(run_frame(&mut watchdog, &[0x8000, 0xC000], false), None);
+    }
+}
@@ -0,0 +1,785 @@
+//! End-to-end golden frame tests
+//!
+//! Test ROMs are built on the fly from hand assembled public-domain programs,
+//! so no ROM needs to be distributed with the repository.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nes_emulator::capture::{BurstSettings, CaptureFormat};
+use nes_emulator::coverage::Access;
+use nes_emulator::errors::{MovieError, StateError, UiError};
+use nes_emulator::events::Event;
+use nes_emulator::graphics::{Frame, Pixel};
+use nes_emulator::hardware::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_emulator::input_script::{InputCommand, InputScript};
+use nes_emulator::interfaces::Bus;
+use nes_emulator::movie::Movie;
+use nes_emulator::settings::{ClockGranularity, CpuSpeed, NesSettings, UiKind};
+use nes_emulator::snapshot::{StateMetadata, MIN_STATE_VERSION, STATE_VERSION};
+use nes_emulator::testing::{
+    assert_frame_hash, check_scroll_split, frame_hash, ines_image, run_headless,
+    scroll_split_cartidge,
+};
+use nes_emulator::ui::Ui;
+use nes_emulator::{Cartidge, Controller, ControllerButtons, ControllerState, Nes};
+
+const PALETTE_ADDRESS: usize = 0x0100;
+
+// Waits two vertical blanks, loads the palettes, fills the first nametable
+// with tiles 0 to 3 and enables background rendering
+#[rustfmt::skip]
+const CHECKERBOARD_PROGRAM: [u8; 84] = [
+    0x78,                   // $8000  SEI
+    0xD8,                   // $8001  CLD
+    0xA2, 0xFF,             // $8002  LDX #$FF
+    0x9A,                   // $8004  TXS
+    0x2C, 0x02, 0x20,       // $8005  BIT $2002
+    0x10, 0xFB,             // $8008  BPL $8005
+    0x2C, 0x02, 0x20,       // $800A  BIT $2002
+    0x10, 0xFB,             // $800D  BPL $800A
+    0xA9, 0x3F,             // $800F  LDA #$3F
+    0x8D, 0x06, 0x20,       // $8011  STA $2006
+    0xA9, 0x00,             // $8014  LDA #$00
+    0x8D, 0x06, 0x20,       // $8016  STA $2006
+    0xA2, 0x00,             // $8019  LDX #$00
+    0xBD, 0x00, 0x81,       // $801B  LDA $8100,X
+    0x8D, 0x07, 0x20,       // $801E  STA $2007
+    0xE8,                   // $8021  INX
+    0xE0, 0x20,             // $8022  CPX #$20
+    0xD0, 0xF5,             // $8024  BNE $801B
+    0xA9, 0x20,             // $8026  LDA #$20
+    0x8D, 0x06, 0x20,       // $8028  STA $2006
+    0xA9, 0x00,             // $802B  LDA #$00
+    0x8D, 0x06, 0x20,       // $802D  STA $2006
+    0xA2, 0x00,             // $8030  LDX #$00
+    0xA0, 0x04,             // $8032  LDY #$04
+    0x8A,                   // $8034  TXA
+    0x29, 0x03,             // $8035  AND #$03
+    0x8D, 0x07, 0x20,       // $8037  STA $2007
+    0xE8,                   // $803A  INX
+    0xD0, 0xF7,             // $803B  BNE $8034
+    0x88,                   // $803D  DEY
+    0xD0, 0xF4,             // $803E  BNE $8034
+    0xA9, 0x00,             // $8040  LDA #$00
+    0x8D, 0x05, 0x20,       // $8042  STA $2005
+    0x8D, 0x05, 0x20,       // $8045  STA $2005
+    0x8D, 0x00, 0x20,       // $8048  STA $2000
+    0xA9, 0x0A,             // $804B  LDA #$0A
+    0x8D, 0x01, 0x20,       // $804D  STA $2001
+    0x4C, 0x50, 0x80,       // $8050  JMP $8050
+    0x40,                   // $8053  RTI
+];
+
+const PALETTES: [u8; 32] = [
+    0x0F, 0x16, 0x2A, 0x12, 0x0F, 0x27, 0x30, 0x1A, 0x0F, 0x11, 0x21, 0x31, 0x0F, 0x06, 0x15, 0x36,
+    0x0F, 0x16, 0x2A, 0x12, 0x0F, 0x27, 0x30, 0x1A, 0x0F, 0x11, 0x21, 0x31, 0x0F, 0x06, 0x15, 0x36,
+];
+
+// Golden hash of the checkerboard program frame. If rendering changes on
+// purpose, review the new picture and update it
+const CHECKERBOARD_GOLDEN_HASH: u64 = 0xEDFF_3266_6D6B_4325;
+
+const SPRITES_ADDRESS: usize = 0x0120;
+
+// Waits two vertical blanks, loads the palettes and sprites and enables sprite
+// rendering only
+#[rustfmt::skip]
+const SPRITES_ONLY_PROGRAM: [u8; 68] = [
+    0x78,                   // $8000  SEI
+    0xD8,                   // $8001  CLD
+    0xA2, 0xFF,             // $8002  LDX #$FF
+    0x9A,                   // $8004  TXS
+    0x2C, 0x02, 0x20,       // $8005  BIT $2002
+    0x10, 0xFB,             // $8008  BPL $8005
+    0x2C, 0x02, 0x20,       // $800A  BIT $2002
+    0x10, 0xFB,             // $800D  BPL $800A
+    0xA9, 0x3F,             // $800F  LDA #$3F
+    0x8D, 0x06, 0x20,       // $8011  STA $2006
+    0xA9, 0x00,             // $8014  LDA #$00
+    0x8D, 0x06, 0x20,       // $8016  STA $2006
+    0xA2, 0x00,             // $8019  LDX #$00
+    0xBD, 0x00, 0x81,       // $801B  LDA $8100,X
+    0x8D, 0x07, 0x20,       // $801E  STA $2007
+    0xE8,                   // $8021  INX
+    0xE0, 0x20,             // $8022  CPX #$20
+    0xD0, 0xF5,             // $8024  BNE $801B
+    0xA9, 0x00,             // $8026  LDA #$00
+    0x8D, 0x03, 0x20,       // $8028  STA $2003
+    0xA2, 0x00,             // $802B  LDX #$00
+    0xBD, 0x20, 0x81,       // $802D  LDA $8120,X
+    0x8D, 0x04, 0x20,       // $8030  STA $2004
+    0xE8,                   // $8033  INX
+    0xD0, 0xF7,             // $8034  BNE $802D
+    0xA9, 0x00,             // $8036  LDA #$00
+    0x8D, 0x00, 0x20,       // $8038  STA $2000
+    0xA9, 0x10,             // $803B  LDA #$10
+    0x8D, 0x01, 0x20,       // $803D  STA $2001
+    0x4C, 0x40, 0x80,       // $8040  JMP $8040
+    0x40,                   // $8043  RTI
+];
+
+// Solid sprite (tile 1) drawn with the first sprite palette at (48, 33)
+const SPRITE_X: usize = 0x30;
+const SPRITE_Y: usize = 0x20;
+
+// Golden hash of the sprites only program frame
+const SPRITES_ONLY_GOLDEN_HASH: u64 = 0xAD5B_EC36_1851_6A25;
+
+fn checkerboard_cartidge() -> Cartidge {
+    let mut prg = vec![0; 16 * 1024];
+    prg[..CHECKERBOARD_PROGRAM.len()].copy_from_slice(&CHECKERBOARD_PROGRAM);
+    prg[PALETTE_ADDRESS..PALETTE_ADDRESS + PALETTES.len()].copy_from_slice(&PALETTES);
+
+    // NMI, reset and IRQ vectors
+    prg[0x3FFA..].copy_from_slice(&[0x53, 0x80, 0x00, 0x80, 0x53, 0x80]);
+
+    // Tiles 0 to 3: empty, solid, checkerboard and stripes
+    let mut chr = vec![0; 8 * 1024];
+    let tiles: [([u8; 8], [u8; 8]); 4] = [
+        ([0x00; 8], [0x00; 8]),
+        ([0xFF; 8], [0x00; 8]),
+        ([0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55], [0xF0; 8]),
+        ([0x0F; 8], [0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00]),
+    ];
+    for (tile, (low, high)) in tiles.iter().enumerate() {
+        chr[tile * 16..tile * 16 + 8].copy_from_slice(low);
+        chr[tile * 16 + 8..tile * 16 + 16].copy_from_slice(high);
+    }
+
+    Cartidge::from_bytes("checkerboard.nes", &ines_image(0, false, &prg, &chr))
+}
+
+fn sprites_only_cartidge() -> Cartidge {
+    let mut prg = vec![0; 16 * 1024];
+    prg[..SPRITES_ONLY_PROGRAM.len()].copy_from_slice(&SPRITES_ONLY_PROGRAM);
+    prg[PALETTE_ADDRESS..PALETTE_ADDRESS + PALETTES.len()].copy_from_slice(&PALETTES);
+
+    // Only the first sprite is on screen, the rest are hidden below it
+    prg[SPRITES_ADDRESS..SPRITES_ADDRESS + 256].fill(0xFF);
+    prg[SPRITES_ADDRESS..SPRITES_ADDRESS + 4].copy_from_slice(&[
+        SPRITE_Y as u8,
+        0x01,
+        0x00,
+        SPRITE_X as u8,
+    ]);
+
+    // NMI, reset and IRQ vectors
+    prg[0x3FFA..].copy_from_slice(&[0x43, 0x80, 0x00, 0x80, 0x43, 0x80]);
+
+    // Tile 1: solid
+    let mut chr = vec![0; 8 * 1024];
+    chr[16..24].fill(0xFF);
+
+    Cartidge::from_bytes("sprites-only.nes", &ines_image(0, false, &prg, &chr))
+}
+
+#[test]
+fn test_checkerboard_golden_frame() {
+    assert_frame_hash(checkerboard_cartidge(), 5, CHECKERBOARD_GOLDEN_HASH);
+}
+
+#[test]
+fn test_sprites_only_golden_frame() {
+    let frame = run_headless(sprites_only_cartidge(), 5);
+
+    // Sprites are drawn one scanline below their Y coordinate
+    let indices = frame.indices().unwrap();
+    for row in 0..SCREEN_HEIGHT {
+        for col in 0..SCREEN_WIDTH {
+            let sprite = (SPRITE_Y + 1..SPRITE_Y + 9).contains(&row)
+                && (SPRITE_X..SPRITE_X + 8).contains(&col);
+            let expected = if sprite { PALETTES[0x11] } else { PALETTES[0] };
+            assert_eq!(
+                indices[row * SCREEN_WIDTH + col],
+                expected,
+                "Pixel ({row}, {col})"
+            );
+        }
+    }
+
+    assert_frame_hash(sprites_only_cartidge(), 5, SPRITES_ONLY_GOLDEN_HASH);
+}
+
+#[test]
+fn test_headless_run_is_deterministic() {
+    let first = run_headless(checkerboard_cartidge(), 5);
+    let second = run_headless(checkerboard_cartidge(), 5);
+    assert_eq!(frame_hash(&first), frame_hash(&second));
+}
+
+#[test]
+fn test_frame_info() {
+    let frame = run_headless(checkerboard_cartidge(), 3);
+
+    assert_eq!(frame.info.index, 2);
+    assert!(!frame.info.odd);
+    // NTSC frames last ~16.64 ms
+    let frame_time_ns = frame.info.emulated_time_ns / 3;
+    assert!((16_600_000..16_700_000).contains(&frame_time_ns));
+}
+
+#[test]
+fn test_sprite_zero_scroll_split() {
+    let frame = run_headless(scroll_split_cartidge(), 5);
+    check_scroll_split(&frame).unwrap();
+}
+
+#[test]
+fn test_clock_granularities_are_equivalent() {
+    let run = |clock_granularity| {
+        let mut nes = Nes::new(NesSettings {
+            ui_kind: UiKind::None,
+            cpu_ppu_alignment: 2,
+            clock_granularity,
+            ..Default::default()
+        });
+        nes.load_cartidge(scroll_split_cartidge());
+        nes.run_frames(3).unwrap();
+        (frame_hash(nes.last_frame().unwrap()), nes.cpu_state())
+    };
+
+    assert_eq!(run(ClockGranularity::Dot), run(ClockGranularity::CpuCycle));
+}
+
+#[test]
+fn test_catch_up_scheduler() {
+    let run = |cartidge: fn() -> Cartidge, beam_racing, clock_granularity| {
+        let mut nes = Nes::new(NesSettings {
+            ui_kind: UiKind::None,
+            cpu_ppu_alignment: 1,
+            beam_racing,
+            clock_granularity,
+            ..Default::default()
+        });
+        nes.load_cartidge(cartidge());
+        let mut scanlines = Vec::new();
+        let mut hashes = Vec::new();
+        for _ in 0..4 {
+            nes.run_frames(1).unwrap();
+            hashes.push(frame_hash(nes.last_frame().unwrap()));
+            scanlines.push(nes.ppu_dot());
+        }
+        (hashes, scanlines, nes.cpu_state(), nes.cpu_cycles())
+    };
+
+    // The PPU catching up in batches renders and interrupts the CPU the same
+    for cartidge in [checkerboard_cartidge, scroll_split_cartidge] {
+        for beam_racing in [None, Some(16)] {
+            assert_eq!(
+                run(cartidge, beam_racing, ClockGranularity::CpuCycle),
+                run(cartidge, beam_racing, ClockGranularity::CatchUp)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(scroll_split_cartidge())
+        .build();
+    nes.run_frames(2).unwrap();
+    let snapshot = nes.snapshot();
+
+    nes.run_frames(3).unwrap();
+    let expected = (frame_hash(nes.last_frame().unwrap()), nes.cpu_state());
+    nes.main_bus.borrow_mut().write(0x0010, 0xAB);
+
+    for _ in 0..2 {
+        nes.restore(&snapshot.clone()).unwrap();
+        assert_eq!(nes.frame_count(), snapshot.frame_count());
+        assert_eq!(nes.main_bus.borrow().read(0x0010), 0);
+
+        nes.run_frames(3).unwrap();
+        let actual = (frame_hash(nes.last_frame().unwrap()), nes.cpu_state());
+        assert_eq!(actual, expected);
+    }
+
+    nes.load_cartidge(checkerboard_cartidge());
+    assert!(nes.restore(&snapshot).is_err());
+
+    // Cartidges are matched by contents, not by name
+    let image = ines_image(0, false, &[0xEA], &[]);
+    nes.load_cartidge(Cartidge::from_bytes("scroll-split.nes", &image));
+    assert!(nes.restore(&snapshot).is_err());
+}
+
+#[test]
+fn test_session_resume() {
+    let directory = std::env::temp_dir().join(format!("nes-sessions-{}", std::process::id()));
+    let settings = || NesSettings {
+        ui_kind: UiKind::None,
+        session_directory: Some(directory.clone()),
+        ..Default::default()
+    };
+
+    let mut nes = Nes::new(settings());
+    nes.load_cartidge(scroll_split_cartidge());
+    nes.run_frames(2).unwrap();
+    nes.event_bus().emit(Event::SwitchOff);
+    nes.run_frames(3).unwrap();
+    let expected = (frame_hash(nes.last_frame().unwrap()), nes.cpu_state());
+
+    let mut resumed = Nes::new(settings());
+    resumed.load_cartidge(scroll_split_cartidge());
+    assert_eq!(resumed.frame_count(), 2);
+    resumed.run_frames(3).unwrap();
+    let actual = (
+        frame_hash(resumed.last_frame().unwrap()),
+        resumed.cpu_state(),
+    );
+    assert_eq!(actual, expected);
+
+    // Other ROMs start from scratch
+    let mut other = Nes::new(settings());
+    other.load_cartidge(checkerboard_cartidge());
+    assert_eq!(other.frame_count(), 0);
+
+    let state = nes.snapshot().to_bytes();
+    assert!(other.load_snapshot(&state).is_err());
+    assert!(nes.load_snapshot(&state[..state.len() - 1]).is_err());
+    assert!(nes.load_snapshot(&state).is_ok());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_saved_state_versions() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(scroll_split_cartidge())
+        .build();
+    nes.run_frames(2).unwrap();
+    let snapshot = nes.snapshot();
+    nes.run_frames(3).unwrap();
+    let expected = (frame_hash(nes.last_frame().unwrap()), nes.cpu_state());
+
+    // States of older versions are migrated when loaded
+    for version in MIN_STATE_VERSION..=STATE_VERSION {
+        let state = snapshot.to_bytes_version(version).unwrap();
+        let metadata = StateMetadata::from_bytes(&state).unwrap();
+        assert_eq!(metadata.version, version);
+        assert_eq!(metadata.rom_checksum, snapshot.cartidge_checksum());
+        assert_eq!(metadata.crate_version.is_some(), version >= 5);
+
+        let loaded = nes.load_snapshot(&state).unwrap();
+        assert_eq!(loaded.metadata(), &metadata);
+        nes.restore(&loaded).unwrap();
+        nes.run_frames(3).unwrap();
+        let actual = (frame_hash(nes.last_frame().unwrap()), nes.cpu_state());
+        assert_eq!(actual, expected, "state version {version}");
+    }
+
+    // Timestamps are saved to the millisecond
+    let taken = snapshot.metadata().timestamp.unwrap();
+    let saved = StateMetadata::from_bytes(&snapshot.to_bytes()).unwrap();
+    let elapsed = taken.duration_since(saved.timestamp.unwrap()).unwrap();
+    assert!(elapsed < Duration::from_millis(1));
+    assert!(snapshot.to_bytes_version(STATE_VERSION + 1).is_err());
+
+    // States saved by other crate versions keep their version when saved again
+    let version = env!("CARGO_PKG_VERSION");
+    let older = "0".repeat(version.len());
+    let mut bytes = snapshot.to_bytes();
+    let start = bytes
+        .windows(version.len())
+        .position(|window| window == version.as_bytes())
+        .unwrap();
+    bytes[start..start + version.len()].copy_from_slice(older.as_bytes());
+    let resaved = nes.load_snapshot(&bytes).unwrap().to_bytes();
+    assert_eq!(
+        StateMetadata::from_bytes(&resaved).unwrap().crate_version,
+        Some(older)
+    );
+
+    let other = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    assert!(matches!(
+        other.load_snapshot(&snapshot.to_bytes()),
+        Err(StateError::CartidgeMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_screenshot_burst() {
+    let directory = std::env::temp_dir().join(format!("nes-burst-{}", std::process::id()));
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    nes.run_frames(2).unwrap();
+    nes.start_screenshot_burst(BurstSettings {
+        directory: directory.clone(),
+        interval: 2,
+        duration: 5,
+        format: CaptureFormat::Png,
+    })
+    .unwrap();
+    nes.run_frames(8).unwrap();
+
+    // Frames 2, 4 and 6 are captured in the 5 frames the burst lasts
+    let burst = nes.stop_screenshot_burst().unwrap();
+    assert!(burst.is_finished());
+    let mut files: Vec<String> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "frame_00000002.png",
+            "frame_00000004.png",
+            "frame_00000006.png"
+        ]
+    );
+    assert_eq!(burst.captured()[0], directory.join(&files[0]));
+    let png = std::fs::read(&burst.captured()[2]).unwrap();
+    assert_eq!(&png[1..4], b"PNG");
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_concurrent_instances() {
+    let expected = frame_hash(&run_headless(checkerboard_cartidge(), 5));
+
+    let mut first = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    let mut second = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    for _ in 0..5 {
+        first.run_frames(1).unwrap();
+        second.run_frames(1).unwrap();
+    }
+
+    assert_eq!(frame_hash(first.last_frame().unwrap()), expected);
+    assert_eq!(frame_hash(second.last_frame().unwrap()), expected);
+
+    let threads: Vec<_> = (0..2)
+        .map(|_| std::thread::spawn(|| frame_hash(&run_headless(checkerboard_cartidge(), 5))))
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_exec_hook() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    let executed = Rc::new(RefCell::new(Vec::new()));
+    let recorder = Rc::clone(&executed);
+    nes.set_exec_hook(move |state, instruction| {
+        recorder.borrow_mut().push((instruction.name, state.pc));
+    });
+    nes.run_frames(3).unwrap();
+
+    // The program ends spinning in a JMP to itself
+    let executed = executed.borrow();
+    assert_eq!(executed[0], ("SEI", 0x8001));
+    assert_eq!(*executed.last().unwrap(), ("JMP", 0x8050));
+
+    let count = executed.len();
+    nes.clear_exec_hook();
+    nes.run_frames(1).unwrap();
+    assert_eq!(executed.len(), count);
+}
+
+#[test]
+fn test_coverage() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    nes.start_coverage();
+    nes.run_frames(3).unwrap();
+
+    let coverage = nes.stop_coverage().unwrap();
+    assert_eq!(coverage.len(), 16 * 1024);
+    // The whole program has run, except the RTI, and has read the palettes
+    for offset in 0..0x53 {
+        assert_eq!(
+            coverage.accesses(offset),
+            Access::EXECUTE,
+            "offset {offset:X}"
+        );
+    }
+    assert_eq!(coverage.accesses(0x53), Access::empty());
+    assert_eq!(coverage.accesses(PALETTE_ADDRESS), Access::READ);
+    assert_eq!(coverage.accesses(PALETTE_ADDRESS + 0x20), Access::empty());
+}
+
+#[test]
+fn test_timing_counters() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    assert_eq!(nes.cpu_cycles(), 0);
+    assert_eq!(nes.ppu_dot(), (0, 0, 0));
+
+    nes.run_frames(3).unwrap();
+    let (scanline, cycle, frame) = nes.ppu_dot();
+    assert_eq!((scanline, frame), (0, 3));
+    // 3 PPU dots per CPU cycle, give or take the CPU/PPU alignment and the
+    // dot skipped on odd frames
+    let dots = 3 * 341 * 262 + cycle as u64;
+    assert!(dots.abs_diff(nes.cpu_cycles() * 3) <= 4);
+
+    // The program ends spinning in a 3 cycles JMP
+    nes.step_instruction().unwrap();
+    let cycles = nes.cpu_cycles();
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu_cycles() - cycles, 3);
+}
+
+#[test]
+fn test_beam_racing() {
+    let mut nes = Nes::new(NesSettings {
+        ui_kind: UiKind::None,
+        beam_racing: Some(64),
+        ..Default::default()
+    });
+    nes.load_cartidge(checkerboard_cartidge());
+
+    let partials = Rc::new(RefCell::new(Vec::new()));
+    let hook_partials = Rc::clone(&partials);
+    nes.set_scanlines_hook(move |partial| hook_partials.borrow_mut().push(partial.clone()));
+    nes.run_frames(3).unwrap();
+
+    let partials = partials.borrow();
+    let last: Vec<_> = partials
+        .iter()
+        .filter(|partial| partial.frame_index == 2)
+        .collect();
+    let rows: Vec<_> = last.iter().map(|partial| partial.rows.clone()).collect();
+    assert_eq!(rows, [0..64, 64..128, 128..192, 192..240]);
+
+    // Rows are the same the complete frame has
+    let rgb = |pixel: &Pixel| (pixel.red(), pixel.green(), pixel.blue());
+    let frame = nes.last_frame().unwrap();
+    for partial in last {
+        for (row, pixels) in partial.rows.clone().zip(&partial.pixels) {
+            assert!(pixels.iter().map(rgb).eq(frame[row].iter().map(rgb)));
+        }
+    }
+}
+
+#[test]
+fn test_warnings_after_rewind() {
+    // LDA #$00, STA $4000, JMP $8000
+    let mut prg = vec![0xA9, 0x00, 0x8D, 0x00, 0x40, 0x4C, 0x00, 0x80];
+    prg.resize(0x4000, 0);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(Cartidge::from_bytes(
+            "apu.nes",
+            &ines_image(0, false, &prg, &[0; 8 * 1024]),
+        ))
+        .build();
+    nes.run_frames(2).unwrap();
+    let snapshot = nes.snapshot();
+    nes.run_frames(700).unwrap();
+
+    // Warnings were emitted after the frame the NES goes back to
+    nes.restore(&snapshot).unwrap();
+    nes.run_frames(3).unwrap();
+    assert_eq!(nes.warnings().len(), 1);
+}
+
+#[test]
+fn test_oam_dma_events() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(scroll_split_cartidge())
+        .build();
+    let events = nes.event_bus().subscribe();
+    nes.run_frames(3).unwrap();
+
+    let transfers: Vec<_> = events
+        .drain()
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::OamDmaStarted(transfer) | Event::OamDmaFinished(transfer) => Some(transfer),
+            _ => None,
+        })
+        .collect();
+    let [started, finished] = transfers[..] else {
+        panic!("Expected a single OAM DMA, got {transfers:?}");
+    };
+    assert_eq!((started.page, finished.page), (0x02, 0x02));
+    // 256 reads and writes after 1 or 2 alignment cycles
+    let alignment_cycles = nes.dma_state().alignment_cycles as u64;
+    assert_eq!(
+        finished.cpu_cycle - started.cpu_cycle + 1,
+        512 + alignment_cycles
+    );
+    assert!(!nes.dma_state().oam_active);
+}
+
+// Keeps the frames it's asked to render
+struct RecordingUi {
+    frames: Rc<RefCell<Vec<Arc<Frame>>>>,
+}
+
+impl Ui for RecordingUi {
+    fn start(&mut self) -> Result<(), UiError> {
+        Ok(())
+    }
+
+    fn render(&mut self, frame: Arc<Frame>) {
+        self.frames.borrow_mut().push(frame);
+    }
+
+    fn stop(&mut self) -> Result<(), UiError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_duplicate_frames_skipped() {
+    let rendered = Rc::new(RefCell::new(Vec::new()));
+    let mut nes = Nes::builder()
+        .with_custom_ui(RecordingUi {
+            frames: Rc::clone(&rendered),
+        })
+        .with_cartidge(checkerboard_cartidge())
+        .build();
+    nes.run_frames(10).unwrap();
+
+    // Frames change while the program draws the checkerboard, which is static
+    // from then on
+    let hashes: Vec<u64> = rendered
+        .borrow()
+        .iter()
+        .map(|frame| frame_hash(frame))
+        .collect();
+    assert_eq!(hashes.len(), 4);
+    assert_eq!(hashes.last(), Some(&CHECKERBOARD_GOLDEN_HASH));
+    assert!(hashes.windows(2).all(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn test_cpu_speed_override() {
+    let run = |cpu_speed, clock_granularity| {
+        let mut nes = Nes::new(NesSettings {
+            ui_kind: UiKind::None,
+            cpu_speed,
+            clock_granularity,
+            ..Default::default()
+        });
+        nes.load_cartidge(checkerboard_cartidge());
+        nes.run_frames(5).unwrap();
+        (
+            frame_hash(nes.last_frame().unwrap()),
+            nes.ppu_dot(),
+            nes.cpu_cycles(),
+        )
+    };
+
+    let (authentic_hash, (scanline, _, frame), authentic_cycles) =
+        run(CpuSpeed::Authentic, ClockGranularity::CpuCycle);
+    for cpu_speed in [CpuSpeed::Overclocked(4), CpuSpeed::SystemClockDivider(10)] {
+        // A step of CPU cycle granularity stops after a single CPU cycle, even
+        // if more are due before the next PPU dot
+        let (hash, dot, cycles) = run(cpu_speed, ClockGranularity::Dot);
+        let (cpu_cycle_hash, cpu_cycle_dot, cpu_cycle_cycles) =
+            run(cpu_speed, ClockGranularity::CpuCycle);
+        assert_eq!((hash, dot), (cpu_cycle_hash, cpu_cycle_dot));
+        assert!(cycles - cpu_cycle_cycles < 4);
+
+        // Video timing is kept while the CPU runs more cycles per frame
+        assert_eq!((hash, dot.0, dot.2), (authentic_hash, scanline, frame));
+        let divider = cpu_speed.clock_divider().unwrap();
+        assert!(cycles.abs_diff(authentic_cycles * 12 / divider) <= 12);
+    }
+}
+
+#[test]
+fn test_input_script_ports() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_controllers(ControllerButtons::default(), None)
+        .with_cartidge(scroll_split_cartidge())
+        .build();
+    let mut script = InputScript::new();
+    for line in ["P1 A 2", "P2 B 2"] {
+        script.push(InputCommand::parse(line).unwrap().unwrap());
+    }
+    nes.play_input_script(script);
+    let read_buttons = |nes: &Nes| {
+        nes.main_bus.borrow_mut().write(0x4016, 1);
+        nes.main_bus.borrow_mut().write(0x4016, 0);
+        let data = nes.main_bus.borrow().read(0x4016);
+        data
+    };
+    assert_eq!(read_buttons(&nes), 0x41);
+
+    // Controller one is back to the keyboard and two is still disconnected
+    nes.run_frames(3).unwrap();
+    assert!(nes.input_script().unwrap().is_finished());
+    assert_eq!(read_buttons(&nes), 0x40);
+    assert!(nes
+        .input_device::<Controller>(1)
+        .is_none_or(|controller| !controller.is_connected()));
+}
+
+#[test]
+fn test_movie_hard_resets() {
+    let mut nes = Nes::builder()
+        .with_ui(UiKind::None)
+        .with_cartidge(scroll_split_cartidge())
+        .build();
+
+    // Recorded from power-up
+    let movie = Movie::from_fm2("|2|........|........||\n|0|....T...|........||\n").unwrap();
+    nes.play_movie(&movie).unwrap();
+
+    let movie = Movie::from_fm2("|0|........|........||\n|2|........|........||\n").unwrap();
+    assert!(matches!(
+        nes.play_movie(&movie),
+        Err(MovieError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_dpcm_controller_conflict() {
+    let read_after_conflict = |dpcm_controller_conflict| {
+        let mut nes = Nes::new(NesSettings {
+            ui_kind: UiKind::None,
+            dpcm_controller_conflict,
+            ..Default::default()
+        });
+        nes.load_cartidge(scroll_split_cartidge());
+        nes.set_controller_one_state(ControllerState::A | ControllerState::SELECT);
+
+        nes.main_bus.borrow_mut().write(0x4016, 1);
+        nes.main_bus.borrow_mut().write(0x4016, 0);
+        assert_eq!(nes.main_bus.borrow().read(0x4016), 0x41);
+
+        nes.request_dmc_read(0x0000);
+        for _ in 0..12 {
+            nes.clock().unwrap();
+        }
+        let data = nes.main_bus.borrow().read(0x4016);
+        data
+    };
+
+    // B button is read, unless the DMC DMA clocked it away
+    assert_eq!(read_after_conflict(false), 0x40);
+    assert_eq!(read_after_conflict(true), 0x41);
+}